@@ -58,6 +58,7 @@ pub enum CandidErc20TwinLedgerSuiteStatus {
     PendingApproval,
     Created,
     Installed,
+    Rejected,
 }
 
 #[derive(CandidType, Deserialize, Debug)]
@@ -6,7 +6,10 @@ use num_traits::ToPrimitive;
 
 use ic_canister_log::log;
 use icrc_ledger_types::{
-    icrc1::account::Account,
+    icrc1::{
+        account::Account,
+        transfer::{Memo as Icrc1Memo, TransferArg, TransferError as Icrc1TransferError},
+    },
     icrc2::transfer_from::{TransferFromArgs, TransferFromError},
 };
 
@@ -40,12 +43,15 @@ pub const MAINNET_LEDGER_CANISTER_ID: Principal =
 const TRANSFER_METHOD: &str = "transfer";
 const NOTIFY_TOP_UP_METHOD: &str = "notify_top_up";
 const TRANSFER_FROM_METHOD: &str = "icrc2_transfer_from";
+const ICRC1_TRANSFER_METHOD: &str = "icrc1_transfer";
 const ICP_BALANCE_FUNCTION: &str = "icrc1_balance_of";
 #[async_trait]
 
 pub trait CmcRunTime {
     fn id(&self) -> Principal;
 
+    fn time(&self) -> u64;
+
     // ICP balance of canister
     async fn icp_balance(&self) -> Result<u64, IcpToCyclesConversionError>;
 
@@ -64,6 +70,16 @@ pub trait CmcRunTime {
         from_subaccount: Option<[u8; 32]>,
     ) -> Result<Result<Nat, TransferFromError>, CallError>;
 
+    // Transfers icp from the canister to the given principal using icrc1_transfer,
+    // the ledger deduplicates transfers with the same memo and created_at_time
+    async fn transfer_icp(
+        &self,
+        to: Principal,
+        icp_amount: u64,
+        memo: u64,
+        created_at_time: u64,
+    ) -> Result<Result<Nat, Icrc1TransferError>, CallError>;
+
     // Making inter canister calls
     async fn call_canister<I, O>(
         &self,
@@ -98,6 +114,10 @@ impl CmcRunTime for CyclesConvertor {
         ic_cdk::id()
     }
 
+    fn time(&self) -> u64 {
+        ic_cdk::api::time()
+    }
+
     async fn icp_balance(&self) -> Result<u64, IcpToCyclesConversionError> {
         let result: Nat = self
             .call_canister(
@@ -209,6 +229,33 @@ impl CmcRunTime for CyclesConvertor {
         result
     }
 
+    async fn transfer_icp(
+        &self,
+        to: Principal,
+        icp_amount: u64,
+        memo: u64,
+        created_at_time: u64,
+    ) -> Result<Result<Nat, Icrc1TransferError>, CallError> {
+        let transfer_args = TransferArg {
+            from_subaccount: None,
+            to: Account {
+                owner: to,
+                subaccount: None,
+            },
+            fee: Some(DEFAULT_TRANSFER_FEE.e8s().into()),
+            created_at_time: Some(created_at_time),
+            memo: Some(Icrc1Memo::from(memo)),
+            amount: icp_amount.into(),
+        };
+
+        self.call_canister(
+            MAINNET_LEDGER_CANISTER_ID,
+            ICRC1_TRANSFER_METHOD,
+            transfer_args,
+        )
+        .await
+    }
+
     async fn call_canister<I, O>(
         &self,
         canister_id: Principal,
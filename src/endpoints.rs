@@ -6,12 +6,35 @@ use std::{
 };
 
 use crate::{
-    ledger_suite_manager::install_ls::InvalidAddErc20ArgError,
+    ledger_suite_manager::canister_pool::PooledCanister as StatePooledCanister,
+    ledger_suite_manager::controllers::{
+        CanisterControllersUpdate as StateCanisterControllersUpdate,
+        ControllersReport as StateControllersReport,
+    },
+    ledger_suite_manager::health_check::UpgradeRollback as StateUpgradeRollback,
+    ledger_suite_manager::install_ls::{
+        ArchiveProfile as StateArchiveProfile, InstallStep as StateInstallStep,
+        InvalidAddErc20ArgError, LedgerSuiteInstallProgress as StateLedgerSuiteInstallProgress,
+    },
+    ledger_suite_manager::module_hash_drift::{
+        DriftedCanister as StateDriftedCanister,
+        ModuleHashDriftReport as StateModuleHashDriftReport,
+    },
+    ledger_suite_manager::notifications::{
+        Notification as StateNotification, OutboxEntry as StateOutboxEntry,
+    },
+    ledger_suite_manager::upgrade_campaign::{
+        UpgradeCampaign, UpgradeCampaignError, UpgradeCampaignStatus as StateUpgradeCampaignStatus,
+    },
+    ledger_suite_manager::upgrade_ls::LoadCanisterSnapshotError as StateLoadCanisterSnapshotError,
     management::CallError,
     state::{
-        Canister, Canisters, CanistersMetadata, Erc20Token, Hash, IndexCanister, LedgerCanister,
-        ManagedCanisterStatus as StateManagedCanister,
+        Archive, Canister, CanisterKind, Canisters, CanistersMetadata, Erc20Token, Hash, Index,
+        IndexCanister, InvalidStateError, Ledger, LedgerCanister, LedgerSuiteVersionRollbackError,
+        ManagedCanisterStatus as StateManagedCanister, ResumeFailedLedgerSuiteInstallError,
+        TokenDenylists as StateTokenDenylists,
     },
+    storage::{RemovedWasm, StorableWasm, WasmHashError, WasmStoreError, WasmUploadError},
 };
 
 type ChainId = Nat;
@@ -39,12 +62,18 @@ pub struct UpgradeArg {
     pub cycles_management: Option<UpdateCyclesManagement>,
     pub twin_ls_creation_fees: Option<UpdateLedgerSuiteCreationFee>,
     pub new_minter_ids: Option<Vec<(ChainId, Principal)>>,
+    pub install_retry_policy: Option<UpdateInstallRetryPolicy>,
+    pub max_concurrent_ls_installs: Option<u32>,
+    pub canister_settings: Option<UpdateManagedCanisterSettings>,
+    pub canister_pool: Option<UpdateCanisterPoolConfig>,
 }
 
 #[derive(Clone, Eq, PartialEq, Debug, CandidType, Deserialize)]
 pub struct AddErc20Arg {
     pub contract: Erc20Contract,
     pub ledger_init_arg: LedgerInitArg,
+    /// Name of the archive profile of the ledger, the default archive options are used if not given.
+    pub archive_profile: Option<String>,
 }
 
 impl AddErc20Arg {
@@ -60,6 +89,23 @@ pub enum AddErc20Error {
     ChainIdNotSupported(String),
     Erc20TwinTokenAlreadyExists,
     InternalError(String),
+    InvalidTokenName(String),
+    InvalidTokenSymbol(String),
+    InvalidDecimals {
+        decimals: u8,
+        max: u8,
+    },
+    InvalidTransferFee {
+        transfer_fee: Nat,
+        min: Nat,
+        max: Nat,
+    },
+    InvalidTokenLogo(String),
+    Erc20ContractDenied,
+    TokenSymbolDenied(String),
+    TokenSymbolAlreadyUsed(String),
+    TokenNameReserved(String),
+    UnknownArchiveProfile(String),
 }
 
 impl From<InvalidAddErc20ArgError> for AddErc20Error {
@@ -80,6 +126,60 @@ impl From<InvalidAddErc20ArgError> for AddErc20Error {
             InvalidAddErc20ArgError::InternalError(_) => {
                 Self::InternalError("Internal Error, please try again later".to_string())
             }
+            InvalidAddErc20ArgError::TokenNameLengthOutOfBounds { length, min, max } => {
+                Self::InvalidTokenName(format!(
+                    "token name has {} characters, expected between {} and {}",
+                    length, min, max
+                ))
+            }
+            InvalidAddErc20ArgError::InvalidTokenNameCharacter(c) => Self::InvalidTokenName(
+                format!("token name contains the forbidden character {:?}", c),
+            ),
+            InvalidAddErc20ArgError::TokenSymbolLengthOutOfBounds { length, min, max } => {
+                Self::InvalidTokenSymbol(format!(
+                    "token symbol has {} characters, expected between {} and {}",
+                    length, min, max
+                ))
+            }
+            InvalidAddErc20ArgError::InvalidTokenSymbolCharacter(c) => Self::InvalidTokenSymbol(
+                format!("token symbol contains the forbidden character {:?}", c),
+            ),
+            InvalidAddErc20ArgError::DecimalsOutOfBounds { decimals, max } => {
+                Self::InvalidDecimals { decimals, max }
+            }
+            InvalidAddErc20ArgError::TransferFeeOutOfBounds {
+                transfer_fee,
+                min,
+                max,
+            } => Self::InvalidTransferFee {
+                transfer_fee,
+                min,
+                max,
+            },
+            InvalidAddErc20ArgError::TokenLogoTooLarge { length, max } => {
+                Self::InvalidTokenLogo(format!(
+                    "token logo is {} bytes long, expected at most {}",
+                    length, max
+                ))
+            }
+            InvalidAddErc20ArgError::TokenLogoNotADataUri => {
+                Self::InvalidTokenLogo("token logo must be a base64 encoded data URI".to_string())
+            }
+            InvalidAddErc20ArgError::UnsupportedTokenLogoMediaType(media_type) => {
+                Self::InvalidTokenLogo(format!(
+                    "token logo media type {} is not supported",
+                    media_type
+                ))
+            }
+            InvalidAddErc20ArgError::Erc20ContractDenied(_) => Self::Erc20ContractDenied,
+            InvalidAddErc20ArgError::TokenSymbolDenied(symbol) => Self::TokenSymbolDenied(symbol),
+            InvalidAddErc20ArgError::TokenSymbolAlreadyUsed { symbol, token: _ } => {
+                Self::TokenSymbolAlreadyUsed(symbol)
+            }
+            InvalidAddErc20ArgError::TokenNameReserved(name) => Self::TokenNameReserved(name),
+            InvalidAddErc20ArgError::UnknownArchiveProfile(name) => {
+                Self::UnknownArchiveProfile(name)
+            }
         }
     }
 }
@@ -99,6 +199,15 @@ pub struct Erc20Contract {
     pub address: String,
 }
 
+impl From<Erc20Token> for Erc20Contract {
+    fn from(token: Erc20Token) -> Self {
+        Self {
+            chain_id: Nat::from(*token.chain_id().as_ref()),
+            address: token.address().to_string(),
+        }
+    }
+}
+
 #[derive(Clone, Eq, PartialEq, Debug, CandidType, Deserialize, serde::Serialize)]
 pub struct LedgerInitArg {
     pub transfer_fee: Nat,
@@ -154,6 +263,67 @@ impl CyclesManagement {
     }
 }
 
+/// Retries of ledger suite installations failing with a recoverable error
+/// and of notifications that could not be delivered.
+///
+/// The delay before the n-th retry is `initial_backoff_seconds * 2^(n-1)`, capped at `max_backoff_seconds`.
+#[derive(Clone, Eq, PartialEq, Debug, CandidType, Deserialize, serde::Serialize)]
+pub struct InstallRetryPolicy {
+    /// Number of failed attempts after which an installation is considered failed.
+    /// Notifications are retried until they are delivered.
+    pub max_attempts: u32,
+    pub initial_backoff_seconds: u64,
+    pub max_backoff_seconds: u64,
+}
+
+impl Default for InstallRetryPolicy {
+    fn default() -> Self {
+        const ONE_MINUTE: u64 = 60;
+        const SIX_HOURS: u64 = 6 * 60 * 60;
+
+        Self {
+            max_attempts: 20,
+            initial_backoff_seconds: ONE_MINUTE,
+            max_backoff_seconds: SIX_HOURS,
+        }
+    }
+}
+
+impl InstallRetryPolicy {
+    /// Delay in nanoseconds before retrying an installation that failed `attempts` times.
+    pub fn backoff_nanos(&self, attempts: u32) -> u64 {
+        const NANOS_PER_SECOND: u64 = 1_000_000_000;
+        let factor = 1_u64
+            .checked_shl(attempts.saturating_sub(1))
+            .unwrap_or(u64::MAX);
+        self.initial_backoff_seconds
+            .saturating_mul(factor)
+            .min(self.max_backoff_seconds)
+            .saturating_mul(NANOS_PER_SECOND)
+    }
+}
+
+#[derive(Clone, Eq, PartialEq, Debug, Default, CandidType, Deserialize, serde::Serialize)]
+pub struct UpdateInstallRetryPolicy {
+    pub max_attempts: Option<u32>,
+    pub initial_backoff_seconds: Option<u64>,
+    pub max_backoff_seconds: Option<u64>,
+}
+
+impl UpdateInstallRetryPolicy {
+    pub fn apply(self, old: &mut InstallRetryPolicy) {
+        if let Some(max_attempts) = self.max_attempts {
+            old.max_attempts = max_attempts;
+        }
+        if let Some(initial_backoff_seconds) = self.initial_backoff_seconds {
+            old.initial_backoff_seconds = initial_backoff_seconds;
+        }
+        if let Some(max_backoff_seconds) = self.max_backoff_seconds {
+            old.max_backoff_seconds = max_backoff_seconds;
+        }
+    }
+}
+
 #[derive(
     Clone, Eq, PartialEq, Ord, PartialOrd, Debug, Default, CandidType, Deserialize, serde::Serialize,
 )]
@@ -181,6 +351,142 @@ impl UpdateCyclesManagement {
     }
 }
 
+/// Settings of a canister created by the lsm.
+/// A setting that is not given is left to the IC default at creation and never updated afterwards.
+#[derive(Clone, Eq, PartialEq, Debug, Default, CandidType, Deserialize, serde::Serialize)]
+pub struct CanisterSettings {
+    /// Number of seconds the canister can run on its cycles balance before being frozen.
+    pub freezing_threshold: Option<u64>,
+    /// Memory reserved for the canister in bytes.
+    pub memory_allocation: Option<u64>,
+    /// Upper limit of the wasm heap memory of the canister in bytes.
+    pub wasm_memory_limit: Option<u64>,
+    /// Upper limit of the cycles the canister may reserve for storage.
+    pub reserved_cycles_limit: Option<u128>,
+    pub log_visibility: Option<LogVisibility>,
+}
+
+#[derive(Clone, Copy, Eq, PartialEq, Debug, CandidType, Deserialize, serde::Serialize)]
+pub enum LogVisibility {
+    Controllers,
+    Public,
+}
+
+/// Settings of the canisters of the managed ledger suites, by canister kind.
+#[derive(Clone, Eq, PartialEq, Debug, Default, CandidType, Deserialize, serde::Serialize)]
+pub struct ManagedCanisterSettings {
+    pub ledger: CanisterSettings,
+    pub index: CanisterSettings,
+    /// Archives are spawned by their ledger, so their settings are only applied once discovered.
+    pub archive: CanisterSettings,
+}
+
+impl ManagedCanisterSettings {
+    pub fn for_kind(&self, kind: CanisterKind) -> &CanisterSettings {
+        match kind {
+            CanisterKind::Ledger => &self.ledger,
+            CanisterKind::Index => &self.index,
+            CanisterKind::Archive => &self.archive,
+        }
+    }
+}
+
+#[derive(Clone, Eq, PartialEq, Debug, Default, CandidType, Deserialize, serde::Serialize)]
+pub struct UpdateManagedCanisterSettings {
+    pub ledger: Option<CanisterSettings>,
+    pub index: Option<CanisterSettings>,
+    pub archive: Option<CanisterSettings>,
+}
+
+impl UpdateManagedCanisterSettings {
+    pub fn apply(self, old: &mut ManagedCanisterSettings) {
+        if let Some(ledger) = self.ledger {
+            old.ledger = ledger;
+        }
+        if let Some(index) = self.index {
+            old.index = index;
+        }
+        if let Some(archive) = self.archive {
+            old.archive = archive;
+        }
+    }
+}
+
+/// Empty canisters created in advance on the subnet of new ledger suites,
+/// taken by ledger suite installations instead of creating new canisters.
+#[derive(Clone, Eq, PartialEq, Debug, CandidType, Deserialize, serde::Serialize)]
+pub struct CanisterPoolConfig {
+    /// Number of empty canisters to keep in the pool, the pool is not refilled if zero.
+    pub target_size: u32,
+    /// Cycles every canister of the pool is created with.
+    pub cycles_per_canister: Nat,
+}
+
+impl Default for CanisterPoolConfig {
+    fn default() -> Self {
+        const FIVE_TRILLIONS: u64 = 5_000_000_000_000;
+
+        Self {
+            target_size: 0,
+            cycles_per_canister: Nat::from(FIVE_TRILLIONS),
+        }
+    }
+}
+
+#[derive(Clone, Eq, PartialEq, Debug, Default, CandidType, Deserialize, serde::Serialize)]
+pub struct UpdateCanisterPoolConfig {
+    pub target_size: Option<u32>,
+    pub cycles_per_canister: Option<Nat>,
+}
+
+impl UpdateCanisterPoolConfig {
+    pub fn apply(self, old: &mut CanisterPoolConfig) {
+        if let Some(target_size) = self.target_size {
+            old.target_size = target_size;
+        }
+        if let Some(cycles_per_canister) = self.cycles_per_canister {
+            old.cycles_per_canister = cycles_per_canister;
+        }
+    }
+}
+
+#[derive(Clone, Eq, PartialEq, Debug, CandidType, Deserialize)]
+pub struct PooledCanister {
+    pub canister_id: Principal,
+    pub cycles: Nat,
+    pub placement: CanisterPlacement,
+    pub created_at: u64,
+}
+
+impl From<StatePooledCanister> for PooledCanister {
+    fn from(value: StatePooledCanister) -> Self {
+        Self {
+            canister_id: value.canister_id,
+            cycles: Nat::from(value.cycles),
+            placement: value.placement,
+            created_at: value.created_at,
+        }
+    }
+}
+
+#[derive(Clone, Eq, PartialEq, Debug, CandidType, Deserialize)]
+pub struct CanisterPool {
+    pub config: CanisterPoolConfig,
+    pub canisters: Vec<PooledCanister>,
+}
+
+/// Subnet on which the lsm creates ledger and index canisters.
+#[derive(Clone, Eq, PartialEq, Debug, Default, CandidType, Deserialize, serde::Serialize)]
+pub enum CanisterPlacement {
+    /// The subnet of the lsm, canisters are created by the management canister.
+    #[default]
+    LsmSubnet,
+    /// The given subnet, canisters are created by the cycles minting canister.
+    Subnet(Principal),
+    /// A subnet of the given type, e.g. `fiduciary`, canisters are created by the cycles minting canister.
+    SubnetType(String),
+}
+
 #[derive(Clone, PartialEq, Debug, CandidType, Deserialize)]
 pub struct InstalledNativeLedgerSuite {
     pub symbol: String,
@@ -213,6 +519,9 @@ impl From<InstalledNativeLedgerSuite> for Canisters {
             metadata: CanistersMetadata {
                 token_symbol: value.symbol,
             },
+            snapshots: Default::default(),
+            archive_profile: None,
+            placement: None,
         }
     }
 }
@@ -306,6 +615,8 @@ pub struct ManagedCanisters {
     pub ledger: Option<ManagedCanisterStatus>,
     pub index: Option<ManagedCanisterStatus>,
     pub archives: Vec<Principal>,
+    /// The subnet the ledger and index were created on, if created by the lsm.
+    pub placement: Option<CanisterPlacement>,
 }
 
 impl From<(Erc20Token, Canisters)> for ManagedCanisters {
@@ -319,6 +630,7 @@ impl From<(Erc20Token, Canisters)> for ManagedCanisters {
             ledger: canisters.ledger.as_ref().map(ManagedCanisterStatus::from),
             index: canisters.index.as_ref().map(ManagedCanisterStatus::from),
             archives: canisters.archives.clone(),
+            placement: canisters.placement.clone(),
         }
     }
 }
@@ -340,13 +652,839 @@ impl From<crate::state::LedgerSuiteVersion> for LedgerSuiteVersion {
     }
 }
 
+#[derive(Clone, Eq, PartialEq, Debug, CandidType, Deserialize)]
+pub struct ActivatedLedgerSuiteVersion {
+    pub version: LedgerSuiteVersion,
+    /// The canister time at which the version became current
+    /// in nanoseconds since the epoch (1970-01-01).
+    pub activated_at: u64,
+}
+
+impl From<crate::state::ActivatedLedgerSuiteVersion> for ActivatedLedgerSuiteVersion {
+    fn from(value: crate::state::ActivatedLedgerSuiteVersion) -> Self {
+        Self {
+            version: value.version.into(),
+            activated_at: value.activated_at,
+        }
+    }
+}
+
+#[derive(Clone, Eq, PartialEq, Debug, CandidType, Deserialize)]
+pub enum RollbackLedgerSuiteVersionError {
+    NoPreviousVersion,
+    UnknownHistoryIndex(u64),
+    AlreadyCurrentVersion,
+    WasmNotFound(String),
+    UpgradeInProgress,
+}
+
+impl From<LedgerSuiteVersionRollbackError> for RollbackLedgerSuiteVersionError {
+    fn from(value: LedgerSuiteVersionRollbackError) -> Self {
+        match value {
+            LedgerSuiteVersionRollbackError::NoPreviousVersion => Self::NoPreviousVersion,
+            LedgerSuiteVersionRollbackError::UnknownHistoryIndex(index) => {
+                Self::UnknownHistoryIndex(index as u64)
+            }
+            LedgerSuiteVersionRollbackError::AlreadyCurrentVersion => Self::AlreadyCurrentVersion,
+        }
+    }
+}
+
 #[derive(Clone, Eq, PartialEq, Debug, CandidType, Deserialize)]
 pub struct LedgerManagerInfo {
     pub managed_canisters: Vec<ManagedCanisters>,
     pub cycles_management: CyclesManagement,
+    pub install_retry_policy: InstallRetryPolicy,
+    pub max_concurrent_ls_installs: u32,
+    pub canister_settings: ManagedCanisterSettings,
+    pub canister_placement: CanisterPlacement,
     pub more_controller_ids: Vec<Principal>,
     pub minter_ids: Vec<(ChainId, Principal)>,
     pub ledger_suite_version: Option<LedgerSuiteVersion>,
     pub ls_creation_icp_fee: Nat,
     pub ls_creation_appic_fee: Option<Nat>,
 }
+
+#[derive(Clone, Copy, Eq, PartialEq, Debug, CandidType, Deserialize)]
+pub enum WasmType {
+    Ledger,
+    Index,
+    Archive,
+}
+
+impl WasmType {
+    /// Decodes the type of a wasm from the marker it is stored with.
+    pub fn from_marker(marker: u8) -> Option<Self> {
+        match marker {
+            Ledger::MARKER => Some(Self::Ledger),
+            Index::MARKER => Some(Self::Index),
+            Archive::MARKER => Some(Self::Archive),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Clone, Eq, PartialEq, Debug, CandidType, Deserialize)]
+pub struct UploadWasmArg {
+    pub wasm_type: WasmType,
+    /// Hex-encoded SHA-256 hash of the complete wasm.
+    pub wasm_hash: String,
+    pub chunk_index: u32,
+    pub total_chunks: u32,
+    pub chunk: serde_bytes::ByteBuf,
+}
+
+#[derive(Clone, Eq, PartialEq, Debug, CandidType, Deserialize)]
+pub enum UploadWasmError {
+    InvalidWasmHash(String),
+    InvalidChunkIndex {
+        chunk_index: u32,
+        total_chunks: u32,
+    },
+    UnexpectedChunk {
+        expected_chunk_index: u32,
+        actual_chunk_index: u32,
+    },
+    HashMismatch {
+        expected: String,
+        actual: String,
+    },
+    WasmTooLarge {
+        max_size: u64,
+    },
+    WasmMismatch {
+        wasm_hash: String,
+        expected_marker: u8,
+        actual_marker: u8,
+    },
+}
+
+impl From<WasmUploadError> for UploadWasmError {
+    fn from(value: WasmUploadError) -> Self {
+        match value {
+            WasmUploadError::InvalidChunkIndex {
+                chunk_index,
+                total_chunks,
+            } => Self::InvalidChunkIndex {
+                chunk_index,
+                total_chunks,
+            },
+            WasmUploadError::UnexpectedChunk {
+                expected_chunk_index,
+                actual_chunk_index,
+            } => Self::UnexpectedChunk {
+                expected_chunk_index,
+                actual_chunk_index,
+            },
+            WasmUploadError::HashMismatch { expected, actual } => Self::HashMismatch {
+                expected: expected.to_string(),
+                actual: actual.to_string(),
+            },
+            WasmUploadError::WasmTooLarge { max_size } => Self::WasmTooLarge { max_size },
+            WasmUploadError::WasmStoreError(WasmStoreError::WasmMismatch {
+                wasm_hash,
+                expected_marker,
+                actual_marker,
+            }) => Self::WasmMismatch {
+                wasm_hash: wasm_hash.to_string(),
+                expected_marker,
+                actual_marker,
+            },
+        }
+    }
+}
+
+#[derive(Clone, Eq, PartialEq, Debug, CandidType, Deserialize)]
+pub struct StoredWasmInfo {
+    pub wasm_hash: String,
+    pub wasm_type: WasmType,
+    /// Size of the compressed wasm in bytes.
+    pub size: u64,
+    /// The canister time at which the lsm stored this wasm
+    /// in nanoseconds since the epoch (1970-01-01).
+    pub timestamp: u64,
+    /// Managed ledger and index canisters on which the lsm installed this wasm.
+    pub installed_on: Vec<Principal>,
+    /// Whether this wasm is part of the current ledger suite version.
+    pub is_current_version: bool,
+}
+
+#[derive(Clone, Eq, PartialEq, Debug, CandidType, Deserialize)]
+pub struct RemovedWasmInfo {
+    pub wasm_hash: String,
+    pub wasm_type: WasmType,
+    /// Size of the compressed wasm in bytes.
+    pub size: u64,
+    /// The canister time at which the lsm stored this wasm
+    /// in nanoseconds since the epoch (1970-01-01).
+    pub timestamp: u64,
+}
+
+impl From<RemovedWasm> for RemovedWasmInfo {
+    fn from(value: RemovedWasm) -> Self {
+        Self {
+            wasm_hash: value.wasm_hash.to_string(),
+            wasm_type: WasmType::from_marker(value.marker).expect("BUG: unknown wasm marker"),
+            size: value.size as u64,
+            timestamp: value.timestamp,
+        }
+    }
+}
+
+#[derive(Clone, Eq, PartialEq, Debug, CandidType, Deserialize)]
+pub struct WasmGarbageCollectionReport {
+    pub removed_wasms: Vec<RemovedWasmInfo>,
+    /// Total size of the removed wasms in bytes.
+    pub freed_bytes: u64,
+}
+
+impl From<Vec<RemovedWasm>> for WasmGarbageCollectionReport {
+    fn from(removed_wasms: Vec<RemovedWasm>) -> Self {
+        let removed_wasms: Vec<RemovedWasmInfo> = removed_wasms
+            .into_iter()
+            .map(RemovedWasmInfo::from)
+            .collect();
+        Self {
+            freed_bytes: removed_wasms.iter().map(|w| w.size).sum(),
+            removed_wasms,
+        }
+    }
+}
+
+impl From<WasmType> for CanisterKind {
+    fn from(value: WasmType) -> Self {
+        match value {
+            WasmType::Ledger => CanisterKind::Ledger,
+            WasmType::Index => CanisterKind::Index,
+            WasmType::Archive => CanisterKind::Archive,
+        }
+    }
+}
+
+impl From<CanisterKind> for WasmType {
+    fn from(value: CanisterKind) -> Self {
+        match value {
+            CanisterKind::Ledger => WasmType::Ledger,
+            CanisterKind::Index => WasmType::Index,
+            CanisterKind::Archive => WasmType::Archive,
+        }
+    }
+}
+
+#[derive(Clone, Eq, PartialEq, Debug, CandidType, Deserialize)]
+pub struct DriftedCanister {
+    pub canister_id: Principal,
+    pub canister_type: WasmType,
+    /// Hash of the wasm the lsm expects to be installed.
+    pub expected_wasm_hash: String,
+    /// Module hash reported by the IC, or `None` if the canister is empty.
+    pub module_hash: Option<String>,
+}
+
+impl From<StateDriftedCanister> for DriftedCanister {
+    fn from(value: StateDriftedCanister) -> Self {
+        Self {
+            canister_id: value.canister_id,
+            canister_type: value.kind.into(),
+            expected_wasm_hash: value.expected_wasm_hash.to_string(),
+            module_hash: value.module_hash.map(|hash| hash.to_string()),
+        }
+    }
+}
+
+#[derive(Clone, Eq, PartialEq, Debug, CandidType, Deserialize)]
+pub struct ModuleHashDriftReport {
+    pub erc20_contract: Erc20Contract,
+    /// The canister time at which the check was done
+    /// in nanoseconds since the epoch (1970-01-01).
+    pub checked_at: u64,
+    pub drifted_canisters: Vec<DriftedCanister>,
+}
+
+impl From<(Erc20Token, StateModuleHashDriftReport)> for ModuleHashDriftReport {
+    fn from((token, report): (Erc20Token, StateModuleHashDriftReport)) -> Self {
+        Self {
+            erc20_contract: token.into(),
+            checked_at: report.checked_at,
+            drifted_canisters: report
+                .drifted_canisters
+                .into_iter()
+                .map(DriftedCanister::from)
+                .collect(),
+        }
+    }
+}
+
+#[derive(Clone, Eq, PartialEq, Debug, CandidType, Deserialize)]
+pub struct UpdateControllersArg {
+    pub add_controller_ids: Vec<Principal>,
+    pub remove_controller_ids: Vec<Principal>,
+}
+
+#[derive(Clone, Eq, PartialEq, Debug, CandidType, Deserialize)]
+pub enum UpdateControllersError {
+    /// The lsm always controls the managed canisters.
+    LsmIsAlwaysController,
+    TooManyAdditionalControllers {
+        max: u64,
+        actual: u64,
+    },
+    ReconciliationInProgress,
+}
+
+impl From<InvalidStateError> for UpdateControllersError {
+    fn from(value: InvalidStateError) -> Self {
+        match value {
+            InvalidStateError::TooManyAdditionalControllers { max, actual } => {
+                Self::TooManyAdditionalControllers {
+                    max: max as u64,
+                    actual: actual as u64,
+                }
+            }
+        }
+    }
+}
+
+#[derive(Clone, Eq, PartialEq, Debug, CandidType, Deserialize)]
+pub struct CanisterControllersUpdate {
+    pub canister_id: Principal,
+    pub canister_type: WasmType,
+    /// The reason the controllers could not be set, if any.
+    pub error: Option<String>,
+}
+
+impl From<StateCanisterControllersUpdate> for CanisterControllersUpdate {
+    fn from(value: StateCanisterControllersUpdate) -> Self {
+        Self {
+            canister_id: value.canister_id,
+            canister_type: value.kind.into(),
+            error: value.error,
+        }
+    }
+}
+
+#[derive(Clone, Eq, PartialEq, Debug, CandidType, Deserialize)]
+pub struct ControllersReport {
+    pub erc20_contract: Erc20Contract,
+    /// The canister time at which the controllers were set
+    /// in nanoseconds since the epoch (1970-01-01).
+    pub updated_at: u64,
+    pub controllers: Vec<Principal>,
+    pub canisters: Vec<CanisterControllersUpdate>,
+}
+
+impl From<(Erc20Token, StateControllersReport)> for ControllersReport {
+    fn from((token, report): (Erc20Token, StateControllersReport)) -> Self {
+        Self {
+            erc20_contract: token.into(),
+            updated_at: report.updated_at,
+            controllers: report.controllers,
+            canisters: report
+                .canisters
+                .into_iter()
+                .map(CanisterControllersUpdate::from)
+                .collect(),
+        }
+    }
+}
+
+#[derive(Clone, Eq, PartialEq, Debug, CandidType, Deserialize)]
+pub struct StartUpgradeCampaignArg {
+    /// Wasm hashes of the ledger suite version to roll out.
+    /// Missing hashes are taken from the current ledger suite version.
+    pub ledger_compressed_wasm_hash: Option<String>,
+    pub index_compressed_wasm_hash: Option<String>,
+    pub archive_compressed_wasm_hash: Option<String>,
+    /// Tokens upgraded in the first batch.
+    pub canary_tokens: Vec<Erc20Contract>,
+    pub batch_size: u32,
+    pub pause_between_batches_secs: u64,
+}
+
+#[derive(Clone, Eq, PartialEq, Debug, CandidType, Deserialize)]
+pub enum StartUpgradeCampaignError {
+    CampaignAlreadyRunning,
+    InvalidBatchSize,
+    InvalidWasmHash(String),
+    WasmHashNotFound(String),
+    InvalidCanaryToken(String),
+    CanaryWithoutUpgrade(Erc20Contract),
+    NothingToUpgrade,
+    UpgradeInProgress,
+}
+
+impl From<UpgradeCampaignError> for StartUpgradeCampaignError {
+    fn from(value: UpgradeCampaignError) -> Self {
+        match value {
+            UpgradeCampaignError::CampaignAlreadyRunning => Self::CampaignAlreadyRunning,
+            UpgradeCampaignError::InvalidBatchSize => Self::InvalidBatchSize,
+            UpgradeCampaignError::CanaryWithoutUpgrade(token) => {
+                Self::CanaryWithoutUpgrade(token.into())
+            }
+            UpgradeCampaignError::NothingToUpgrade => Self::NothingToUpgrade,
+        }
+    }
+}
+
+impl From<WasmHashError> for StartUpgradeCampaignError {
+    fn from(value: WasmHashError) -> Self {
+        match value {
+            WasmHashError::Invalid(reason) => Self::InvalidWasmHash(reason),
+            WasmHashError::NotFound(wasm_hash) => Self::WasmHashNotFound(wasm_hash.to_string()),
+        }
+    }
+}
+
+#[derive(Clone, Eq, PartialEq, Debug, CandidType, Deserialize)]
+pub enum ResumeUpgradeCampaignError {
+    NoStoppedCampaign,
+}
+
+#[derive(Clone, Eq, PartialEq, Debug, CandidType, Deserialize)]
+pub enum CancelUpgradeCampaignError {
+    NoActiveCampaign,
+}
+
+#[derive(Clone, Eq, PartialEq, Debug, CandidType, Deserialize)]
+pub enum UpgradeCampaignStatus {
+    Running,
+    Stopped,
+    Completed,
+    Cancelled,
+}
+
+impl From<StateUpgradeCampaignStatus> for UpgradeCampaignStatus {
+    fn from(value: StateUpgradeCampaignStatus) -> Self {
+        match value {
+            StateUpgradeCampaignStatus::Running => Self::Running,
+            StateUpgradeCampaignStatus::Stopped => Self::Stopped,
+            StateUpgradeCampaignStatus::Completed => Self::Completed,
+            StateUpgradeCampaignStatus::Cancelled => Self::Cancelled,
+        }
+    }
+}
+
+#[derive(Clone, Eq, PartialEq, Debug, CandidType, Deserialize)]
+pub struct FailedUpgrade {
+    pub erc20_contract: Erc20Contract,
+    pub error: String,
+}
+
+#[derive(Clone, Eq, PartialEq, Debug, CandidType, Deserialize)]
+pub struct UpgradeCampaignInfo {
+    pub status: UpgradeCampaignStatus,
+    pub ledger_suite_version: LedgerSuiteVersion,
+    pub canary_tokens: Vec<Erc20Contract>,
+    pub batch_size: u64,
+    pub pause_between_batches_secs: u64,
+    /// The canister time at which the campaign started
+    /// in nanoseconds since the epoch (1970-01-01).
+    pub started_at: u64,
+    /// The canister time from which the next batch may start
+    /// in nanoseconds since the epoch (1970-01-01).
+    pub next_batch_at: u64,
+    pub pending: Vec<Erc20Contract>,
+    pub in_progress: Vec<Erc20Contract>,
+    pub done: Vec<Erc20Contract>,
+    pub failed: Vec<FailedUpgrade>,
+}
+
+impl From<UpgradeCampaign> for UpgradeCampaignInfo {
+    fn from(campaign: UpgradeCampaign) -> Self {
+        fn contracts<'a>(tokens: impl IntoIterator<Item = &'a Erc20Token>) -> Vec<Erc20Contract> {
+            tokens
+                .into_iter()
+                .cloned()
+                .map(Erc20Contract::from)
+                .collect()
+        }
+        Self {
+            status: campaign.status().clone().into(),
+            ledger_suite_version: campaign.ledger_suite_version().clone().into(),
+            canary_tokens: contracts(campaign.canary_tokens()),
+            batch_size: campaign.batch_size() as u64,
+            pause_between_batches_secs: campaign.pause_between_batches() / 1_000_000_000,
+            started_at: campaign.started_at(),
+            next_batch_at: campaign.next_batch_at(),
+            pending: contracts(campaign.pending()),
+            in_progress: contracts(campaign.in_progress()),
+            done: contracts(campaign.done()),
+            failed: campaign
+                .failed()
+                .iter()
+                .map(|(token, error)| FailedUpgrade {
+                    erc20_contract: token.clone().into(),
+                    error: error.clone(),
+                })
+                .collect(),
+        }
+    }
+}
+
+#[derive(Clone, Eq, PartialEq, Debug, CandidType, Deserialize)]
+pub struct UpgradeRollback {
+    pub erc20_contract: Erc20Contract,
+    pub canister_id: Principal,
+    pub canister_type: WasmType,
+    pub failed_wasm_hash: String,
+    pub restored_wasm_hash: String,
+    pub reason: String,
+    /// The canister time at which the canister was rolled back
+    /// in nanoseconds since the epoch (1970-01-01).
+    pub rolled_back_at: u64,
+}
+
+impl From<(Erc20Token, StateUpgradeRollback)> for UpgradeRollback {
+    fn from((token, rollback): (Erc20Token, StateUpgradeRollback)) -> Self {
+        Self {
+            erc20_contract: token.into(),
+            canister_id: rollback.canister_id,
+            canister_type: rollback.kind.into(),
+            failed_wasm_hash: rollback.failed_wasm_hash.to_string(),
+            restored_wasm_hash: rollback.restored_wasm_hash.to_string(),
+            reason: rollback.reason,
+            rolled_back_at: rollback.rolled_back_at,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Eq, PartialEq, Debug, CandidType, Deserialize)]
+pub enum InstallStep {
+    CreateLedger,
+    InstallLedger,
+    CreateIndex,
+    InstallIndex,
+    EnqueueNotifications,
+}
+
+impl From<StateInstallStep> for InstallStep {
+    fn from(value: StateInstallStep) -> Self {
+        match value {
+            StateInstallStep::CreateLedger => Self::CreateLedger,
+            StateInstallStep::InstallLedger => Self::InstallLedger,
+            StateInstallStep::CreateIndex => Self::CreateIndex,
+            StateInstallStep::InstallIndex => Self::InstallIndex,
+            StateInstallStep::EnqueueNotifications => Self::EnqueueNotifications,
+        }
+    }
+}
+
+#[derive(Clone, Eq, PartialEq, Debug, CandidType, Deserialize)]
+pub struct InstallStepProgress {
+    pub step: InstallStep,
+    /// The canister time of the last execution of the step
+    /// in nanoseconds since the epoch (1970-01-01).
+    pub attempted_at: u64,
+    pub completed_at: Option<u64>,
+    pub last_error: Option<String>,
+}
+
+#[derive(Clone, Eq, PartialEq, Debug, CandidType, Deserialize)]
+pub struct LedgerSuiteInstallProgress {
+    pub erc20_contract: Erc20Contract,
+    /// The first step that did not complete yet.
+    pub current_step: Option<InstallStep>,
+    /// The executed steps, in execution order.
+    pub steps: Vec<InstallStepProgress>,
+}
+
+impl From<(Erc20Token, StateLedgerSuiteInstallProgress)> for LedgerSuiteInstallProgress {
+    fn from((token, progress): (Erc20Token, StateLedgerSuiteInstallProgress)) -> Self {
+        Self {
+            erc20_contract: token.into(),
+            current_step: progress.current_step().map(InstallStep::from),
+            steps: progress
+                .steps()
+                .iter()
+                .map(|(step, step_progress)| InstallStepProgress {
+                    step: (*step).into(),
+                    attempted_at: step_progress.attempted_at,
+                    completed_at: step_progress.completed_at,
+                    last_error: step_progress.last_error.clone(),
+                })
+                .collect(),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Eq, PartialEq, Debug, CandidType, Deserialize)]
+pub enum NotificationKind {
+    /// Registers the ledger with the appic helper.
+    AddIcpToken,
+    /// Registers the ledger with the minter of its chain.
+    AddErc20Token,
+    /// Updates the status of the ledger suite request in the appic helper.
+    UpdateLedgerSuiteRequest,
+    /// Asks the appic helper to refresh its bridge pairs.
+    UpdateBridgePairs,
+}
+
+impl From<&StateNotification> for NotificationKind {
+    fn from(value: &StateNotification) -> Self {
+        match value {
+            StateNotification::AddIcpToken { .. } => Self::AddIcpToken,
+            StateNotification::AddErc20Token { .. } => Self::AddErc20Token,
+            StateNotification::UpdateLedgerSuiteRequest { .. } => Self::UpdateLedgerSuiteRequest,
+            StateNotification::UpdateBridgePairs { .. } => Self::UpdateBridgePairs,
+        }
+    }
+}
+
+/// Notification about a twin token that was not delivered yet.
+#[derive(Clone, Eq, PartialEq, Debug, CandidType, Deserialize)]
+pub struct PendingNotification {
+    pub id: u64,
+    pub erc20_contract: Erc20Contract,
+    pub kind: NotificationKind,
+    /// The canister time at which the notification was enqueued
+    /// in nanoseconds since the epoch (1970-01-01).
+    pub enqueued_at: u64,
+    /// Number of failed deliveries.
+    pub attempts: u32,
+    pub last_error: Option<String>,
+    /// The canister time from which the delivery may be attempted again
+    /// in nanoseconds since the epoch (1970-01-01).
+    pub next_attempt_at: u64,
+}
+
+impl From<(u64, StateOutboxEntry)> for PendingNotification {
+    fn from((id, entry): (u64, StateOutboxEntry)) -> Self {
+        Self {
+            id,
+            erc20_contract: entry.notification.token().clone().into(),
+            kind: NotificationKind::from(&entry.notification),
+            enqueued_at: entry.enqueued_at,
+            attempts: entry.attempts,
+            last_error: entry.last_error,
+            next_attempt_at: entry.next_attempt_at,
+        }
+    }
+}
+
+#[derive(Clone, Eq, PartialEq, Debug, CandidType, Deserialize)]
+pub struct FailedLedgerSuiteInstall {
+    pub erc20_contract: Erc20Contract,
+    pub creator: Principal,
+    pub token_name: String,
+    pub token_symbol: String,
+    /// The canister time at which the ledger suite was requested
+    /// in nanoseconds since the epoch (1970-01-01).
+    pub created_at: u64,
+    /// The amount of ICP deposited by the creator, if any.
+    pub fee_paid: Option<Nat>,
+    pub attempts: u32,
+    pub failure_reason: Option<String>,
+    /// The icp ledger block of the refund of the fee, once refunded.
+    pub refund_block_index: Option<u64>,
+}
+
+#[derive(Clone, Eq, PartialEq, Debug, CandidType, Deserialize)]
+pub struct PendingLedgerSuiteRequest {
+    pub erc20_contract: Erc20Contract,
+    pub creator: Principal,
+    pub token_name: String,
+    pub token_symbol: String,
+    /// The canister time at which the ledger suite was requested
+    /// in nanoseconds since the epoch (1970-01-01).
+    pub created_at: u64,
+    /// The amount of ICP deposited by the creator, if any.
+    pub fee_paid: Option<Nat>,
+}
+
+#[derive(Clone, Eq, PartialEq, Debug, CandidType, Deserialize)]
+pub enum LedgerSuiteRequestModerationError {
+    InvalidErc20Contract(String),
+    RequestNotFound,
+}
+
+#[derive(Clone, Eq, PartialEq, Debug, CandidType, Deserialize)]
+pub struct ResumeFailedInstallArg {
+    pub erc20_contract: Erc20Contract,
+    /// Wasm hashes to install instead of the ones of the failed installation.
+    pub ledger_compressed_wasm_hash: Option<String>,
+    pub index_compressed_wasm_hash: Option<String>,
+}
+
+#[derive(Clone, Eq, PartialEq, Debug, CandidType, Deserialize)]
+pub enum ResumeFailedInstallError {
+    InvalidErc20Contract(String),
+    InvalidWasmHash(String),
+    WasmHashNotFound(String),
+    FailedInstallNotFound,
+    /// The fee deposited by the creator was already refunded.
+    DepositRefunded,
+}
+
+impl From<ResumeFailedLedgerSuiteInstallError> for ResumeFailedInstallError {
+    fn from(value: ResumeFailedLedgerSuiteInstallError) -> Self {
+        match value {
+            ResumeFailedLedgerSuiteInstallError::FailedInstallNotFound => {
+                Self::FailedInstallNotFound
+            }
+            ResumeFailedLedgerSuiteInstallError::DepositRefunded => Self::DepositRefunded,
+        }
+    }
+}
+
+impl From<WasmHashError> for ResumeFailedInstallError {
+    fn from(value: WasmHashError) -> Self {
+        match value {
+            WasmHashError::Invalid(reason) => Self::InvalidWasmHash(reason),
+            WasmHashError::NotFound(wasm_hash) => Self::WasmHashNotFound(wasm_hash.to_string()),
+        }
+    }
+}
+
+#[derive(Clone, Eq, PartialEq, Debug, CandidType, Deserialize)]
+pub enum DiscardFailedInstallError {
+    InvalidErc20Contract(String),
+    FailedInstallNotFound,
+}
+
+/// Refund whose transfer may have been executed by the icp ledger but can no longer be retried.
+/// The transfer is looked up on the icp ledger by its memo and `created_at_time`.
+#[derive(Clone, Eq, PartialEq, Debug, CandidType, Deserialize)]
+pub struct IcpRefundToReconcile {
+    pub erc20_contract: Erc20Contract,
+    pub depositor: Principal,
+    pub amount: Nat,
+    /// The memo of the refund transfer, which is the ledger index of the deposit.
+    pub memo: u64,
+    /// The `created_at_time` of the refund transfer
+    /// in nanoseconds since the epoch (1970-01-01).
+    pub created_at_time: u64,
+}
+
+#[derive(Clone, Eq, PartialEq, Debug, CandidType, Deserialize)]
+pub struct ReconcileIcpRefundArg {
+    /// The ledger index of the deposit, which is the memo of its refund transfer.
+    pub memo: u64,
+    /// The icp ledger block of the refund transfer, if it was executed.
+    /// Without it, a new refund is scheduled.
+    pub refund_block_index: Option<u64>,
+}
+
+#[derive(Clone, Eq, PartialEq, Debug, CandidType, Deserialize)]
+pub enum ReconcileIcpRefundError {
+    RefundNotToReconcile,
+}
+
+#[derive(Clone, Eq, PartialEq, Debug, CandidType, Deserialize)]
+pub struct LoadCanisterSnapshotArg {
+    pub erc20_contract: Erc20Contract,
+    /// Only snapshots of ledgers and indexes are taken.
+    pub canister_type: WasmType,
+}
+
+#[derive(Clone, Eq, PartialEq, Debug, CandidType, Deserialize)]
+pub enum LoadCanisterSnapshotError {
+    InvalidErc20Contract(String),
+    UpgradeInProgress,
+    TokenNotFound,
+    SnapshotNotFound,
+    StopCanisterError(String),
+    LoadCanisterSnapshotError(String),
+    StartCanisterError(String),
+}
+
+impl From<StateLoadCanisterSnapshotError> for LoadCanisterSnapshotError {
+    fn from(value: StateLoadCanisterSnapshotError) -> Self {
+        match value {
+            StateLoadCanisterSnapshotError::TokenNotFound(_) => Self::TokenNotFound,
+            StateLoadCanisterSnapshotError::SnapshotNotFound { .. } => Self::SnapshotNotFound,
+            StateLoadCanisterSnapshotError::StopCanisterError(e) => {
+                Self::StopCanisterError(e.to_string())
+            }
+            StateLoadCanisterSnapshotError::LoadCanisterSnapshotError(e) => {
+                Self::LoadCanisterSnapshotError(e.to_string())
+            }
+            StateLoadCanisterSnapshotError::StartCanisterError(e) => {
+                Self::StartCanisterError(e.to_string())
+            }
+        }
+    }
+}
+
+#[derive(Clone, Eq, PartialEq, Debug, CandidType, Deserialize)]
+pub struct TokenDenylists {
+    pub denied_token_symbols: Vec<String>,
+    pub denied_erc20_contracts: Vec<Erc20Contract>,
+    pub reserved_token_names: Vec<String>,
+}
+
+impl From<&StateTokenDenylists> for TokenDenylists {
+    fn from(denylists: &StateTokenDenylists) -> Self {
+        Self {
+            denied_token_symbols: denylists.denied_token_symbols().iter().cloned().collect(),
+            denied_erc20_contracts: denylists
+                .denied_erc20_contracts()
+                .iter()
+                .cloned()
+                .map(Erc20Contract::from)
+                .collect(),
+            reserved_token_names: denylists.reserved_token_names().iter().cloned().collect(),
+        }
+    }
+}
+
+/// Entries to add to or remove from the token denylists.
+/// Removals are applied after additions.
+#[derive(Clone, Eq, PartialEq, Debug, Default, CandidType, Deserialize)]
+pub struct UpdateTokenDenylistsArg {
+    pub add_denied_token_symbols: Vec<String>,
+    pub remove_denied_token_symbols: Vec<String>,
+    pub add_denied_erc20_contracts: Vec<Erc20Contract>,
+    pub remove_denied_erc20_contracts: Vec<Erc20Contract>,
+    pub add_reserved_token_names: Vec<String>,
+    pub remove_reserved_token_names: Vec<String>,
+}
+
+#[derive(Clone, Eq, PartialEq, Debug, CandidType, Deserialize)]
+pub enum UpdateTokenDenylistsError {
+    InvalidErc20Contract(String),
+}
+
+#[derive(Clone, Eq, PartialEq, Debug, CandidType, Deserialize)]
+pub struct ArchiveProfile {
+    pub trigger_threshold: u64,
+    pub num_blocks_to_archive: u64,
+    pub node_max_memory_size_bytes: u64,
+    pub max_transactions_per_response: Option<u64>,
+}
+
+impl From<StateArchiveProfile> for ArchiveProfile {
+    fn from(profile: StateArchiveProfile) -> Self {
+        Self {
+            trigger_threshold: profile.trigger_threshold,
+            num_blocks_to_archive: profile.num_blocks_to_archive,
+            node_max_memory_size_bytes: profile.node_max_memory_size_bytes,
+            max_transactions_per_response: profile.max_transactions_per_response,
+        }
+    }
+}
+
+impl From<ArchiveProfile> for StateArchiveProfile {
+    fn from(profile: ArchiveProfile) -> Self {
+        Self {
+            trigger_threshold: profile.trigger_threshold,
+            num_blocks_to_archive: profile.num_blocks_to_archive,
+            node_max_memory_size_bytes: profile.node_max_memory_size_bytes,
+            max_transactions_per_response: profile.max_transactions_per_response,
+        }
+    }
+}
+
+#[derive(Clone, Eq, PartialEq, Debug, CandidType, Deserialize)]
+pub struct NamedArchiveProfile {
+    pub name: String,
+    pub profile: ArchiveProfile,
+}
+
+#[derive(Clone, Eq, PartialEq, Debug, CandidType, Deserialize)]
+pub enum ArchiveProfileError {
+    InvalidName,
+    InvalidArchiveProfile(String),
+    ArchiveProfileNotFound,
+    InvalidErc20Contract(String),
+    TokenNotFound,
+}
@@ -0,0 +1,107 @@
+use candid::Principal;
+use ic_canister_log::log;
+use num_traits::ToPrimitive;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    endpoints::{CanisterPlacement, CanisterSettings},
+    logs::INFO,
+    management::CanisterRuntime,
+    state::{mutate_state, read_state},
+};
+
+use super::TaskError;
+
+/// An empty canister controlled by the lsm, waiting to become the ledger or index of a ledger suite.
+#[derive(Clone, Eq, PartialEq, Debug, Deserialize, Serialize)]
+pub struct PooledCanister {
+    pub canister_id: Principal,
+    /// The cycles the canister was created with.
+    pub cycles: u128,
+    pub placement: CanisterPlacement,
+    /// The canister time at which the canister was created
+    /// in nanoseconds since the epoch (1970-01-01).
+    pub created_at: u64,
+    /// The settings the canister was created with.
+    #[serde(default)]
+    pub settings: CanisterSettings,
+    /// The controllers the canister was created with.
+    /// Empty for canisters pooled before the controllers were recorded,
+    /// so that their controllers are reconciled once they are taken from the pool.
+    #[serde(default)]
+    pub controllers: Vec<Principal>,
+}
+
+/// Creates empty canisters until the pool holds the configured number of canisters
+/// on the subnet of new ledger suites.
+///
+/// A canister is only created if the lsm keeps enough cycles afterwards
+/// to create a ledger suite and top up the managed canisters.
+pub async fn refill_canister_pool<R: CanisterRuntime>(runtime: &R) -> Result<(), TaskError> {
+    let (config, placement, pool_size, minimum_manager_cycles) = read_state(|s| {
+        let placement = s.canister_placement().clone();
+        let pool_size = s.pooled_canisters_on(&placement).count();
+        (
+            s.canister_pool_config().clone(),
+            placement,
+            pool_size,
+            s.cycles_management().minimum_manager_cycles(),
+        )
+    });
+    let missing = (config.target_size as usize).saturating_sub(pool_size);
+    if missing == 0 {
+        return Ok(());
+    }
+    let cycles_per_canister = config
+        .cycles_per_canister
+        .0
+        .to_u64()
+        .expect("BUG: cycles per pooled canister does not fit in a u64");
+    let required = minimum_manager_cycles
+        .0
+        .to_u128()
+        .unwrap_or(u128::MAX)
+        .saturating_add(cycles_per_canister as u128);
+
+    let mut lsm_cycle_balance = runtime
+        .canister_cycles(runtime.id())
+        .await
+        .map_err(TaskError::CanisterStatusError)?;
+    for _ in 0..missing {
+        if lsm_cycle_balance < required {
+            return Err(TaskError::InsufficientCyclesToRefillPool {
+                required,
+                available: lsm_cycle_balance,
+            });
+        }
+        let controllers = vec![runtime.id()];
+        let settings = CanisterSettings::default();
+        let canister_id = runtime
+            .create_canister(
+                controllers.clone(),
+                cycles_per_canister,
+                settings.clone(),
+                placement.clone(),
+            )
+            .await
+            .map_err(TaskError::CanisterCreationError)?;
+        lsm_cycle_balance = lsm_cycle_balance.saturating_sub(cycles_per_canister as u128);
+        log!(
+            INFO,
+            "[refill_canister_pool]: created canister {} on {:?}",
+            canister_id,
+            placement
+        );
+        mutate_state(|s| {
+            s.record_pooled_canister(PooledCanister {
+                canister_id,
+                cycles: cycles_per_canister as u128,
+                placement: placement.clone(),
+                created_at: runtime.time(),
+                settings,
+                controllers,
+            })
+        });
+    }
+    Ok(())
+}
@@ -0,0 +1,55 @@
+use candid::Principal;
+use futures::future;
+use ic_canister_log::log;
+
+use crate::{
+    logs::INFO,
+    management::{CallError, CanisterRuntime},
+    state::{mutate_state, read_state},
+};
+
+use super::TaskError;
+
+/// Pushes the configured settings to every managed ledger, index and archive canister
+/// whose settings differ from the ones last applied to it.
+///
+/// Canisters whose update failed are retried on the next run.
+pub async fn reconcile_canister_settings<R: CanisterRuntime>(runtime: &R) -> Result<(), TaskError> {
+    let canisters = read_state(|s| s.canister_settings_to_reconcile());
+    if canisters.is_empty() {
+        return Ok(());
+    }
+
+    let results = future::join_all(canisters.iter().map(|(canister_id, _kind, settings)| {
+        runtime.update_settings(*canister_id, settings.clone())
+    }))
+    .await;
+    let mut errors: Vec<(Principal, CallError)> = Vec::new();
+    for ((canister_id, kind, settings), result) in canisters.into_iter().zip(results) {
+        match result {
+            Ok(()) => {
+                log!(
+                    INFO,
+                    "[reconcile_canister_settings]: updated settings of {:?} canister {} to {:?}",
+                    kind,
+                    canister_id,
+                    settings
+                );
+                mutate_state(|s| s.record_applied_canister_settings(canister_id, settings));
+            }
+            Err(e) => errors.push((canister_id, e)),
+        }
+    }
+
+    if !errors.is_empty() {
+        log!(
+            INFO,
+            "[reconcile_canister_settings]: {} errors. Failed to update the settings of {:?}",
+            errors.len(),
+            errors
+        );
+        let first_error = errors.swap_remove(0);
+        return Err(TaskError::UpdateSettingsError(first_error.1));
+    }
+    Ok(())
+}
@@ -0,0 +1,102 @@
+use std::collections::BTreeMap;
+
+use candid::Principal;
+use futures::future;
+use ic_canister_log::log;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    logs::INFO,
+    management::CanisterRuntime,
+    state::{mutate_state, read_state, CanisterKind, Erc20Token},
+};
+
+/// Result of setting the controllers of the canisters of a ledger suite.
+#[derive(Clone, PartialEq, Debug, Deserialize, Serialize)]
+pub struct ControllersReport {
+    /// The canister time at which the controllers were set
+    /// in nanoseconds since the epoch (1970-01-01).
+    pub updated_at: u64,
+    pub controllers: Vec<Principal>,
+    pub canisters: Vec<CanisterControllersUpdate>,
+}
+
+#[derive(Clone, PartialEq, Debug, Deserialize, Serialize)]
+pub struct CanisterControllersUpdate {
+    pub canister_id: Principal,
+    pub kind: CanisterKind,
+    /// The reason the controllers could not be set, if any.
+    pub error: Option<String>,
+}
+
+/// Sets the controllers of every managed ledger, index and archive canister
+/// to the lsm and the additional controllers, unless they were already set to these controllers.
+///
+/// Records and returns a report for every ledger suite with canisters whose controllers were set.
+/// Canisters whose update failed are retried on the next run.
+pub async fn reconcile_controllers<R: CanisterRuntime>(
+    runtime: &R,
+) -> BTreeMap<Erc20Token, ControllersReport> {
+    let (controllers, canisters) = read_state(|s| {
+        let controllers = s.canister_controllers(runtime.id());
+        let canisters = s.canisters_with_outdated_controllers(&controllers);
+        (controllers, canisters)
+    });
+    if canisters.is_empty() {
+        return BTreeMap::new();
+    }
+
+    let controllers: Vec<Principal> = controllers.into_iter().collect();
+    let results = future::join_all(canisters.iter().map(|(_token, canister_id, _kind)| {
+        runtime.set_controllers(*canister_id, controllers.clone())
+    }))
+    .await;
+    let updated_at = runtime.time();
+    let mut reports: BTreeMap<Erc20Token, ControllersReport> = BTreeMap::new();
+    for ((token, canister_id, kind), result) in canisters.into_iter().zip(results) {
+        let error = match result {
+            Ok(()) => {
+                mutate_state(|s| {
+                    s.record_applied_controllers(canister_id, controllers.iter().copied().collect())
+                });
+                None
+            }
+            Err(e) => {
+                log!(
+                    INFO,
+                    "[reconcile_controllers]: failed to set the controllers of {:?} canister {} of {:?}: {}",
+                    kind,
+                    canister_id,
+                    token,
+                    e
+                );
+                Some(format!("{:?}", e))
+            }
+        };
+        reports
+            .entry(token)
+            .or_insert_with(|| ControllersReport {
+                updated_at,
+                controllers: controllers.clone(),
+                canisters: vec![],
+            })
+            .canisters
+            .push(CanisterControllersUpdate {
+                canister_id,
+                kind,
+                error,
+            });
+    }
+    log!(
+        INFO,
+        "[reconcile_controllers]: set the controllers of the canisters of {} ledger suites to {:?}",
+        reports.len(),
+        controllers
+    );
+    mutate_state(|s| {
+        for (token, report) in reports.iter() {
+            s.record_controllers_report(token.clone(), report.clone());
+        }
+    });
+    reports
+}
@@ -0,0 +1,149 @@
+use std::collections::{BTreeMap, BTreeSet};
+
+use candid::{Nat, Principal};
+use ic_icrc1_index_ng::Status as IndexStatus;
+use icrc_ledger_types::icrc::generic_metadata_value::MetadataValue;
+use icrc_ledger_types::icrc3::archive::{GetArchivesArgs, GetArchivesResult};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    management::{CallError, CanisterRuntime},
+    state::{CanisterKind, WasmHash},
+};
+
+/// Ledger metadata entries that an upgrade must not change.
+const PRESERVED_LEDGER_METADATA_KEYS: [&str; 4] =
+    ["icrc1:name", "icrc1:symbol", "icrc1:decimals", "icrc1:fee"];
+
+/// Reinstallation of the previous wasm on a canister
+/// whose health checks failed after an upgrade.
+#[derive(Clone, PartialEq, Debug, Deserialize, Serialize)]
+pub struct UpgradeRollback {
+    pub canister_id: Principal,
+    pub kind: CanisterKind,
+    /// The hash of the wasm whose health checks failed.
+    pub failed_wasm_hash: WasmHash,
+    /// The hash of the wasm the canister was rolled back to.
+    pub restored_wasm_hash: WasmHash,
+    pub reason: String,
+    /// The canister time at which the canister was rolled back
+    /// in nanoseconds since the epoch (1970-01-01).
+    pub rolled_back_at: u64,
+}
+
+/// Health of the ledger or index probed before its upgrade,
+/// against which the upgraded canister is checked.
+#[derive(Clone, PartialEq, Debug, Deserialize, Serialize)]
+pub enum HealthBaseline {
+    Ledger(LedgerHealth),
+    Index(IndexHealth),
+}
+
+/// Values reported by a ledger that must survive an upgrade.
+#[derive(Clone, PartialEq, Debug, Deserialize, Serialize)]
+pub struct LedgerHealth {
+    pub total_supply: Nat,
+    pub metadata: BTreeMap<String, MetadataValue>,
+    pub archives: BTreeSet<Principal>,
+}
+
+impl LedgerHealth {
+    /// Probes the ledger with `icrc1_total_supply`, `icrc1_metadata` and `icrc3_get_archives`.
+    pub async fn probe<R: CanisterRuntime>(
+        ledger_id: Principal,
+        runtime: &R,
+    ) -> Result<Self, CallError> {
+        let total_supply: Nat = runtime
+            .call_canister(ledger_id, "icrc1_total_supply", ())
+            .await?;
+        let metadata: Vec<(String, MetadataValue)> = runtime
+            .call_canister(ledger_id, "icrc1_metadata", ())
+            .await?;
+        let archives: GetArchivesResult = runtime
+            .call_canister(
+                ledger_id,
+                "icrc3_get_archives",
+                GetArchivesArgs { from: None },
+            )
+            .await?;
+        Ok(Self {
+            total_supply,
+            metadata: metadata.into_iter().collect(),
+            archives: archives.into_iter().map(|a| a.canister_id).collect(),
+        })
+    }
+
+    /// Checks that the ledger kept its state across the upgrade.
+    ///
+    /// The total supply may legitimately change between probing the ledger and stopping it,
+    /// so only a total supply dropping to zero is considered inconsistent.
+    pub fn check_consistent_with(&self, before: &LedgerHealth) -> Result<(), String> {
+        let zero = Nat::from(0_u8);
+        if self.total_supply == zero && before.total_supply != zero {
+            return Err(format!(
+                "total supply dropped from {} to 0",
+                before.total_supply
+            ));
+        }
+        for key in PRESERVED_LEDGER_METADATA_KEYS {
+            if self.metadata.get(key) != before.metadata.get(key) {
+                return Err(format!(
+                    "metadata {} changed from {:?} to {:?}",
+                    key,
+                    before.metadata.get(key),
+                    self.metadata.get(key)
+                ));
+            }
+        }
+        if let Some(archive) = before.archives.difference(&self.archives).next() {
+            return Err(format!("archive {} is no longer reported", archive));
+        }
+        Ok(())
+    }
+}
+
+/// Values reported by an index that must survive an upgrade.
+#[derive(Clone, PartialEq, Debug, Deserialize, Serialize)]
+pub struct IndexHealth {
+    pub ledger_id: Principal,
+    pub num_blocks_synced: Nat,
+}
+
+impl IndexHealth {
+    /// Probes the index with `ledger_id` and `status`.
+    pub async fn probe<R: CanisterRuntime>(
+        index_id: Principal,
+        runtime: &R,
+    ) -> Result<Self, CallError> {
+        let ledger_id: Principal = runtime.call_canister(index_id, "ledger_id", ()).await?;
+        let status: IndexStatus = runtime.call_canister(index_id, "status", ()).await?;
+        Ok(Self {
+            ledger_id,
+            num_blocks_synced: status.num_blocks_synced,
+        })
+    }
+
+    /// Checks that the index still follows the expected ledger
+    /// and did not lose any of the blocks it had synced before the upgrade.
+    pub fn check_consistent_with(
+        &self,
+        expected_ledger_id: Option<&Principal>,
+        before: &IndexHealth,
+    ) -> Result<(), String> {
+        if let Some(expected_ledger_id) = expected_ledger_id {
+            if &self.ledger_id != expected_ledger_id {
+                return Err(format!(
+                    "index follows ledger {} instead of {}",
+                    self.ledger_id, expected_ledger_id
+                ));
+            }
+        }
+        if self.num_blocks_synced < before.num_blocks_synced {
+            return Err(format!(
+                "number of synced blocks dropped from {} to {}",
+                before.num_blocks_synced, self.num_blocks_synced
+            ));
+        }
+        Ok(())
+    }
+}
@@ -2,19 +2,23 @@ use crate::cmc_client::{
     CmcRunTime, CyclesConvertor, IcpToCyclesConversionError, DEFAULT_TRANSFER_FEE,
 };
 
+/// Converts the icp balance of the canister to cycles,
+/// except for the `reserved_icp` that may still have to be refunded.
 pub async fn convert_icp_balance_to_cycles(
     cycles_convertor: CyclesConvertor,
+    reserved_icp: u64,
 ) -> Result<u128, IcpToCyclesConversionError> {
     let icp_balance = cycles_convertor.icp_balance().await?;
 
     // Fetch icp balance
-    if icp_balance == 0 {
+    let convertible_icp = icp_balance.saturating_sub(reserved_icp);
+    if convertible_icp <= DEFAULT_TRANSFER_FEE.e8s() {
         return Err(IcpToCyclesConversionError::ZeroIcpBalance);
     }
 
     // Transfer available icp to Cycles minter canister
     let transfer_block_index = cycles_convertor
-        .transfer_cmc(icp_balance - DEFAULT_TRANSFER_FEE.e8s())
+        .transfer_cmc(convertible_icp - DEFAULT_TRANSFER_FEE.e8s())
         .await?;
 
     // Notify cycles minter canister to top up the canister with cycles
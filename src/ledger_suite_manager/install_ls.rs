@@ -1,18 +1,13 @@
-use crate::appic_helper_client::appic_helper_types::{
-    CandidAddErc20TwinLedgerSuiteRequest, CandidErc20TwinLedgerSuiteFee,
-    CandidErc20TwinLedgerSuiteStatus, CandidIcpToken, IcpTokenType,
-};
-use crate::appic_helper_client::AppicHelperClient;
-use crate::endpoints::CyclesManagement;
+use crate::endpoints::{CanisterPlacement, CanisterSettings, CyclesManagement, InstallRetryPolicy};
 use crate::logs::INFO;
-use crate::management::CanisterRuntime;
+use crate::management::{CallError, CanisterRuntime, InstallMode};
 use crate::state::{read_state, ManageSingleCanister, ManagedCanisterStatus};
 use crate::storage::{read_wasm_store, wasm_store_try_get, StorableWasm};
 use crate::{
     endpoints::{AddErc20Arg, LedgerInitArg},
     state::{
         mutate_state, Canisters, CanistersMetadata, Erc20Token, Index, Ledger, LedgerSuiteVersion,
-        State, WasmHash,
+        State, Wasm, WasmHash,
     },
     storage::{wasm_store_contain, WasmHashError, WasmStore},
 };
@@ -20,16 +15,46 @@ use candid::{CandidType, Encode, Nat, Principal};
 use ic_base_types::PrincipalId;
 use ic_canister_log::log;
 use ic_icrc1_index_ng::{IndexArg, InitArg as IndexInitArg};
-use ic_icrc1_ledger::{ArchiveOptions, InitArgs as LedgerInitArgs, LedgerArgument};
+use ic_icrc1_ledger::{
+    ArchiveOptions, ChangeArchiveOptions, InitArgs as LedgerInitArgs, LedgerArgument,
+};
 use num_traits::ToPrimitive;
 use serde::{Deserialize, Serialize};
 use std::cmp::Ordering;
+use std::collections::BTreeMap;
 use std::fmt::Debug;
+use std::future::Future;
 
+use super::notifications::{LedgerSuiteRequestStatus, Notification};
 use super::TaskError;
 
 const THREE_GIGA_BYTES: u64 = 3_221_225_472;
 
+/// Wasms larger than this are installed in chunks,
+/// leaving room for the init args in the 2 MiB limit of an `install_code` call.
+pub const CHUNKED_INSTALL_THRESHOLD: usize = 1_800_000;
+
+/// Maximum size of a chunk accepted by the management canister's `upload_chunk`.
+pub const WASM_CHUNK_SIZE: usize = 1024 * 1024;
+
+/// Number of ledger suites installed concurrently, unless configured otherwise by an upgrade.
+pub const DEFAULT_MAX_CONCURRENT_LS_INSTALLS: u32 = 5;
+
+pub const MIN_TOKEN_NAME_LENGTH: usize = 1;
+pub const MAX_TOKEN_NAME_LENGTH: usize = 64;
+pub const MIN_TOKEN_SYMBOL_LENGTH: usize = 1;
+pub const MAX_TOKEN_SYMBOL_LENGTH: usize = 16;
+
+/// ERC-20 tokens use at most 18 decimals.
+pub const MAX_DECIMALS: u8 = 18;
+
+/// Maximum length of the token logo data URI in bytes.
+pub const MAX_TOKEN_LOGO_LENGTH: usize = 128 * 1024;
+
+/// Image types accepted in the token logo data URI.
+pub const ALLOWED_TOKEN_LOGO_MEDIA_TYPES: [&str; 4] =
+    ["image/png", "image/jpeg", "image/svg+xml", "image/webp"];
+
 #[derive(Clone, Eq, PartialEq, Debug, Deserialize, Serialize)]
 pub struct InstallLedgerSuiteArgs {
     pub contract: Erc20Token,
@@ -39,6 +64,18 @@ pub struct InstallLedgerSuiteArgs {
     pub ledger_init_arg: LedgerInitArg,
     pub ledger_compressed_wasm_hash: WasmHash,
     pub index_compressed_wasm_hash: WasmHash,
+    /// Number of failed installation attempts.
+    #[serde(default)]
+    pub attempts: u32,
+    #[serde(default)]
+    pub last_error: Option<String>,
+    /// The canister time from which the installation may be attempted again
+    /// in nanoseconds since the epoch (1970-01-01).
+    #[serde(default)]
+    pub next_attempt_at: u64,
+    /// Archiving settings of the ledger, re-applied when the ledger is upgraded.
+    #[serde(default)]
+    pub archive_profile: ArchiveProfile,
 }
 impl PartialOrd for InstallLedgerSuiteArgs {
     fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
@@ -52,6 +89,137 @@ impl Ord for InstallLedgerSuiteArgs {
     }
 }
 
+/// The steps of a ledger suite installation, in execution order.
+#[derive(Clone, Copy, Eq, PartialEq, Ord, PartialOrd, Debug, Deserialize, Serialize)]
+pub enum InstallStep {
+    CreateLedger,
+    InstallLedger,
+    CreateIndex,
+    InstallIndex,
+    /// Enqueues the notifications of the appic helper and the minter in the outbox,
+    /// where their delivery can be followed with `get_notification_outbox`.
+    EnqueueNotifications,
+}
+
+impl InstallStep {
+    pub const ALL: [InstallStep; 5] = [
+        InstallStep::CreateLedger,
+        InstallStep::InstallLedger,
+        InstallStep::CreateIndex,
+        InstallStep::InstallIndex,
+        InstallStep::EnqueueNotifications,
+    ];
+}
+
+#[derive(Clone, Eq, PartialEq, Debug, Deserialize, Serialize)]
+pub struct InstallStepProgress {
+    /// The canister time of the last execution of the step
+    /// in nanoseconds since the epoch (1970-01-01).
+    pub attempted_at: u64,
+    /// The canister time at which the step completed, or `None` if its last execution failed.
+    pub completed_at: Option<u64>,
+    /// The last error the step failed with, kept after the step eventually completed.
+    pub last_error: Option<String>,
+}
+
+/// Progress of the installation of a ledger suite, recorded after each executed step.
+#[derive(Clone, Eq, PartialEq, Debug, Default, Deserialize, Serialize)]
+pub struct LedgerSuiteInstallProgress {
+    steps: BTreeMap<InstallStep, InstallStepProgress>,
+}
+
+impl LedgerSuiteInstallProgress {
+    pub fn steps(&self) -> &BTreeMap<InstallStep, InstallStepProgress> {
+        &self.steps
+    }
+
+    /// Returns the first step that did not complete, or `None` if all steps completed.
+    pub fn current_step(&self) -> Option<InstallStep> {
+        InstallStep::ALL.into_iter().find(|step| {
+            self.steps
+                .get(step)
+                .map_or(true, |progress| progress.completed_at.is_none())
+        })
+    }
+
+    pub fn record_step(&mut self, step: InstallStep, timestamp: u64, error: Option<String>) {
+        let progress = self.steps.entry(step).or_insert(InstallStepProgress {
+            attempted_at: timestamp,
+            completed_at: None,
+            last_error: None,
+        });
+        progress.attempted_at = timestamp;
+        match error {
+            None => {
+                progress.completed_at.get_or_insert(timestamp);
+            }
+            Some(error) => {
+                progress.completed_at = None;
+                progress.last_error = Some(error);
+            }
+        }
+    }
+}
+
+/// Settings of a ledger controlling when and how blocks are moved to archive canisters.
+#[derive(Clone, Eq, PartialEq, Ord, PartialOrd, Debug, Deserialize, Serialize)]
+pub struct ArchiveProfile {
+    /// Number of blocks in the ledger that triggers archiving.
+    pub trigger_threshold: u64,
+    /// Number of blocks moved to an archive when archiving is triggered.
+    pub num_blocks_to_archive: u64,
+    pub node_max_memory_size_bytes: u64,
+    pub max_transactions_per_response: Option<u64>,
+}
+
+impl Default for ArchiveProfile {
+    fn default() -> Self {
+        Self {
+            trigger_threshold: 2_000,
+            num_blocks_to_archive: 1_000,
+            node_max_memory_size_bytes: THREE_GIGA_BYTES,
+            max_transactions_per_response: None,
+        }
+    }
+}
+
+impl ArchiveProfile {
+    pub fn validate(&self) -> Result<(), String> {
+        if self.num_blocks_to_archive == 0 {
+            return Err("num_blocks_to_archive must be positive".to_string());
+        }
+        if self.num_blocks_to_archive > self.trigger_threshold {
+            return Err(format!(
+                "num_blocks_to_archive {} exceeds trigger_threshold {}",
+                self.num_blocks_to_archive, self.trigger_threshold
+            ));
+        }
+        if self.node_max_memory_size_bytes == 0 {
+            return Err("node_max_memory_size_bytes must be positive".to_string());
+        }
+        if self.max_transactions_per_response == Some(0) {
+            return Err("max_transactions_per_response must be positive".to_string());
+        }
+        Ok(())
+    }
+
+    /// Changes the archive options of an existing ledger to the ones of the profile,
+    /// leaving the archive controllers and cycles untouched.
+    pub fn change_archive_options(&self) -> ChangeArchiveOptions {
+        ChangeArchiveOptions {
+            trigger_threshold: Some(to_usize(self.trigger_threshold)),
+            num_blocks_to_archive: Some(to_usize(self.num_blocks_to_archive)),
+            node_max_memory_size_bytes: Some(self.node_max_memory_size_bytes),
+            max_transactions_per_response: self.max_transactions_per_response,
+            ..Default::default()
+        }
+    }
+}
+
+fn to_usize(value: u64) -> usize {
+    usize::try_from(value).unwrap_or(usize::MAX)
+}
+
 #[derive(Clone, PartialEq, Debug)]
 pub enum InvalidAddErc20ArgError {
     InvalidErc20Contract(String),
@@ -59,6 +227,41 @@ pub enum InvalidAddErc20ArgError {
     Erc20ContractAlreadyManaged(Erc20Token),
     WasmHashError(WasmHashError),
     InternalError(String),
+    TokenNameLengthOutOfBounds {
+        length: usize,
+        min: usize,
+        max: usize,
+    },
+    InvalidTokenNameCharacter(char),
+    TokenSymbolLengthOutOfBounds {
+        length: usize,
+        min: usize,
+        max: usize,
+    },
+    InvalidTokenSymbolCharacter(char),
+    DecimalsOutOfBounds {
+        decimals: u8,
+        max: u8,
+    },
+    TransferFeeOutOfBounds {
+        transfer_fee: Nat,
+        min: Nat,
+        max: Nat,
+    },
+    TokenLogoTooLarge {
+        length: usize,
+        max: usize,
+    },
+    TokenLogoNotADataUri,
+    UnsupportedTokenLogoMediaType(String),
+    Erc20ContractDenied(Erc20Token),
+    TokenSymbolDenied(String),
+    TokenSymbolAlreadyUsed {
+        symbol: String,
+        token: Erc20Token,
+    },
+    TokenNameReserved(String),
+    UnknownArchiveProfile(String),
 }
 
 impl InstallLedgerSuiteArgs {
@@ -71,6 +274,7 @@ impl InstallLedgerSuiteArgs {
     ) -> Result<InstallLedgerSuiteArgs, InvalidAddErc20ArgError> {
         let token = Erc20Token::try_from(args.contract.clone())
             .map_err(|e| InvalidAddErc20ArgError::InvalidErc20Contract(e.to_string()))?;
+        validate_ledger_init_arg(&args.ledger_init_arg)?;
 
         // Check if the chain is supported by checking the minter id
         let minter_id = state.minter_id(token.chain_id()).cloned().ok_or(
@@ -81,6 +285,32 @@ impl InstallLedgerSuiteArgs {
         if let Some(_canisters) = state.managed_canisters(&token) {
             return Err(InvalidAddErc20ArgError::Erc20ContractAlreadyManaged(token));
         }
+        let denylists = state.token_denylists();
+        if denylists.is_erc20_contract_denied(&token) {
+            return Err(InvalidAddErc20ArgError::Erc20ContractDenied(token));
+        }
+        let symbol = &args.ledger_init_arg.token_symbol;
+        if denylists.is_token_symbol_denied(symbol) {
+            return Err(InvalidAddErc20ArgError::TokenSymbolDenied(symbol.clone()));
+        }
+        if let Some(other_token) = state.token_with_symbol(symbol, &token) {
+            return Err(InvalidAddErc20ArgError::TokenSymbolAlreadyUsed {
+                symbol: symbol.clone(),
+                token: other_token,
+            });
+        }
+        if denylists.is_token_name_reserved(&args.ledger_init_arg.token_name) {
+            return Err(InvalidAddErc20ArgError::TokenNameReserved(
+                args.ledger_init_arg.token_name.clone(),
+            ));
+        }
+        let archive_profile = match &args.archive_profile {
+            Some(name) => state
+                .archive_profile(name)
+                .cloned()
+                .ok_or_else(|| InvalidAddErc20ArgError::UnknownArchiveProfile(name.clone()))?,
+            None => ArchiveProfile::default(),
+        };
         let (ledger_compressed_wasm_hash, index_compressed_wasm_hash) = {
             let LedgerSuiteVersion {
                 ledger_compressed_wasm_hash,
@@ -113,8 +343,117 @@ impl InstallLedgerSuiteArgs {
             index_compressed_wasm_hash,
             creator,
             created_at,
+            attempts: 0,
+            last_error: None,
+            next_attempt_at: created_at,
+            archive_profile,
         })
     }
+
+    pub fn is_due(&self, now: u64) -> bool {
+        self.next_attempt_at <= now
+    }
+
+    /// Records a failed installation attempt and schedules the next one according to the retry policy.
+    pub fn record_failed_attempt(
+        &mut self,
+        error: String,
+        now: u64,
+        retry_policy: &InstallRetryPolicy,
+    ) {
+        self.attempts = self.attempts.saturating_add(1);
+        self.last_error = Some(error);
+        self.next_attempt_at = now.saturating_add(retry_policy.backoff_nanos(self.attempts));
+    }
+
+    pub fn retries_exhausted(&self, retry_policy: &InstallRetryPolicy) -> bool {
+        self.attempts >= retry_policy.max_attempts
+    }
+}
+
+/// Checks the token metadata of a new ledger:
+/// * the name is 1 to 64 ASCII alphanumeric characters, spaces, `-`, `.` or `_`
+/// * the symbol is 1 to 16 ASCII alphanumeric characters, `-`, `.` or `_`
+/// * the decimals are at most 18
+/// * the transfer fee is non-zero and at most one whole token
+/// * the logo is a base64 encoded data URI of a PNG, JPEG, SVG or WebP image of at most 128 KiB
+pub fn validate_ledger_init_arg(arg: &LedgerInitArg) -> Result<(), InvalidAddErc20ArgError> {
+    let name_length = arg.token_name.chars().count();
+    if !(MIN_TOKEN_NAME_LENGTH..=MAX_TOKEN_NAME_LENGTH).contains(&name_length) {
+        return Err(InvalidAddErc20ArgError::TokenNameLengthOutOfBounds {
+            length: name_length,
+            min: MIN_TOKEN_NAME_LENGTH,
+            max: MAX_TOKEN_NAME_LENGTH,
+        });
+    }
+    if let Some(c) = arg
+        .token_name
+        .chars()
+        .find(|c| !(c.is_ascii_alphanumeric() || matches!(c, ' ' | '-' | '.' | '_')))
+    {
+        return Err(InvalidAddErc20ArgError::InvalidTokenNameCharacter(c));
+    }
+
+    let symbol_length = arg.token_symbol.chars().count();
+    if !(MIN_TOKEN_SYMBOL_LENGTH..=MAX_TOKEN_SYMBOL_LENGTH).contains(&symbol_length) {
+        return Err(InvalidAddErc20ArgError::TokenSymbolLengthOutOfBounds {
+            length: symbol_length,
+            min: MIN_TOKEN_SYMBOL_LENGTH,
+            max: MAX_TOKEN_SYMBOL_LENGTH,
+        });
+    }
+    if let Some(c) = arg
+        .token_symbol
+        .chars()
+        .find(|c| !(c.is_ascii_alphanumeric() || matches!(c, '-' | '.' | '_')))
+    {
+        return Err(InvalidAddErc20ArgError::InvalidTokenSymbolCharacter(c));
+    }
+
+    if arg.decimals > MAX_DECIMALS {
+        return Err(InvalidAddErc20ArgError::DecimalsOutOfBounds {
+            decimals: arg.decimals,
+            max: MAX_DECIMALS,
+        });
+    }
+
+    let min_transfer_fee = Nat::from(1_u8);
+    let max_transfer_fee = Nat::from(10_u64.pow(u32::from(arg.decimals)));
+    if arg.transfer_fee < min_transfer_fee || arg.transfer_fee > max_transfer_fee {
+        return Err(InvalidAddErc20ArgError::TransferFeeOutOfBounds {
+            transfer_fee: arg.transfer_fee.clone(),
+            min: min_transfer_fee,
+            max: max_transfer_fee,
+        });
+    }
+
+    validate_token_logo(&arg.token_logo)
+}
+
+fn validate_token_logo(logo: &str) -> Result<(), InvalidAddErc20ArgError> {
+    if logo.len() > MAX_TOKEN_LOGO_LENGTH {
+        return Err(InvalidAddErc20ArgError::TokenLogoTooLarge {
+            length: logo.len(),
+            max: MAX_TOKEN_LOGO_LENGTH,
+        });
+    }
+    let (media_type, data) = logo
+        .strip_prefix("data:")
+        .and_then(|uri| uri.split_once(";base64,"))
+        .ok_or(InvalidAddErc20ArgError::TokenLogoNotADataUri)?;
+    if !ALLOWED_TOKEN_LOGO_MEDIA_TYPES.contains(&media_type) {
+        return Err(InvalidAddErc20ArgError::UnsupportedTokenLogoMediaType(
+            media_type.to_string(),
+        ));
+    }
+    if data.is_empty()
+        || !data
+            .bytes()
+            .all(|b| b.is_ascii_alphanumeric() || matches!(b, b'+' | b'/' | b'='))
+    {
+        return Err(InvalidAddErc20ArgError::TokenLogoNotADataUri);
+    }
+    Ok(())
 }
 
 pub async fn install_ledger_suite<R: CanisterRuntime>(
@@ -127,112 +466,143 @@ pub async fn install_ledger_suite<R: CanisterRuntime>(
             token_symbol: args.ledger_init_arg.token_symbol.clone(),
         },
     );
-    // Get amount of ICP token required for ledger suite creation
-    let twin_creation_fee_amount_in_icp =
-        read_state(|s| s.minimum_tokens_for_new_ledger_suite().icp);
-
     let CyclesManagement {
         cycles_for_ledger_creation,
         cycles_for_index_creation,
         cycles_for_archive_creation,
         ..
     } = read_state(|s| s.cycles_management().clone());
-    let ledger_canister_id =
-        create_canister_once::<Ledger, _>(&args.contract, runtime, cycles_for_ledger_creation)
-            .await?;
+    let canister_settings = read_state(|s| s.canister_settings().clone());
+    let placement = read_state(|s| s.canister_placement_of(&args.contract));
+    let ledger_canister_id = run_install_step(
+        &args.contract,
+        InstallStep::CreateLedger,
+        runtime,
+        create_canister_once::<Ledger, _>(
+            &args.contract,
+            runtime,
+            cycles_for_ledger_creation,
+            canister_settings.ledger,
+            placement.clone(),
+        ),
+    )
+    .await?;
 
     let more_controllers = read_state(|s| s.more_controller_ids().to_vec())
         .into_iter()
         .map(PrincipalId)
         .collect();
-    install_canister_once::<Ledger, _, _>(
+    run_install_step(
         &args.contract,
-        &args.ledger_compressed_wasm_hash,
-        &LedgerArgument::Init(icrc1_ledger_init_arg(
-            args.minter_id,
-            args.ledger_init_arg.clone(),
-            runtime.id().into(),
-            more_controllers,
-            cycles_for_archive_creation,
-        )),
+        InstallStep::InstallLedger,
         runtime,
+        install_canister_once::<Ledger, _, _>(
+            &args.contract,
+            &args.ledger_compressed_wasm_hash,
+            &LedgerArgument::Init(icrc1_ledger_init_arg(
+                args.minter_id,
+                args.ledger_init_arg.clone(),
+                runtime.id().into(),
+                more_controllers,
+                cycles_for_archive_creation,
+                &args.archive_profile,
+            )),
+            runtime,
+        ),
     )
     .await?;
+    mutate_state(|s| s.record_archive_profile(&args.contract, args.archive_profile.clone()));
 
-    // Notify appic helper of new erc20 twins
-    let helper_client = AppicHelperClient::new();
-
-    // Generate new icp token to be added to appic helper
-    let icp_token = CandidIcpToken {
-        fee: args.ledger_init_arg.transfer_fee.clone(),
-        decimals: args.ledger_init_arg.decimals,
-        usd_price: "0".to_string(),
-        logo: args.ledger_init_arg.token_logo.clone(),
-        name: args.ledger_init_arg.token_name.clone(),
-        rank: Some(1_u32),
-        ledger_id: ledger_canister_id,
-        token_type: IcpTokenType::Icrc2,
-        symbol: args.ledger_init_arg.token_symbol.clone(),
-    };
-
-    helper_client
-        .add_icp_token(icp_token)
-        .await
-        .map_err(|e| TaskError::InterCanisterCallError(e))?;
-
-    let _index_principal =
-        create_canister_once::<Index, _>(&args.contract, runtime, cycles_for_index_creation)
-            .await?;
+    let _index_principal = run_install_step(
+        &args.contract,
+        InstallStep::CreateIndex,
+        runtime,
+        create_canister_once::<Index, _>(
+            &args.contract,
+            runtime,
+            cycles_for_index_creation,
+            canister_settings.index,
+            placement,
+        ),
+    )
+    .await?;
     let index_arg = Some(IndexArg::Init(IndexInitArg {
         ledger_id: ledger_canister_id,
         retrieve_blocks_from_ledger_interval_seconds: Some(60_u64),
     }));
-    install_canister_once::<Index, _, _>(
+    run_install_step(
         &args.contract,
-        &args.index_compressed_wasm_hash,
-        &index_arg,
+        InstallStep::InstallIndex,
         runtime,
+        install_canister_once::<Index, _, _>(
+            &args.contract,
+            &args.index_compressed_wasm_hash,
+            &index_arg,
+            runtime,
+        ),
     )
     .await?;
 
-    // notifying minter for new erc token
-    let minter_id = read_state(|s| s.minter_id_owned(args.contract.chain_id()));
-
-    let notify_result = match minter_id {
-        Some(minter_id) => notify_erc20_added(&args.contract, &minter_id, runtime).await,
-        None => Err(TaskError::MinterNotFound(args.contract.chain_id().clone())),
-    }?;
-
-    let update_ls_args = CandidAddErc20TwinLedgerSuiteRequest {
-        status: CandidErc20TwinLedgerSuiteStatus::Installed,
-        creator: args.creator,
-        icp_ledger_id: Some(ledger_canister_id),
-        icp_token_name: args.ledger_init_arg.token_name.clone(),
-        created_at: args.created_at,
-        fee_charged: CandidErc20TwinLedgerSuiteFee::Icp(twin_creation_fee_amount_in_icp.into()),
-        icp_token_symbol: args.ledger_init_arg.token_symbol.clone(),
-        evm_token_contract: args.contract.address().to_string(),
-        evm_token_chain_id: Nat::from(args.contract.chain_id().as_ref().clone()),
-    };
-
-    helper_client
-        .update_ls_request(update_ls_args)
-        .await
-        .map_err(|e| TaskError::InterCanisterCallError(e))?;
-
-    helper_client
-        .request_update_bridge_pairs()
-        .await
-        .map_err(|e| TaskError::InterCanisterCallError(e))?;
-
-    log!(
-        INFO,
-        "Notified minter {}  for {:?}",
-        minter_id.unwrap().to_text(),
-        args.contract,
-    );
+    // The appic helper and the minter are notified of the new twin token by the outbox task.
+    run_install_step(
+        &args.contract,
+        InstallStep::EnqueueNotifications,
+        runtime,
+        async {
+            let minter_id = read_state(|s| s.minter_id_owned(args.contract.chain_id()))
+                .ok_or_else(|| TaskError::MinterNotFound(args.contract.chain_id().clone()))?;
+            let now = runtime.time();
+            mutate_state(|s| {
+                // The fee may have changed since the creator paid it.
+                let fee_charged_icp = s
+                    .received_deposit(&args.contract)
+                    .map(|deposit| deposit.amount)
+                    .unwrap_or_default();
+                for notification in [
+                    Notification::AddIcpToken {
+                        token: args.contract.clone(),
+                        ledger_id: ledger_canister_id,
+                        ledger_init_arg: args.ledger_init_arg.clone(),
+                    },
+                    Notification::AddErc20Token {
+                        token: args.contract.clone(),
+                        minter_id,
+                    },
+                    Notification::UpdateLedgerSuiteRequest {
+                        token: args.contract.clone(),
+                        creator: args.creator,
+                        ledger_id: Some(ledger_canister_id),
+                        token_name: args.ledger_init_arg.token_name.clone(),
+                        token_symbol: args.ledger_init_arg.token_symbol.clone(),
+                        created_at: args.created_at,
+                        fee_charged_icp,
+                        status: LedgerSuiteRequestStatus::Installed,
+                    },
+                    Notification::UpdateBridgePairs {
+                        token: args.contract.clone(),
+                    },
+                ] {
+                    s.enqueue_notification(notification, now);
+                }
+            });
+            Ok(())
+        },
+    )
+    .await
+}
 
-    Ok(notify_result)
+/// Executes a step of the installation and records its outcome in the install progress of the token.
+async fn run_install_step<T, R: CanisterRuntime>(
+    token: &Erc20Token,
+    step: InstallStep,
+    runtime: &R,
+    step_execution: impl Future<Output = Result<T, TaskError>>,
+) -> Result<T, TaskError> {
+    let result = step_execution.await;
+    let error = result.as_ref().err().map(|e| format!("{:?}", e));
+    let now = runtime.time();
+    mutate_state(|s| s.record_ledger_suite_install_step(token, step, now, error));
+    result
 }
 
 fn record_new_erc20_token_once(token: Erc20Token, metadata: CanistersMetadata) {
@@ -250,6 +620,7 @@ fn icrc1_ledger_init_arg(
     archive_controller_id: PrincipalId,
     archive_more_controller_ids: Vec<PrincipalId>,
     cycles_for_archive_creation: Nat,
+    archive_profile: &ArchiveProfile,
 ) -> LedgerInitArgs {
     use ic_icrc1_ledger::FeatureFlags as LedgerFeatureFlags;
     use icrc_ledger_types::icrc::generic_metadata_value::MetadataValue as LedgerMetadataValue;
@@ -281,6 +652,7 @@ fn icrc1_ledger_init_arg(
             archive_controller_id,
             archive_more_controller_ids,
             cycles_for_archive_creation,
+            archive_profile,
         ),
         max_memo_length: Some(MAX_MEMO_LENGTH),
         feature_flags: Some(ICRC2_FEATURE),
@@ -293,11 +665,12 @@ fn icrc1_archive_options(
     archive_controller_id: PrincipalId,
     archive_more_controller_ids: Vec<PrincipalId>,
     cycles_for_archive_creation: Nat,
+    archive_profile: &ArchiveProfile,
 ) -> ArchiveOptions {
     ArchiveOptions {
-        trigger_threshold: 2_000,
-        num_blocks_to_archive: 1_000,
-        node_max_memory_size_bytes: Some(THREE_GIGA_BYTES),
+        trigger_threshold: to_usize(archive_profile.trigger_threshold),
+        num_blocks_to_archive: to_usize(archive_profile.num_blocks_to_archive),
+        node_max_memory_size_bytes: Some(archive_profile.node_max_memory_size_bytes),
         max_message_size_bytes: None,
         controller_id: archive_controller_id,
         more_controller_ids: Some(archive_more_controller_ids),
@@ -307,7 +680,7 @@ fn icrc1_archive_options(
                 .to_u64()
                 .expect("BUG: cycles for archive creation does not fit in a u64"),
         ),
-        max_transactions_per_response: None,
+        max_transactions_per_response: archive_profile.max_transactions_per_response,
     }
 }
 
@@ -315,6 +688,8 @@ async fn create_canister_once<C, R>(
     token: &Erc20Token,
     runtime: &R,
     cycles_for_canister_creation: Nat,
+    settings: CanisterSettings,
+    placement: CanisterPlacement,
 ) -> Result<Principal, TaskError>
 where
     C: Debug,
@@ -328,23 +703,50 @@ where
     }) {
         return Ok(canister_id);
     }
+    let cycles_for_canister_creation = cycles_for_canister_creation
+        .0
+        .to_u64()
+        .expect("BUG: cycles for canister creation does not fit in a u64");
+    // The settings and controllers a pooled canister was created with are recorded,
+    // so that the reconcile tasks update them to the configured ones.
+    if let Some(pooled) = mutate_state(|s| {
+        let pooled = s.take_pooled_canister(&placement, cycles_for_canister_creation as u128)?;
+        s.record_created_canister::<C>(token, pooled.canister_id);
+        s.record_canister_placement(token, placement.clone());
+        s.record_applied_canister_settings(pooled.canister_id, pooled.settings.clone());
+        s.record_applied_controllers(
+            pooled.canister_id,
+            pooled.controllers.iter().copied().collect(),
+        );
+        Some(pooled)
+    }) {
+        log!(
+            INFO,
+            "took {} canister for {:?} from the pool: {:?}",
+            Canisters::display_name(),
+            token,
+            pooled
+        );
+        return Ok(pooled.canister_id);
+    }
+    let controllers = read_state(|s| s.canister_controllers(runtime.id()));
     let canister_id = match runtime
         .create_canister(
-            vec![runtime.id()],
-            cycles_for_canister_creation
-                .0
-                .to_u64()
-                .expect("BUG: cycles for canister creation does not fit in a u64"),
+            controllers.iter().copied().collect(),
+            cycles_for_canister_creation,
+            settings.clone(),
+            placement.clone(),
         )
         .await
     {
         Ok(id) => {
             log!(
                 INFO,
-                "created {} canister for {:?} at '{}'",
+                "created {} canister for {:?} at '{}' on {:?}",
                 Canisters::display_name(),
                 token,
-                id
+                id,
+                placement
             );
             id
         }
@@ -359,7 +761,12 @@ where
             return Err(TaskError::CanisterCreationError(e));
         }
     };
-    mutate_state(|s| s.record_created_canister::<C>(token, canister_id));
+    mutate_state(|s| {
+        s.record_created_canister::<C>(token, canister_id);
+        s.record_applied_canister_settings(canister_id, settings);
+        s.record_applied_controllers(canister_id, controllers);
+        s.record_canister_placement(token, placement);
+    });
     Ok(canister_id)
 }
 
@@ -412,13 +819,14 @@ where
         }
     }?;
 
-    match runtime
-        .install_code(
-            canister_id,
-            wasm.to_bytes(),
-            Encode!(init_args).expect("BUG: failed to encode init arg"),
-        )
-        .await
+    match install_wasm(
+        canister_id,
+        InstallMode::Install,
+        wasm,
+        Encode!(init_args).expect("BUG: failed to encode init arg"),
+        runtime,
+    )
+    .await
     {
         Ok(_) => {
             log!(
@@ -449,6 +857,64 @@ where
     Ok(())
 }
 
+/// Installs or upgrades the wasm on the given canister.
+///
+/// Wasms larger than [`CHUNKED_INSTALL_THRESHOLD`] do not fit in a single `install_code` call
+/// and are uploaded in chunks to the chunk store of the canister itself before being installed.
+pub async fn install_wasm<T, R: CanisterRuntime>(
+    canister_id: Principal,
+    mode: InstallMode,
+    wasm: Wasm<T>,
+    arg: Vec<u8>,
+    runtime: &R,
+) -> Result<(), CallError> {
+    let wasm_hash = wasm.hash().clone();
+    let wasm_module = wasm.to_bytes();
+    if wasm_module.len() <= CHUNKED_INSTALL_THRESHOLD {
+        return match mode {
+            InstallMode::Install => runtime.install_code(canister_id, wasm_module, arg).await,
+            InstallMode::Upgrade => {
+                runtime
+                    .upgrade_canister(canister_id, wasm_module, arg)
+                    .await
+            }
+        };
+    }
+
+    log!(
+        INFO,
+        "installing wasm {} of {} bytes on canister '{}' in chunks",
+        wasm_hash,
+        wasm_module.len(),
+        canister_id
+    );
+    // chunks of a previously failed installation may still be stored
+    runtime.clear_chunk_store(canister_id).await?;
+    let mut chunk_hashes = Vec::new();
+    for chunk in wasm_module.chunks(WASM_CHUNK_SIZE) {
+        chunk_hashes.push(runtime.upload_chunk(canister_id, chunk.to_vec()).await?);
+    }
+    runtime
+        .install_chunked_code(
+            canister_id,
+            mode,
+            chunk_hashes,
+            wasm_hash.as_ref().to_vec(),
+            arg,
+        )
+        .await?;
+    // the wasm is installed, failing to free the chunk store must not cause the installation to be retried
+    if let Err(e) = runtime.clear_chunk_store(canister_id).await {
+        log!(
+            INFO,
+            "failed to clear the chunk store of canister '{}': {}",
+            canister_id,
+            e
+        );
+    }
+    Ok(())
+}
+
 // Type for adding Erc20 to minter
 #[derive(CandidType, Deserialize, Clone, Debug, PartialEq)]
 pub struct AddErc20Token {
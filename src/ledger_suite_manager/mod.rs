@@ -4,43 +4,67 @@ pub(crate) mod test_fixtures;
 #[cfg(test)]
 pub mod tests;
 
+pub mod canister_pool;
+pub mod canister_settings;
+pub mod controllers;
 pub mod discover_archives;
+pub mod health_check;
 pub mod icp_cycles_convertor;
 pub mod install_ls;
+pub mod module_hash_drift;
+pub mod notifications;
+pub mod refund;
 pub mod top_up;
+pub mod upgrade_campaign;
+pub mod upgrade_ls;
 use crate::cmc_client::CyclesConvertor;
 use crate::ledger_suite_manager::icp_cycles_convertor::convert_icp_balance_to_cycles;
-// mod upgrade_ls;
 use crate::ledger_suite_manager::top_up::maybe_top_up;
 use crate::logs::{DEBUG, INFO};
+use canister_pool::refill_canister_pool;
+use canister_settings::reconcile_canister_settings;
+use controllers::reconcile_controllers;
 use discover_archives::{discover_archives, select_all, DiscoverArchivesError};
+use futures::future;
 use ic_canister_log::log;
 use install_ls::{install_ledger_suite, InstallLedgerSuiteArgs};
+use module_hash_drift::check_module_hash_drift;
+use notifications::drain_notification_outbox;
+use refund::refund_icp_deposit;
 use serde::{Deserialize, Serialize};
 use std::fmt::{Debug, Display};
-// use upgrade_ls::{UpgradeLedgerSuite, UpgradeLedgerSuiteError};
+use upgrade_campaign::UpgradeCampaign;
+use upgrade_ls::{UpgradeLedgerSuite, UpgradeLedgerSuiteError};
 
 use crate::guard::TimerGuard;
-use crate::management::{CallError, IcCanisterRuntime, Reason};
+use crate::management::{CallError, CanisterRuntime, IcCanisterRuntime, Reason};
 use crate::state::{mutate_state, read_state, ChainId, Erc20Token, WasmHash};
-use crate::storage::WasmStoreError;
+use crate::storage::{
+    mutate_wasm_store, wasm_store_remove_unreferenced, RemovedWasm, WasmStoreError,
+};
+use crate::WASM_RETENTION_PERIOD;
 
 // User for TimerGuard to prevent Concurrency problems
 #[derive(Clone, Eq, PartialEq, Ord, PartialOrd, Debug, Deserialize, Serialize, Hash, Copy)]
 pub enum PeriodicTasksTypes {
     InstallLedgerSuite,
-    // UpgradeLedgerSuite,
+    UpgradeLedgerSuite,
     MaybeTopUp,
     DiscoverArchives,
     ConvertIcpToCycles,
     NotifyErc20Added,
+    CheckModuleHashDrift,
+    RefundIcpDeposits,
+    ReconcileCanisterSettings,
+    RefillCanisterPool,
+    ReconcileControllers,
 }
 
 #[allow(clippy::large_enum_variant)]
-#[derive(Clone, Eq, PartialEq, Ord, PartialOrd, Debug, Deserialize, Serialize)]
+#[derive(Clone, PartialEq, Debug, Deserialize, Serialize)]
 pub enum Task {
     InstallLedgerSuite(InstallLedgerSuiteArgs),
-    // UpgradeLedgerSuite(UpgradeLedgerSuite),
+    UpgradeLedgerSuite(UpgradeLedgerSuite),
     MaybeTopUp,
     DiscoverArchives,
     NotifyErc20Added,
@@ -53,14 +77,16 @@ pub enum TaskError {
     CanisterCreationError(CallError),
     InstallCodeError(CallError),
     CanisterStatusError(CallError),
+    UpdateSettingsError(CallError),
     WasmHashNotFound(WasmHash),
     WasmStoreError(WasmStoreError),
     LedgerNotFound(Erc20Token),
     InterCanisterCallError(CallError),
     InsufficientCyclesToTopUp { required: u128, available: u128 },
+    InsufficientCyclesToRefillPool { required: u128, available: u128 },
     DiscoverArchivesError(DiscoverArchivesError),
     MinterNotFound(ChainId),
-    // UpgradeLedgerSuiteError(UpgradeLedgerSuiteError),
+    UpgradeLedgerSuiteError(UpgradeLedgerSuiteError),
 }
 
 impl TaskError {
@@ -71,18 +97,26 @@ impl TaskError {
             TaskError::CanisterCreationError(_) => true,
             TaskError::InstallCodeError(_) => true,
             TaskError::CanisterStatusError(_) => true,
+            TaskError::UpdateSettingsError(_) => true,
             TaskError::WasmHashNotFound(_) => false,
             TaskError::WasmStoreError(_) => false,
             TaskError::LedgerNotFound(_) => true, //ledger may not yet be created
             TaskError::InterCanisterCallError(e) => is_recoverable(e),
             TaskError::InsufficientCyclesToTopUp { .. } => false, //top-up task is periodic, will retry on next interval
+            TaskError::InsufficientCyclesToRefillPool { .. } => false, //refill task is periodic, will retry on next interval
             TaskError::DiscoverArchivesError(e) => e.is_recoverable(),
             TaskError::MinterNotFound(..) => false,
-            // TaskError::UpgradeLedgerSuiteError(e) => e.is_recoverable(),
+            TaskError::UpgradeLedgerSuiteError(e) => e.is_recoverable(),
         }
     }
 }
 
+impl From<UpgradeLedgerSuiteError> for TaskError {
+    fn from(value: UpgradeLedgerSuiteError) -> Self {
+        TaskError::UpgradeLedgerSuiteError(value)
+    }
+}
+
 fn is_recoverable(e: &CallError) -> bool {
     match &e.reason {
         Reason::OutOfCycles => true,
@@ -115,48 +149,158 @@ pub async fn process_install_ledger_suites() {
         }
     };
 
-    let twin_ledger_suites_to_be_installed =
-        read_state(|s| s.twin_ledger_suites_to_be_installed.clone());
+    let runtime = IcCanisterRuntime {};
+    install_ledger_suites(&runtime).await;
+}
+
+/// Installs the ledger suites that are due, at most `max_concurrent_ls_installs` at a time.
+/// Installations of different tokens are independent, each one only updating the state of its token.
+async fn install_ledger_suites<R: CanisterRuntime>(runtime: &R) {
+    let now = runtime.time();
+    let (due_installs, max_concurrent_installs): (Vec<_>, _) = read_state(|s| {
+        (
+            s.twin_ledger_suites_to_be_installed
+                .iter()
+                .filter(|(_contract, install_args)| install_args.is_due(now))
+                .map(|(contract, install_args)| (contract.clone(), install_args.clone()))
+                .collect(),
+            s.max_concurrent_ls_installs(),
+        )
+    });
+    let batch_size = usize::try_from(max_concurrent_installs)
+        .unwrap_or(usize::MAX)
+        .max(1);
+
+    for batch in due_installs.chunks(batch_size) {
+        future::join_all(batch.iter().map(|(contract, install_args)| {
+            install_ledger_suite_and_record_outcome(contract, install_args, runtime)
+        }))
+        .await;
+    }
+}
+
+async fn install_ledger_suite_and_record_outcome<R: CanisterRuntime>(
+    contract: &Erc20Token,
+    install_args: &InstallLedgerSuiteArgs,
+    runtime: &R,
+) {
+    log!(
+        INFO,
+        "Installing a ledger suite for contract address: {}, chain_id:{:?}",
+        contract.address(),
+        contract.chain_id()
+    );
+
+    let ledger_suite_result = install_ledger_suite(install_args, runtime).await;
+    match ledger_suite_result {
+        Ok(_) => {
+            mutate_state(|s| {
+                s.remove_installed_ls_from_installing_queue(contract.clone());
+                s.remove_ledger_suite_install_progress(contract);
+            });
+            log!(
+                INFO,
+                "Installed a ledger suite for contract address: {}, chain_id:{:?}",
+                contract.address(),
+                contract.chain_id()
+            );
+        }
+
+        Err(task_error) => {
+            let will_retry = mutate_state(|s| {
+                s.record_failed_ls_install_attempt(
+                    contract,
+                    format!("{:?}", task_error),
+                    task_error.is_recoverable(),
+                    runtime.time(),
+                )
+            });
+            match will_retry {
+                true => {
+                    log!(
+                        INFO,
+                        "Failed to install due to {:?} for contract address: {}, chain_id:{:?}. Error is recoverable and will be retried after a backoff",
+                        task_error,
+                        contract.address(),
+                        contract.chain_id()
+                    );
+                }
+                false => {
+                    log!(
+                        DEBUG,
+                        "Failed to install due to {:?} for contract address: {}, chain_id:{:?}. Error is not recoverable or the maximum number of attempts was reached.",
+                        task_error,
+                        contract.address(),
+                        contract.chain_id()
+                    );
+                }
+            }
+        }
+    }
+}
+
+pub async fn process_upgrade_ledger_suites() {
+    let _guard = match TimerGuard::new(PeriodicTasksTypes::UpgradeLedgerSuite) {
+        Ok(guard) => guard,
+        Err(e) => {
+            log!(
+                DEBUG,
+                "Failed retrieving timer guard to upgrade ledger suites: {e:?}",
+            );
+            return;
+        }
+    };
 
     let runtime = IcCanisterRuntime {};
 
-    for (contract, install_args) in twin_ledger_suites_to_be_installed {
+    if read_state(|s| s.upgrade_campaign().is_some_and(UpgradeCampaign::is_active)) {
+        mutate_state(|s| s.advance_upgrade_campaign(runtime.time()));
+    }
+    let ledger_suites_to_be_upgraded = read_state(|s| s.ledger_suite_upgrades_to_execute());
+
+    for (token, upgrade_task) in ledger_suites_to_be_upgraded {
         log!(
             INFO,
-            "Installing a ledger suite for contract address: {}, chain_id:{:?}",
-            contract.address(),
-            contract.chain_id()
+            "Upgrading the ledger suite for contract address: {}, chain_id:{:?}, starting at subtask {} of {}",
+            token.address(),
+            token.chain_id(),
+            upgrade_task.next_subtask_index(),
+            upgrade_task.subtasks().len()
         );
 
-        let ledger_suite_result = install_ledger_suite(&install_args, &runtime).await;
-        match ledger_suite_result {
-            Ok(_) => {
-                mutate_state(|s| s.remove_installed_ls_from_installing_queue(contract.clone()));
+        let upgrade_result = upgrade_task
+            .execute(&token, &runtime)
+            .await
+            .map_err(TaskError::from);
+        match upgrade_result {
+            Ok(()) => {
                 log!(
                     INFO,
-                    "Installed a ledger suite for contract address: {}, chain_id:{:?}",
-                    contract.address(),
-                    contract.chain_id()
+                    "Upgraded the ledger suite for contract address: {}, chain_id:{:?}",
+                    token.address(),
+                    token.chain_id()
                 );
             }
-
             Err(task_error) => match task_error.is_recoverable() {
                 true => {
                     log!(
                         INFO,
-                        "Failed to install for contract address: {}, chain_id:{:?}. Error is recoverable and will try again in the next iteration",
-                        contract.address(),
-                        contract.chain_id()
+                        "Failed to upgrade the ledger suite for contract address: {}, chain_id:{:?} due to {:?}. Error is recoverable and will try again in the next iteration",
+                        token.address(),
+                        token.chain_id(),
+                        task_error
                     );
                 }
                 false => {
-                    mutate_state(|s| s.record_failed_ls_install(contract.clone(), install_args));
+                    mutate_state(|s| {
+                        s.record_failed_ledger_suite_upgrade(&token, format!("{:?}", task_error))
+                    });
                     log!(
-                        DEBUG,
-                        "Failed to install due to {:?} for contract address: {}, chain_id:{:?}. Error is not recoverable.",
+                        INFO,
+                        "ERROR: Failed to upgrade due to {:?} for contract address: {}, chain_id:{:?}. Error is not recoverable and the upgrade is dropped.",
                         task_error,
-                        contract.address(),
-                        contract.chain_id()
+                        token.address(),
+                        token.chain_id()
                     );
                 }
             },
@@ -234,6 +378,102 @@ pub async fn process_maybe_topup() {
     }
 }
 
+pub async fn process_check_module_hash_drift() {
+    let _guard = match TimerGuard::new(PeriodicTasksTypes::CheckModuleHashDrift) {
+        Ok(guard) => guard,
+        Err(e) => {
+            log!(
+                DEBUG,
+                "Failed retrieving timer guard to run check_module_hash_drift process: {e:?}",
+            );
+            return;
+        }
+    };
+
+    let runtime = IcCanisterRuntime {};
+
+    if let Err(task_error) = check_module_hash_drift(&runtime).await {
+        log!(
+            INFO,
+            "Failed to check the module hash of some canisters, will try again in the next iteration. error: {:?}",
+            task_error
+        );
+    }
+}
+
+pub async fn process_reconcile_canister_settings() {
+    let _guard = match TimerGuard::new(PeriodicTasksTypes::ReconcileCanisterSettings) {
+        Ok(guard) => guard,
+        Err(e) => {
+            log!(
+                DEBUG,
+                "Failed retrieving timer guard to run reconcile_canister_settings process: {e:?}",
+            );
+            return;
+        }
+    };
+
+    let runtime = IcCanisterRuntime {};
+
+    if let Err(task_error) = reconcile_canister_settings(&runtime).await {
+        log!(
+            INFO,
+            "Failed to update the settings of some canisters, will try again in the next iteration. error: {:?}",
+            task_error
+        );
+    }
+}
+
+pub async fn process_reconcile_controllers() {
+    let _guard = match TimerGuard::new(PeriodicTasksTypes::ReconcileControllers) {
+        Ok(guard) => guard,
+        Err(e) => {
+            log!(
+                DEBUG,
+                "Failed retrieving timer guard to run reconcile_controllers process: {e:?}",
+            );
+            return;
+        }
+    };
+
+    let reports = reconcile_controllers(&IcCanisterRuntime {}).await;
+    let errors = reports
+        .values()
+        .flat_map(|report| report.canisters.iter())
+        .filter(|canister| canister.error.is_some())
+        .count();
+    if errors > 0 {
+        log!(
+            INFO,
+            "Failed to set the controllers of {} canisters, will try again in the next iteration",
+            errors
+        );
+    }
+}
+
+pub async fn process_refill_canister_pool() {
+    let _guard = match TimerGuard::new(PeriodicTasksTypes::RefillCanisterPool) {
+        Ok(guard) => guard,
+        Err(e) => {
+            log!(
+                DEBUG,
+                "Failed retrieving timer guard to run refill_canister_pool process: {e:?}",
+            );
+            return;
+        }
+    };
+
+    let runtime = IcCanisterRuntime {};
+
+    if let Err(task_error) = refill_canister_pool(&runtime).await {
+        log!(
+            INFO,
+            "Failed to refill the canister pool, will try again in the next iteration. error: {:?}",
+            task_error
+        );
+    }
+}
+
 pub async fn process_convert_icp_to_cycles() {
     let _guard = match TimerGuard::new(PeriodicTasksTypes::ConvertIcpToCycles) {
         Ok(guard) => guard,
@@ -247,8 +487,11 @@ pub async fn process_convert_icp_to_cycles() {
     };
 
     let runtime = CyclesConvertor {};
+    let reserved_icp = read_state(|s| s.icp_reserved_for_refunds())
+        .try_into()
+        .unwrap_or(u64::MAX);
 
-    let top_up_result = convert_icp_balance_to_cycles(runtime).await;
+    let top_up_result = convert_icp_balance_to_cycles(runtime, reserved_icp).await;
 
     match top_up_result {
         Ok(cycles) => {
@@ -263,3 +506,83 @@ pub async fn process_convert_icp_to_cycles() {
         }
     }
 }
+
+pub async fn process_notification_outbox() {
+    let _guard = match TimerGuard::new(PeriodicTasksTypes::NotifyErc20Added) {
+        Ok(guard) => guard,
+        Err(e) => {
+            log!(
+                DEBUG,
+                "Failed retrieving timer guard to send notifications: {e:?}",
+            );
+            return;
+        }
+    };
+
+    drain_notification_outbox(&IcCanisterRuntime {}).await;
+}
+
+pub async fn process_refund_icp_deposits() {
+    let _guard = match TimerGuard::new(PeriodicTasksTypes::RefundIcpDeposits) {
+        Ok(guard) => guard,
+        Err(e) => {
+            log!(
+                DEBUG,
+                "Failed retrieving timer guard to refund icp deposits: {e:?}",
+            );
+            return;
+        }
+    };
+
+    let runtime = CyclesConvertor {};
+
+    for deposit in read_state(|s| s.icp_refunds_to_execute()) {
+        match refund_icp_deposit(&deposit, &runtime).await {
+            Ok(block_index) => {
+                log!(
+                    INFO,
+                    "Refunded deposit {} of {} for {:?} in block {}",
+                    deposit.transfer_index,
+                    deposit.from_principal,
+                    deposit.erc20_token,
+                    block_index
+                );
+            }
+            Err(e) => {
+                log!(
+                    INFO,
+                    "Failed to refund deposit {} of {} for {:?}: {:?}. Will retry in the next iteration",
+                    deposit.transfer_index,
+                    deposit.from_principal,
+                    deposit.erc20_token,
+                    e
+                );
+            }
+        }
+    }
+}
+
+/// Removes the wasms that are no longer referenced by the lsm from the wasm store.
+/// Wasms stored less than [`WASM_RETENTION_PERIOD`] ago are kept.
+pub fn process_wasm_garbage_collection() -> Vec<RemovedWasm> {
+    let referenced_wasm_hashes = read_state(|s| s.referenced_wasm_hashes());
+
+    let removed_wasms = mutate_wasm_store(|w| {
+        wasm_store_remove_unreferenced(
+            w,
+            &referenced_wasm_hashes,
+            ic_cdk::api::time(),
+            WASM_RETENTION_PERIOD,
+        )
+    });
+
+    for removed_wasm in &removed_wasms {
+        log!(
+            INFO,
+            "Removed unreferenced wasm {} ({} bytes) from the wasm store",
+            removed_wasm.wasm_hash,
+            removed_wasm.size
+        );
+    }
+    removed_wasms
+}
@@ -0,0 +1,165 @@
+use std::collections::BTreeMap;
+
+use candid::Principal;
+use futures::future;
+use ic_canister_log::log;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    logs::{ERROR, INFO},
+    management::{CallError, CanisterRuntime},
+    state::{mutate_state, read_state, CanisterKind, Erc20Token, WasmHash},
+    storage::{read_wasm_store, wasm_store_try_get_uncompressed_hash},
+};
+
+use super::TaskError;
+
+/// Result of comparing the module hashes reported by the IC
+/// with the wasm hashes recorded by the lsm for the canisters of a ledger suite.
+#[derive(Clone, PartialEq, Debug, Deserialize, Serialize)]
+pub struct ModuleHashDriftReport {
+    /// The canister time at which the check was done
+    /// in nanoseconds since the epoch (1970-01-01).
+    pub checked_at: u64,
+    pub drifted_canisters: Vec<DriftedCanister>,
+}
+
+#[derive(Clone, PartialEq, Debug, Deserialize, Serialize)]
+pub struct DriftedCanister {
+    pub canister_id: Principal,
+    pub kind: CanisterKind,
+    /// The hash of the wasm the lsm expects to be installed.
+    pub expected_wasm_hash: WasmHash,
+    /// The module hash reported by the IC, or `None` if the canister is empty.
+    pub module_hash: Option<WasmHash>,
+}
+
+struct CanisterToCheck {
+    token_id: Erc20Token,
+    canister_id: Principal,
+    kind: CanisterKind,
+    expected_wasm_hash: WasmHash,
+}
+
+/// Compares the module hash of every installed ledger, index and archive canister
+/// with the wasm hash recorded by the lsm and records a drift report for each ledger suite.
+///
+/// The lsm records the hash of the possibly gzip-compressed wasm, while the IC may report
+/// the hash of the uncompressed module, so a canister runs the expected wasm if its module hash
+/// matches either of them.
+///
+/// Archives spawned by their ledger run the archive wasm embedded in the ledger,
+/// so only archives upgraded by the lsm are checked.
+/// Ledger suites with a scheduled upgrade are not checked, since they are expected to run an older wasm.
+pub async fn check_module_hash_drift<R: CanisterRuntime>(runtime: &R) -> Result<(), TaskError> {
+    let canisters = read_state(|s| {
+        let mut result = vec![];
+        for (token_id, canisters) in s.all_managed_canisters_iter() {
+            if s.ledger_suite_upgrade(&token_id).is_some() {
+                continue;
+            }
+            let ledger = canisters.ledger.as_ref().and_then(|c| {
+                c.installed_wasm_hash()
+                    .map(|hash| (*c.canister_id(), CanisterKind::Ledger, hash.clone()))
+            });
+            let index = canisters.index.as_ref().and_then(|c| {
+                c.installed_wasm_hash()
+                    .map(|hash| (*c.canister_id(), CanisterKind::Index, hash.clone()))
+            });
+            let archives = canisters
+                .archive_canister_ids()
+                .iter()
+                .filter_map(|archive_id| {
+                    s.archive_wasm_hash(archive_id)
+                        .map(|hash| (*archive_id, CanisterKind::Archive, hash.clone()))
+                });
+            for (canister_id, kind, expected_wasm_hash) in
+                ledger.into_iter().chain(index).chain(archives)
+            {
+                result.push(CanisterToCheck {
+                    token_id: token_id.clone(),
+                    canister_id,
+                    kind,
+                    expected_wasm_hash,
+                });
+            }
+        }
+        result
+    });
+    if canisters.is_empty() {
+        return Ok(());
+    }
+
+    let results = future::join_all(
+        canisters
+            .iter()
+            .map(|c| runtime.canister_module_hash(c.canister_id)),
+    )
+    .await;
+    let checked_at = runtime.time();
+    let mut uncompressed_wasm_hashes: BTreeMap<WasmHash, Option<WasmHash>> = BTreeMap::new();
+    let mut reports: BTreeMap<Erc20Token, ModuleHashDriftReport> = BTreeMap::new();
+    let mut errors: Vec<(Principal, CallError)> = Vec::new();
+    for (canister, result) in canisters.into_iter().zip(results) {
+        let module_hash = match result {
+            Ok(module_hash) => module_hash.and_then(|hash| <[u8; 32]>::try_from(hash).ok()),
+            Err(e) => {
+                errors.push((canister.canister_id, e));
+                continue;
+            }
+        };
+        let report =
+            reports
+                .entry(canister.token_id.clone())
+                .or_insert_with(|| ModuleHashDriftReport {
+                    checked_at,
+                    drifted_canisters: vec![],
+                });
+        let module_hash = module_hash.map(WasmHash::from);
+        let uncompressed_wasm_hash = uncompressed_wasm_hashes
+            .entry(canister.expected_wasm_hash.clone())
+            .or_insert_with(|| {
+                read_wasm_store(|w| {
+                    wasm_store_try_get_uncompressed_hash(w, &canister.expected_wasm_hash)
+                })
+            });
+        let is_expected = module_hash.as_ref().is_some_and(|module_hash| {
+            module_hash == &canister.expected_wasm_hash
+                || Some(module_hash) == uncompressed_wasm_hash.as_ref()
+        });
+        if !is_expected {
+            log!(
+                ERROR,
+                "[check_module_hash_drift]: {:?} canister {} of {:?} runs module {:?} instead of wasm {}",
+                canister.kind,
+                canister.canister_id,
+                canister.token_id,
+                module_hash.as_ref().map(ToString::to_string),
+                canister.expected_wasm_hash
+            );
+            report.drifted_canisters.push(DriftedCanister {
+                canister_id: canister.canister_id,
+                kind: canister.kind,
+                expected_wasm_hash: canister.expected_wasm_hash,
+                module_hash,
+            });
+        }
+    }
+    mutate_state(|s| {
+        for (token_id, report) in reports {
+            s.record_module_hash_drift_report(token_id, report);
+        }
+    });
+
+    if !errors.is_empty() {
+        log!(
+            INFO,
+            "[check_module_hash_drift]: {} errors. Failed to retrieve the module hash of {:?}",
+            errors.len(),
+            errors
+        );
+        let first_error = errors.swap_remove(0);
+        return Err(TaskError::CanisterStatusError(first_error.1));
+    }
+    Ok(())
+}
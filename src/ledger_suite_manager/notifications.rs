@@ -0,0 +1,188 @@
+use std::collections::BTreeSet;
+
+use candid::{Nat, Principal};
+use ic_canister_log::log;
+use serde::{Deserialize, Serialize};
+
+use crate::appic_helper_client::appic_helper_types::{
+    CandidAddErc20TwinLedgerSuiteRequest, CandidErc20TwinLedgerSuiteFee,
+    CandidErc20TwinLedgerSuiteStatus, CandidIcpToken, IcpTokenType,
+};
+use crate::appic_helper_client::AppicHelperClient;
+use crate::endpoints::LedgerInitArg;
+use crate::logs::INFO;
+use crate::management::CanisterRuntime;
+use crate::state::{mutate_state, read_state, Erc20Token};
+
+use super::install_ls::notify_erc20_added;
+use super::TaskError;
+
+/// A message to another canister, kept in the outbox until it is delivered.
+#[derive(Clone, PartialEq, Debug, Deserialize, Serialize)]
+pub enum Notification {
+    /// Registers the ledger of a twin token with the appic helper.
+    AddIcpToken {
+        token: Erc20Token,
+        ledger_id: Principal,
+        ledger_init_arg: LedgerInitArg,
+    },
+    /// Registers the ledger of a twin token with the minter of its chain.
+    AddErc20Token {
+        token: Erc20Token,
+        minter_id: Principal,
+    },
+    /// Updates the status of a ledger suite request in the appic helper.
+    UpdateLedgerSuiteRequest {
+        token: Erc20Token,
+        creator: Principal,
+        ledger_id: Option<Principal>,
+        token_name: String,
+        token_symbol: String,
+        created_at: u64,
+        fee_charged_icp: u128,
+        status: LedgerSuiteRequestStatus,
+    },
+    /// Asks the appic helper to refresh its bridge pairs after a twin token was added.
+    UpdateBridgePairs { token: Erc20Token },
+}
+
+impl Notification {
+    /// The token the notification is about.
+    /// Notifications about the same token are delivered in the order they were enqueued.
+    pub fn token(&self) -> &Erc20Token {
+        match self {
+            Notification::AddIcpToken { token, .. }
+            | Notification::AddErc20Token { token, .. }
+            | Notification::UpdateLedgerSuiteRequest { token, .. }
+            | Notification::UpdateBridgePairs { token } => token,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Eq, PartialEq, Debug, Deserialize, Serialize)]
+pub enum LedgerSuiteRequestStatus {
+    Installed,
+    Rejected,
+}
+
+impl From<LedgerSuiteRequestStatus> for CandidErc20TwinLedgerSuiteStatus {
+    fn from(value: LedgerSuiteRequestStatus) -> Self {
+        match value {
+            LedgerSuiteRequestStatus::Installed => CandidErc20TwinLedgerSuiteStatus::Installed,
+            LedgerSuiteRequestStatus::Rejected => CandidErc20TwinLedgerSuiteStatus::Rejected,
+        }
+    }
+}
+
+#[derive(Clone, PartialEq, Debug, Deserialize, Serialize)]
+pub struct OutboxEntry {
+    pub notification: Notification,
+    /// The canister time at which the notification was enqueued
+    /// in nanoseconds since the epoch (1970-01-01).
+    pub enqueued_at: u64,
+    /// Number of failed deliveries.
+    pub attempts: u32,
+    pub last_error: Option<String>,
+    /// The canister time from which the delivery may be attempted again
+    /// in nanoseconds since the epoch (1970-01-01).
+    pub next_attempt_at: u64,
+}
+
+/// Delivers the notifications of the outbox that are due.
+/// A notification is removed from the outbox once delivered,
+/// otherwise its delivery is retried after a backoff.
+pub async fn drain_notification_outbox<R: CanisterRuntime>(runtime: &R) {
+    let mut attempted = BTreeSet::new();
+    loop {
+        let notifications: Vec<_> = read_state(|s| s.notifications_to_send(runtime.time()))
+            .into_iter()
+            .filter(|(id, _)| !attempted.contains(id))
+            .collect();
+        if notifications.is_empty() {
+            return;
+        }
+        for (id, notification) in notifications {
+            attempted.insert(id);
+            match send_notification(&notification, runtime).await {
+                Ok(()) => {
+                    mutate_state(|s| s.record_notification_sent(id));
+                    log!(INFO, "Sent notification {}: {:?}", id, notification);
+                }
+                Err(e) => {
+                    log!(
+                        INFO,
+                        "Failed to send notification {}: {:?}. Error: {:?}",
+                        id,
+                        notification,
+                        e
+                    );
+                    mutate_state(|s| {
+                        s.record_notification_failure(id, format!("{:?}", e), runtime.time())
+                    });
+                }
+            }
+        }
+    }
+}
+
+async fn send_notification<R: CanisterRuntime>(
+    notification: &Notification,
+    runtime: &R,
+) -> Result<(), TaskError> {
+    match notification {
+        Notification::AddIcpToken {
+            token: _,
+            ledger_id,
+            ledger_init_arg,
+        } => {
+            let icp_token = CandidIcpToken {
+                fee: ledger_init_arg.transfer_fee.clone(),
+                decimals: ledger_init_arg.decimals,
+                usd_price: "0".to_string(),
+                logo: ledger_init_arg.token_logo.clone(),
+                name: ledger_init_arg.token_name.clone(),
+                rank: Some(1_u32),
+                ledger_id: *ledger_id,
+                token_type: IcpTokenType::Icrc2,
+                symbol: ledger_init_arg.token_symbol.clone(),
+            };
+            AppicHelperClient::new()
+                .add_icp_token(icp_token)
+                .await
+                .map_err(TaskError::InterCanisterCallError)
+        }
+        Notification::AddErc20Token { token, minter_id } => {
+            notify_erc20_added(token, minter_id, runtime).await
+        }
+        Notification::UpdateLedgerSuiteRequest {
+            token,
+            creator,
+            ledger_id,
+            token_name,
+            token_symbol,
+            created_at,
+            fee_charged_icp,
+            status,
+        } => {
+            let update_ls_args = CandidAddErc20TwinLedgerSuiteRequest {
+                status: (*status).into(),
+                creator: *creator,
+                icp_ledger_id: *ledger_id,
+                icp_token_name: token_name.clone(),
+                created_at: *created_at,
+                fee_charged: CandidErc20TwinLedgerSuiteFee::Icp((*fee_charged_icp).into()),
+                icp_token_symbol: token_symbol.clone(),
+                evm_token_contract: token.address().to_string(),
+                evm_token_chain_id: Nat::from(token.chain_id().as_ref().clone()),
+            };
+            AppicHelperClient::new()
+                .update_ls_request(update_ls_args)
+                .await
+                .map_err(TaskError::InterCanisterCallError)
+        }
+        Notification::UpdateBridgePairs { token: _ } => AppicHelperClient::new()
+            .request_update_bridge_pairs()
+            .await
+            .map_err(TaskError::InterCanisterCallError),
+    }
+}
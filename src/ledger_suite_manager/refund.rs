@@ -0,0 +1,152 @@
+use candid::Nat;
+use ic_canister_log::log;
+use icrc_ledger_types::icrc1::transfer::TransferError;
+use num_traits::ToPrimitive;
+
+use crate::cmc_client::{CmcRunTime, IcpToCyclesConversionError, DEFAULT_TRANSFER_FEE};
+use crate::logs::ERROR;
+use crate::management::CallError;
+use crate::state::{mutate_state, IcpRefund, ReceivedDeposit};
+
+#[derive(Clone, PartialEq, Debug)]
+pub enum RefundError {
+    /// The deposit does not cover the transfer fee of the refund.
+    AmountTooSmall(u128),
+    /// The icp may already have been converted to cycles.
+    InsufficientIcpBalance {
+        required: u128,
+        available: u64,
+    },
+    IcpBalanceError(IcpToCyclesConversionError),
+    /// The refund was no longer scheduled once the icp balance was checked,
+    /// e.g. because the installation was resumed.
+    RefundNotScheduled,
+    CallError(CallError),
+    TransferError(TransferError),
+    /// The ledger no longer deduplicates the retried transfer, whose outcome is unknown.
+    /// The refund is left to a manual reconciliation.
+    RequiresReconciliation {
+        created_at_time: u64,
+        error: TransferError,
+    },
+}
+
+/// Transfers a deposit back to its depositor, minus the transfer fee.
+///
+/// The `created_at_time` of the transfer is recorded before the transfer is sent,
+/// so that a retry after an unknown outcome is deduplicated by the icp ledger.
+/// A new transfer is only sent if the icp balance still covers the deposit,
+/// since the daily conversion to cycles may already have spent it.
+///
+/// A retried transfer is only rescheduled if the ledger rejected it for a reason proving
+/// that no transfer was executed. Once the ledger no longer deduplicates the retry
+/// (`TooOld` or `CreatedInFuture`), the first transfer may have been executed,
+/// so the refund stays in flight until it is reconciled manually.
+pub async fn refund_icp_deposit<C: CmcRunTime>(
+    deposit: &ReceivedDeposit,
+    runtime: &C,
+) -> Result<u64, RefundError> {
+    let refund_amount = deposit
+        .amount
+        .checked_sub(u128::from(DEFAULT_TRANSFER_FEE.e8s()))
+        .filter(|amount| *amount > 0)
+        .and_then(|amount| u64::try_from(amount).ok())
+        .ok_or(RefundError::AmountTooSmall(deposit.amount))?;
+
+    let (created_at_time, is_retry) = match deposit.refund {
+        Some(IcpRefund::InFlight {
+            created_at_time, ..
+        }) => (created_at_time, true),
+        _ => {
+            let available = runtime
+                .icp_balance()
+                .await
+                .map_err(RefundError::IcpBalanceError)?;
+            if u128::from(available) < deposit.amount {
+                return Err(RefundError::InsufficientIcpBalance {
+                    required: deposit.amount,
+                    available,
+                });
+            }
+            let created_at_time = runtime.time();
+            if !mutate_state(|s| s.start_icp_refund(deposit.transfer_index, created_at_time)) {
+                return Err(RefundError::RefundNotScheduled);
+            }
+            (created_at_time, false)
+        }
+    };
+
+    let block_index = match runtime
+        .transfer_icp(
+            deposit.from_principal,
+            refund_amount,
+            deposit.transfer_index,
+            created_at_time,
+        )
+        .await
+        .map_err(RefundError::CallError)?
+    {
+        Ok(block_index)
+        | Err(TransferError::Duplicate {
+            duplicate_of: block_index,
+        }) => block_index,
+        Err(transfer_error) if !is_retry || proves_no_transfer(&transfer_error) => {
+            // No transfer was executed, so a new one can safely be created.
+            mutate_state(|s| s.record_icp_refund(deposit.transfer_index, IcpRefund::Scheduled));
+            return Err(RefundError::TransferError(transfer_error));
+        }
+        Err(transfer_error) => {
+            log!(
+                ERROR,
+                "[refund_icp_deposit]: outcome of refund of deposit {} of {} created at {} is unknown: {:?}. Look up the transfer with memo {} on the icp ledger and reconcile the refund",
+                deposit.transfer_index,
+                deposit.from_principal,
+                created_at_time,
+                transfer_error,
+                deposit.transfer_index
+            );
+            mutate_state(|s| {
+                s.record_icp_refund(
+                    deposit.transfer_index,
+                    IcpRefund::InFlight {
+                        created_at_time,
+                        requires_reconciliation: true,
+                    },
+                )
+            });
+            return Err(RefundError::RequiresReconciliation {
+                created_at_time,
+                error: transfer_error,
+            });
+        }
+    };
+    let block_index = nat_to_u64(block_index);
+    mutate_state(|s| {
+        s.record_icp_refund(
+            deposit.transfer_index,
+            IcpRefund::Refunded {
+                block_index,
+                refunded_at: runtime.time(),
+            },
+        )
+    });
+    Ok(block_index)
+}
+
+/// Whether the ledger rejected a transfer for a reason proving
+/// that no transfer with the same `created_at_time` was executed.
+fn proves_no_transfer(error: &TransferError) -> bool {
+    matches!(
+        error,
+        TransferError::InsufficientFunds { .. }
+            | TransferError::BadFee { .. }
+            | TransferError::TemporarilyUnavailable
+    )
+}
+
+fn nat_to_u64(value: Nat) -> u64 {
+    value
+        .0
+        .to_u64()
+        .expect("BUG: icp ledger block index does not fit into u64")
+}
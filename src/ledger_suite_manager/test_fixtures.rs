@@ -45,6 +45,9 @@ pub fn usdc_ledger_suite() -> Canisters {
         })),
         archives: vec!["t4dy3-uiaaa-aaaar-qafua-cai".parse().unwrap()],
         metadata: usdc_metadata(),
+        snapshots: Default::default(),
+        archive_profile: None,
+        placement: None,
     }
 }
 
@@ -1,32 +1,79 @@
-use crate::endpoints::LedgerInitArg;
-use crate::ledger_suite_manager::install_ls::install_ledger_suite;
-use crate::ledger_suite_manager::test_fixtures::{usdc, usdc_metadata};
-use crate::ledger_suite_manager::tests::mock::MockCanisterRuntime;
-use crate::ledger_suite_manager::{InstallLedgerSuiteArgs, TaskError};
-use crate::management::{CallError, Reason};
+use crate::endpoints::{
+    CanisterPlacement, CanisterPoolConfig, CanisterSettings, InstallRetryPolicy, LedgerInitArg,
+    LogVisibility, NotificationKind, PendingNotification, UpdateManagedCanisterSettings,
+};
+use crate::ledger_suite_manager::canister_pool::{refill_canister_pool, PooledCanister};
+use crate::ledger_suite_manager::canister_settings::reconcile_canister_settings;
+use crate::ledger_suite_manager::controllers::{
+    reconcile_controllers, CanisterControllersUpdate, ControllersReport,
+};
+use crate::ledger_suite_manager::health_check::{HealthBaseline, UpgradeRollback};
+use crate::ledger_suite_manager::install_ls::{
+    install_ledger_suite, install_wasm, AddErc20Token, ArchiveProfile, InstallStep,
+    InstallStepProgress, CHUNKED_INSTALL_THRESHOLD, WASM_CHUNK_SIZE,
+};
+use crate::ledger_suite_manager::module_hash_drift::{
+    check_module_hash_drift, DriftedCanister, ModuleHashDriftReport,
+};
+use crate::ledger_suite_manager::notifications::{
+    drain_notification_outbox, LedgerSuiteRequestStatus, Notification,
+};
+use crate::ledger_suite_manager::refund::{refund_icp_deposit, RefundError};
+use crate::ledger_suite_manager::test_fixtures::{
+    usdc, usdc_matic, usdc_metadata, usdt, usdt_metadata,
+};
+use crate::ledger_suite_manager::tests::mock::{MockCanisterRuntime, MockCmcRunTime};
+use crate::ledger_suite_manager::upgrade_ls::{
+    load_canister_snapshot, LoadCanisterSnapshotError, UpgradeLedgerSuite, UpgradeLedgerSuiteError,
+};
+use crate::ledger_suite_manager::{install_ledger_suites, InstallLedgerSuiteArgs, TaskError};
+use crate::management::{CallError, CanisterSnapshotResponse, InstallMode, Reason};
 use crate::state::test_fixtures::new_state;
 use crate::state::{
-    read_state, Canisters, IndexCanister, LedgerCanister, LedgerSuiteVersion,
-    ManagedCanisterStatus, WasmHash,
+    mutate_state, read_state, CanisterKind, CanisterSnapshot, Canisters, IcpRefund, Index,
+    IndexCanister, Ledger, LedgerCanister, LedgerSuiteVersion, ManagedCanisterStatus,
+    RejectedLedgerSuiteRequest, ResumeFailedLedgerSuiteInstallError, Wasm, WasmHash,
 };
 
-use crate::storage::{mutate_wasm_store, record_icrc1_ledger_suite_wasms};
+use crate::storage::{
+    mutate_wasm_store, read_wasm_store, record_icrc1_ledger_suite_wasms,
+    wasm_store_try_get_uncompressed_hash, wasm_store_try_insert,
+};
 use crate::storage::{INDEX_BYTECODE, LEDGER_BYTECODE};
-use candid::Principal;
+use assert_matches::assert_matches;
+use candid::{Encode, Nat, Principal};
+use ic_icrc1_index_ng::Status as IndexStatus;
+use ic_icrc1_ledger::{ChangeArchiveOptions, LedgerArgument, UpgradeArgs as LedgerUpgradeArgs};
+use icrc_ledger_types::icrc::generic_metadata_value::MetadataValue;
+use icrc_ledger_types::icrc1::transfer::TransferError;
+use icrc_ledger_types::icrc3::archive::{GetArchivesArgs, GetArchivesResult};
+use maplit::btreemap;
 
 const LSM_PRINCIPAL: Principal = Principal::from_slice(&[0_u8; 29]);
 const LEDGER_PRINCIPAL: Principal = Principal::from_slice(&[1_u8; 29]);
 const INDEX_PRINCIPAL: Principal = Principal::from_slice(&[2_u8; 29]);
 const MINTER_PRINCIPAL: Principal = Principal::from_slice(&[3_u8; 29]);
+const SNAPSHOT_TIMESTAMP: u64 = 1_733_145_500_000_000_000;
 
 // TODO: Convert test cases to pocket-ic
 
 #[tokio::test]
 async fn should_install_ledger_suite() {
+    const NOW: u64 = 1_733_145_560_000_000_000;
+    const DEPOSIT_AMOUNT: u128 = 99_990_000;
     init_state();
+    mutate_state(|s| {
+        s.record_new_icp_deposit(usdc(), 7, DEPOSIT_AMOUNT, usdc_install_args().creator, 0)
+    });
+    // the creator paid another fee than the current one
+    assert_ne!(
+        read_state(|s| s.minimum_tokens_for_new_ledger_suite().icp),
+        DEPOSIT_AMOUNT
+    );
     let mut runtime = MockCanisterRuntime::new();
 
     runtime.expect_id().return_const(LSM_PRINCIPAL);
+    runtime.expect_time().return_const(NOW);
     expect_create_canister_returning(
         &mut runtime,
         vec![LSM_PRINCIPAL],
@@ -52,16 +99,193 @@ async fn should_install_ledger_suite() {
             })),
             archives: vec![],
             metadata: usdc_metadata(),
+            snapshots: Default::default(),
+            archive_profile: Some(ArchiveProfile::default()),
+            placement: Some(CanisterPlacement::LsmSubnet),
+        })
+    );
+    assert_eq!(
+        read_state(|s| s
+            .notification_outbox()
+            .map(|(_id, entry)| entry.notification.clone())
+            .collect::<Vec<_>>()),
+        vec![
+            Notification::AddIcpToken {
+                token: usdc(),
+                ledger_id: LEDGER_PRINCIPAL,
+                ledger_init_arg: ledger_init_arg(),
+            },
+            Notification::AddErc20Token {
+                token: usdc(),
+                minter_id: MINTER_PRINCIPAL,
+            },
+            Notification::UpdateLedgerSuiteRequest {
+                token: usdc(),
+                creator: usdc_install_args().creator,
+                ledger_id: Some(LEDGER_PRINCIPAL),
+                token_name: ledger_init_arg().token_name,
+                token_symbol: ledger_init_arg().token_symbol,
+                created_at: 0,
+                fee_charged_icp: DEPOSIT_AMOUNT,
+                status: LedgerSuiteRequestStatus::Installed,
+            },
+            Notification::UpdateBridgePairs { token: usdc() },
+        ]
+    );
+}
+
+#[tokio::test]
+async fn should_install_due_ledger_suites_independently() {
+    const NOW: u64 = 1_733_145_560_000_000_000;
+    init_state();
+    let usdc_matic_install_args = InstallLedgerSuiteArgs {
+        contract: usdc_matic(),
+        ..usdc_install_args()
+    };
+    let not_due_install_args = InstallLedgerSuiteArgs {
+        contract: usdt(),
+        next_attempt_at: NOW + 1,
+        ..usdc_install_args()
+    };
+    mutate_state(|s| {
+        s.set_max_concurrent_ls_installs(1);
+        for install_args in [
+            usdc_install_args(),
+            usdc_matic_install_args,
+            not_due_install_args.clone(),
+        ] {
+            s.twin_ledger_suites_to_be_installed
+                .insert(install_args.contract.clone(), install_args);
+        }
+    });
+    let mut runtime = MockCanisterRuntime::new();
+    runtime.expect_id().return_const(LSM_PRINCIPAL);
+    runtime.expect_time().return_const(NOW);
+    expect_create_canister_returning(
+        &mut runtime,
+        vec![LSM_PRINCIPAL],
+        (10_u8..14)
+            .map(|i| Ok(Principal::from_slice(&[i; 29])))
+            .collect(),
+    );
+    runtime.expect_install_code().times(4).return_const(Ok(()));
+
+    install_ledger_suites(&runtime).await;
+
+    // The minter of the Polygon chain is unknown, which fails the installation
+    // of the USDC twin of Polygon without affecting the one of Ethereum.
+    read_state(|s| {
+        assert_eq!(
+            s.twin_ledger_suites_to_be_installed
+                .keys()
+                .collect::<Vec<_>>(),
+            vec![&usdt()]
+        );
+        assert!(s
+            .managed_canisters(&usdc())
+            .and_then(|canisters| canisters.index.as_ref())
+            .is_some_and(|index| matches!(
+                index.status(),
+                ManagedCanisterStatus::Installed { .. }
+            )));
+        assert_eq!(
+            s.failed_ledger_suite_installs().keys().collect::<Vec<_>>(),
+            vec![&usdc_matic()]
+        );
+        assert!(s.failed_ledger_suite_installs()[&usdc_matic()]
+            .last_error
+            .as_ref()
+            .is_some_and(|error| error.contains("MinterNotFound")));
+        assert!(s
+            .notification_outbox()
+            .all(|(_id, entry)| entry.notification.token() == &usdc()));
+        assert_eq!(s.managed_canisters(&usdt()), None);
+    });
+}
+
+#[tokio::test]
+async fn should_deliver_notifications_of_same_token_in_order() {
+    const NOW: u64 = 1_733_145_560_000_000_000;
+    const ONE_MINUTE: u64 = 60_000_000_000;
+    init_state();
+    mutate_state(|s| {
+        s.record_new_erc20_token(usdc(), usdc_metadata());
+        s.record_created_canister::<Ledger>(&usdc(), LEDGER_PRINCIPAL);
+        for _ in 0..2 {
+            s.enqueue_notification(
+                Notification::AddErc20Token {
+                    token: usdc(),
+                    minter_id: MINTER_PRINCIPAL,
+                },
+                NOW,
+            );
+        }
+    });
+    let mut runtime = MockCanisterRuntime::new();
+    runtime.expect_time().return_const(NOW);
+    let expected_error = CallError {
+        method: "add_erc20_token".to_string(),
+        reason: Reason::OutOfCycles,
+    };
+    runtime
+        .expect_call_canister::<AddErc20Token, ()>()
+        .withf(|canister_id, method, _args| {
+            canister_id == &MINTER_PRINCIPAL && method == "add_erc20_token"
         })
+        .times(1)
+        .return_const(Err(expected_error.clone()));
+
+    drain_notification_outbox(&runtime).await;
+
+    let outbox = read_state(|s| {
+        s.notification_outbox()
+            .map(|(id, entry)| (*id, entry.clone()))
+            .collect::<Vec<_>>()
+    });
+    assert_eq!(outbox.len(), 2);
+    assert_eq!(outbox[0].1.attempts, 1);
+    assert_eq!(
+        outbox[0].1.last_error,
+        Some(format!(
+            "{:?}",
+            TaskError::InterCanisterCallError(expected_error)
+        ))
+    );
+    assert_eq!(outbox[0].1.next_attempt_at, NOW + ONE_MINUTE);
+    assert_eq!(outbox[1].1.attempts, 0);
+    assert_eq!(
+        PendingNotification::from(outbox[0].clone()),
+        PendingNotification {
+            id: outbox[0].0,
+            erc20_contract: usdc().into(),
+            kind: NotificationKind::AddErc20Token,
+            enqueued_at: NOW,
+            attempts: 1,
+            last_error: outbox[0].1.last_error.clone(),
+            next_attempt_at: NOW + ONE_MINUTE,
+        }
     );
+
+    runtime.checkpoint();
+    runtime.expect_time().return_const(NOW + ONE_MINUTE);
+    runtime
+        .expect_call_canister::<AddErc20Token, ()>()
+        .times(2)
+        .return_const(Ok(()));
+
+    drain_notification_outbox(&runtime).await;
+
+    assert_eq!(read_state(|s| s.notification_outbox().count()), 0);
 }
 
 #[tokio::test]
 async fn should_not_retry_successful_operation_after_failing_one() {
+    const NOW: u64 = 1_733_145_560_000_000_000;
     init_state();
     let mut runtime = MockCanisterRuntime::new();
 
     runtime.expect_id().return_const(LSM_PRINCIPAL);
+    runtime.expect_time().return_const(NOW);
     expect_create_canister_returning(
         &mut runtime,
         vec![LSM_PRINCIPAL],
@@ -89,11 +313,37 @@ async fn should_not_retry_successful_operation_after_failing_one() {
             index: None,
             archives: vec![],
             metadata: usdc_metadata(),
+            snapshots: Default::default(),
+            archive_profile: None,
+            placement: Some(CanisterPlacement::LsmSubnet),
         })
     );
+    let progress = read_state(|s| {
+        s.ledger_suite_install_progress()
+            .find(|(token, _)| *token == &usdc())
+            .map(|(_, progress)| progress.clone())
+            .unwrap()
+    });
+    assert_eq!(progress.current_step(), Some(InstallStep::InstallLedger));
+    assert_eq!(
+        progress.steps(),
+        &btreemap! {
+            InstallStep::CreateLedger => InstallStepProgress {
+                attempted_at: NOW,
+                completed_at: Some(NOW),
+                last_error: None,
+            },
+            InstallStep::InstallLedger => InstallStepProgress {
+                attempted_at: NOW,
+                completed_at: None,
+                last_error: Some(format!("{:?}", TaskError::InstallCodeError(expected_error.clone()))),
+            },
+        }
+    );
 
     runtime.checkpoint();
     runtime.expect_id().return_const(LSM_PRINCIPAL);
+    runtime.expect_time().return_const(NOW);
     let expected_error = CallError {
         method: "create_canister".to_string(),
         reason: Reason::OutOfCycles,
@@ -119,11 +369,15 @@ async fn should_not_retry_successful_operation_after_failing_one() {
             index: None,
             archives: vec![],
             metadata: usdc_metadata(),
+            snapshots: Default::default(),
+            archive_profile: Some(ArchiveProfile::default()),
+            placement: Some(CanisterPlacement::LsmSubnet),
         })
     );
 
     runtime.checkpoint();
     runtime.expect_id().return_const(LSM_PRINCIPAL);
+    runtime.expect_time().return_const(NOW);
     expect_create_canister_returning(&mut runtime, vec![LSM_PRINCIPAL], vec![Ok(INDEX_PRINCIPAL)]);
     let expected_error = CallError {
         method: "install_code".to_string(),
@@ -150,11 +404,15 @@ async fn should_not_retry_successful_operation_after_failing_one() {
             })),
             archives: vec![],
             metadata: usdc_metadata(),
+            snapshots: Default::default(),
+            archive_profile: Some(ArchiveProfile::default()),
+            placement: Some(CanisterPlacement::LsmSubnet),
         })
     );
 
     runtime.checkpoint();
     runtime.expect_id().return_const(LSM_PRINCIPAL);
+    runtime.expect_time().return_const(NOW);
     runtime.expect_install_code().times(1).return_const(Ok(()));
     assert_eq!(
         install_ledger_suite(&usdc_install_args(), &runtime).await,
@@ -173,113 +431,1741 @@ async fn should_not_retry_successful_operation_after_failing_one() {
             })),
             archives: vec![],
             metadata: usdc_metadata(),
+            snapshots: Default::default(),
+            archive_profile: Some(ArchiveProfile::default()),
+            placement: Some(CanisterPlacement::LsmSubnet),
         })
     );
 }
 
-fn init_state() {
-    crate::state::init_state(new_state());
-    let _version = register_embedded_wasms();
+#[tokio::test]
+async fn should_create_index_on_subnet_of_ledger() {
+    const NOW: u64 = 1_733_145_560_000_000_000;
+    const SUBNET: Principal = Principal::from_slice(&[5_u8; 29]);
+    init_state();
+    mutate_state(|s| s.set_canister_placement(CanisterPlacement::Subnet(SUBNET)));
+    let mut runtime = MockCanisterRuntime::new();
+    runtime.expect_id().return_const(LSM_PRINCIPAL);
+    runtime.expect_time().return_const(NOW);
+    runtime
+        .expect_create_canister()
+        .withf(|_controllers, _cycles, _settings, placement| {
+            placement == &CanisterPlacement::Subnet(SUBNET)
+        })
+        .times(1)
+        .return_const(Ok(LEDGER_PRINCIPAL));
+    let expected_error = CallError {
+        method: "install_code".to_string(),
+        reason: Reason::OutOfCycles,
+    };
+    runtime
+        .expect_install_code()
+        .times(1)
+        .return_const(Err(expected_error.clone()));
+
+    assert_eq!(
+        install_ledger_suite(&usdc_install_args(), &runtime).await,
+        Err(TaskError::InstallCodeError(expected_error))
+    );
+    assert_eq!(
+        read_state(|s| s
+            .managed_canisters(&usdc())
+            .and_then(|c| c.placement.clone())),
+        Some(CanisterPlacement::Subnet(SUBNET))
+    );
+
+    mutate_state(|s| {
+        s.set_canister_placement(CanisterPlacement::SubnetType("fiduciary".to_string()))
+    });
+    runtime.checkpoint();
+    runtime.expect_id().return_const(LSM_PRINCIPAL);
+    runtime.expect_time().return_const(NOW);
+    runtime.expect_install_code().times(2).return_const(Ok(()));
+    runtime
+        .expect_create_canister()
+        .withf(|_controllers, _cycles, _settings, placement| {
+            placement == &CanisterPlacement::Subnet(SUBNET)
+        })
+        .times(1)
+        .return_const(Ok(INDEX_PRINCIPAL));
+
+    assert_eq!(
+        install_ledger_suite(&usdc_install_args(), &runtime).await,
+        Ok(())
+    );
+    assert_eq!(
+        read_state(|s| s.canister_placement_of(&usdc())),
+        CanisterPlacement::Subnet(SUBNET)
+    );
 }
 
-fn register_embedded_wasms() -> LedgerSuiteVersion {
-    mutate_wasm_store(|s| record_icrc1_ledger_suite_wasms(s, 1_620_328_630_000_000_000)).unwrap()
+#[test]
+fn should_back_off_recoverable_install_failures_until_max_attempts() {
+    const NOW: u64 = 1_733_145_560_000_000_000;
+    const ONE_SECOND: u64 = 1_000_000_000;
+    init_state();
+    mutate_state(|s| {
+        *s.install_retry_policy_mut() = InstallRetryPolicy {
+            max_attempts: 3,
+            initial_backoff_seconds: 60,
+            max_backoff_seconds: 100,
+        };
+        s.record_new_ledger_suite_request(usdc(), usdc_install_args());
+        s.approve_ledger_suite_request(&usdc(), 0);
+    });
+    let pending_install =
+        || read_state(|s| s.twin_ledger_suites_to_be_installed.get(&usdc()).cloned());
+
+    assert!(mutate_state(|s| s.record_failed_ls_install_attempt(
+        &usdc(),
+        "first error".to_string(),
+        true,
+        NOW
+    )));
+    let install_args = pending_install().unwrap();
+    assert_eq!(install_args.attempts, 1);
+    assert_eq!(install_args.next_attempt_at, NOW + 60 * ONE_SECOND);
+    assert!(!install_args.is_due(NOW + 59 * ONE_SECOND));
+    assert!(install_args.is_due(NOW + 60 * ONE_SECOND));
+
+    assert!(mutate_state(|s| s.record_failed_ls_install_attempt(
+        &usdc(),
+        "second error".to_string(),
+        true,
+        NOW
+    )));
+    let install_args = pending_install().unwrap();
+    assert_eq!(install_args.attempts, 2);
+    assert_eq!(install_args.next_attempt_at, NOW + 100 * ONE_SECOND);
+
+    assert!(!mutate_state(|s| s.record_failed_ls_install_attempt(
+        &usdc(),
+        "last error".to_string(),
+        true,
+        NOW
+    )));
+    assert_eq!(pending_install(), None);
+    let failed_install =
+        read_state(|s| s.failed_ledger_suite_installs().get(&usdc()).cloned()).unwrap();
+    assert_eq!(failed_install.attempts, 3);
+    assert_eq!(failed_install.last_error, Some("last error".to_string()));
 }
 
-fn usdc_install_args() -> InstallLedgerSuiteArgs {
-    InstallLedgerSuiteArgs {
-        contract: usdc(),
-        minter_id: MINTER_PRINCIPAL,
-        ledger_init_arg: ledger_init_arg(),
-        ledger_compressed_wasm_hash: read_ledger_wasm_hash(),
-        index_compressed_wasm_hash: read_index_wasm_hash(),
-        creator: Principal::from_text(
-            "tb3vi-54bcb-4oudm-fmp2s-nntjp-rmhd3-ukvnq-lawfq-vk5vy-mnlc7-pae",
-        )
-        .unwrap(),
-        created_at: 0,
-    }
+#[test]
+fn should_not_retry_unrecoverable_install_failure() {
+    init_state();
+    mutate_state(|s| {
+        s.record_new_ledger_suite_request(usdc(), usdc_install_args());
+        s.approve_ledger_suite_request(&usdc(), 0);
+    });
+    let error = format!("{:?}", TaskError::MinterNotFound(usdc().chain_id().clone()));
+
+    assert!(!mutate_state(|s| s.record_failed_ls_install_attempt(
+        &usdc(),
+        error.clone(),
+        false,
+        0
+    )));
+
+    assert_eq!(
+        read_state(|s| s.twin_ledger_suites_to_be_installed.get(&usdc()).cloned()),
+        None
+    );
+    let failed_install =
+        read_state(|s| s.failed_ledger_suite_installs().get(&usdc()).cloned()).unwrap();
+    assert_eq!(failed_install.attempts, 1);
+    assert_eq!(failed_install.last_error, Some(error));
 }
 
-fn ledger_init_arg() -> LedgerInitArg {
-    LedgerInitArg {
-        transfer_fee: 10_000_u32.into(),
-        decimals: 6,
-        token_name: "Ethereum Twin USDC".to_string(),
-        token_symbol: "icUSDC".to_string(),
-        token_logo: "".to_string(),
-    }
+#[test]
+fn should_resume_failed_install_with_other_wasm() {
+    const NOW: u64 = 1_733_145_560_000_000_000;
+    init_state();
+    let other_ledger_wasm_hash = WasmHash::from([42_u8; 32]);
+    mutate_state(|s| {
+        s.record_new_ledger_suite_request(usdc(), usdc_install_args());
+        s.approve_ledger_suite_request(&usdc(), 0);
+        s.record_failed_ls_install_attempt(&usdc(), "error".to_string(), false, 0);
+    });
+
+    assert_eq!(
+        mutate_state(|s| s.resume_failed_ls_install(&usdc_matic(), None, None, NOW)),
+        Err(ResumeFailedLedgerSuiteInstallError::FailedInstallNotFound)
+    );
+    assert_eq!(
+        mutate_state(|s| s.resume_failed_ls_install(
+            &usdc(),
+            Some(other_ledger_wasm_hash.clone()),
+            None,
+            NOW
+        )),
+        Ok(())
+    );
+
+    assert_eq!(read_state(|s| s.failed_ledger_suite_installs().len()), 0);
+    assert_eq!(
+        read_state(|s| s.twin_ledger_suites_to_be_installed.get(&usdc()).cloned()),
+        Some(InstallLedgerSuiteArgs {
+            ledger_compressed_wasm_hash: other_ledger_wasm_hash,
+            next_attempt_at: NOW,
+            ..usdc_install_args()
+        })
+    );
 }
 
-fn read_index_wasm_hash() -> WasmHash {
-    WasmHash::from(ic_crypto_sha2::Sha256::hash(INDEX_BYTECODE))
+#[test]
+fn should_not_resume_failed_install_once_deposit_refunded() {
+    const NOW: u64 = 1_733_145_560_000_000_000;
+    const DEPOSIT_INDEX: u64 = 7;
+    let creator = usdc_install_args().creator;
+    init_state();
+    mutate_state(|s| {
+        s.record_new_icp_deposit(usdc(), DEPOSIT_INDEX, 99_990_000, creator, 0);
+        s.record_new_ledger_suite_request(usdc(), usdc_install_args());
+        s.approve_ledger_suite_request(&usdc(), 0);
+        s.record_failed_ls_install_attempt(&usdc(), "error".to_string(), false, 0);
+    });
+    let resume = || mutate_state(|s| s.resume_failed_ls_install(&usdc(), None, None, NOW));
+
+    for refund in [
+        IcpRefund::InFlight {
+            created_at_time: NOW,
+            requires_reconciliation: false,
+        },
+        IcpRefund::Refunded {
+            block_index: 42,
+            refunded_at: NOW,
+        },
+    ] {
+        mutate_state(|s| s.record_icp_refund(DEPOSIT_INDEX, refund));
+        assert_eq!(
+            resume(),
+            Err(ResumeFailedLedgerSuiteInstallError::DepositRefunded)
+        );
+        assert_eq!(read_state(|s| s.failed_ledger_suite_installs().len()), 1);
+    }
 }
 
-fn read_ledger_wasm_hash() -> WasmHash {
-    WasmHash::from(ic_crypto_sha2::Sha256::hash(LEDGER_BYTECODE))
+#[test]
+fn should_not_start_refund_of_resumed_install() {
+    const NOW: u64 = 1_733_145_560_000_000_000;
+    const DEPOSIT_INDEX: u64 = 7;
+    let creator = usdc_install_args().creator;
+    init_state();
+    mutate_state(|s| {
+        s.record_new_icp_deposit(usdc(), DEPOSIT_INDEX, 99_990_000, creator, 0);
+        s.record_new_ledger_suite_request(usdc(), usdc_install_args());
+        s.approve_ledger_suite_request(&usdc(), 0);
+        s.record_failed_ls_install_attempt(&usdc(), "error".to_string(), false, 0);
+    });
+
+    assert_eq!(
+        mutate_state(|s| s.resume_failed_ls_install(&usdc(), None, None, NOW)),
+        Ok(())
+    );
+
+    assert!(!mutate_state(|s| s.start_icp_refund(DEPOSIT_INDEX, NOW)));
+    assert_eq!(
+        read_state(|s| s.received_deposit(&usdc()).cloned().unwrap().refund),
+        None
+    );
 }
 
-fn expect_create_canister_returning(
-    runtime: &mut MockCanisterRuntime,
-    expected_controllers: Vec<Principal>,
-    results: Vec<Result<Principal, CallError>>,
-) {
-    assert!(!results.is_empty(), "must return at least one result");
-    let mut create_canister_call_counter = 0_usize;
-    runtime
-        .expect_create_canister()
-        .withf(move |controllers, _cycles| controllers == &expected_controllers)
-        .times(results.len())
-        .returning(move |_controllers, _cycles| {
-            if create_canister_call_counter >= results.len() {
-                panic!("create_canister called too many times!");
-            }
-            let result = results[create_canister_call_counter].clone();
-            create_canister_call_counter += 1;
-            result
-        });
+#[test]
+fn should_discard_failed_install() {
+    init_state();
+    mutate_state(|s| {
+        s.record_new_ledger_suite_request(usdc(), usdc_install_args());
+        s.approve_ledger_suite_request(&usdc(), 0);
+        s.record_ledger_suite_install_step(&usdc(), InstallStep::CreateLedger, 0, None);
+        s.record_failed_ls_install_attempt(&usdc(), "error".to_string(), false, 0);
+    });
+
+    assert_eq!(
+        mutate_state(|s| s.discard_failed_ls_install(&usdc())).map(|args| args.contract),
+        Some(usdc())
+    );
+
+    assert_eq!(mutate_state(|s| s.discard_failed_ls_install(&usdc())), None);
+    assert_eq!(read_state(|s| s.failed_ledger_suite_installs().len()), 0);
+    assert_eq!(read_state(|s| s.ledger_suite_install_progress().count()), 0);
+    assert_eq!(
+        read_state(|s| s.twin_ledger_suites_to_be_installed.get(&usdc()).cloned()),
+        None
+    );
 }
 
-mod mock {
-    use crate::ledger_suite_manager::CallError;
-    use crate::management::CanisterRuntime;
-    use async_trait::async_trait;
-    use candid::CandidType;
-    use candid::Principal;
-    use core::fmt::Debug;
-    use mockall::mock;
-    use serde::de::DeserializeOwned;
-    use std::marker::Send;
+#[test]
+fn should_only_install_approved_ledger_suite_requests() {
+    const NOW: u64 = 1_733_145_560_000_000_000;
+    init_state();
+    mutate_state(|s| s.record_new_ledger_suite_request(usdc(), usdc_install_args()));
 
-    mock! {
-        pub CanisterRuntime{}
+    assert_eq!(
+        read_state(|s| s.twin_ledger_suites_to_be_installed.get(&usdc()).cloned()),
+        None
+    );
+    assert!(!mutate_state(
+        |s| s.approve_ledger_suite_request(&usdc_matic(), NOW)
+    ));
+    assert!(mutate_state(
+        |s| s.approve_ledger_suite_request(&usdc(), NOW)
+    ));
 
-        #[async_trait]
-        impl CanisterRuntime for CanisterRuntime {
+    assert_eq!(
+        read_state(|s| s.ledger_suite_requests_pending_approval().len()),
+        0
+    );
+    assert_eq!(
+        read_state(|s| s.twin_ledger_suites_to_be_installed.get(&usdc()).cloned()),
+        Some(InstallLedgerSuiteArgs {
+            next_attempt_at: NOW,
+            ..usdc_install_args()
+        })
+    );
+}
 
-            fn id(&self) -> Principal;
+#[test]
+fn should_refund_rejected_ledger_suite_request() {
+    const NOW: u64 = 1_733_145_560_000_000_000;
+    let creator = usdc_install_args().creator;
+    init_state();
+    mutate_state(|s| {
+        s.record_new_icp_deposit(usdc(), 7, 99_990_000, creator, 0);
+        s.record_new_ledger_suite_request(usdc(), usdc_install_args());
+    });
 
-            fn time(&self) -> u64;
+    assert_eq!(
+        mutate_state(|s| s.reject_ledger_suite_request(&usdc(), "spam".to_string(), NOW)),
+        Some(usdc_install_args())
+    );
 
-            fn global_timer_set(&self, timestamp: u64);
+    assert_eq!(
+        mutate_state(|s| s.reject_ledger_suite_request(&usdc(), "spam".to_string(), NOW)),
+        None
+    );
+    assert_eq!(
+        read_state(|s| s.rejected_ledger_suite_requests().get(&usdc()).cloned()),
+        Some(RejectedLedgerSuiteRequest {
+            install_args: usdc_install_args(),
+            reason: "spam".to_string(),
+            rejected_at: NOW,
+        })
+    );
+    assert_eq!(
+        read_state(|s| s.received_deposit(&usdc()).cloned().unwrap().refund),
+        Some(IcpRefund::Scheduled)
+    );
+    assert_eq!(
+        read_state(|s| s.twin_ledger_suites_to_be_installed.get(&usdc()).cloned()),
+        None
+    );
+}
 
-            async fn create_canister(
-                &self,
-                controllers: Vec<Principal>,
-                cycles_for_canister_creation: u64,
-            ) -> Result<Principal, CallError>;
+#[tokio::test]
+async fn should_refund_deposit_of_failed_install_once() {
+    const NOW: u64 = 1_733_145_560_000_000_000;
+    const DEPOSIT_INDEX: u64 = 7;
+    const DEPOSIT_AMOUNT: u128 = 99_990_000;
+    let creator = usdc_install_args().creator;
+    init_state();
+    mutate_state(|s| {
+        s.record_new_icp_deposit(usdc(), DEPOSIT_INDEX, DEPOSIT_AMOUNT, creator, 0);
+        s.record_new_ledger_suite_request(usdc(), usdc_install_args());
+        s.approve_ledger_suite_request(&usdc(), 0);
+    });
+    assert_eq!(read_state(|s| s.icp_reserved_for_refunds()), DEPOSIT_AMOUNT);
+    mutate_state(|s| s.record_failed_ls_install_attempt(&usdc(), "error".to_string(), false, 0));
+    let deposit = || read_state(|s| s.received_deposit(&usdc()).cloned().unwrap());
+    assert_eq!(deposit().refund, Some(IcpRefund::Scheduled));
 
-            async fn stop_canister(&self, canister_id: Principal) -> Result<(), CallError>;
+    let mut runtime = MockCmcRunTime::new();
+    runtime
+        .expect_icp_balance()
+        .times(1)
+        .return_const(Ok(DEPOSIT_AMOUNT as u64 - 1));
+    runtime.expect_transfer_icp().never();
+    assert_eq!(
+        refund_icp_deposit(&deposit(), &runtime).await,
+        Err(RefundError::InsufficientIcpBalance {
+            required: DEPOSIT_AMOUNT,
+            available: DEPOSIT_AMOUNT as u64 - 1
+        })
+    );
+    assert_eq!(deposit().refund, Some(IcpRefund::Scheduled));
 
-            async fn start_canister(&self, canister_id: Principal) -> Result<(), CallError>;
+    runtime.checkpoint();
+    let expected_error = CallError {
+        method: "icrc1_transfer".to_string(),
+        reason: Reason::OutOfCycles,
+    };
+    runtime
+        .expect_icp_balance()
+        .times(1)
+        .return_const(Ok(DEPOSIT_AMOUNT as u64));
+    runtime.expect_time().return_const(NOW);
+    runtime
+        .expect_transfer_icp()
+        .withf(move |to, icp_amount, memo, created_at_time| {
+            to == &creator
+                && *icp_amount == DEPOSIT_AMOUNT as u64 - 10_000
+                && *memo == DEPOSIT_INDEX
+                && *created_at_time == NOW
+        })
+        .times(1)
+        .return_const(Err(expected_error.clone()));
+    assert_eq!(
+        refund_icp_deposit(&deposit(), &runtime).await,
+        Err(RefundError::CallError(expected_error))
+    );
+    assert_eq!(
+        deposit().refund,
+        Some(IcpRefund::InFlight {
+            created_at_time: NOW,
+            requires_reconciliation: false,
+        })
+    );
+
+    runtime.checkpoint();
+    runtime.expect_icp_balance().never();
+    runtime.expect_time().return_const(NOW + 1);
+    runtime
+        .expect_transfer_icp()
+        .withf(|_to, _icp_amount, _memo, created_at_time| *created_at_time == NOW)
+        .times(1)
+        .return_const(Ok(Err(TransferError::Duplicate {
+            duplicate_of: Nat::from(42_u64),
+        })));
+    assert_eq!(refund_icp_deposit(&deposit(), &runtime).await, Ok(42));
+
+    assert_eq!(
+        deposit().refund,
+        Some(IcpRefund::Refunded {
+            block_index: 42,
+            refunded_at: NOW + 1
+        })
+    );
+    assert_eq!(read_state(|s| s.icp_refunds_to_execute()), vec![]);
+    assert_eq!(read_state(|s| s.icp_reserved_for_refunds()), 0);
+}
+
+#[tokio::test]
+async fn should_not_resend_refund_once_ledger_no_longer_deduplicates_it() {
+    const NOW: u64 = 1_733_145_560_000_000_000;
+    const DEPOSIT_INDEX: u64 = 7;
+    const DEPOSIT_AMOUNT: u128 = 99_990_000;
+    let creator = usdc_install_args().creator;
+    init_state();
+    mutate_state(|s| {
+        s.record_new_icp_deposit(usdc(), DEPOSIT_INDEX, DEPOSIT_AMOUNT, creator, 0);
+        s.record_new_ledger_suite_request(usdc(), usdc_install_args());
+        s.approve_ledger_suite_request(&usdc(), 0);
+        s.record_failed_ls_install_attempt(&usdc(), "error".to_string(), false, 0);
+        s.record_icp_refund(
+            DEPOSIT_INDEX,
+            IcpRefund::InFlight {
+                created_at_time: NOW,
+                requires_reconciliation: false,
+            },
+        );
+    });
+    let deposit = || read_state(|s| s.received_deposit(&usdc()).cloned().unwrap());
+
+    let mut runtime = MockCmcRunTime::new();
+    runtime.expect_icp_balance().never();
+    runtime
+        .expect_time()
+        .return_const(NOW + 25 * 60 * 60 * 1_000_000_000);
+    runtime
+        .expect_transfer_icp()
+        .withf(|_to, _icp_amount, _memo, created_at_time| *created_at_time == NOW)
+        .times(1)
+        .return_const(Ok(Err(TransferError::TooOld)));
+    assert_eq!(
+        refund_icp_deposit(&deposit(), &runtime).await,
+        Err(RefundError::RequiresReconciliation {
+            created_at_time: NOW,
+            error: TransferError::TooOld
+        })
+    );
+
+    assert_eq!(
+        deposit().refund,
+        Some(IcpRefund::InFlight {
+            created_at_time: NOW,
+            requires_reconciliation: true,
+        })
+    );
+    assert_eq!(read_state(|s| s.icp_refunds_to_execute()), vec![]);
+    assert_eq!(
+        read_state(|s| s.icp_refunds_to_reconcile()),
+        vec![deposit()]
+    );
+    assert_eq!(read_state(|s| s.icp_reserved_for_refunds()), DEPOSIT_AMOUNT);
+
+    assert!(!mutate_state(|s| s.reconcile_icp_refund(
+        DEPOSIT_INDEX + 1,
+        Some(42),
+        NOW
+    )));
+    assert!(mutate_state(|s| s.reconcile_icp_refund(
+        DEPOSIT_INDEX,
+        Some(42),
+        NOW
+    )));
+    assert_eq!(
+        deposit().refund,
+        Some(IcpRefund::Refunded {
+            block_index: 42,
+            refunded_at: NOW
+        })
+    );
+    assert_eq!(read_state(|s| s.icp_refunds_to_reconcile()), vec![]);
+    assert_eq!(read_state(|s| s.icp_reserved_for_refunds()), 0);
+}
+
+#[tokio::test]
+async fn should_install_large_wasm_in_chunks() {
+    let wasm = Wasm::<Ledger>::from(vec![42_u8; CHUNKED_INSTALL_THRESHOLD + 1]);
+    let expected_wasm_hash = wasm.hash().as_ref().to_vec();
+    let expected_chunk_hashes: Vec<Vec<u8>> = vec![42_u8; CHUNKED_INSTALL_THRESHOLD + 1]
+        .chunks(WASM_CHUNK_SIZE)
+        .map(|chunk| ic_crypto_sha2::Sha256::hash(chunk).to_vec())
+        .collect();
+    assert_eq!(expected_chunk_hashes.len(), 2);
+    let mut runtime = MockCanisterRuntime::new();
+
+    runtime.expect_install_code().never();
+    runtime
+        .expect_clear_chunk_store()
+        .withf(|canister_id| canister_id == &LEDGER_PRINCIPAL)
+        .times(2)
+        .return_const(Ok(()));
+    runtime
+        .expect_upload_chunk()
+        .withf(|canister_id, chunk| {
+            canister_id == &LEDGER_PRINCIPAL && chunk.len() <= WASM_CHUNK_SIZE
+        })
+        .times(2)
+        .returning(|_canister_id, chunk| Ok(ic_crypto_sha2::Sha256::hash(&chunk).to_vec()));
+    runtime
+        .expect_install_chunked_code()
+        .withf(
+            move |canister_id, mode, chunk_hashes, wasm_module_hash, arg| {
+                canister_id == &LEDGER_PRINCIPAL
+                    && mode == &InstallMode::Install
+                    && chunk_hashes == &expected_chunk_hashes
+                    && wasm_module_hash == &expected_wasm_hash
+                    && arg == &vec![1_u8, 2, 3]
+            },
+        )
+        .times(1)
+        .return_const(Ok(()));
+
+    assert_eq!(
+        install_wasm(
+            LEDGER_PRINCIPAL,
+            InstallMode::Install,
+            wasm,
+            vec![1_u8, 2, 3],
+            &runtime
+        )
+        .await,
+        Ok(())
+    );
+}
+
+#[tokio::test]
+async fn should_upgrade_index_then_ledger() {
+    init_state();
+    let upgrade = install_outdated_usdc_ledger_suite();
+    let mut runtime = MockCanisterRuntime::new();
+    expect_healthy_index_probes(&mut runtime);
+    expect_healthy_ledger_probes(&mut runtime);
+    expect_take_canister_snapshot(&mut runtime, INDEX_PRINCIPAL, None);
+    expect_take_canister_snapshot(&mut runtime, LEDGER_PRINCIPAL, None);
+
+    runtime
+        .expect_stop_canister()
+        .withf(|canister_id| canister_id == &INDEX_PRINCIPAL)
+        .times(1)
+        .return_const(Ok(()));
+    runtime
+        .expect_stop_canister()
+        .withf(|canister_id| canister_id == &LEDGER_PRINCIPAL)
+        .times(1)
+        .return_const(Ok(()));
+    runtime
+        .expect_upgrade_canister()
+        .times(2)
+        .return_const(Ok(()));
+    runtime
+        .expect_start_canister()
+        .times(2)
+        .return_const(Ok(()));
+
+    assert_eq!(upgrade.execute(&usdc(), &runtime).await, Ok(()));
+
+    assert_eq!(
+        read_state(|s| s.ledger_suite_upgrade(&usdc()).cloned()),
+        None
+    );
+    assert_eq!(
+        read_state(|s| s.managed_canisters(&usdc()).cloned()),
+        Some(Canisters {
+            ledger: Some(LedgerCanister::new(ManagedCanisterStatus::Installed {
+                canister_id: LEDGER_PRINCIPAL,
+                installed_wasm_hash: read_ledger_wasm_hash(),
+            })),
+            index: Some(IndexCanister::new(ManagedCanisterStatus::Installed {
+                canister_id: INDEX_PRINCIPAL,
+                installed_wasm_hash: read_index_wasm_hash(),
+            })),
+            archives: vec![],
+            metadata: usdc_metadata(),
+            snapshots: btreemap! {
+                CanisterKind::Ledger => CanisterSnapshot {
+                    snapshot_id: LEDGER_PRINCIPAL.as_slice().to_vec(),
+                    wasm_hash: WasmHash::from([1_u8; 32]),
+                    taken_at: SNAPSHOT_TIMESTAMP,
+                },
+                CanisterKind::Index => CanisterSnapshot {
+                    snapshot_id: INDEX_PRINCIPAL.as_slice().to_vec(),
+                    wasm_hash: WasmHash::from([2_u8; 32]),
+                    taken_at: SNAPSHOT_TIMESTAMP,
+                },
+            },
+            archive_profile: None,
+            placement: None,
+        })
+    );
+}
+
+#[tokio::test]
+async fn should_reapply_archive_profile_when_upgrading_ledger() {
+    init_state();
+    let archive_profile = ArchiveProfile {
+        trigger_threshold: 20_000,
+        num_blocks_to_archive: 10_000,
+        node_max_memory_size_bytes: 1_073_741_824,
+        max_transactions_per_response: Some(5_000),
+    };
+    let upgrade = UpgradeLedgerSuite::builder(usdc())
+        .ledger_wasm_hash(read_ledger_wasm_hash())
+        .build();
+    mutate_state(|s| {
+        s.record_new_erc20_token(usdc(), usdc_metadata());
+        s.record_created_canister::<Ledger>(&usdc(), LEDGER_PRINCIPAL);
+        s.record_installed_canister::<Ledger>(&usdc(), WasmHash::from([1_u8; 32]));
+        assert!(s.set_token_archive_profile(&usdc(), archive_profile.clone()));
+        s.record_ledger_suite_upgrade(usdc(), upgrade.clone());
+    });
+    let mut runtime = MockCanisterRuntime::new();
+    expect_healthy_ledger_probes(&mut runtime);
+    expect_take_canister_snapshot(&mut runtime, LEDGER_PRINCIPAL, None);
+    runtime.expect_stop_canister().times(1).return_const(Ok(()));
+    let expected_upgrade_arg = Encode!(&LedgerArgument::Upgrade(Some(LedgerUpgradeArgs {
+        change_archive_options: Some(ChangeArchiveOptions {
+            trigger_threshold: Some(20_000),
+            num_blocks_to_archive: Some(10_000),
+            node_max_memory_size_bytes: Some(1_073_741_824),
+            max_transactions_per_response: Some(5_000),
+            ..Default::default()
+        }),
+        ..Default::default()
+    })))
+    .unwrap();
+    runtime
+        .expect_upgrade_canister()
+        .withf(move |canister_id, _wasm, arg| {
+            canister_id == &LEDGER_PRINCIPAL && arg == &expected_upgrade_arg
+        })
+        .times(1)
+        .return_const(Ok(()));
+    runtime
+        .expect_start_canister()
+        .times(1)
+        .return_const(Ok(()));
+
+    assert_eq!(upgrade.execute(&usdc(), &runtime).await, Ok(()));
+    assert_eq!(
+        read_state(|s| s
+            .managed_canisters(&usdc())
+            .unwrap()
+            .archive_profile
+            .clone()),
+        Some(archive_profile)
+    );
+}
+
+#[tokio::test]
+async fn should_upgrade_ledger_to_large_wasm_in_chunks() {
+    const NOW: u64 = 1_733_145_560_000_000_000;
+    init_state();
+    let large_ledger_wasm = Wasm::<Ledger>::from(vec![42_u8; CHUNKED_INSTALL_THRESHOLD + 1]);
+    let large_ledger_wasm_hash = large_ledger_wasm.hash().clone();
+    mutate_wasm_store(|w| wasm_store_try_insert(w, NOW, large_ledger_wasm)).unwrap();
+    let upgrade = UpgradeLedgerSuite::builder(usdc())
+        .ledger_wasm_hash(large_ledger_wasm_hash.clone())
+        .build();
+    mutate_state(|s| {
+        s.record_new_erc20_token(usdc(), usdc_metadata());
+        s.record_created_canister::<Ledger>(&usdc(), LEDGER_PRINCIPAL);
+        s.record_installed_canister::<Ledger>(&usdc(), read_ledger_wasm_hash());
+        s.record_ledger_suite_upgrade(usdc(), upgrade.clone());
+    });
+    let mut runtime = MockCanisterRuntime::new();
+    expect_healthy_ledger_probes(&mut runtime);
+    expect_take_canister_snapshot(&mut runtime, LEDGER_PRINCIPAL, None);
+    runtime.expect_stop_canister().times(1).return_const(Ok(()));
+    runtime.expect_upgrade_canister().never();
+    runtime
+        .expect_clear_chunk_store()
+        .withf(|canister_id| canister_id == &LEDGER_PRINCIPAL)
+        .times(2)
+        .return_const(Ok(()));
+    runtime
+        .expect_upload_chunk()
+        .times(2)
+        .returning(|_canister_id, chunk| Ok(ic_crypto_sha2::Sha256::hash(&chunk).to_vec()));
+    let expected_wasm_hash = large_ledger_wasm_hash.as_ref().to_vec();
+    runtime
+        .expect_install_chunked_code()
+        .withf(
+            move |canister_id, mode, _chunk_hashes, wasm_module_hash, _arg| {
+                canister_id == &LEDGER_PRINCIPAL
+                    && mode == &InstallMode::Upgrade
+                    && wasm_module_hash == &expected_wasm_hash
+            },
+        )
+        .times(1)
+        .return_const(Ok(()));
+    runtime
+        .expect_start_canister()
+        .times(1)
+        .return_const(Ok(()));
+
+    assert_eq!(upgrade.execute(&usdc(), &runtime).await, Ok(()));
+    assert_eq!(
+        read_state(|s| s
+            .managed_canisters(&usdc())
+            .and_then(|c| c.ledger.as_ref())
+            .and_then(|c| c.installed_wasm_hash().cloned())),
+        Some(large_ledger_wasm_hash)
+    );
+}
+
+#[tokio::test]
+async fn should_resume_upgrade_at_failed_subtask() {
+    init_state();
+    let upgrade = install_outdated_usdc_ledger_suite();
+    let mut runtime = MockCanisterRuntime::new();
+    expect_healthy_index_probes(&mut runtime);
+    expect_healthy_ledger_probes(&mut runtime);
+    expect_take_canister_snapshot(&mut runtime, INDEX_PRINCIPAL, None);
+
+    let expected_error = CallError {
+        method: "stop_canister".to_string(),
+        reason: Reason::TransientInternalError("overloaded".to_string()),
+    };
+    runtime
+        .expect_stop_canister()
+        .withf(|canister_id| canister_id == &INDEX_PRINCIPAL)
+        .times(1)
+        .return_const(Ok(()));
+    runtime
+        .expect_stop_canister()
+        .withf(|canister_id| canister_id == &LEDGER_PRINCIPAL)
+        .times(1)
+        .return_const(Err(expected_error.clone()));
+    runtime
+        .expect_upgrade_canister()
+        .times(1)
+        .return_const(Ok(()));
+    runtime
+        .expect_start_canister()
+        .times(1)
+        .return_const(Ok(()));
+
+    assert_eq!(
+        upgrade.execute(&usdc(), &runtime).await,
+        Err(UpgradeLedgerSuiteError::StopCanisterError(expected_error))
+    );
+    let upgrade = read_state(|s| s.ledger_suite_upgrade(&usdc()).cloned())
+        .expect("BUG: upgrade should be resumed");
+    assert_eq!(upgrade.next_subtask_index(), 1);
+
+    runtime.checkpoint();
+    expect_healthy_ledger_probes(&mut runtime);
+    expect_take_canister_snapshot(&mut runtime, LEDGER_PRINCIPAL, None);
+    runtime
+        .expect_stop_canister()
+        .withf(|canister_id| canister_id == &LEDGER_PRINCIPAL)
+        .times(1)
+        .return_const(Ok(()));
+    runtime
+        .expect_upgrade_canister()
+        .withf(|canister_id, _wasm, _args| canister_id == &LEDGER_PRINCIPAL)
+        .times(1)
+        .return_const(Ok(()));
+    runtime
+        .expect_start_canister()
+        .times(1)
+        .return_const(Ok(()));
+
+    assert_eq!(upgrade.execute(&usdc(), &runtime).await, Ok(()));
+    assert_eq!(
+        read_state(|s| s.ledger_suite_upgrade(&usdc()).cloned()),
+        None
+    );
+}
+
+#[tokio::test]
+async fn should_keep_snapshot_when_retrying_upgrade() {
+    init_state();
+    let upgrade = UpgradeLedgerSuite::builder(usdc())
+        .ledger_wasm_hash(read_ledger_wasm_hash())
+        .build();
+    mutate_state(|s| {
+        s.record_new_erc20_token(usdc(), usdc_metadata());
+        s.record_created_canister::<Ledger>(&usdc(), LEDGER_PRINCIPAL);
+        s.record_installed_canister::<Ledger>(&usdc(), WasmHash::from([1_u8; 32]));
+        s.record_ledger_suite_upgrade(usdc(), upgrade.clone());
+    });
+    let expected_error = CallError {
+        method: "install_code".to_string(),
+        reason: Reason::TransientInternalError("overloaded".to_string()),
+    };
+    let mut runtime = MockCanisterRuntime::new();
+    expect_healthy_ledger_probes(&mut runtime);
+    expect_take_canister_snapshot(&mut runtime, LEDGER_PRINCIPAL, None);
+    runtime.expect_stop_canister().times(1).return_const(Ok(()));
+    runtime
+        .expect_upgrade_canister()
+        .times(1)
+        .return_const(Err(expected_error.clone()));
+
+    assert_eq!(
+        upgrade.execute(&usdc(), &runtime).await,
+        Err(UpgradeLedgerSuiteError::UpgradeCanisterError(
+            expected_error
+        ))
+    );
+
+    runtime.checkpoint();
+    let upgrade = read_state(|s| s.ledger_suite_upgrade(&usdc()).cloned())
+        .expect("BUG: upgrade should be retried");
+    assert!(upgrade.next_subtask_snapshot_taken());
+    expect_healthy_ledger_probes(&mut runtime);
+    runtime.expect_take_canister_snapshot().never();
+    runtime.expect_stop_canister().times(1).return_const(Ok(()));
+    runtime
+        .expect_upgrade_canister()
+        .times(1)
+        .return_const(Ok(()));
+    runtime
+        .expect_start_canister()
+        .times(1)
+        .return_const(Ok(()));
+
+    assert_eq!(upgrade.execute(&usdc(), &runtime).await, Ok(()));
+    assert_eq!(
+        read_state(|s| s.managed_canisters(&usdc()).unwrap().snapshots.clone()),
+        btreemap! {
+            CanisterKind::Ledger => CanisterSnapshot {
+                snapshot_id: LEDGER_PRINCIPAL.as_slice().to_vec(),
+                wasm_hash: WasmHash::from([1_u8; 32]),
+                taken_at: SNAPSHOT_TIMESTAMP,
+            },
+        }
+    );
+}
+
+#[tokio::test]
+async fn should_roll_back_ledger_failing_health_check_after_upgrade() {
+    const NOW: u64 = 1_733_145_560_000_000_000;
+    init_state();
+    let new_ledger_wasm = Wasm::<Ledger>::from(b"new ledger".to_vec());
+    let new_ledger_wasm_hash = new_ledger_wasm.hash().clone();
+    mutate_wasm_store(|w| wasm_store_try_insert(w, NOW, new_ledger_wasm)).unwrap();
+    let upgrade = UpgradeLedgerSuite::builder(usdc())
+        .ledger_wasm_hash(new_ledger_wasm_hash.clone())
+        .build();
+    mutate_state(|s| {
+        s.record_new_erc20_token(usdc(), usdc_metadata());
+        s.record_created_canister::<Ledger>(&usdc(), LEDGER_PRINCIPAL);
+        s.record_installed_canister::<Ledger>(&usdc(), read_ledger_wasm_hash());
+        assert!(s.set_token_archive_profile(
+            &usdc(),
+            ArchiveProfile {
+                trigger_threshold: 20_000,
+                num_blocks_to_archive: 10_000,
+                node_max_memory_size_bytes: 1_073_741_824,
+                max_transactions_per_response: None,
+            }
+        ));
+        s.record_ledger_suite_upgrade(usdc(), upgrade.clone());
+    });
+    // the rollback re-applies the archive profile like the upgrade
+    let expected_upgrade_arg = Encode!(&LedgerArgument::Upgrade(Some(LedgerUpgradeArgs {
+        change_archive_options: Some(ChangeArchiveOptions {
+            trigger_threshold: Some(20_000),
+            num_blocks_to_archive: Some(10_000),
+            node_max_memory_size_bytes: Some(1_073_741_824),
+            ..Default::default()
+        }),
+        ..Default::default()
+    })))
+    .unwrap();
+    let mut runtime = MockCanisterRuntime::new();
+    runtime.expect_time().return_const(NOW);
+    expect_ledger_probes(&mut runtime, vec![Nat::from(42_u8), Nat::from(0_u8)]);
+    expect_take_canister_snapshot(&mut runtime, LEDGER_PRINCIPAL, None);
+    runtime
+        .expect_stop_canister()
+        .withf(|canister_id| canister_id == &LEDGER_PRINCIPAL)
+        .times(2)
+        .return_const(Ok(()));
+    runtime
+        .expect_upgrade_canister()
+        .withf({
+            let expected_upgrade_arg = expected_upgrade_arg.clone();
+            move |canister_id, wasm, arg| {
+                canister_id == &LEDGER_PRINCIPAL
+                    && wasm.as_slice() == b"new ledger"
+                    && arg == &expected_upgrade_arg
+            }
+        })
+        .times(1)
+        .return_const(Ok(()));
+    runtime
+        .expect_upgrade_canister()
+        .withf(move |canister_id, wasm, arg| {
+            canister_id == &LEDGER_PRINCIPAL
+                && wasm.as_slice() == LEDGER_BYTECODE
+                && arg == &expected_upgrade_arg
+        })
+        .times(1)
+        .return_const(Ok(()));
+    runtime
+        .expect_start_canister()
+        .withf(|canister_id| canister_id == &LEDGER_PRINCIPAL)
+        .times(2)
+        .return_const(Ok(()));
+
+    let expected_reason = "total supply dropped from 42 to 0".to_string();
+    assert_eq!(
+        upgrade.execute(&usdc(), &runtime).await,
+        Err(UpgradeLedgerSuiteError::HealthCheckFailed {
+            canister_id: LEDGER_PRINCIPAL,
+            reason: expected_reason.clone(),
+        })
+    );
+    assert_eq!(
+        read_state(|s| s
+            .managed_canisters(&usdc())
+            .and_then(|c| c.ledger.as_ref())
+            .and_then(|c| c.installed_wasm_hash().cloned())),
+        Some(read_ledger_wasm_hash())
+    );
+    assert_eq!(
+        read_state(|s| s
+            .upgrade_rollbacks()
+            .map(|(token, rollbacks)| (token.clone(), rollbacks.clone()))
+            .collect::<Vec<_>>()),
+        vec![(
+            usdc(),
+            vec![UpgradeRollback {
+                canister_id: LEDGER_PRINCIPAL,
+                kind: CanisterKind::Ledger,
+                failed_wasm_hash: new_ledger_wasm_hash,
+                restored_wasm_hash: read_ledger_wasm_hash(),
+                reason: expected_reason,
+                rolled_back_at: NOW,
+            }]
+        )]
+    );
+}
+
+#[tokio::test]
+async fn should_check_retried_upgrade_against_health_before_first_attempt() {
+    const NOW: u64 = 1_733_145_560_000_000_000;
+    init_state();
+    let new_ledger_wasm = Wasm::<Ledger>::from(b"new ledger".to_vec());
+    let new_ledger_wasm_hash = new_ledger_wasm.hash().clone();
+    mutate_wasm_store(|w| wasm_store_try_insert(w, NOW, new_ledger_wasm)).unwrap();
+    let upgrade = UpgradeLedgerSuite::builder(usdc())
+        .ledger_wasm_hash(new_ledger_wasm_hash.clone())
+        .build();
+    mutate_state(|s| {
+        s.record_new_erc20_token(usdc(), usdc_metadata());
+        s.record_created_canister::<Ledger>(&usdc(), LEDGER_PRINCIPAL);
+        s.record_installed_canister::<Ledger>(&usdc(), read_ledger_wasm_hash());
+        s.record_ledger_suite_upgrade(usdc(), upgrade.clone());
+    });
+    let expected_error = CallError {
+        method: "start_canister".to_string(),
+        reason: Reason::TransientInternalError("overloaded".to_string()),
+    };
+    let mut runtime = MockCanisterRuntime::new();
+    expect_ledger_probes(&mut runtime, vec![Nat::from(42_u8)]);
+    expect_take_canister_snapshot(&mut runtime, LEDGER_PRINCIPAL, None);
+    runtime.expect_stop_canister().times(1).return_const(Ok(()));
+    runtime
+        .expect_upgrade_canister()
+        .times(1)
+        .return_const(Ok(()));
+    runtime
+        .expect_start_canister()
+        .times(1)
+        .return_const(Err(expected_error.clone()));
+
+    assert_eq!(
+        upgrade.execute(&usdc(), &runtime).await,
+        Err(UpgradeLedgerSuiteError::StartCanisterError(expected_error))
+    );
+    assert_matches!(
+        read_state(|s| s.upgrade_health_baseline(&usdc())),
+        Some(HealthBaseline::Ledger(before)) if before.total_supply == Nat::from(42_u8)
+    );
+
+    runtime.checkpoint();
+    let upgrade = read_state(|s| s.ledger_suite_upgrade(&usdc()).cloned())
+        .expect("BUG: upgrade should be retried");
+    runtime.expect_time().return_const(NOW);
+    // only the upgraded ledger is probed
+    expect_ledger_probes(&mut runtime, vec![Nat::from(0_u8)]);
+    runtime.expect_take_canister_snapshot().never();
+    runtime.expect_stop_canister().times(2).return_const(Ok(()));
+    runtime
+        .expect_upgrade_canister()
+        .withf(|_canister_id, wasm, _args| wasm.as_slice() == b"new ledger")
+        .times(1)
+        .return_const(Ok(()));
+    runtime
+        .expect_upgrade_canister()
+        .withf(|_canister_id, wasm, _args| wasm.as_slice() == LEDGER_BYTECODE)
+        .times(1)
+        .return_const(Ok(()));
+    runtime
+        .expect_start_canister()
+        .times(2)
+        .return_const(Ok(()));
+
+    assert_eq!(
+        upgrade.execute(&usdc(), &runtime).await,
+        Err(UpgradeLedgerSuiteError::HealthCheckFailed {
+            canister_id: LEDGER_PRINCIPAL,
+            reason: "total supply dropped from 42 to 0".to_string(),
+        })
+    );
+}
+
+#[tokio::test]
+async fn should_not_stop_canister_failing_health_probe_before_upgrade() {
+    init_state();
+    let upgrade = UpgradeLedgerSuite::builder(usdc())
+        .ledger_wasm_hash(read_ledger_wasm_hash())
+        .build();
+    mutate_state(|s| {
+        s.record_new_erc20_token(usdc(), usdc_metadata());
+        s.record_created_canister::<Ledger>(&usdc(), LEDGER_PRINCIPAL);
+        s.record_installed_canister::<Ledger>(&usdc(), WasmHash::from([1_u8; 32]));
+        s.record_ledger_suite_upgrade(usdc(), upgrade.clone());
+    });
+    let expected_error = CallError {
+        method: "icrc1_total_supply".to_string(),
+        reason: Reason::CanisterError("stopped".to_string()),
+    };
+    let mut runtime = MockCanisterRuntime::new();
+    runtime
+        .expect_call_canister::<(), Nat>()
+        .times(1)
+        .return_const(Err(expected_error.clone()));
+    runtime.expect_stop_canister().never();
+    runtime.expect_upgrade_canister().never();
+
+    assert_eq!(
+        upgrade.execute(&usdc(), &runtime).await,
+        Err(UpgradeLedgerSuiteError::HealthProbeError(expected_error))
+    );
+    assert_eq!(read_state(|s| s.upgrade_health_baseline(&usdc())), None);
+}
+
+#[tokio::test]
+async fn should_load_canister_snapshot() {
+    init_state();
+    mutate_state(|s| {
+        s.record_new_erc20_token(usdc(), usdc_metadata());
+        s.record_created_canister::<Ledger>(&usdc(), LEDGER_PRINCIPAL);
+        s.record_installed_canister::<Ledger>(&usdc(), read_ledger_wasm_hash());
+        s.record_canister_snapshot(
+            &usdc(),
+            CanisterKind::Ledger,
+            CanisterSnapshot {
+                snapshot_id: vec![42_u8; 8],
+                wasm_hash: WasmHash::from([1_u8; 32]),
+                taken_at: SNAPSHOT_TIMESTAMP,
+            },
+        );
+    });
+    let mut runtime = MockCanisterRuntime::new();
+
+    assert_eq!(
+        load_canister_snapshot(&usdc(), CanisterKind::Index, &runtime).await,
+        Err(LoadCanisterSnapshotError::SnapshotNotFound {
+            token_id: usdc(),
+            kind: CanisterKind::Index
+        })
+    );
+
+    runtime
+        .expect_stop_canister()
+        .withf(|canister_id| canister_id == &LEDGER_PRINCIPAL)
+        .times(1)
+        .return_const(Ok(()));
+    runtime
+        .expect_load_canister_snapshot()
+        .withf(|canister_id, snapshot_id| {
+            canister_id == &LEDGER_PRINCIPAL && snapshot_id == &vec![42_u8; 8]
+        })
+        .times(1)
+        .return_const(Ok(()));
+    runtime
+        .expect_start_canister()
+        .withf(|canister_id| canister_id == &LEDGER_PRINCIPAL)
+        .times(1)
+        .return_const(Ok(()));
+
+    assert_eq!(
+        load_canister_snapshot(&usdc(), CanisterKind::Ledger, &runtime).await,
+        Ok(())
+    );
+    assert_eq!(
+        read_state(|s| s
+            .managed_canisters(&usdc())
+            .and_then(|c| c.ledger.as_ref())
+            .and_then(|c| c.installed_wasm_hash().cloned())),
+        Some(WasmHash::from([1_u8; 32]))
+    );
+}
+
+#[tokio::test]
+async fn should_restart_canister_when_loading_snapshot_fails() {
+    init_state();
+    mutate_state(|s| {
+        s.record_new_erc20_token(usdc(), usdc_metadata());
+        s.record_created_canister::<Ledger>(&usdc(), LEDGER_PRINCIPAL);
+        s.record_installed_canister::<Ledger>(&usdc(), read_ledger_wasm_hash());
+        s.record_canister_snapshot(
+            &usdc(),
+            CanisterKind::Ledger,
+            CanisterSnapshot {
+                snapshot_id: vec![42_u8; 8],
+                wasm_hash: WasmHash::from([1_u8; 32]),
+                taken_at: SNAPSHOT_TIMESTAMP,
+            },
+        );
+    });
+    let expected_error = CallError {
+        method: "load_canister_snapshot".to_string(),
+        reason: Reason::Rejected("snapshot not found".to_string()),
+    };
+    let mut runtime = MockCanisterRuntime::new();
+    runtime.expect_stop_canister().times(1).return_const(Ok(()));
+    runtime
+        .expect_load_canister_snapshot()
+        .times(1)
+        .return_const(Err(expected_error.clone()));
+    runtime
+        .expect_start_canister()
+        .withf(|canister_id| canister_id == &LEDGER_PRINCIPAL)
+        .times(1)
+        .return_const(Ok(()));
+
+    assert_eq!(
+        load_canister_snapshot(&usdc(), CanisterKind::Ledger, &runtime).await,
+        Err(LoadCanisterSnapshotError::LoadCanisterSnapshotError(
+            expected_error
+        ))
+    );
+    assert_eq!(
+        read_state(|s| s
+            .managed_canisters(&usdc())
+            .and_then(|c| c.ledger.as_ref())
+            .and_then(|c| c.installed_wasm_hash().cloned())),
+        Some(read_ledger_wasm_hash())
+    );
+}
+
+#[tokio::test]
+async fn should_report_canisters_running_unexpected_module() {
+    const NOW: u64 = 1_733_145_560_000_000_000;
+    const UPGRADED_ARCHIVE_PRINCIPAL: Principal = Principal::from_slice(&[4_u8; 29]);
+    const SPAWNED_ARCHIVE_PRINCIPAL: Principal = Principal::from_slice(&[5_u8; 29]);
+    const USDT_LEDGER_PRINCIPAL: Principal = Principal::from_slice(&[6_u8; 29]);
+    init_state();
+    mutate_state(|s| {
+        s.record_new_erc20_token(usdc(), usdc_metadata());
+        s.record_created_canister::<Ledger>(&usdc(), LEDGER_PRINCIPAL);
+        s.record_installed_canister::<Ledger>(&usdc(), read_ledger_wasm_hash());
+        s.record_created_canister::<Index>(&usdc(), INDEX_PRINCIPAL);
+        s.record_installed_canister::<Index>(&usdc(), read_index_wasm_hash());
+        s.record_archives(
+            &usdc(),
+            vec![UPGRADED_ARCHIVE_PRINCIPAL, SPAWNED_ARCHIVE_PRINCIPAL],
+        );
+        s.record_upgraded_archive(UPGRADED_ARCHIVE_PRINCIPAL, WasmHash::from([3_u8; 32]));
+        // ledger suite being upgraded is not checked
+        s.record_new_erc20_token(usdt(), usdt_metadata());
+        s.record_created_canister::<Ledger>(&usdt(), USDT_LEDGER_PRINCIPAL);
+        s.record_installed_canister::<Ledger>(&usdt(), WasmHash::from([1_u8; 32]));
+        s.record_ledger_suite_upgrade(
+            usdt(),
+            UpgradeLedgerSuite::builder(usdt())
+                .ledger_wasm_hash(read_ledger_wasm_hash())
+                .build(),
+        );
+    });
+    let mut runtime = MockCanisterRuntime::new();
+    runtime.expect_time().return_const(NOW);
+    runtime
+        .expect_canister_module_hash()
+        .withf(|canister_id| canister_id == &LEDGER_PRINCIPAL)
+        .times(1)
+        .return_const(Ok(Some(read_ledger_wasm_hash().as_ref().to_vec())));
+    // the IC may report the hash of the uncompressed module
+    let uncompressed_index_hash =
+        read_wasm_store(|w| wasm_store_try_get_uncompressed_hash(w, &read_index_wasm_hash()))
+            .unwrap();
+    assert_ne!(uncompressed_index_hash, read_index_wasm_hash());
+    runtime
+        .expect_canister_module_hash()
+        .withf(|canister_id| canister_id == &INDEX_PRINCIPAL)
+        .times(1)
+        .return_const(Ok(Some(uncompressed_index_hash.as_ref().to_vec())));
+    runtime
+        .expect_canister_module_hash()
+        .withf(|canister_id| canister_id == &UPGRADED_ARCHIVE_PRINCIPAL)
+        .times(1)
+        .return_const(Ok(Some(vec![42_u8; 32])));
+
+    assert_eq!(check_module_hash_drift(&runtime).await, Ok(()));
+
+    assert_eq!(
+        read_state(|s| s
+            .module_hash_drift_reports()
+            .map(|(token, report)| (token.clone(), report.clone()))
+            .collect::<Vec<_>>()),
+        vec![(
+            usdc(),
+            ModuleHashDriftReport {
+                checked_at: NOW,
+                drifted_canisters: vec![DriftedCanister {
+                    canister_id: UPGRADED_ARCHIVE_PRINCIPAL,
+                    kind: CanisterKind::Archive,
+                    expected_wasm_hash: WasmHash::from([3_u8; 32]),
+                    module_hash: Some(WasmHash::from([42_u8; 32])),
+                }],
+            }
+        )]
+    );
+}
+
+#[tokio::test]
+async fn should_push_changed_canister_settings() {
+    const ARCHIVE_PRINCIPAL: Principal = Principal::from_slice(&[4_u8; 29]);
+    init_state();
+    let old_ledger_settings = CanisterSettings {
+        freezing_threshold: Some(2_592_000),
+        ..Default::default()
+    };
+    let new_ledger_settings = CanisterSettings {
+        freezing_threshold: Some(7_776_000),
+        reserved_cycles_limit: Some(5_000_000_000_000),
+        log_visibility: Some(LogVisibility::Public),
+        ..Default::default()
+    };
+    let archive_settings = CanisterSettings {
+        wasm_memory_limit: Some(3_221_225_472),
+        ..Default::default()
+    };
+    mutate_state(|s| {
+        s.record_new_erc20_token(usdc(), usdc_metadata());
+        s.record_created_canister::<Ledger>(&usdc(), LEDGER_PRINCIPAL);
+        s.record_installed_canister::<Ledger>(&usdc(), read_ledger_wasm_hash());
+        s.record_created_canister::<Index>(&usdc(), INDEX_PRINCIPAL);
+        s.record_installed_canister::<Index>(&usdc(), read_index_wasm_hash());
+        s.record_archives(&usdc(), vec![ARCHIVE_PRINCIPAL]);
+        s.record_applied_canister_settings(LEDGER_PRINCIPAL, old_ledger_settings);
+        UpdateManagedCanisterSettings {
+            ledger: Some(new_ledger_settings.clone()),
+            archive: Some(archive_settings.clone()),
+            ..Default::default()
+        }
+        .apply(s.canister_settings_mut());
+    });
+    let expected_error = CallError {
+        method: "update_settings".to_string(),
+        reason: Reason::OutOfCycles,
+    };
+    let mut runtime = MockCanisterRuntime::new();
+    let expected_ledger_settings = new_ledger_settings.clone();
+    runtime
+        .expect_update_settings()
+        .withf(move |canister_id, settings| {
+            canister_id == &LEDGER_PRINCIPAL && settings == &expected_ledger_settings
+        })
+        .times(1)
+        .return_const(Ok(()));
+    let expected_archive_settings = archive_settings.clone();
+    runtime
+        .expect_update_settings()
+        .withf(move |canister_id, settings| {
+            canister_id == &ARCHIVE_PRINCIPAL && settings == &expected_archive_settings
+        })
+        .times(1)
+        .return_const(Err(expected_error.clone()));
+
+    assert_eq!(
+        reconcile_canister_settings(&runtime).await,
+        Err(TaskError::UpdateSettingsError(expected_error))
+    );
+
+    // the index keeps the IC defaults, only the archive is left to update
+    assert_eq!(
+        read_state(|s| s.canister_settings_to_reconcile()),
+        vec![(ARCHIVE_PRINCIPAL, CanisterKind::Archive, archive_settings)]
+    );
+}
+
+#[tokio::test]
+async fn should_set_additional_controllers_on_managed_canisters() {
+    const NOW: u64 = 1_733_145_560_000_000_000;
+    const CONTROLLER_PRINCIPAL: Principal = Principal::from_slice(&[8_u8; 29]);
+    init_state();
+    mutate_state(|s| {
+        s.record_new_erc20_token(usdc(), usdc_metadata());
+        s.record_created_canister::<Ledger>(&usdc(), LEDGER_PRINCIPAL);
+        s.record_created_canister::<Index>(&usdc(), INDEX_PRINCIPAL);
+        s.update_more_controller_ids(vec![CONTROLLER_PRINCIPAL], &[])
+            .unwrap();
+    });
+    let expected_controllers = vec![LSM_PRINCIPAL, CONTROLLER_PRINCIPAL];
+    let expected_error = CallError {
+        method: "update_settings".to_string(),
+        reason: Reason::OutOfCycles,
+    };
+    let mut runtime = MockCanisterRuntime::new();
+    runtime.expect_id().return_const(LSM_PRINCIPAL);
+    runtime.expect_time().return_const(NOW);
+    let controllers = expected_controllers.clone();
+    runtime
+        .expect_set_controllers()
+        .withf(move |canister_id, c| canister_id == &LEDGER_PRINCIPAL && c == &controllers)
+        .times(1)
+        .return_const(Ok(()));
+    let controllers = expected_controllers.clone();
+    runtime
+        .expect_set_controllers()
+        .withf(move |canister_id, c| canister_id == &INDEX_PRINCIPAL && c == &controllers)
+        .times(1)
+        .return_const(Err(expected_error.clone()));
+
+    let expected_report = ControllersReport {
+        updated_at: NOW,
+        controllers: expected_controllers.clone(),
+        canisters: vec![
+            CanisterControllersUpdate {
+                canister_id: LEDGER_PRINCIPAL,
+                kind: CanisterKind::Ledger,
+                error: None,
+            },
+            CanisterControllersUpdate {
+                canister_id: INDEX_PRINCIPAL,
+                kind: CanisterKind::Index,
+                error: Some(format!("{:?}", expected_error)),
+            },
+        ],
+    };
+    assert_eq!(
+        reconcile_controllers(&runtime).await,
+        btreemap! { usdc() => expected_report.clone() }
+    );
+
+    assert_eq!(
+        read_state(|s| s
+            .controllers_reports()
+            .map(|(token, report)| (token.clone(), report.clone()))
+            .collect::<Vec<_>>()),
+        vec![(usdc(), expected_report)]
+    );
+    assert_eq!(
+        read_state(
+            |s| s.canisters_with_outdated_controllers(&expected_controllers.into_iter().collect())
+        ),
+        vec![(usdc(), INDEX_PRINCIPAL, CanisterKind::Index)]
+    );
+}
+
+#[tokio::test]
+async fn should_take_ledger_canister_from_pool() {
+    const NOW: u64 = 1_733_145_560_000_000_000;
+    const POOLED_PRINCIPAL: Principal = Principal::from_slice(&[6_u8; 29]);
+    const OTHER_SUBNET_POOLED_PRINCIPAL: Principal = Principal::from_slice(&[7_u8; 29]);
+    init_state();
+    let other_subnet_pooled_canister = PooledCanister {
+        canister_id: OTHER_SUBNET_POOLED_PRINCIPAL,
+        cycles: 5_000_000_000_000,
+        placement: CanisterPlacement::Subnet(Principal::from_slice(&[5_u8; 29])),
+        created_at: NOW,
+        settings: CanisterSettings::default(),
+        controllers: vec![LSM_PRINCIPAL],
+    };
+    mutate_state(|s| {
+        s.record_pooled_canister(other_subnet_pooled_canister.clone());
+        s.record_pooled_canister(PooledCanister {
+            canister_id: POOLED_PRINCIPAL,
+            cycles: 5_000_000_000_000,
+            placement: CanisterPlacement::LsmSubnet,
+            created_at: NOW,
+            settings: CanisterSettings {
+                freezing_threshold: Some(1),
+                ..Default::default()
+            },
+            controllers: vec![LSM_PRINCIPAL],
+        });
+    });
+    let mut runtime = MockCanisterRuntime::new();
+    runtime.expect_id().return_const(LSM_PRINCIPAL);
+    runtime.expect_time().return_const(NOW);
+    expect_create_canister_returning(&mut runtime, vec![LSM_PRINCIPAL], vec![Ok(INDEX_PRINCIPAL)]);
+    runtime.expect_install_code().times(2).return_const(Ok(()));
+
+    assert_eq!(
+        install_ledger_suite(&usdc_install_args(), &runtime).await,
+        Ok(())
+    );
+
+    let canisters = read_state(|s| s.managed_canisters(&usdc()).cloned()).unwrap();
+    assert_eq!(canisters.ledger_canister_id(), Some(&POOLED_PRINCIPAL));
+    assert_eq!(canisters.index_canister_id(), Some(&INDEX_PRINCIPAL));
+    assert_eq!(
+        read_state(|s| s.pooled_canisters().to_vec()),
+        vec![other_subnet_pooled_canister]
+    );
+    // the pooled canister was created with other settings than the configured ones
+    assert_eq!(
+        read_state(|s| s.canister_settings_to_reconcile()),
+        vec![(
+            POOLED_PRINCIPAL,
+            CanisterKind::Ledger,
+            CanisterSettings::default()
+        )]
+    );
+}
+
+#[tokio::test]
+async fn should_refill_canister_pool_within_cycles_budget() {
+    const NOW: u64 = 1_733_145_560_000_000_000;
+    const FIVE_TRILLIONS: u128 = 5_000_000_000_000;
+    const POOLED_PRINCIPALS: [Principal; 2] = [
+        Principal::from_slice(&[6_u8; 29]),
+        Principal::from_slice(&[7_u8; 29]),
+    ];
+    init_state();
+    mutate_state(|s| {
+        *s.canister_pool_config_mut() = CanisterPoolConfig {
+            target_size: 3,
+            cycles_per_canister: Nat::from(FIVE_TRILLIONS),
+        }
+    });
+    let minimum_manager_cycles: u128 = read_state(|s| {
+        s.cycles_management()
+            .minimum_manager_cycles()
+            .0
+            .try_into()
+            .unwrap()
+    });
+    let mut runtime = MockCanisterRuntime::new();
+    runtime.expect_id().return_const(LSM_PRINCIPAL);
+    runtime.expect_time().return_const(NOW);
+    runtime
+        .expect_canister_cycles()
+        .withf(|canister_id| canister_id == &LSM_PRINCIPAL)
+        .times(1)
+        .return_const(Ok(minimum_manager_cycles + 2 * FIVE_TRILLIONS));
+    expect_create_canister_returning(
+        &mut runtime,
+        vec![LSM_PRINCIPAL],
+        POOLED_PRINCIPALS.into_iter().map(Ok).collect(),
+    );
+
+    assert_eq!(
+        refill_canister_pool(&runtime).await,
+        Err(TaskError::InsufficientCyclesToRefillPool {
+            required: minimum_manager_cycles + FIVE_TRILLIONS,
+            available: minimum_manager_cycles,
+        })
+    );
+    assert_eq!(
+        read_state(|s| s.pooled_canisters().to_vec()),
+        POOLED_PRINCIPALS
+            .into_iter()
+            .map(|canister_id| PooledCanister {
+                canister_id,
+                cycles: FIVE_TRILLIONS,
+                placement: CanisterPlacement::LsmSubnet,
+                created_at: NOW,
+                settings: CanisterSettings::default(),
+                controllers: vec![LSM_PRINCIPAL],
+            })
+            .collect::<Vec<_>>()
+    );
+}
+
+/// Records an installed USDC ledger suite running outdated wasms
+/// and schedules its upgrade to the embedded ledger and index wasms.
+fn install_outdated_usdc_ledger_suite() -> UpgradeLedgerSuite {
+    let upgrade = UpgradeLedgerSuite::builder(usdc())
+        .ledger_wasm_hash(read_ledger_wasm_hash())
+        .index_wasm_hash(read_index_wasm_hash())
+        .build();
+    mutate_state(|s| {
+        s.record_new_erc20_token(usdc(), usdc_metadata());
+        s.record_created_canister::<Ledger>(&usdc(), LEDGER_PRINCIPAL);
+        s.record_installed_canister::<Ledger>(&usdc(), WasmHash::from([1_u8; 32]));
+        s.record_created_canister::<Index>(&usdc(), INDEX_PRINCIPAL);
+        s.record_installed_canister::<Index>(&usdc(), WasmHash::from([2_u8; 32]));
+        s.record_ledger_suite_upgrade(usdc(), upgrade.clone());
+    });
+    upgrade
+}
+
+fn init_state() {
+    crate::state::init_state(new_state());
+    mutate_state(|s| s.record_new_minter_ids(vec![(usdc().chain_id().clone(), MINTER_PRINCIPAL)]));
+    let _version = register_embedded_wasms();
+}
+
+fn register_embedded_wasms() -> LedgerSuiteVersion {
+    mutate_wasm_store(|s| record_icrc1_ledger_suite_wasms(s, 1_620_328_630_000_000_000)).unwrap()
+}
+
+fn usdc_install_args() -> InstallLedgerSuiteArgs {
+    InstallLedgerSuiteArgs {
+        contract: usdc(),
+        minter_id: MINTER_PRINCIPAL,
+        ledger_init_arg: ledger_init_arg(),
+        ledger_compressed_wasm_hash: read_ledger_wasm_hash(),
+        index_compressed_wasm_hash: read_index_wasm_hash(),
+        creator: Principal::from_text(
+            "tb3vi-54bcb-4oudm-fmp2s-nntjp-rmhd3-ukvnq-lawfq-vk5vy-mnlc7-pae",
+        )
+        .unwrap(),
+        created_at: 0,
+        attempts: 0,
+        last_error: None,
+        next_attempt_at: 0,
+        archive_profile: ArchiveProfile::default(),
+    }
+}
+
+fn ledger_init_arg() -> LedgerInitArg {
+    LedgerInitArg {
+        transfer_fee: 10_000_u32.into(),
+        decimals: 6,
+        token_name: "Ethereum Twin USDC".to_string(),
+        token_symbol: "icUSDC".to_string(),
+        token_logo: "".to_string(),
+    }
+}
+
+fn read_index_wasm_hash() -> WasmHash {
+    WasmHash::from(ic_crypto_sha2::Sha256::hash(INDEX_BYTECODE))
+}
+
+fn read_ledger_wasm_hash() -> WasmHash {
+    WasmHash::from(ic_crypto_sha2::Sha256::hash(LEDGER_BYTECODE))
+}
+
+fn expect_create_canister_returning(
+    runtime: &mut MockCanisterRuntime,
+    expected_controllers: Vec<Principal>,
+    results: Vec<Result<Principal, CallError>>,
+) {
+    assert!(!results.is_empty(), "must return at least one result");
+    let mut create_canister_call_counter = 0_usize;
+    runtime
+        .expect_create_canister()
+        .withf(move |controllers, _cycles, _settings, _placement| {
+            controllers == &expected_controllers
+        })
+        .times(results.len())
+        .returning(move |_controllers, _cycles, _settings, _placement| {
+            if create_canister_call_counter >= results.len() {
+                panic!("create_canister called too many times!");
+            }
+            let result = results[create_canister_call_counter].clone();
+            create_canister_call_counter += 1;
+            result
+        });
+}
+
+fn expect_take_canister_snapshot(
+    runtime: &mut MockCanisterRuntime,
+    expected_canister_id: Principal,
+    expected_replace_snapshot: Option<Vec<u8>>,
+) {
+    runtime
+        .expect_take_canister_snapshot()
+        .withf(move |canister_id, replace_snapshot| {
+            canister_id == &expected_canister_id && replace_snapshot == &expected_replace_snapshot
+        })
+        .times(1)
+        .return_const(Ok(CanisterSnapshotResponse {
+            id: expected_canister_id.as_slice().to_vec(),
+            taken_at_timestamp: SNAPSHOT_TIMESTAMP,
+            total_size: 1_000,
+        }));
+}
+
+fn expect_healthy_ledger_probes(runtime: &mut MockCanisterRuntime) {
+    runtime
+        .expect_call_canister::<(), Nat>()
+        .withf(|canister_id, method, _args| {
+            canister_id == &LEDGER_PRINCIPAL && method == "icrc1_total_supply"
+        })
+        .return_const(Ok(Nat::from(42_u8)));
+    expect_ledger_metadata_and_archives_probes(runtime);
+}
+
+fn expect_ledger_probes(runtime: &mut MockCanisterRuntime, total_supplies: Vec<Nat>) {
+    let mut total_supply_call_counter = 0_usize;
+    runtime
+        .expect_call_canister::<(), Nat>()
+        .withf(|canister_id, method, _args| {
+            canister_id == &LEDGER_PRINCIPAL && method == "icrc1_total_supply"
+        })
+        .times(total_supplies.len())
+        .returning(move |_canister_id, _method, _args| {
+            let result = total_supplies[total_supply_call_counter].clone();
+            total_supply_call_counter += 1;
+            Ok(result)
+        });
+    expect_ledger_metadata_and_archives_probes(runtime);
+}
+
+fn expect_ledger_metadata_and_archives_probes(runtime: &mut MockCanisterRuntime) {
+    runtime
+        .expect_call_canister::<(), Vec<(String, MetadataValue)>>()
+        .withf(|canister_id, method, _args| {
+            canister_id == &LEDGER_PRINCIPAL && method == "icrc1_metadata"
+        })
+        .return_const(Ok(vec![(
+            "icrc1:symbol".to_string(),
+            MetadataValue::from("icUSDC"),
+        )]));
+    runtime
+        .expect_call_canister::<GetArchivesArgs, GetArchivesResult>()
+        .withf(|canister_id, method, _args| {
+            canister_id == &LEDGER_PRINCIPAL && method == "icrc3_get_archives"
+        })
+        .return_const(Ok(vec![]));
+}
+
+fn expect_healthy_index_probes(runtime: &mut MockCanisterRuntime) {
+    runtime
+        .expect_call_canister::<(), Principal>()
+        .withf(|canister_id, method, _args| {
+            canister_id == &INDEX_PRINCIPAL && method == "ledger_id"
+        })
+        .return_const(Ok(LEDGER_PRINCIPAL));
+    runtime
+        .expect_call_canister::<(), IndexStatus>()
+        .withf(|canister_id, method, _args| canister_id == &INDEX_PRINCIPAL && method == "status")
+        .returning(|_canister_id, _method, _args| {
+            Ok(IndexStatus {
+                num_blocks_synced: Nat::from(10_u8),
+            })
+        });
+}
+
+mod mock {
+    use crate::cmc_client::{CmcRunTime, IcpToCyclesConversionError};
+    use crate::endpoints::{CanisterPlacement, CanisterSettings};
+    use crate::ledger_suite_manager::CallError;
+    use crate::management::{CanisterRuntime, CanisterSnapshotResponse};
+    use async_trait::async_trait;
+    use candid::CandidType;
+    use candid::{Nat, Principal};
+    use core::fmt::Debug;
+    use icrc_ledger_types::icrc1::transfer::TransferError;
+    use icrc_ledger_types::icrc2::transfer_from::TransferFromError;
+    use mockall::mock;
+    use serde::de::DeserializeOwned;
+    use std::marker::Send;
+
+    mock! {
+        pub CanisterRuntime{}
+
+        #[async_trait]
+        impl CanisterRuntime for CanisterRuntime {
+
+            fn id(&self) -> Principal;
+
+            fn time(&self) -> u64;
+
+            fn global_timer_set(&self, timestamp: u64);
+
+            async fn create_canister(
+                &self,
+                controllers: Vec<Principal>,
+                cycles_for_canister_creation: u64,
+                settings: CanisterSettings,
+                placement: CanisterPlacement,
+            ) -> Result<Principal, CallError>;
+
+            async fn update_settings(
+                &self,
+                canister_id: Principal,
+                settings: CanisterSettings,
+            ) -> Result<(), CallError>;
+
+            async fn set_controllers(
+                &self,
+                canister_id: Principal,
+                controllers: Vec<Principal>,
+            ) -> Result<(), CallError>;
+
+            async fn stop_canister(&self, canister_id: Principal) -> Result<(), CallError>;
+
+            async fn start_canister(&self, canister_id: Principal) -> Result<(), CallError>;
+
+            async fn install_code(
+                &self,
+                canister_id: Principal,
+                wasm_module:Vec<u8>,
+                arg: Vec<u8>,
+            ) -> Result<(), CallError>;
+
+            async fn upload_chunk(
+                &self,
+                canister_id: Principal,
+                chunk: Vec<u8>,
+            ) -> Result<Vec<u8>, CallError>;
 
-            async fn install_code(
+            async fn install_chunked_code(
                 &self,
                 canister_id: Principal,
-                wasm_module:Vec<u8>,
+                mode: InstallMode,
+                chunk_hashes: Vec<Vec<u8>>,
+                wasm_module_hash: Vec<u8>,
                 arg: Vec<u8>,
             ) -> Result<(), CallError>;
 
+            async fn clear_chunk_store(&self, canister_id: Principal) -> Result<(), CallError>;
+
             async fn upgrade_canister(
                 &self,
                 canister_id: Principal,
@@ -287,11 +2173,28 @@ mod mock {
                 upgrade_args:Vec<u8>,
             ) -> Result<(), CallError>;
 
+            async fn take_canister_snapshot(
+                &self,
+                canister_id: Principal,
+                replace_snapshot: Option<Vec<u8>>,
+            ) -> Result<CanisterSnapshotResponse, CallError>;
+
+            async fn load_canister_snapshot(
+                &self,
+                canister_id: Principal,
+                snapshot_id: Vec<u8>,
+            ) -> Result<(), CallError>;
+
             async fn canister_cycles(
                 &self,
                 canister_id: Principal,
             ) -> Result<u128, CallError>;
 
+            async fn canister_module_hash(
+                &self,
+                canister_id: Principal,
+            ) -> Result<Option<Vec<u8>>, CallError>;
+
             fn send_cycles(
                 &self,
                 canister_id: Principal,
@@ -309,13 +2212,58 @@ mod mock {
                 O: CandidType + DeserializeOwned + Debug + 'static;
         }
     }
+
+    mock! {
+        pub CmcRunTime{}
+
+        #[async_trait]
+        impl CmcRunTime for CmcRunTime {
+
+            fn id(&self) -> Principal;
+
+            fn time(&self) -> u64;
+
+            async fn icp_balance(&self) -> Result<u64, IcpToCyclesConversionError>;
+
+            async fn transfer_cmc(&self, icp_amount: u64) -> Result<u64, IcpToCyclesConversionError>;
+
+            async fn notify_top_up(&self, block_index: u64) -> Result<u128, IcpToCyclesConversionError>;
+
+            async fn deposit_icp(
+                &self,
+                icp_amount: u64,
+                from: Principal,
+                from_subaccount: Option<[u8; 32]>,
+            ) -> Result<Result<Nat, TransferFromError>, CallError>;
+
+            async fn transfer_icp(
+                &self,
+                to: Principal,
+                icp_amount: u64,
+                memo: u64,
+                created_at_time: u64,
+            ) -> Result<Result<Nat, TransferError>, CallError>;
+
+            async fn call_canister<I, O>(
+                &self,
+                canister_id: Principal,
+                method: &str,
+                args: I,
+            ) -> Result<O, CallError>
+            where
+                I: CandidType + Debug + Send + 'static,
+                O: CandidType + DeserializeOwned + Debug + 'static;
+        }
+    }
 }
 
 mod install_ledger_suite_args {
     use crate::endpoints::{AddErc20Arg, InitArg, LedgerInitArg};
+    use crate::ledger_suite_manager::test_fixtures::{usdc_matic, USDT_ADDRESS};
     use crate::ledger_suite_manager::tests::{usdc_metadata, MINTER_PRINCIPAL};
     use crate::ledger_suite_manager::{
-        install_ls::InvalidAddErc20ArgError, Erc20Token, InstallLedgerSuiteArgs,
+        install_ls::{ArchiveProfile, InvalidAddErc20ArgError},
+        Erc20Token, InstallLedgerSuiteArgs,
     };
     use crate::state::test_fixtures::{expect_panic_with_message, new_state, new_state_from};
     use crate::state::{ChainId, IndexWasm, LedgerSuiteVersion, LedgerWasm, WasmHash};
@@ -326,6 +2274,7 @@ mod install_ledger_suite_args {
     use proptest::proptest;
 
     const ERC20_CONTRACT_ADDRESS: &str = "0xa0b86991c6218b36c1d19d4a2e9eb0ce3606eb48";
+    const VALID_TOKEN_LOGO: &str = "data:image/png;base64,iVBORw0KGgoAAAANSUhEUgAAAAEAAAABCAQAAAC1HAwCAAAAC0lEQVR42mNkYAAAAAYAAjCB0C8AAAAASUVORK5CYII=";
 
     #[test]
     fn should_error_if_minter_id_missing() {
@@ -345,7 +2294,7 @@ mod install_ledger_suite_args {
             ..Default::default()
         });
         let wasm_store = wasm_store_with_icrc1_ledger_suite();
-        state.update_ledger_suite_version(embedded_ledger_suite_version());
+        state.update_ledger_suite_version(embedded_ledger_suite_version(), 0);
         let arg = valid_add_erc20_arg();
         let contract: Erc20Token = arg.contract.clone().try_into().unwrap();
         state.record_new_erc20_token(contract.clone(), usdc_metadata());
@@ -373,7 +2322,7 @@ mod install_ledger_suite_args {
         fn should_error_on_invalid_ethereum_address(invalid_address in "0x[0-9a-fA-F]{0,39}|[0-9a-fA-F]{41,}") {
             let mut state = new_state();
             let wasm_store = wasm_store_with_icrc1_ledger_suite();
-            state.update_ledger_suite_version(embedded_ledger_suite_version());
+            state.update_ledger_suite_version(embedded_ledger_suite_version(), 0);
             let mut arg = valid_add_erc20_arg();
             arg.contract.address = invalid_address;
             assert_matches!(
@@ -386,7 +2335,7 @@ mod install_ledger_suite_args {
         fn should_error_on_large_chain_id(offset in 0_u128..=u64::MAX as u128) {
             let mut state = new_state();
             let wasm_store = wasm_store_with_icrc1_ledger_suite();
-            state.update_ledger_suite_version(embedded_ledger_suite_version());
+            state.update_ledger_suite_version(embedded_ledger_suite_version(), 0);
             let mut arg = valid_add_erc20_arg();
             arg.contract.chain_id = Nat::from((u64::MAX as u128) + offset);
 
@@ -439,7 +2388,7 @@ mod install_ledger_suite_args {
                 minter_ids: vec![(Nat::from(1_u64), MINTER_PRINCIPAL)],
                 ..Default::default()
             });
-            state.update_ledger_suite_version(version);
+            state.update_ledger_suite_version(version, 0);
             let wasm_store = wasm_store_with_icrc1_ledger_suite();
 
             expect_panic_with_message(
@@ -467,7 +2416,7 @@ mod install_ledger_suite_args {
             ..Default::default()
         });
         let wasm_store = wasm_store_with_icrc1_ledger_suite();
-        state.update_ledger_suite_version(embedded_ledger_suite_version());
+        state.update_ledger_suite_version(embedded_ledger_suite_version(), 0);
         let arg = valid_add_erc20_arg();
         let ledger_init_arg = arg.ledger_init_arg.clone();
 
@@ -500,11 +2449,396 @@ mod install_ledger_suite_args {
                     "tb3vi-54bcb-4oudm-fmp2s-nntjp-rmhd3-ukvnq-lawfq-vk5vy-mnlc7-pae"
                 )
                 .unwrap(),
-                created_at: 0
+                created_at: 0,
+                attempts: 0,
+                last_error: None,
+                next_attempt_at: 0,
+                archive_profile: ArchiveProfile::default(),
             }
         );
     }
 
+    #[test]
+    fn should_error_on_invalid_ledger_init_arg() {
+        let mut state = new_state_from(InitArg {
+            minter_ids: vec![(Nat::from(1_u64), MINTER_PRINCIPAL)],
+            ..Default::default()
+        });
+        let wasm_store = wasm_store_with_icrc1_ledger_suite();
+        state.update_ledger_suite_version(embedded_ledger_suite_version(), 0);
+
+        for (modify_arg, expected_error) in [
+            (
+                (|arg: &mut LedgerInitArg| arg.token_name = String::new())
+                    as fn(&mut LedgerInitArg),
+                InvalidAddErc20ArgError::TokenNameLengthOutOfBounds {
+                    length: 0,
+                    min: 1,
+                    max: 64,
+                },
+            ),
+            (
+                |arg| arg.token_name = "a".repeat(65),
+                InvalidAddErc20ArgError::TokenNameLengthOutOfBounds {
+                    length: 65,
+                    min: 1,
+                    max: 64,
+                },
+            ),
+            (
+                |arg| arg.token_name = "USD <Coin>".to_string(),
+                InvalidAddErc20ArgError::InvalidTokenNameCharacter('<'),
+            ),
+            (
+                |arg| arg.token_symbol = String::new(),
+                InvalidAddErc20ArgError::TokenSymbolLengthOutOfBounds {
+                    length: 0,
+                    min: 1,
+                    max: 16,
+                },
+            ),
+            (
+                |arg| arg.token_symbol = "A".repeat(10 * 1024),
+                InvalidAddErc20ArgError::TokenSymbolLengthOutOfBounds {
+                    length: 10 * 1024,
+                    min: 1,
+                    max: 16,
+                },
+            ),
+            (
+                |arg| arg.token_symbol = "USD C".to_string(),
+                InvalidAddErc20ArgError::InvalidTokenSymbolCharacter(' '),
+            ),
+            (
+                |arg| arg.decimals = 255,
+                InvalidAddErc20ArgError::DecimalsOutOfBounds {
+                    decimals: 255,
+                    max: 18,
+                },
+            ),
+            (
+                |arg| arg.transfer_fee = Nat::from(0_u8),
+                InvalidAddErc20ArgError::TransferFeeOutOfBounds {
+                    transfer_fee: Nat::from(0_u8),
+                    min: Nat::from(1_u8),
+                    max: Nat::from(1_000_000_u32),
+                },
+            ),
+            (
+                |arg| arg.transfer_fee = Nat::from(u128::MAX),
+                InvalidAddErc20ArgError::TransferFeeOutOfBounds {
+                    transfer_fee: Nat::from(u128::MAX),
+                    min: Nat::from(1_u8),
+                    max: Nat::from(1_000_000_u32),
+                },
+            ),
+            (
+                |arg| arg.token_logo = String::new(),
+                InvalidAddErc20ArgError::TokenLogoNotADataUri,
+            ),
+            (
+                |arg| arg.token_logo = "https://example.com/usdc.png".to_string(),
+                InvalidAddErc20ArgError::TokenLogoNotADataUri,
+            ),
+            (
+                |arg| arg.token_logo = "data:image/png;base64,<script>".to_string(),
+                InvalidAddErc20ArgError::TokenLogoNotADataUri,
+            ),
+            (
+                |arg| arg.token_logo = "data:text/html;base64,PGgxPg==".to_string(),
+                InvalidAddErc20ArgError::UnsupportedTokenLogoMediaType("text/html".to_string()),
+            ),
+            (
+                |arg| {
+                    arg.token_logo =
+                        format!("data:image/png;base64,{}", "A".repeat(5 * 1024 * 1024))
+                },
+                InvalidAddErc20ArgError::TokenLogoTooLarge {
+                    length: 5 * 1024 * 1024 + 22,
+                    max: 128 * 1024,
+                },
+            ),
+        ] {
+            let mut arg = valid_add_erc20_arg();
+            modify_arg(&mut arg.ledger_init_arg);
+
+            assert_eq!(
+                InstallLedgerSuiteArgs::validate_add_erc20(
+                    &state,
+                    &wasm_store,
+                    arg,
+                    Principal::from_text(
+                        "tb3vi-54bcb-4oudm-fmp2s-nntjp-rmhd3-ukvnq-lawfq-vk5vy-mnlc7-pae"
+                    )
+                    .unwrap(),
+                    0
+                ),
+                Err(expected_error)
+            );
+        }
+    }
+
+    #[test]
+    fn should_accept_ledger_init_arg_within_bounds() {
+        let mut state = new_state_from(InitArg {
+            minter_ids: vec![(Nat::from(1_u64), MINTER_PRINCIPAL)],
+            ..Default::default()
+        });
+        let wasm_store = wasm_store_with_icrc1_ledger_suite();
+        state.update_ledger_suite_version(embedded_ledger_suite_version(), 0);
+
+        for modify_arg in [
+            (|arg: &mut LedgerInitArg| arg.token_name = "a".repeat(64)) as fn(&mut LedgerInitArg),
+            |arg| arg.token_symbol = "USDC.e".to_string(),
+            |arg| {
+                arg.decimals = 18;
+                arg.transfer_fee = Nat::from(1_000_000_000_000_000_000_u64);
+            },
+            |arg| {
+                arg.decimals = 0;
+                arg.transfer_fee = Nat::from(1_u8);
+            },
+            |arg| arg.token_logo = "data:image/svg+xml;base64,PHN2Zy8+".to_string(),
+        ] {
+            let mut arg = valid_add_erc20_arg();
+            modify_arg(&mut arg.ledger_init_arg);
+
+            assert_matches!(
+                InstallLedgerSuiteArgs::validate_add_erc20(
+                    &state,
+                    &wasm_store,
+                    arg,
+                    Principal::from_text(
+                        "tb3vi-54bcb-4oudm-fmp2s-nntjp-rmhd3-ukvnq-lawfq-vk5vy-mnlc7-pae"
+                    )
+                    .unwrap(),
+                    0
+                ),
+                Ok(_)
+            );
+        }
+    }
+
+    #[test]
+    fn should_error_if_contract_is_denied() {
+        let mut state = new_state_from(InitArg {
+            minter_ids: vec![(Nat::from(1_u64), MINTER_PRINCIPAL)],
+            ..Default::default()
+        });
+        let wasm_store = wasm_store_with_icrc1_ledger_suite();
+        state.update_ledger_suite_version(embedded_ledger_suite_version(), 0);
+        let arg = valid_add_erc20_arg();
+        let contract: Erc20Token = arg.contract.clone().try_into().unwrap();
+        state
+            .token_denylists_mut()
+            .deny_erc20_contract(contract.clone());
+
+        assert_eq!(
+            InstallLedgerSuiteArgs::validate_add_erc20(
+                &state,
+                &wasm_store,
+                arg,
+                Principal::from_text(
+                    "tb3vi-54bcb-4oudm-fmp2s-nntjp-rmhd3-ukvnq-lawfq-vk5vy-mnlc7-pae"
+                )
+                .unwrap(),
+                0
+            ),
+            Err(InvalidAddErc20ArgError::Erc20ContractDenied(contract))
+        );
+    }
+
+    #[test]
+    fn should_error_if_symbol_is_denied_or_name_is_reserved() {
+        let mut state = new_state_from(InitArg {
+            minter_ids: vec![(Nat::from(1_u64), MINTER_PRINCIPAL)],
+            ..Default::default()
+        });
+        let wasm_store = wasm_store_with_icrc1_ledger_suite();
+        state.update_ledger_suite_version(embedded_ledger_suite_version(), 0);
+        state.token_denylists_mut().deny_token_symbol("ckUSDC");
+        state
+            .token_denylists_mut()
+            .reserve_token_name("ckUSDC Token");
+
+        let mut arg = valid_add_erc20_arg();
+        arg.ledger_init_arg.token_symbol = "CKusdc".to_string();
+        assert_eq!(
+            InstallLedgerSuiteArgs::validate_add_erc20(
+                &state,
+                &wasm_store,
+                arg,
+                Principal::from_text(
+                    "tb3vi-54bcb-4oudm-fmp2s-nntjp-rmhd3-ukvnq-lawfq-vk5vy-mnlc7-pae"
+                )
+                .unwrap(),
+                0
+            ),
+            Err(InvalidAddErc20ArgError::TokenSymbolDenied(
+                "CKusdc".to_string()
+            ))
+        );
+
+        let mut arg = valid_add_erc20_arg();
+        arg.ledger_init_arg.token_name = "CKUSDC  token".to_string();
+        assert_eq!(
+            InstallLedgerSuiteArgs::validate_add_erc20(
+                &state,
+                &wasm_store,
+                arg,
+                Principal::from_text(
+                    "tb3vi-54bcb-4oudm-fmp2s-nntjp-rmhd3-ukvnq-lawfq-vk5vy-mnlc7-pae"
+                )
+                .unwrap(),
+                0
+            ),
+            Err(InvalidAddErc20ArgError::TokenNameReserved(
+                "CKUSDC  token".to_string()
+            ))
+        );
+
+        state.token_denylists_mut().allow_token_symbol("CKUSDC");
+        state
+            .token_denylists_mut()
+            .release_token_name("ckusdc token");
+        let mut arg = valid_add_erc20_arg();
+        arg.ledger_init_arg.token_symbol = "ckUSDC".to_string();
+        arg.ledger_init_arg.token_name = "ckUSDC Token".to_string();
+        assert_matches!(
+            InstallLedgerSuiteArgs::validate_add_erc20(
+                &state,
+                &wasm_store,
+                arg,
+                Principal::from_text(
+                    "tb3vi-54bcb-4oudm-fmp2s-nntjp-rmhd3-ukvnq-lawfq-vk5vy-mnlc7-pae"
+                )
+                .unwrap(),
+                0
+            ),
+            Ok(_)
+        );
+    }
+
+    #[test]
+    fn should_error_if_symbol_is_used_by_another_token() {
+        let mut state = new_state_from(InitArg {
+            minter_ids: vec![
+                (Nat::from(1_u64), MINTER_PRINCIPAL),
+                (Nat::from(137_u64), MINTER_PRINCIPAL),
+            ],
+            ..Default::default()
+        });
+        let wasm_store = wasm_store_with_icrc1_ledger_suite();
+        state.update_ledger_suite_version(embedded_ledger_suite_version(), 0);
+        state.record_new_erc20_token(usdc_matic(), usdc_metadata());
+
+        let mut arg = valid_add_erc20_arg();
+        arg.ledger_init_arg.token_symbol = "ICUSDC".to_string();
+        assert_eq!(
+            InstallLedgerSuiteArgs::validate_add_erc20(
+                &state,
+                &wasm_store,
+                arg,
+                Principal::from_text(
+                    "tb3vi-54bcb-4oudm-fmp2s-nntjp-rmhd3-ukvnq-lawfq-vk5vy-mnlc7-pae"
+                )
+                .unwrap(),
+                0
+            ),
+            Err(InvalidAddErc20ArgError::TokenSymbolAlreadyUsed {
+                symbol: "ICUSDC".to_string(),
+                token: usdc_matic(),
+            })
+        );
+
+        // A request pending approval reserves its symbol as well.
+        let pending_request = InstallLedgerSuiteArgs::validate_add_erc20(
+            &state,
+            &wasm_store,
+            valid_add_erc20_arg(),
+            Principal::from_text("tb3vi-54bcb-4oudm-fmp2s-nntjp-rmhd3-ukvnq-lawfq-vk5vy-mnlc7-pae")
+                .unwrap(),
+            0,
+        )
+        .unwrap();
+        let pending_token = pending_request.contract.clone();
+        state.record_new_ledger_suite_request(pending_token.clone(), pending_request);
+        let mut arg = valid_add_erc20_arg();
+        arg.contract.address = USDT_ADDRESS.to_string();
+        assert_eq!(
+            InstallLedgerSuiteArgs::validate_add_erc20(
+                &state,
+                &wasm_store,
+                arg,
+                Principal::from_text(
+                    "tb3vi-54bcb-4oudm-fmp2s-nntjp-rmhd3-ukvnq-lawfq-vk5vy-mnlc7-pae"
+                )
+                .unwrap(),
+                0
+            ),
+            Err(InvalidAddErc20ArgError::TokenSymbolAlreadyUsed {
+                symbol: "USDC".to_string(),
+                token: pending_token,
+            })
+        );
+    }
+
+    #[test]
+    fn should_resolve_archive_profile_of_request() {
+        let mut state = new_state_from(InitArg {
+            minter_ids: vec![(Nat::from(1_u64), MINTER_PRINCIPAL)],
+            ..Default::default()
+        });
+        let wasm_store = wasm_store_with_icrc1_ledger_suite();
+        state.update_ledger_suite_version(embedded_ledger_suite_version(), 0);
+        let stablecoin_profile = ArchiveProfile {
+            trigger_threshold: 20_000,
+            num_blocks_to_archive: 10_000,
+            ..ArchiveProfile::default()
+        };
+        state.upsert_archive_profile("stablecoin".to_string(), stablecoin_profile.clone());
+
+        let mut arg = valid_add_erc20_arg();
+        arg.archive_profile = Some("meme".to_string());
+        assert_eq!(
+            InstallLedgerSuiteArgs::validate_add_erc20(
+                &state,
+                &wasm_store,
+                arg,
+                Principal::from_text(
+                    "tb3vi-54bcb-4oudm-fmp2s-nntjp-rmhd3-ukvnq-lawfq-vk5vy-mnlc7-pae"
+                )
+                .unwrap(),
+                0
+            ),
+            Err(InvalidAddErc20ArgError::UnknownArchiveProfile(
+                "meme".to_string()
+            ))
+        );
+
+        let mut arg = valid_add_erc20_arg();
+        arg.archive_profile = Some("stablecoin".to_string());
+        let install_args = InstallLedgerSuiteArgs::validate_add_erc20(
+            &state,
+            &wasm_store,
+            arg,
+            Principal::from_text("tb3vi-54bcb-4oudm-fmp2s-nntjp-rmhd3-ukvnq-lawfq-vk5vy-mnlc7-pae")
+                .unwrap(),
+            0,
+        )
+        .unwrap();
+        assert_eq!(install_args.archive_profile, stablecoin_profile);
+
+        // Removing the profile does not affect the requests that chose it.
+        let token = install_args.contract.clone();
+        state.record_new_ledger_suite_request(token.clone(), install_args);
+        state.remove_archive_profile("stablecoin");
+        assert_eq!(
+            state.ledger_suite_requests_pending_approval()[&token].archive_profile,
+            stablecoin_profile
+        );
+    }
+
     fn valid_add_erc20_arg() -> AddErc20Arg {
         AddErc20Arg {
             contract: crate::endpoints::Erc20Contract {
@@ -516,8 +2850,9 @@ mod install_ledger_suite_args {
                 decimals: 6,
                 token_name: "USD Coin".to_string(),
                 token_symbol: "USDC".to_string(),
-                token_logo: "".to_string(),
+                token_logo: VALID_TOKEN_LOGO.to_string(),
             },
+            archive_profile: None,
         }
     }
 
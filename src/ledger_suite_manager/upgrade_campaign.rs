@@ -0,0 +1,220 @@
+use std::collections::{BTreeMap, BTreeSet};
+
+use serde::{Deserialize, Serialize};
+
+use crate::state::{Erc20Token, LedgerSuiteVersion};
+
+/// A staged rollout of the scheduled ledger suite upgrades.
+///
+/// The tokens are upgraded in batches: the canary tokens first, then batches of `batch_size` tokens.
+/// A batch is only started once the previous one is done and the pause between batches elapsed.
+/// The upgrades of tokens that were not yet reached by a batch are held back.
+/// The campaign stops as soon as an upgrade of a batch fails with an unrecoverable error.
+#[derive(Clone, PartialEq, Debug, Deserialize, Serialize)]
+pub struct UpgradeCampaign {
+    ledger_suite_version: LedgerSuiteVersion,
+    canary_tokens: BTreeSet<Erc20Token>,
+    batch_size: usize,
+    /// Pause between two batches in nanoseconds.
+    pause_between_batches: u64,
+    /// The canister time at which the campaign started
+    /// in nanoseconds since the epoch (1970-01-01).
+    started_at: u64,
+    /// The canister time from which the next batch may start
+    /// in nanoseconds since the epoch (1970-01-01).
+    next_batch_at: u64,
+    status: UpgradeCampaignStatus,
+    /// Tokens not yet reached by a batch, in upgrade order.
+    pending: Vec<Erc20Token>,
+    in_progress: BTreeSet<Erc20Token>,
+    done: BTreeSet<Erc20Token>,
+    failed: BTreeMap<Erc20Token, String>,
+}
+
+#[derive(Clone, Eq, PartialEq, Debug, Deserialize, Serialize)]
+pub enum UpgradeCampaignStatus {
+    Running,
+    /// An upgrade failed with an unrecoverable error.
+    Stopped,
+    Completed,
+    /// The ledger suite version changed before the campaign completed.
+    Cancelled,
+}
+
+#[derive(Clone, Eq, PartialEq, Debug)]
+pub enum UpgradeCampaignError {
+    CampaignAlreadyRunning,
+    InvalidBatchSize,
+    CanaryWithoutUpgrade(Erc20Token),
+    NothingToUpgrade,
+}
+
+impl UpgradeCampaign {
+    /// Creates a campaign rolling out the upgrades of `tokens_to_upgrade`.
+    ///
+    /// # Errors
+    /// * [`UpgradeCampaignError::InvalidBatchSize`] if the batch size is zero
+    /// * [`UpgradeCampaignError::CanaryWithoutUpgrade`] if a canary token is not to be upgraded
+    /// * [`UpgradeCampaignError::NothingToUpgrade`] if there are no tokens to upgrade
+    pub fn new(
+        ledger_suite_version: LedgerSuiteVersion,
+        tokens_to_upgrade: BTreeSet<Erc20Token>,
+        canary_tokens: BTreeSet<Erc20Token>,
+        batch_size: usize,
+        pause_between_batches: u64,
+        now: u64,
+    ) -> Result<Self, UpgradeCampaignError> {
+        if batch_size == 0 {
+            return Err(UpgradeCampaignError::InvalidBatchSize);
+        }
+        if let Some(token) = canary_tokens.difference(&tokens_to_upgrade).next() {
+            return Err(UpgradeCampaignError::CanaryWithoutUpgrade(token.clone()));
+        }
+        if tokens_to_upgrade.is_empty() {
+            return Err(UpgradeCampaignError::NothingToUpgrade);
+        }
+        let pending = canary_tokens
+            .iter()
+            .chain(tokens_to_upgrade.difference(&canary_tokens))
+            .cloned()
+            .collect();
+        Ok(Self {
+            ledger_suite_version,
+            canary_tokens,
+            batch_size,
+            pause_between_batches,
+            started_at: now,
+            next_batch_at: now,
+            status: UpgradeCampaignStatus::Running,
+            pending,
+            in_progress: BTreeSet::new(),
+            done: BTreeSet::new(),
+            failed: BTreeMap::new(),
+        })
+    }
+
+    pub fn ledger_suite_version(&self) -> &LedgerSuiteVersion {
+        &self.ledger_suite_version
+    }
+
+    pub fn canary_tokens(&self) -> &BTreeSet<Erc20Token> {
+        &self.canary_tokens
+    }
+
+    pub fn batch_size(&self) -> usize {
+        self.batch_size
+    }
+
+    pub fn pause_between_batches(&self) -> u64 {
+        self.pause_between_batches
+    }
+
+    pub fn started_at(&self) -> u64 {
+        self.started_at
+    }
+
+    pub fn next_batch_at(&self) -> u64 {
+        self.next_batch_at
+    }
+
+    pub fn status(&self) -> &UpgradeCampaignStatus {
+        &self.status
+    }
+
+    pub fn pending(&self) -> &[Erc20Token] {
+        &self.pending
+    }
+
+    pub fn in_progress(&self) -> &BTreeSet<Erc20Token> {
+        &self.in_progress
+    }
+
+    pub fn done(&self) -> &BTreeSet<Erc20Token> {
+        &self.done
+    }
+
+    pub fn failed(&self) -> &BTreeMap<Erc20Token, String> {
+        &self.failed
+    }
+
+    pub fn is_running(&self) -> bool {
+        self.status == UpgradeCampaignStatus::Running
+    }
+
+    /// Returns `true` if the campaign is neither completed nor cancelled.
+    pub fn is_active(&self) -> bool {
+        matches!(
+            self.status,
+            UpgradeCampaignStatus::Running | UpgradeCampaignStatus::Stopped
+        )
+    }
+
+    /// Returns `true` if the upgrade of the given token must not be executed yet.
+    pub fn holds_back(&self, token: &Erc20Token) -> bool {
+        self.is_active() && self.pending.contains(token)
+    }
+
+    /// Moves the campaign forward:
+    /// tokens of the current batch that are no longer in `scheduled_upgrades` are done
+    /// and, once the whole batch is done and the pause elapsed, the next batch is started.
+    /// No new batch is started once the campaign stopped.
+    pub fn advance(&mut self, scheduled_upgrades: &BTreeSet<Erc20Token>, now: u64) {
+        if !self.is_active() {
+            return;
+        }
+        let (still_in_progress, done): (BTreeSet<_>, BTreeSet<_>) =
+            std::mem::take(&mut self.in_progress)
+                .into_iter()
+                .partition(|token| scheduled_upgrades.contains(token));
+        self.in_progress = still_in_progress;
+        let batch_completed = !done.is_empty() && self.in_progress.is_empty();
+        self.done.extend(done);
+        if batch_completed {
+            self.next_batch_at = now.saturating_add(self.pause_between_batches);
+        }
+        if !self.is_running() || !self.in_progress.is_empty() {
+            return;
+        }
+        if self.pending.is_empty() {
+            self.status = UpgradeCampaignStatus::Completed;
+            return;
+        }
+        if now < self.next_batch_at {
+            return;
+        }
+        let is_first_batch = self.done.is_empty();
+        let next_batch_size = if is_first_batch && !self.canary_tokens.is_empty() {
+            self.canary_tokens.len()
+        } else {
+            self.batch_size
+        };
+        let next_batch_size = next_batch_size.min(self.pending.len());
+        self.in_progress = self.pending.drain(..next_batch_size).collect();
+    }
+
+    /// Records that the upgrade of a token of the current batch failed with an unrecoverable error,
+    /// which stops the campaign.
+    pub fn record_failed_upgrade(&mut self, token: &Erc20Token, error: String) {
+        if self.in_progress.remove(token) {
+            self.failed.insert(token.clone(), error);
+            self.status = UpgradeCampaignStatus::Stopped;
+        }
+    }
+
+    /// Resumes a stopped campaign with its next batch.
+    /// The upgrades that failed are not retried.
+    /// Returns `false` if the campaign was not stopped.
+    pub fn resume(&mut self) -> bool {
+        if self.status != UpgradeCampaignStatus::Stopped {
+            return false;
+        }
+        self.status = UpgradeCampaignStatus::Running;
+        true
+    }
+
+    pub fn cancel(&mut self) {
+        if self.is_active() {
+            self.status = UpgradeCampaignStatus::Cancelled;
+        }
+    }
+}
@@ -1,319 +1,792 @@
-// use crate::{
-//     ledger_suite_manager::{
-//         discover_archives::{self, select_equal_to},
-//         display_iter,
-//     },
-//     logs::{DEBUG, INFO},
-//     management::{CallError, CanisterRuntime},
-//     state::{
-//         read_state, Archive, Canister, Erc20Token, Index, Ledger, ManagedCanisterStatus, WasmHash,
-//     },
-//     storage::{read_wasm_store, wasm_store_try_get, StorableWasm, WasmStoreError},
-// };
-// use candid::Principal;
-// use ic_canister_log::log;
-// use serde::{Deserialize, Serialize};
-
-// use super::discover_archives::{discover_archives, DiscoverArchivesError};
-
-// #[derive(Clone, Eq, PartialEq, Ord, PartialOrd, Debug, Deserialize, Serialize)]
-// pub struct UpgradeLedgerSuite {
-//     subtasks: Vec<UpgradeLedgerSuiteSubtask>,
-//     next_subtask_index: usize,
-// }
-
-// impl UpgradeLedgerSuite {
-//     /// Create a new upgrade ledger suite task containing multiple subtasks
-//     /// depending on which canisters need to be upgraded. Due to the dependencies between the canisters of a ledger suite, e.g.,
-//     /// the index pulls transactions from the ledger, the order of the subtasks is important.
-//     ///
-//     /// The order of the subtasks is as follows:
-//     /// 1. Upgrade the index canister
-//     /// 2. Upgrade the ledger canister
-//     /// 3. Fetch the list of archives from the ledger and upgrade all archive canisters
-//     ///
-//     /// For each canister, upgrading involves 3 (potentially failing) steps:
-//     /// 1. Stop the canister
-//     /// 2. Upgrade the canister
-//     /// 3. Start the canister
-//     ///
-//     /// Note that after having upgraded the index, but before having upgraded the ledger, the upgraded index may fetch information from the not yet upgraded ledger.
-//     /// However, this is deemed preferable to trying to do some kind of atomic upgrade,
-//     /// where the ledger would be stopped before upgrading the index, since this would result in 2 canisters being stopped at the same time,
-//     /// which could be more problematic, especially if for some unexpected reason the upgrade fails.
-//     fn new(
-//         token_id: Erc20Token,
-//         ledger_compressed_wasm_hash: Option<WasmHash>,
-//         index_compressed_wasm_hash: Option<WasmHash>,
-//         archive_compressed_wasm_hash: Option<WasmHash>,
-//     ) -> Self {
-//         let mut subtasks = Vec::new();
-//         if let Some(index_compressed_wasm_hash) = index_compressed_wasm_hash {
-//             subtasks.push(UpgradeLedgerSuiteSubtask::UpgradeIndex {
-//                 token_id: token_id.clone(),
-//                 compressed_wasm_hash: index_compressed_wasm_hash,
-//             });
-//         }
-//         if let Some(ledger_compressed_wasm_hash) = ledger_compressed_wasm_hash {
-//             subtasks.push(UpgradeLedgerSuiteSubtask::UpgradeLedger {
-//                 token_id: token_id.clone(),
-//                 compressed_wasm_hash: ledger_compressed_wasm_hash,
-//             });
-//         }
-//         if let Some(archive_compressed_wasm_hash) = archive_compressed_wasm_hash {
-//             subtasks.push(UpgradeLedgerSuiteSubtask::DiscoverArchives {
-//                 token_id: token_id.clone(),
-//             });
-//             subtasks.push(UpgradeLedgerSuiteSubtask::UpgradeArchives {
-//                 token_id: token_id.clone(),
-//                 compressed_wasm_hash: archive_compressed_wasm_hash,
-//             });
-//         }
-//         Self {
-//             subtasks,
-//             next_subtask_index: 0,
-//         }
-//     }
-
-//     fn builder(token_id: Erc20Token) -> UpgradeLedgerSuiteBuilder {
-//         UpgradeLedgerSuiteBuilder::new(token_id)
-//     }
-// }
-
-// #[derive(Clone, Eq, PartialEq, Ord, PartialOrd, Debug, Deserialize, Serialize)]
-// pub enum UpgradeLedgerSuiteSubtask {
-//     UpgradeIndex {
-//         token_id: Erc20Token,
-//         compressed_wasm_hash: WasmHash,
-//     },
-//     UpgradeLedger {
-//         token_id: Erc20Token,
-//         compressed_wasm_hash: WasmHash,
-//     },
-//     DiscoverArchives {
-//         token_id: Erc20Token,
-//     },
-//     UpgradeArchives {
-//         token_id: Erc20Token,
-//         compressed_wasm_hash: WasmHash,
-//     },
-// }
-
-// struct UpgradeLedgerSuiteBuilder {
-//     token_id: Erc20Token,
-//     ledger_wasm_hash: Option<WasmHash>,
-//     index_wasm_hash: Option<WasmHash>,
-//     archive_wasm_hash: Option<WasmHash>,
-// }
-
-// impl UpgradeLedgerSuiteBuilder {
-//     fn new(token_id: Erc20Token) -> Self {
-//         Self {
-//             token_id,
-//             ledger_wasm_hash: None,
-//             index_wasm_hash: None,
-//             archive_wasm_hash: None,
-//         }
-//     }
-
-//     fn ledger_wasm_hash<T: Into<Option<WasmHash>>>(mut self, ledger_wasm_hash: T) -> Self {
-//         self.ledger_wasm_hash = ledger_wasm_hash.into();
-//         self
-//     }
-
-//     fn index_wasm_hash<T: Into<Option<WasmHash>>>(mut self, index_wasm_hash: T) -> Self {
-//         self.index_wasm_hash = index_wasm_hash.into();
-//         self
-//     }
-
-//     fn archive_wasm_hash<T: Into<Option<WasmHash>>>(mut self, archive_wasm_hash: T) -> Self {
-//         self.archive_wasm_hash = archive_wasm_hash.into();
-//         self
-//     }
-
-//     fn build(self) -> UpgradeLedgerSuite {
-//         UpgradeLedgerSuite::new(
-//             self.token_id,
-//             self.ledger_wasm_hash,
-//             self.index_wasm_hash,
-//             self.archive_wasm_hash,
-//         )
-//     }
-// }
-
-// impl UpgradeLedgerSuiteSubtask {
-//     pub async fn execute<R: CanisterRuntime>(
-//         &self,
-//         runtime: &R,
-//     ) -> Result<(), UpgradeLedgerSuiteError> {
-//         match self {
-//             UpgradeLedgerSuiteSubtask::UpgradeIndex {
-//                 token_id,
-//                 compressed_wasm_hash,
-//             } => {
-//                 log!(
-//                     INFO,
-//                     "Upgrading index canister for {:?} to {}",
-//                     token_id,
-//                     compressed_wasm_hash
-//                 );
-//                 let canisters = read_state(|s| s.managed_canisters(token_id).cloned())
-//                     .ok_or(UpgradeLedgerSuiteError::TokenNotFound(token_id.clone()))?;
-//                 let canister_id = ensure_canister_is_installed(token_id, canisters.index)?;
-//                 upgrade_canister::<Index, _>(canister_id, compressed_wasm_hash, runtime).await
-//             }
-//             UpgradeLedgerSuiteSubtask::UpgradeLedger {
-//                 token_id,
-//                 compressed_wasm_hash,
-//             } => {
-//                 log!(
-//                     INFO,
-//                     "Upgrading ledger canister for {:?} to {}",
-//                     token_id,
-//                     compressed_wasm_hash
-//                 );
-//                 let canisters = read_state(|s| s.managed_canisters(token_id).cloned())
-//                     .ok_or(UpgradeLedgerSuiteError::TokenNotFound(token_id.clone()))?;
-//                 let canister_id = ensure_canister_is_installed(token_id, canisters.ledger)?;
-//                 upgrade_canister::<Ledger, _>(canister_id, compressed_wasm_hash, runtime).await
-//             }
-//             UpgradeLedgerSuiteSubtask::DiscoverArchives { token_id } => {
-//                 log!(INFO, "Discovering archive canister(s) for {:?}", token_id);
-//                 discover_archives(select_equal_to(token_id), runtime)
-//                     .await
-//                     .map_err(UpgradeLedgerSuiteError::DiscoverArchivesError)
-//             }
-//             UpgradeLedgerSuiteSubtask::UpgradeArchives {
-//                 token_id,
-//                 compressed_wasm_hash,
-//             } => {
-//                 let archives = read_state(|s| s.managed_canisters(token_id).cloned())
-//                     .ok_or(UpgradeLedgerSuiteError::TokenNotFound(token_id.clone()))?
-//                     .archives;
-//                 if archives.is_empty() {
-//                     log!(
-//                         INFO,
-//                         "No archive canisters found for {:?}. Skipping upgrade of archives.",
-//                         token_id
-//                     );
-//                     return Ok(());
-//                 }
-//                 log!(
-//                     INFO,
-//                     "Upgrading archive canisters {} for {:?} to {}",
-//                     display_iter(&archives),
-//                     token_id,
-//                     compressed_wasm_hash
-//                 );
-//                 //We expect usually 0 or 1 archive, so a simple sequential strategy is good enough.
-//                 for canister_id in archives {
-//                     upgrade_canister::<Archive, _>(canister_id, compressed_wasm_hash, runtime)
-//                         .await?;
-//                 }
-//                 Ok(())
-//             }
-//         }
-//     }
-// }
-
-// async fn upgrade_canister<T: StorableWasm, R: CanisterRuntime>(
-//     canister_id: Principal,
-//     wasm_hash: &WasmHash,
-//     runtime: &R,
-// ) -> Result<(), UpgradeLedgerSuiteError> {
-//     let wasm = match read_wasm_store(|s| wasm_store_try_get::<T>(s, wasm_hash)) {
-//         Ok(Some(wasm)) => Ok(wasm),
-//         Ok(None) => Err(UpgradeLedgerSuiteError::WasmHashNotFound(wasm_hash.clone())),
-//         Err(e) => Err(UpgradeLedgerSuiteError::WasmStoreError(e)),
-//     }?;
-
-//     log!(DEBUG, "Stopping canister {}", canister_id);
-//     runtime
-//         .stop_canister(canister_id)
-//         .await
-//         .map_err(UpgradeLedgerSuiteError::StopCanisterError)?;
-
-//     log!(
-//         DEBUG,
-//         "Upgrading wasm module of canister {} to {}",
-//         canister_id,
-//         wasm_hash
-//     );
-//     runtime
-//         .upgrade_canister(canister_id, wasm.to_bytes())
-//         .await
-//         .map_err(UpgradeLedgerSuiteError::UpgradeCanisterError)?;
-
-//     log!(DEBUG, "Starting canister {}", canister_id);
-//     runtime
-//         .start_canister(canister_id)
-//         .await
-//         .map_err(UpgradeLedgerSuiteError::StartCanisterError)?;
-
-//     log!(
-//         DEBUG,
-//         "Upgrade of canister {} to {} completed",
-//         canister_id,
-//         wasm_hash
-//     );
-//     Ok(())
-// }
-
-// #[derive(Clone, PartialEq, Debug)]
-// pub enum UpgradeLedgerSuiteError {
-//     TokenNotFound(Erc20Token),
-//     CanisterNotReady {
-//         token_id: Erc20Token,
-//         status: Option<ManagedCanisterStatus>,
-//         message: String,
-//     },
-//     StopCanisterError(CallError),
-//     StartCanisterError(CallError),
-//     UpgradeCanisterError(CallError),
-//     WasmHashNotFound(WasmHash),
-//     WasmStoreError(WasmStoreError),
-//     DiscoverArchivesError(DiscoverArchivesError),
-// }
-
-// impl UpgradeLedgerSuiteError {
-//     fn is_recoverable(&self) -> bool {
-//         match self {
-//             UpgradeLedgerSuiteError::TokenNotFound(_) => false,
-//             UpgradeLedgerSuiteError::CanisterNotReady { .. } => true,
-//             UpgradeLedgerSuiteError::WasmHashNotFound(_) => false,
-//             UpgradeLedgerSuiteError::WasmStoreError(_) => false,
-//             UpgradeLedgerSuiteError::StopCanisterError(_) => true,
-//             UpgradeLedgerSuiteError::StartCanisterError(_) => true,
-//             UpgradeLedgerSuiteError::UpgradeCanisterError(_) => true,
-//             UpgradeLedgerSuiteError::DiscoverArchivesError(e) => e.is_recoverable(),
-//         }
-//     }
-// }
-
-// fn ensure_canister_is_installed<T>(
-//     token_id: &Erc20Token,
-//     canister: Option<Canister<T>>,
-// ) -> Result<Principal, UpgradeLedgerSuiteError> {
-//     match canister {
-//         None => Err(UpgradeLedgerSuiteError::CanisterNotReady {
-//             token_id: token_id.clone(),
-//             status: None,
-//             message: "canister not yet created".to_string(),
-//         }),
-//         Some(canister) => match canister.status() {
-//             ManagedCanisterStatus::Created { canister_id } => {
-//                 Err(UpgradeLedgerSuiteError::CanisterNotReady {
-//                     token_id: token_id.clone(),
-//                     status: Some(ManagedCanisterStatus::Created {
-//                         canister_id: *canister_id,
-//                     }),
-//                     message: "canister not yet installed".to_string(),
-//                 })
-//             }
-//             ManagedCanisterStatus::Installed {
-//                 canister_id,
-//                 installed_wasm_hash: _,
-//             } => Ok(*canister_id),
-//         },
-//     }
-// }
+use crate::{
+    ledger_suite_manager::{
+        discover_archives::{discover_archives, select_equal_to, DiscoverArchivesError},
+        display_iter,
+        health_check::{HealthBaseline, IndexHealth, LedgerHealth, UpgradeRollback},
+        install_ls::{install_wasm, ArchiveProfile},
+    },
+    logs::{DEBUG, ERROR, INFO},
+    management::{CallError, CanisterRuntime, InstallMode},
+    state::{
+        mutate_state, read_state, Archive, Canister, CanisterKind, CanisterSnapshot, Canisters,
+        Erc20Token, Index, Ledger, ManageSingleCanister, ManagedCanisterStatus, WasmHash,
+    },
+    storage::{read_wasm_store, wasm_store_try_get, StorableWasm, WasmStoreError},
+};
+use candid::{Encode, Principal};
+use ic_canister_log::log;
+use ic_icrc1_ledger::{LedgerArgument, UpgradeArgs as LedgerUpgradeArgs};
+use serde::{Deserialize, Serialize};
+
+#[derive(Clone, PartialEq, Debug, Deserialize, Serialize)]
+pub struct UpgradeLedgerSuite {
+    subtasks: Vec<UpgradeLedgerSuiteSubtask>,
+    next_subtask_index: usize,
+    /// Whether the canister of the next subtask was already snapshotted by this upgrade,
+    /// in which case retrying the subtask keeps that snapshot.
+    #[serde(default)]
+    next_subtask_snapshot_taken: bool,
+    /// Health of the canister of the next subtask probed before the canister was first stopped.
+    /// A retried subtask, whose canister may already be upgraded, is checked against it.
+    #[serde(default)]
+    next_subtask_health_baseline: Option<HealthBaseline>,
+}
+
+impl UpgradeLedgerSuite {
+    /// Create a new upgrade ledger suite task containing multiple subtasks
+    /// depending on which canisters need to be upgraded. Due to the dependencies between the canisters of a ledger suite, e.g.,
+    /// the index pulls transactions from the ledger, the order of the subtasks is important.
+    ///
+    /// The order of the subtasks is as follows:
+    /// 1. Upgrade the index canister
+    /// 2. Upgrade the ledger canister
+    /// 3. Fetch the list of archives from the ledger and upgrade all archive canisters
+    ///
+    /// For each canister, upgrading involves 3 (potentially failing) steps:
+    /// 1. Stop the canister
+    /// 2. Upgrade the canister
+    /// 3. Start the canister
+    ///
+    /// Once the index or the ledger is stopped and before it is upgraded, a snapshot of it is taken,
+    /// which can be loaded by an admin if the upgraded canister turns out to have a corrupted state.
+    /// A retried subtask keeps the snapshot it already took.
+    ///
+    /// After the upgrade of the index or the ledger, the canister is probed and compared with the values it reported
+    /// before the upgrade. If the canister is unhealthy, the previously installed wasm is reinstalled in upgrade mode.
+    /// The values reported before the upgrade are probed once, before the canister is first stopped,
+    /// and the subtask fails without stopping the canister if they cannot be probed.
+    ///
+    /// Note that after having upgraded the index, but before having upgraded the ledger, the upgraded index may fetch information from the not yet upgraded ledger.
+    /// However, this is deemed preferable to trying to do some kind of atomic upgrade,
+    /// where the ledger would be stopped before upgrading the index, since this would result in 2 canisters being stopped at the same time,
+    /// which could be more problematic, especially if for some unexpected reason the upgrade fails.
+    fn new(
+        token_id: Erc20Token,
+        ledger_compressed_wasm_hash: Option<WasmHash>,
+        index_compressed_wasm_hash: Option<WasmHash>,
+        archive_compressed_wasm_hash: Option<WasmHash>,
+    ) -> Self {
+        let mut subtasks = Vec::new();
+        if let Some(index_compressed_wasm_hash) = index_compressed_wasm_hash {
+            subtasks.push(UpgradeLedgerSuiteSubtask::UpgradeIndex {
+                token_id: token_id.clone(),
+                compressed_wasm_hash: index_compressed_wasm_hash,
+            });
+        }
+        if let Some(ledger_compressed_wasm_hash) = ledger_compressed_wasm_hash {
+            subtasks.push(UpgradeLedgerSuiteSubtask::UpgradeLedger {
+                token_id: token_id.clone(),
+                compressed_wasm_hash: ledger_compressed_wasm_hash,
+            });
+        }
+        if let Some(archive_compressed_wasm_hash) = archive_compressed_wasm_hash {
+            subtasks.push(UpgradeLedgerSuiteSubtask::DiscoverArchives {
+                token_id: token_id.clone(),
+            });
+            subtasks.push(UpgradeLedgerSuiteSubtask::UpgradeArchives {
+                token_id: token_id.clone(),
+                compressed_wasm_hash: archive_compressed_wasm_hash,
+            });
+        }
+        Self {
+            subtasks,
+            next_subtask_index: 0,
+            next_subtask_snapshot_taken: false,
+            next_subtask_health_baseline: None,
+        }
+    }
+
+    pub fn builder(token_id: Erc20Token) -> UpgradeLedgerSuiteBuilder {
+        UpgradeLedgerSuiteBuilder::new(token_id)
+    }
+
+    pub fn subtasks(&self) -> &[UpgradeLedgerSuiteSubtask] {
+        &self.subtasks
+    }
+
+    pub fn next_subtask_index(&self) -> usize {
+        self.next_subtask_index
+    }
+
+    pub fn next_subtask_snapshot_taken(&self) -> bool {
+        self.next_subtask_snapshot_taken
+    }
+
+    pub fn record_next_subtask_snapshot_taken(&mut self) {
+        self.next_subtask_snapshot_taken = true;
+    }
+
+    pub fn next_subtask_health_baseline(&self) -> Option<&HealthBaseline> {
+        self.next_subtask_health_baseline.as_ref()
+    }
+
+    pub fn record_next_subtask_health_baseline(&mut self, baseline: HealthBaseline) {
+        self.next_subtask_health_baseline = Some(baseline);
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.subtasks.is_empty()
+    }
+
+    pub fn is_completed(&self) -> bool {
+        self.next_subtask_index >= self.subtasks.len()
+    }
+
+    /// Returns `true` if the archives still need to be upgraded.
+    pub fn has_pending_archives_upgrade(&self) -> bool {
+        self.subtasks
+            .iter()
+            .skip(self.next_subtask_index)
+            .any(|subtask| matches!(subtask, UpgradeLedgerSuiteSubtask::UpgradeArchives { .. }))
+    }
+
+    /// Returns the hashes of the wasms still to be installed by the remaining subtasks.
+    pub fn remaining_wasm_hashes(&self) -> impl Iterator<Item = &WasmHash> {
+        self.subtasks
+            .iter()
+            .skip(self.next_subtask_index)
+            .filter_map(UpgradeLedgerSuiteSubtask::compressed_wasm_hash)
+    }
+
+    /// Marks the next subtask as done.
+    /// Returns `true` if all subtasks are done.
+    pub fn complete_next_subtask(&mut self) -> bool {
+        assert!(
+            !self.is_completed(),
+            "BUG: all subtasks of {:?} are already completed",
+            self
+        );
+        self.next_subtask_index += 1;
+        self.next_subtask_snapshot_taken = false;
+        self.next_subtask_health_baseline = None;
+        self.is_completed()
+    }
+
+    /// Executes the remaining subtasks, starting at `next_subtask_index`.
+    ///
+    /// Progress is persisted in the state after each successful subtask,
+    /// so that a failed or interrupted upgrade resumes where it stopped.
+    pub async fn execute<R: CanisterRuntime>(
+        &self,
+        token_id: &Erc20Token,
+        runtime: &R,
+    ) -> Result<(), UpgradeLedgerSuiteError> {
+        for subtask in self.subtasks.iter().skip(self.next_subtask_index) {
+            subtask.execute(runtime).await?;
+            mutate_state(|s| s.record_completed_upgrade_subtask(token_id));
+        }
+        Ok(())
+    }
+}
+
+#[derive(Clone, Eq, PartialEq, Ord, PartialOrd, Debug, Deserialize, Serialize)]
+pub enum UpgradeLedgerSuiteSubtask {
+    UpgradeIndex {
+        token_id: Erc20Token,
+        compressed_wasm_hash: WasmHash,
+    },
+    UpgradeLedger {
+        token_id: Erc20Token,
+        compressed_wasm_hash: WasmHash,
+    },
+    DiscoverArchives {
+        token_id: Erc20Token,
+    },
+    UpgradeArchives {
+        token_id: Erc20Token,
+        compressed_wasm_hash: WasmHash,
+    },
+}
+
+pub struct UpgradeLedgerSuiteBuilder {
+    token_id: Erc20Token,
+    ledger_wasm_hash: Option<WasmHash>,
+    index_wasm_hash: Option<WasmHash>,
+    archive_wasm_hash: Option<WasmHash>,
+}
+
+impl UpgradeLedgerSuiteBuilder {
+    fn new(token_id: Erc20Token) -> Self {
+        Self {
+            token_id,
+            ledger_wasm_hash: None,
+            index_wasm_hash: None,
+            archive_wasm_hash: None,
+        }
+    }
+
+    pub fn ledger_wasm_hash<T: Into<Option<WasmHash>>>(mut self, ledger_wasm_hash: T) -> Self {
+        self.ledger_wasm_hash = ledger_wasm_hash.into();
+        self
+    }
+
+    pub fn index_wasm_hash<T: Into<Option<WasmHash>>>(mut self, index_wasm_hash: T) -> Self {
+        self.index_wasm_hash = index_wasm_hash.into();
+        self
+    }
+
+    pub fn archive_wasm_hash<T: Into<Option<WasmHash>>>(mut self, archive_wasm_hash: T) -> Self {
+        self.archive_wasm_hash = archive_wasm_hash.into();
+        self
+    }
+
+    pub fn build(self) -> UpgradeLedgerSuite {
+        UpgradeLedgerSuite::new(
+            self.token_id,
+            self.ledger_wasm_hash,
+            self.index_wasm_hash,
+            self.archive_wasm_hash,
+        )
+    }
+}
+
+impl UpgradeLedgerSuiteSubtask {
+    pub fn compressed_wasm_hash(&self) -> Option<&WasmHash> {
+        match self {
+            UpgradeLedgerSuiteSubtask::UpgradeIndex {
+                compressed_wasm_hash,
+                ..
+            }
+            | UpgradeLedgerSuiteSubtask::UpgradeLedger {
+                compressed_wasm_hash,
+                ..
+            }
+            | UpgradeLedgerSuiteSubtask::UpgradeArchives {
+                compressed_wasm_hash,
+                ..
+            } => Some(compressed_wasm_hash),
+            UpgradeLedgerSuiteSubtask::DiscoverArchives { .. } => None,
+        }
+    }
+
+    pub async fn execute<R: CanisterRuntime>(
+        &self,
+        runtime: &R,
+    ) -> Result<(), UpgradeLedgerSuiteError> {
+        match self {
+            UpgradeLedgerSuiteSubtask::UpgradeIndex {
+                token_id,
+                compressed_wasm_hash,
+            } => {
+                log!(
+                    INFO,
+                    "Upgrading index canister for {:?} to {}",
+                    token_id,
+                    compressed_wasm_hash
+                );
+                let canisters = read_state(|s| s.managed_canisters(token_id).cloned())
+                    .ok_or(UpgradeLedgerSuiteError::TokenNotFound(token_id.clone()))?;
+                let ledger_id = canisters.ledger_canister_id().cloned();
+                let (canister_id, previous_wasm_hash) =
+                    ensure_canister_is_installed(token_id, canisters.index)?;
+                let before = match read_state(|s| s.upgrade_health_baseline(token_id)) {
+                    Some(HealthBaseline::Index(before)) => before,
+                    _ => {
+                        let before = IndexHealth::probe(canister_id, runtime)
+                            .await
+                            .map_err(UpgradeLedgerSuiteError::HealthProbeError)?;
+                        mutate_state(|s| {
+                            s.record_upgrade_health_baseline(
+                                token_id,
+                                HealthBaseline::Index(before.clone()),
+                            )
+                        });
+                        before
+                    }
+                };
+                upgrade_canister::<Index, _>(
+                    canister_id,
+                    compressed_wasm_hash,
+                    empty_upgrade_arg(),
+                    Some(SnapshotBeforeUpgrade {
+                        token_id,
+                        kind: CanisterKind::Index,
+                        installed_wasm_hash: &previous_wasm_hash,
+                    }),
+                    runtime,
+                )
+                .await?;
+                let health = match IndexHealth::probe(canister_id, runtime).await {
+                    Ok(after) => after.check_consistent_with(ledger_id.as_ref(), &before),
+                    Err(e) => Err(format!("health probe failed: {}", e)),
+                };
+                if let Err(reason) = health {
+                    return Err(roll_back_upgrade::<Index, _>(
+                        token_id,
+                        canister_id,
+                        CanisterKind::Index,
+                        compressed_wasm_hash,
+                        previous_wasm_hash,
+                        empty_upgrade_arg(),
+                        reason,
+                        runtime,
+                    )
+                    .await);
+                }
+                record_upgraded_canister::<Index>(token_id, compressed_wasm_hash);
+                Ok(())
+            }
+            UpgradeLedgerSuiteSubtask::UpgradeLedger {
+                token_id,
+                compressed_wasm_hash,
+            } => {
+                log!(
+                    INFO,
+                    "Upgrading ledger canister for {:?} to {}",
+                    token_id,
+                    compressed_wasm_hash
+                );
+                let canisters = read_state(|s| s.managed_canisters(token_id).cloned())
+                    .ok_or(UpgradeLedgerSuiteError::TokenNotFound(token_id.clone()))?;
+                let (canister_id, previous_wasm_hash) =
+                    ensure_canister_is_installed(token_id, canisters.ledger)?;
+                let upgrade_arg = ledger_upgrade_arg(canisters.archive_profile.as_ref());
+                let before = match read_state(|s| s.upgrade_health_baseline(token_id)) {
+                    Some(HealthBaseline::Ledger(before)) => before,
+                    _ => {
+                        let before = LedgerHealth::probe(canister_id, runtime)
+                            .await
+                            .map_err(UpgradeLedgerSuiteError::HealthProbeError)?;
+                        mutate_state(|s| {
+                            s.record_upgrade_health_baseline(
+                                token_id,
+                                HealthBaseline::Ledger(before.clone()),
+                            )
+                        });
+                        before
+                    }
+                };
+                upgrade_canister::<Ledger, _>(
+                    canister_id,
+                    compressed_wasm_hash,
+                    upgrade_arg.clone(),
+                    Some(SnapshotBeforeUpgrade {
+                        token_id,
+                        kind: CanisterKind::Ledger,
+                        installed_wasm_hash: &previous_wasm_hash,
+                    }),
+                    runtime,
+                )
+                .await?;
+                let health = match LedgerHealth::probe(canister_id, runtime).await {
+                    Ok(after) => after.check_consistent_with(&before),
+                    Err(e) => Err(format!("health probe failed: {}", e)),
+                };
+                if let Err(reason) = health {
+                    return Err(roll_back_upgrade::<Ledger, _>(
+                        token_id,
+                        canister_id,
+                        CanisterKind::Ledger,
+                        compressed_wasm_hash,
+                        previous_wasm_hash,
+                        upgrade_arg,
+                        reason,
+                        runtime,
+                    )
+                    .await);
+                }
+                record_upgraded_canister::<Ledger>(token_id, compressed_wasm_hash);
+                Ok(())
+            }
+            UpgradeLedgerSuiteSubtask::DiscoverArchives { token_id } => {
+                log!(INFO, "Discovering archive canister(s) for {:?}", token_id);
+                discover_archives(select_equal_to(token_id), runtime)
+                    .await
+                    .map_err(UpgradeLedgerSuiteError::DiscoverArchivesError)
+            }
+            UpgradeLedgerSuiteSubtask::UpgradeArchives {
+                token_id,
+                compressed_wasm_hash,
+            } => {
+                let archives = read_state(|s| s.managed_canisters(token_id).cloned())
+                    .ok_or(UpgradeLedgerSuiteError::TokenNotFound(token_id.clone()))?
+                    .archives;
+                if archives.is_empty() {
+                    log!(
+                        INFO,
+                        "No archive canisters found for {:?}. Skipping upgrade of archives.",
+                        token_id
+                    );
+                    return Ok(());
+                }
+                log!(
+                    INFO,
+                    "Upgrading archive canisters {} for {:?} to {}",
+                    display_iter(&archives),
+                    token_id,
+                    compressed_wasm_hash
+                );
+                //We expect usually 0 or 1 archive, so a simple sequential strategy is good enough.
+                for canister_id in archives {
+                    upgrade_canister::<Archive, _>(
+                        canister_id,
+                        compressed_wasm_hash,
+                        empty_upgrade_arg(),
+                        None,
+                        runtime,
+                    )
+                    .await?;
+                    mutate_state(|s| {
+                        s.record_upgraded_archive(canister_id, compressed_wasm_hash.clone())
+                    });
+                }
+                Ok(())
+            }
+        }
+    }
+}
+
+/// Ledger, index and archive canisters all accept an empty upgrade argument.
+fn empty_upgrade_arg() -> Vec<u8> {
+    Encode!().expect("BUG: failed to encode empty upgrade arg")
+}
+
+/// Re-applies the archive profile of the ledger, if any.
+fn ledger_upgrade_arg(archive_profile: Option<&ArchiveProfile>) -> Vec<u8> {
+    match archive_profile {
+        Some(archive_profile) => Encode!(&LedgerArgument::Upgrade(Some(LedgerUpgradeArgs {
+            change_archive_options: Some(archive_profile.change_archive_options()),
+            ..Default::default()
+        })))
+        .expect("BUG: failed to encode ledger upgrade arg"),
+        None => empty_upgrade_arg(),
+    }
+}
+
+/// The ledger or index to snapshot once it is stopped, before its wasm is upgraded.
+struct SnapshotBeforeUpgrade<'a> {
+    token_id: &'a Erc20Token,
+    kind: CanisterKind,
+    installed_wasm_hash: &'a WasmHash,
+}
+
+async fn upgrade_canister<T: StorableWasm, R: CanisterRuntime>(
+    canister_id: Principal,
+    wasm_hash: &WasmHash,
+    upgrade_arg: Vec<u8>,
+    snapshot: Option<SnapshotBeforeUpgrade<'_>>,
+    runtime: &R,
+) -> Result<(), UpgradeLedgerSuiteError> {
+    let wasm = match read_wasm_store(|s| wasm_store_try_get::<T>(s, wasm_hash)) {
+        Ok(Some(wasm)) => Ok(wasm),
+        Ok(None) => Err(UpgradeLedgerSuiteError::WasmHashNotFound(wasm_hash.clone())),
+        Err(e) => Err(UpgradeLedgerSuiteError::WasmStoreError(e)),
+    }?;
+
+    log!(DEBUG, "Stopping canister {}", canister_id);
+    runtime
+        .stop_canister(canister_id)
+        .await
+        .map_err(UpgradeLedgerSuiteError::StopCanisterError)?;
+
+    if let Some(snapshot) = snapshot {
+        if let Err(e) = take_canister_snapshot(
+            snapshot.token_id,
+            canister_id,
+            snapshot.kind,
+            snapshot.installed_wasm_hash,
+            runtime,
+        )
+        .await
+        {
+            // the upgrade is retried later, the canister must not stay stopped meanwhile
+            if let Err(start_error) = runtime.start_canister(canister_id).await {
+                log!(
+                    INFO,
+                    "Failed to restart canister {} after failing to snapshot it: {}",
+                    canister_id,
+                    start_error
+                );
+            }
+            return Err(e);
+        }
+    }
+
+    log!(
+        DEBUG,
+        "Upgrading wasm module of canister {} to {}",
+        canister_id,
+        wasm_hash
+    );
+    install_wasm(
+        canister_id,
+        InstallMode::Upgrade,
+        wasm,
+        upgrade_arg,
+        runtime,
+    )
+    .await
+    .map_err(UpgradeLedgerSuiteError::UpgradeCanisterError)?;
+
+    log!(DEBUG, "Starting canister {}", canister_id);
+    runtime
+        .start_canister(canister_id)
+        .await
+        .map_err(UpgradeLedgerSuiteError::StartCanisterError)?;
+
+    log!(
+        DEBUG,
+        "Upgrade of canister {} to {} completed",
+        canister_id,
+        wasm_hash
+    );
+    Ok(())
+}
+
+/// Takes a snapshot of the canister, replacing its previous snapshot, and records it on the token,
+/// unless the upgrade of the token already took a snapshot of the canister.
+async fn take_canister_snapshot<R: CanisterRuntime>(
+    token_id: &Erc20Token,
+    canister_id: Principal,
+    kind: CanisterKind,
+    installed_wasm_hash: &WasmHash,
+    runtime: &R,
+) -> Result<(), UpgradeLedgerSuiteError> {
+    let already_taken = read_state(|s| {
+        s.ledger_suite_upgrade(token_id)
+            .is_some_and(UpgradeLedgerSuite::next_subtask_snapshot_taken)
+    });
+    if already_taken {
+        log!(
+            DEBUG,
+            "Keeping snapshot of canister {} taken before the upgrade was retried",
+            canister_id
+        );
+        return Ok(());
+    }
+    let replace_snapshot = read_state(|s| {
+        s.managed_canisters(token_id)
+            .and_then(|canisters| canisters.snapshots.get(&kind))
+            .map(|snapshot| snapshot.snapshot_id.clone())
+    });
+    log!(DEBUG, "Taking snapshot of canister {}", canister_id);
+    let snapshot = runtime
+        .take_canister_snapshot(canister_id, replace_snapshot)
+        .await
+        .map_err(UpgradeLedgerSuiteError::TakeCanisterSnapshotError)?;
+    mutate_state(|s| {
+        s.record_canister_snapshot(
+            token_id,
+            kind,
+            CanisterSnapshot {
+                snapshot_id: snapshot.id,
+                wasm_hash: installed_wasm_hash.clone(),
+                taken_at: snapshot.taken_at_timestamp,
+            },
+        );
+        s.record_upgrade_snapshot_taken(token_id);
+    });
+    Ok(())
+}
+
+/// Reinstalls the previously installed wasm on a canister whose health checks failed after its upgrade
+/// and records the rollback on the token.
+/// The previous wasm is installed with the same upgrade argument as the failed upgrade.
+///
+/// Returns the error to report for the failed upgrade.
+async fn roll_back_upgrade<T: StorableWasm, R: CanisterRuntime>(
+    token_id: &Erc20Token,
+    canister_id: Principal,
+    kind: CanisterKind,
+    failed_wasm_hash: &WasmHash,
+    previous_wasm_hash: WasmHash,
+    upgrade_arg: Vec<u8>,
+    reason: String,
+    runtime: &R,
+) -> UpgradeLedgerSuiteError {
+    log!(
+        ERROR,
+        "Health check of canister {} for {:?} failed after upgrade to {}: {}. Rolling back to {}",
+        canister_id,
+        token_id,
+        failed_wasm_hash,
+        reason,
+        previous_wasm_hash
+    );
+    if let Err(e) =
+        upgrade_canister::<T, _>(canister_id, &previous_wasm_hash, upgrade_arg, None, runtime).await
+    {
+        log!(
+            ERROR,
+            "Failed to roll back canister {} for {:?} to {}: {:?}",
+            canister_id,
+            token_id,
+            previous_wasm_hash,
+            e
+        );
+        return UpgradeLedgerSuiteError::RollbackFailed {
+            canister_id,
+            reason,
+            error: Box::new(e),
+        };
+    }
+    let rollback = UpgradeRollback {
+        canister_id,
+        kind,
+        failed_wasm_hash: failed_wasm_hash.clone(),
+        restored_wasm_hash: previous_wasm_hash,
+        reason: reason.clone(),
+        rolled_back_at: runtime.time(),
+    };
+    mutate_state(|s| s.record_upgrade_rollback(token_id, rollback));
+    UpgradeLedgerSuiteError::HealthCheckFailed {
+        canister_id,
+        reason,
+    }
+}
+
+fn record_upgraded_canister<T>(token_id: &Erc20Token, wasm_hash: &WasmHash)
+where
+    Canisters: ManageSingleCanister<T>,
+{
+    mutate_state(|s| s.record_installed_canister::<T>(token_id, wasm_hash.clone()));
+}
+
+#[derive(Clone, PartialEq, Debug)]
+pub enum UpgradeLedgerSuiteError {
+    TokenNotFound(Erc20Token),
+    CanisterNotReady {
+        token_id: Erc20Token,
+        status: Option<ManagedCanisterStatus>,
+        message: String,
+    },
+    TakeCanisterSnapshotError(CallError),
+    /// The canister could not be probed before its upgrade.
+    HealthProbeError(CallError),
+    StopCanisterError(CallError),
+    StartCanisterError(CallError),
+    UpgradeCanisterError(CallError),
+    WasmHashNotFound(WasmHash),
+    WasmStoreError(WasmStoreError),
+    DiscoverArchivesError(DiscoverArchivesError),
+    /// The canister was rolled back to its previous wasm after failing its post-upgrade health checks.
+    HealthCheckFailed {
+        canister_id: Principal,
+        reason: String,
+    },
+    /// The canister failed its post-upgrade health checks and could not be rolled back.
+    RollbackFailed {
+        canister_id: Principal,
+        reason: String,
+        error: Box<UpgradeLedgerSuiteError>,
+    },
+}
+
+impl UpgradeLedgerSuiteError {
+    pub fn is_recoverable(&self) -> bool {
+        match self {
+            UpgradeLedgerSuiteError::TokenNotFound(_) => false,
+            UpgradeLedgerSuiteError::CanisterNotReady { .. } => true,
+            UpgradeLedgerSuiteError::WasmHashNotFound(_) => false,
+            UpgradeLedgerSuiteError::WasmStoreError(_) => false,
+            UpgradeLedgerSuiteError::TakeCanisterSnapshotError(_) => true,
+            UpgradeLedgerSuiteError::HealthProbeError(_) => true,
+            UpgradeLedgerSuiteError::StopCanisterError(_) => true,
+            UpgradeLedgerSuiteError::StartCanisterError(_) => true,
+            UpgradeLedgerSuiteError::UpgradeCanisterError(_) => true,
+            UpgradeLedgerSuiteError::DiscoverArchivesError(e) => e.is_recoverable(),
+            UpgradeLedgerSuiteError::HealthCheckFailed { .. } => false,
+            UpgradeLedgerSuiteError::RollbackFailed { .. } => false,
+        }
+    }
+}
+
+#[derive(Clone, PartialEq, Debug)]
+pub enum LoadCanisterSnapshotError {
+    TokenNotFound(Erc20Token),
+    SnapshotNotFound {
+        token_id: Erc20Token,
+        kind: CanisterKind,
+    },
+    StopCanisterError(CallError),
+    LoadCanisterSnapshotError(CallError),
+    StartCanisterError(CallError),
+}
+
+/// Loads the snapshot taken before the last upgrade of the ledger or index of the given token
+/// and records the wasm hash installed at the time of the snapshot.
+pub async fn load_canister_snapshot<R: CanisterRuntime>(
+    token_id: &Erc20Token,
+    kind: CanisterKind,
+    runtime: &R,
+) -> Result<(), LoadCanisterSnapshotError> {
+    let canisters = read_state(|s| s.managed_canisters(token_id).cloned())
+        .ok_or(LoadCanisterSnapshotError::TokenNotFound(token_id.clone()))?;
+    let canister_id = match kind {
+        CanisterKind::Ledger => canisters.ledger_canister_id(),
+        CanisterKind::Index => canisters.index_canister_id(),
+        CanisterKind::Archive => None,
+    };
+    let (canister_id, snapshot) = canister_id.zip(canisters.snapshots.get(&kind)).ok_or(
+        LoadCanisterSnapshotError::SnapshotNotFound {
+            token_id: token_id.clone(),
+            kind,
+        },
+    )?;
+
+    log!(
+        INFO,
+        "Loading snapshot taken at {} of canister {} for {:?}",
+        snapshot.taken_at,
+        canister_id,
+        token_id
+    );
+    runtime
+        .stop_canister(*canister_id)
+        .await
+        .map_err(LoadCanisterSnapshotError::StopCanisterError)?;
+    if let Err(e) = runtime
+        .load_canister_snapshot(*canister_id, snapshot.snapshot_id.clone())
+        .await
+    {
+        // the canister keeps its current state and must not stay stopped
+        if let Err(start_error) = runtime.start_canister(*canister_id).await {
+            log!(
+                INFO,
+                "Failed to restart canister {} after failing to load its snapshot: {}",
+                canister_id,
+                start_error
+            );
+        }
+        return Err(LoadCanisterSnapshotError::LoadCanisterSnapshotError(e));
+    }
+    match kind {
+        CanisterKind::Ledger => record_upgraded_canister::<Ledger>(token_id, &snapshot.wasm_hash),
+        CanisterKind::Index => record_upgraded_canister::<Index>(token_id, &snapshot.wasm_hash),
+        CanisterKind::Archive => unreachable!("BUG: snapshots of archives are not supported"),
+    }
+    runtime
+        .start_canister(*canister_id)
+        .await
+        .map_err(LoadCanisterSnapshotError::StartCanisterError)?;
+    Ok(())
+}
+
+/// Returns the canister id and the installed wasm hash of the given canister.
+fn ensure_canister_is_installed<T>(
+    token_id: &Erc20Token,
+    canister: Option<Canister<T>>,
+) -> Result<(Principal, WasmHash), UpgradeLedgerSuiteError> {
+    match canister {
+        None => Err(UpgradeLedgerSuiteError::CanisterNotReady {
+            token_id: token_id.clone(),
+            status: None,
+            message: "canister not yet created".to_string(),
+        }),
+        Some(canister) => match canister.status() {
+            ManagedCanisterStatus::Created { canister_id } => {
+                Err(UpgradeLedgerSuiteError::CanisterNotReady {
+                    token_id: token_id.clone(),
+                    status: Some(ManagedCanisterStatus::Created {
+                        canister_id: *canister_id,
+                    }),
+                    message: "canister not yet installed".to_string(),
+                })
+            }
+            ManagedCanisterStatus::Installed {
+                canister_id,
+                installed_wasm_hash,
+            } => Ok((*canister_id, installed_wasm_hash.clone())),
+        },
+    }
+}
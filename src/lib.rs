@@ -12,6 +12,16 @@ pub mod state;
 pub mod storage;
 
 pub const ICP_TO_CYCLES_CONVERSION_INTERVAL: Duration = Duration::from_secs(24 * 60 * 60);
+pub const REFUND_ICP_DEPOSITS_INTERVAL: Duration = Duration::from_secs(10 * 60);
 pub const DISCOVER_ARCHIVES_INTERVAL: Duration = Duration::from_secs(24 * 60 * 60);
 pub const MAYBE_TOP_OP_INTERVAL: Duration = Duration::from_secs(24 * 60 * 60);
 pub const INSTALL_LEDGER_SUITE_INTERVAL: Duration = Duration::from_secs(1 * 60);
+pub const UPGRADE_LEDGER_SUITE_INTERVAL: Duration = Duration::from_secs(1 * 60);
+pub const NOTIFY_ERC20_ADDED_INTERVAL: Duration = Duration::from_secs(1 * 60);
+pub const CHECK_MODULE_HASH_DRIFT_INTERVAL: Duration = Duration::from_secs(24 * 60 * 60);
+pub const RECONCILE_CANISTER_SETTINGS_INTERVAL: Duration = Duration::from_secs(60 * 60);
+pub const REFILL_CANISTER_POOL_INTERVAL: Duration = Duration::from_secs(10 * 60);
+pub const RECONCILE_CONTROLLERS_INTERVAL: Duration = Duration::from_secs(60 * 60);
+pub const WASM_GARBAGE_COLLECTION_INTERVAL: Duration = Duration::from_secs(24 * 60 * 60);
+// Wasms stored more recently are never garbage collected, e.g. to allow an upload to be followed by an upgrade.
+pub const WASM_RETENTION_PERIOD: Duration = Duration::from_secs(7 * 24 * 60 * 60);
@@ -1,7 +1,10 @@
 use crate::endpoints::{InitArg, UpgradeArg};
 use crate::logs::INFO;
-use crate::state::{init_state, mutate_state, ChainId, State};
-use crate::storage::{mutate_wasm_store, record_icrc1_ledger_suite_wasms};
+use crate::state::{init_state, mutate_state, read_state, ChainId, State};
+use crate::storage::{
+    embedded_ledger_suite_version, find_missing_ledger_suite_wasm, mutate_wasm_store,
+    read_wasm_store, record_icrc1_ledger_suite_wasms, validate_wasm_hashes,
+};
 use candid::{CandidType, Deserialize};
 use ic_canister_log::log;
 
@@ -25,19 +28,56 @@ pub fn init(init_arg: InitArg) {
             .expect("BUG: failed to record icrc1 ledger suite wasms during init");
 
     // Add the ls version (wasm hashes) to the state.
-    generate_state_from_init_args.init_ledger_suite_version(ledger_suite_version);
+    generate_state_from_init_args
+        .init_ledger_suite_version(ledger_suite_version, ic_cdk::api::time());
 
     // Init the state with generated state that includes first ledger suite version.
     init_state(generate_state_from_init_args);
 }
 
 pub fn post_upgrade(upgrade_arg: Option<UpgradeArg>) {
+    mutate_state(|s| s.init_ledger_suite_version_history(ic_cdk::api::time()));
+
+    // Record the embedded wasms first, so that the upgrade arg can refer to them.
+    upgrade_to_embedded_ledger_suite_version();
+
     if let Some(arg) = upgrade_arg {
         log!(INFO, "[init]: upgrading lsm with arg: {:?}", arg);
 
+        let [ledger_compressed_wasm_hash, index_compressed_wasm_hash, archive_compressed_wasm_hash] =
+            read_wasm_store(|w| {
+                validate_wasm_hashes(
+                    w,
+                    arg.ledger_compressed_wasm_hash.as_deref(),
+                    arg.index_compressed_wasm_hash.as_deref(),
+                    arg.archive_compressed_wasm_hash.as_deref(),
+                )
+            })
+            .unwrap_or_else(|e| {
+                ic_cdk::trap(&format!(
+                    "ERROR: invalid wasm hashes in upgrade arg: {:?}",
+                    e
+                ))
+            });
+
         if let Some(update) = arg.cycles_management {
             mutate_state(|s| update.apply(s.cycles_management_mut()));
         }
+        if let Some(update) = arg.install_retry_policy {
+            mutate_state(|s| update.apply(s.install_retry_policy_mut()));
+        }
+        if let Some(max_concurrent_ls_installs) = arg.max_concurrent_ls_installs {
+            if max_concurrent_ls_installs == 0 {
+                ic_cdk::trap("ERROR: max_concurrent_ls_installs must be at least 1");
+            }
+            mutate_state(|s| s.set_max_concurrent_ls_installs(max_concurrent_ls_installs));
+        }
+        if let Some(update) = arg.canister_settings {
+            mutate_state(|s| update.apply(s.canister_settings_mut()));
+        }
+        if let Some(update) = arg.canister_pool {
+            mutate_state(|s| update.apply(s.canister_pool_config_mut()));
+        }
         if let Some(update) = arg.twin_ls_creation_fees {
             mutate_state(|s| s.update_minimum_tokens_for_new_ledger_suite(update.into()));
         }
@@ -50,6 +90,60 @@ pub fn post_upgrade(upgrade_arg: Option<UpgradeArg>) {
             mutate_state(|s| s.record_new_minter_ids(remapped_minter_ids));
         }
 
-        // TODO: Mechanism for upgrading ledger suite wasm hash.
+        if ledger_compressed_wasm_hash.is_some()
+            || index_compressed_wasm_hash.is_some()
+            || archive_compressed_wasm_hash.is_some()
+        {
+            mutate_state(|s| {
+                let current_version = s
+                    .ledger_suite_version()
+                    .cloned()
+                    .expect("BUG: ledger suite version missing");
+                let new_version = current_version.with_wasm_hashes(
+                    ledger_compressed_wasm_hash,
+                    index_compressed_wasm_hash,
+                    archive_compressed_wasm_hash,
+                );
+                log!(
+                    INFO,
+                    "[upgrade]: new ledger suite version {:?}",
+                    new_version
+                );
+                s.update_ledger_suite_version(new_version, ic_cdk::api::time());
+            });
+        }
     }
 }
+
+/// Records the ledger suite wasms embedded in the lsm if they were never activated
+/// and some of them are not yet in the wasm store.
+/// In that case they become the new ledger suite version,
+/// and every managed ledger suite is scheduled for an upgrade.
+///
+/// The wasms of an embedded version that was already activated may have been garbage collected
+/// once the version was replaced, so their absence from the wasm store is not a new version.
+fn upgrade_to_embedded_ledger_suite_version() {
+    let embedded_version = embedded_ledger_suite_version();
+    let was_activated = read_state(|s| {
+        s.ledger_suite_version_history()
+            .iter()
+            .any(|activated| activated.version == embedded_version)
+    });
+    let is_already_recorded =
+        read_wasm_store(|s| find_missing_ledger_suite_wasm(s, &embedded_version).is_none());
+    if was_activated || is_already_recorded {
+        return;
+    }
+
+    let ledger_suite_version =
+        mutate_wasm_store(|s| record_icrc1_ledger_suite_wasms(s, ic_cdk::api::time()))
+            .expect("BUG: failed to record icrc1 ledger suite wasms during upgrade");
+    assert_eq!(ledger_suite_version, embedded_version);
+
+    log!(
+        INFO,
+        "[upgrade]: new embedded ledger suite version {:?}",
+        ledger_suite_version
+    );
+    mutate_state(|s| s.update_ledger_suite_version(ledger_suite_version, ic_cdk::api::time()));
+}
@@ -1,46 +1,68 @@
-use std::collections::BTreeSet;
-use std::time::Duration;
-
-use candid::{Encode, Nat, Principal};
+use candid::{Nat, Principal};
 use ic_canister_log::log;
 use ic_cdk::api::management_canister::main::{
     canister_status, CanisterIdRecord, CanisterStatusResponse,
 };
 use ic_cdk_macros::{init, post_upgrade, query, update};
-use ic_icrc1_index_ng::UpgradeArg;
-use ic_stable_structures::Storable;
 use lsm::appic_helper_client::appic_helper_types::IcpTokenType;
 use lsm::cmc_client::{CmcRunTime, CyclesConvertor};
 use lsm::endpoints::{
-    Erc20Contract, InstalledNativeLedgerSuite, InvalidNativeInstalledCanistersError,
-    LedgerManagerInfo, ManagedCanisterIds, ManagedCanisters, UpdateLedgerSuiteCreationFee,
+    ActivatedLedgerSuiteVersion, ArchiveProfileError, CancelUpgradeCampaignError,
+    CanisterPlacement, CanisterPool, ControllersReport, DiscardFailedInstallError, Erc20Contract,
+    FailedLedgerSuiteInstall, IcpRefundToReconcile, InstalledNativeLedgerSuite,
+    InvalidNativeInstalledCanistersError, LedgerManagerInfo, LedgerSuiteInstallProgress,
+    LedgerSuiteRequestModerationError, LedgerSuiteVersion, LoadCanisterSnapshotArg,
+    LoadCanisterSnapshotError, ManagedCanisterIds, ManagedCanisters, ModuleHashDriftReport,
+    NamedArchiveProfile, PendingLedgerSuiteRequest, PendingNotification, PooledCanister,
+    ReconcileIcpRefundArg, ReconcileIcpRefundError, ResumeFailedInstallArg,
+    ResumeFailedInstallError, ResumeUpgradeCampaignError, RollbackLedgerSuiteVersionError,
+    StartUpgradeCampaignArg, StartUpgradeCampaignError, StoredWasmInfo, TokenDenylists,
+    UpdateControllersArg, UpdateControllersError, UpdateLedgerSuiteCreationFee,
+    UpdateTokenDenylistsArg, UpdateTokenDenylistsError, UpgradeCampaignInfo, UpgradeRollback,
+    UploadWasmArg, UploadWasmError, WasmGarbageCollectionReport, WasmType,
 };
-use lsm::ledger_suite_manager::install_ls::InstallLedgerSuiteArgs;
+use lsm::guard::TimerGuard;
+use lsm::ledger_suite_manager::controllers::reconcile_controllers;
+use lsm::ledger_suite_manager::install_ls::{ArchiveProfile, InstallLedgerSuiteArgs};
+use lsm::ledger_suite_manager::notifications::{LedgerSuiteRequestStatus, Notification};
+use lsm::ledger_suite_manager::upgrade_ls;
 use lsm::ledger_suite_manager::{
-    process_convert_icp_to_cycles, process_discover_archives, process_install_ledger_suites,
-    process_maybe_topup, TaskError,
+    process_check_module_hash_drift, process_convert_icp_to_cycles, process_discover_archives,
+    process_install_ledger_suites, process_maybe_topup, process_notification_outbox,
+    process_reconcile_canister_settings, process_reconcile_controllers,
+    process_refill_canister_pool, process_refund_icp_deposits, process_upgrade_ledger_suites,
+    process_wasm_garbage_collection, PeriodicTasksTypes,
 };
 
 use lsm::appic_helper_client::appic_helper_types::CandidIcpToken;
 use lsm::lifecycle::{self, LSMarg};
 use lsm::logs::INFO;
-use lsm::management::{CanisterRuntime, IcCanisterRuntime};
-use lsm::state::{mutate_state, read_state, Canisters, Erc20Token, Index, LedgerSuiteVersion};
-use lsm::storage::{read_wasm_store, wasm_store_try_get};
+use lsm::management::IcCanisterRuntime;
+use lsm::state::{
+    mutate_state, read_state, Archive, Canisters, Erc20Token, IcpRefund, Index, Ledger, WasmHash,
+};
+use lsm::storage::{
+    find_missing_ledger_suite_wasm, mutate_wasm_store, mutate_wasm_uploads, read_wasm_store,
+    validate_wasm_hashes, wasm_store_try_insert_chunk, StorableWasm, WasmUploadError,
+};
 use lsm::{
     appic_helper_client::appic_helper_types::{
         CandidAddErc20TwinLedgerSuiteRequest, CandidErc20TwinLedgerSuiteFee,
         CandidErc20TwinLedgerSuiteStatus,
     },
     appic_helper_client::AppicHelperClient,
-    INSTALL_LEDGER_SUITE_INTERVAL,
+    INSTALL_LEDGER_SUITE_INTERVAL, UPGRADE_LEDGER_SUITE_INTERVAL, WASM_GARBAGE_COLLECTION_INTERVAL,
 };
 use lsm::{
     endpoints::{AddErc20Arg, AddErc20Error},
-    DISCOVER_ARCHIVES_INTERVAL, ICP_TO_CYCLES_CONVERSION_INTERVAL, MAYBE_TOP_OP_INTERVAL,
+    CHECK_MODULE_HASH_DRIFT_INTERVAL, DISCOVER_ARCHIVES_INTERVAL,
+    ICP_TO_CYCLES_CONVERSION_INTERVAL, MAYBE_TOP_OP_INTERVAL, NOTIFY_ERC20_ADDED_INTERVAL,
+    RECONCILE_CANISTER_SETTINGS_INTERVAL, RECONCILE_CONTROLLERS_INTERVAL,
+    REFILL_CANISTER_POOL_INTERVAL, REFUND_ICP_DEPOSITS_INTERVAL,
 };
 
 use num_traits::ToPrimitive;
+use std::str::FromStr;
 
 const ADMIN_ID: &str = "tb3vi-54bcb-4oudm-fmp2s-nntjp-rmhd3-ukvnq-lawfq-vk5vy-mnlc7-pae";
 
@@ -85,6 +107,16 @@ fn setup_timers() {
         ic_cdk::spawn(process_convert_icp_to_cycles())
     });
 
+    // Deliver the notifications of the appic helper and the minters
+    ic_cdk_timers::set_timer_interval(NOTIFY_ERC20_ADDED_INTERVAL, || {
+        ic_cdk::spawn(process_notification_outbox())
+    });
+
+    // Refund the deposits of ledger suite installations that failed
+    ic_cdk_timers::set_timer_interval(REFUND_ICP_DEPOSITS_INTERVAL, || {
+        ic_cdk::spawn(process_refund_icp_deposits())
+    });
+
     // Discovering Archives spawned by ledgers.
     ic_cdk_timers::set_timer_interval(DISCOVER_ARCHIVES_INTERVAL, || {
         ic_cdk::spawn(process_discover_archives())
@@ -100,8 +132,35 @@ fn setup_timers() {
         ic_cdk::spawn(process_install_ledger_suites())
     });
 
-    // Update index canisters
-    // ic_cdk_timers::set_timer(Duration::from_secs(10), || ic_cdk::spawn(upgrade_indexes()));
+    // Upgrade managed ledger suites to the current ledger suite version
+    ic_cdk_timers::set_timer_interval(UPGRADE_LEDGER_SUITE_INTERVAL, || {
+        ic_cdk::spawn(process_upgrade_ledger_suites())
+    });
+
+    // Compare the module hashes of managed canisters with the recorded wasm hashes
+    ic_cdk_timers::set_timer_interval(CHECK_MODULE_HASH_DRIFT_INTERVAL, || {
+        ic_cdk::spawn(process_check_module_hash_drift())
+    });
+
+    // Push the configured settings to the managed canisters whose settings changed
+    ic_cdk_timers::set_timer_interval(RECONCILE_CANISTER_SETTINGS_INTERVAL, || {
+        ic_cdk::spawn(process_reconcile_canister_settings())
+    });
+
+    // Set the controllers of the managed canisters whose controllers changed
+    ic_cdk_timers::set_timer_interval(RECONCILE_CONTROLLERS_INTERVAL, || {
+        ic_cdk::spawn(process_reconcile_controllers())
+    });
+
+    // Create empty canisters in advance for new ledger suites
+    ic_cdk_timers::set_timer_interval(REFILL_CANISTER_POOL_INTERVAL, || {
+        ic_cdk::spawn(process_refill_canister_pool())
+    });
+
+    // Remove wasms that are no longer referenced from the wasm store
+    ic_cdk_timers::set_timer_interval(WASM_GARBAGE_COLLECTION_INTERVAL, || {
+        process_wasm_garbage_collection();
+    });
 }
 
 #[update]
@@ -151,6 +210,10 @@ fn get_lsm_info() -> LedgerManagerInfo {
                 .map(|(token_id, canisters)| (token_id, canisters.clone()).into())
                 .collect(),
             cycles_management: s.cycles_management().clone(),
+            install_retry_policy: s.install_retry_policy().clone(),
+            max_concurrent_ls_installs: s.max_concurrent_ls_installs(),
+            canister_settings: s.canister_settings().clone(),
+            canister_placement: s.canister_placement().clone(),
             more_controller_ids: s.more_controller_ids().to_vec(),
             minter_ids: all_minter_ids
                 .into_iter()
@@ -165,6 +228,83 @@ fn get_lsm_info() -> LedgerManagerInfo {
     })
 }
 
+#[query]
+fn get_stored_wasms() -> Vec<StoredWasmInfo> {
+    let (mut installed_canisters, ledger_suite_version) = read_state(|s| {
+        (
+            s.managed_canisters_by_installed_wasm_hash(),
+            s.ledger_suite_version().cloned(),
+        )
+    });
+    read_wasm_store(|w| {
+        w.iter()
+            .map(|(wasm_hash, stored_wasm)| StoredWasmInfo {
+                wasm_type: WasmType::from_marker(stored_wasm.marker())
+                    .expect("BUG: unknown wasm marker"),
+                size: stored_wasm.size() as u64,
+                timestamp: stored_wasm.timestamp(),
+                installed_on: installed_canisters.remove(&wasm_hash).unwrap_or_default(),
+                is_current_version: ledger_suite_version
+                    .as_ref()
+                    .map(|version| version.contains(&wasm_hash))
+                    .unwrap_or(false),
+                wasm_hash: wasm_hash.to_string(),
+            })
+            .collect()
+    })
+}
+
+#[query]
+fn get_module_hash_drift_reports() -> Vec<ModuleHashDriftReport> {
+    read_state(|s| {
+        s.module_hash_drift_reports()
+            .map(|(token, report)| (token.clone(), report.clone()).into())
+            .collect()
+    })
+}
+
+#[query]
+fn get_notification_outbox() -> Vec<PendingNotification> {
+    read_state(|s| {
+        s.notification_outbox()
+            .map(|(id, entry)| (*id, entry.clone()).into())
+            .collect()
+    })
+}
+
+#[query]
+fn get_upgrade_rollbacks() -> Vec<UpgradeRollback> {
+    read_state(|s| {
+        s.upgrade_rollbacks()
+            .flat_map(|(token, rollbacks)| {
+                rollbacks
+                    .iter()
+                    .map(|rollback| (token.clone(), rollback.clone()).into())
+            })
+            .collect()
+    })
+}
+
+#[query]
+fn get_ledger_suite_install_progress() -> Vec<LedgerSuiteInstallProgress> {
+    read_state(|s| {
+        s.ledger_suite_install_progress()
+            .map(|(token, progress)| (token.clone(), progress.clone()).into())
+            .collect()
+    })
+}
+
+#[query]
+fn get_ledger_suite_version_history() -> Vec<ActivatedLedgerSuiteVersion> {
+    read_state(|s| {
+        s.ledger_suite_version_history()
+            .iter()
+            .cloned()
+            .map(ActivatedLedgerSuiteVersion::from)
+            .collect()
+    })
+}
+
 #[update]
 async fn add_native_ls(
     native_ls: InstalledNativeLedgerSuite,
@@ -301,9 +441,10 @@ async fn add_erc20_ls(erc20_args: AddErc20Arg) -> Result<(), AddErc20Error> {
                         .expect("Nat to u64 should not fail"),
                     twin_creation_fee_amount_in_icp.checked_sub(10_000).unwrap(),
                     caller,
+                    time,
                 );
 
-                // Add the ledger suit creation to the queue
+                // Add the ledger suite creation request, installed once approved by an admin
                 s.record_new_ledger_suite_request(erc20_token, install_ledger_suite_args);
 
                 Ok(())
@@ -322,70 +463,653 @@ fn update_twin_creation_fees(twin_ls_creation_fees: UpdateLedgerSuiteCreationFee
     mutate_state(|s| s.update_minimum_tokens_for_new_ledger_suite(twin_ls_creation_fees.into()));
 }
 
-// pub async fn upgrade_indexes() -> () {
-//     let runtime = IcCanisterRuntime {};
-//     let managed_principals: BTreeSet<_> = read_state(|s| {
-//         s.all_managed_canisters_iter()
-//             .map(|(_token, canisters)| *canisters.index_canister_id().unwrap())
-//             .collect()
-//     });
-//     if managed_principals.is_empty() {
-//         log!(INFO, "[Upgrade Index]: No managed canisters to top-up");
-//         return ();
-//     }
-
-//     let LedgerSuiteVersion {
-//         ledger_compressed_wasm_hash,
-//         index_compressed_wasm_hash,
-//         archive_compressed_wasm_hash: _,
-//     } = read_state(|s| {
-//         s.ledger_suite_version()
-//             .map(|lsv| lsv.clone())
-//             .expect("ERROR: [Upgrade Index] ledger suite version missing")
-//     });
-
-//     let wasm =
-//         match read_wasm_store(|s| wasm_store_try_get::<Index>(s, &index_compressed_wasm_hash)) {
-//             Ok(Some(wasm)) => Ok(wasm),
-//             Ok(None) => {
-//                 log!(
-//                     INFO,
-//                     "ERROR: failed to install  canister for  at 'wasm hash  not found",
-//                 );
-//                 Err(TaskError::WasmHashNotFound(
-//                     index_compressed_wasm_hash.clone(),
-//                 ))
-//             }
-//             Err(e) => {
-//                 log!(INFO, "ERROR: failed to install  canister for  at ",);
-//                 Err(TaskError::WasmStoreError(e))
-//             }
-//         }
-//         .unwrap();
-
-//     for (canister_id) in managed_principals.into_iter() {
-//         let result = runtime
-//             .upgrade_canister(
-//                 canister_id,
-//                 wasm.clone().to_bytes(),
-//                 Encode!(&UpgradeArg {
-//                     ledger_id: None,
-//                     retrieve_blocks_from_ledger_interval_seconds: Some(600),
-//                 })
-//                 .expect("BUG:[Upgrade Index] failed to encode init arg"),
-//             )
-//             .await;
-
-//         log!(
-//             INFO,
-//             "[Upgrade Index]: Upgraded index {} with result {:?}",
-//             canister_id,
-//             result
-//         );
-//     }
-
-//     ()
-// }
+/// Adds and removes additional controllers of the managed canisters
+/// and sets the controllers of every managed canister accordingly.
+/// Returns the result for every ledger suite with canisters whose controllers were set.
+#[update]
+async fn update_controllers(
+    arg: UpdateControllersArg,
+) -> Result<Vec<ControllersReport>, UpdateControllersError> {
+    if !is_authorized_caller(ic_cdk::caller()) {
+        panic!("Only admins can change the controllers of managed canisters")
+    }
+
+    let lsm_id = ic_cdk::id();
+    if arg.add_controller_ids.contains(&lsm_id) || arg.remove_controller_ids.contains(&lsm_id) {
+        return Err(UpdateControllersError::LsmIsAlwaysController);
+    }
+    let _guard = TimerGuard::new(PeriodicTasksTypes::ReconcileControllers)
+        .map_err(|_| UpdateControllersError::ReconciliationInProgress)?;
+    mutate_state(|s| {
+        s.update_more_controller_ids(arg.add_controller_ids, &arg.remove_controller_ids)
+    })?;
+    log!(
+        INFO,
+        "[update_controllers]: additional controllers are now {:?}",
+        read_state(|s| s.more_controller_ids().to_vec())
+    );
+
+    Ok(reconcile_controllers(&IcCanisterRuntime {})
+        .await
+        .into_iter()
+        .map(ControllersReport::from)
+        .collect())
+}
+
+#[query]
+fn get_controllers_reports() -> Vec<ControllersReport> {
+    read_state(|s| {
+        s.controllers_reports()
+            .map(|(token, report)| (token.clone(), report.clone()).into())
+            .collect()
+    })
+}
+
+#[query]
+fn get_canister_pool() -> CanisterPool {
+    read_state(|s| CanisterPool {
+        config: s.canister_pool_config().clone(),
+        canisters: s
+            .pooled_canisters()
+            .iter()
+            .cloned()
+            .map(PooledCanister::from)
+            .collect(),
+    })
+}
+
+/// Sets the subnet on which the canisters of new ledger suites are created.
+/// Ledger suites whose ledger is already created keep the subnet of their ledger.
+#[update]
+fn set_canister_placement(placement: CanisterPlacement) -> () {
+    if !is_authorized_caller(ic_cdk::caller()) {
+        panic!("Only admins can change the subnet of new ledger suites")
+    }
+    if let CanisterPlacement::SubnetType(subnet_type) = &placement {
+        if subnet_type.trim().is_empty() {
+            ic_cdk::trap("ERROR: the subnet type must not be empty");
+        }
+    }
+    log!(
+        INFO,
+        "[set_canister_placement]: new ledger suites are created on {:?}",
+        placement
+    );
+    mutate_state(|s| s.set_canister_placement(placement));
+}
+
+#[update]
+fn upload_wasm(arg: UploadWasmArg) -> Result<(), UploadWasmError> {
+    if !is_authorized_caller(ic_cdk::caller()) {
+        panic!("Only admins can upload wasms")
+    }
+
+    let wasm_hash = WasmHash::from_str(&arg.wasm_hash).map_err(UploadWasmError::InvalidWasmHash)?;
+
+    let is_complete = match arg.wasm_type {
+        WasmType::Ledger => upload_wasm_chunk::<Ledger>(wasm_hash.clone(), arg),
+        WasmType::Index => upload_wasm_chunk::<Index>(wasm_hash.clone(), arg),
+        WasmType::Archive => upload_wasm_chunk::<Archive>(wasm_hash.clone(), arg),
+    }
+    .map_err(|e| {
+        log!(
+            INFO,
+            "[upload_wasm]: failed to upload wasm {}: {:?}",
+            wasm_hash,
+            e
+        );
+        UploadWasmError::from(e)
+    })?;
+
+    if is_complete {
+        log!(
+            INFO,
+            "[upload_wasm]: recorded wasm {} in the wasm store",
+            wasm_hash
+        );
+    }
+    Ok(())
+}
+
+/// Makes a previous ledger suite version current again,
+/// i.e. the version at `history_index` in the version history or, if not given, the previous version.
+/// Managed ledger suites are then upgraded (or downgraded) to that version.
+/// The wasms of every version of the history are kept in the wasm store,
+/// see [`lsm::state::State::referenced_wasm_hashes`].
+#[update]
+fn rollback_ledger_suite_version(
+    history_index: Option<u64>,
+) -> Result<LedgerSuiteVersion, RollbackLedgerSuiteVersionError> {
+    if !is_authorized_caller(ic_cdk::caller()) {
+        panic!("Only admins can roll back the ledger suite version")
+    }
+
+    let history_index = history_index
+        .map(|index| {
+            usize::try_from(index)
+                .map_err(|_| RollbackLedgerSuiteVersionError::UnknownHistoryIndex(index))
+        })
+        .transpose()?;
+    // Prevents the upgrade task from completing subtasks of upgrades replaced by the rollback.
+    let _guard = TimerGuard::new(PeriodicTasksTypes::UpgradeLedgerSuite)
+        .map_err(|_| RollbackLedgerSuiteVersionError::UpgradeInProgress)?;
+    let version = read_state(|s| s.ledger_suite_version_to_roll_back_to(history_index))?;
+    if let Some(wasm_hash) = read_wasm_store(|w| find_missing_ledger_suite_wasm(w, &version)) {
+        return Err(RollbackLedgerSuiteVersionError::WasmNotFound(
+            wasm_hash.to_string(),
+        ));
+    }
+
+    log!(
+        INFO,
+        "[rollback_ledger_suite_version]: rolling back to ledger suite version {:?}",
+        version
+    );
+    mutate_state(|s| s.update_ledger_suite_version(version.clone(), ic_cdk::api::time()));
+    Ok(version.into())
+}
+
+#[update]
+fn start_upgrade_campaign(arg: StartUpgradeCampaignArg) -> Result<(), StartUpgradeCampaignError> {
+    if !is_authorized_caller(ic_cdk::caller()) {
+        panic!("Only admins can start an upgrade campaign")
+    }
+
+    let [ledger_compressed_wasm_hash, index_compressed_wasm_hash, archive_compressed_wasm_hash] =
+        read_wasm_store(|w| {
+            validate_wasm_hashes(
+                w,
+                arg.ledger_compressed_wasm_hash.as_deref(),
+                arg.index_compressed_wasm_hash.as_deref(),
+                arg.archive_compressed_wasm_hash.as_deref(),
+            )
+        })?;
+    let canary_tokens = arg
+        .canary_tokens
+        .into_iter()
+        .map(Erc20Token::try_from)
+        .collect::<Result<_, _>>()
+        .map_err(StartUpgradeCampaignError::InvalidCanaryToken)?;
+    // Prevents the upgrade task from completing subtasks of upgrades replaced by the campaign.
+    let _guard = TimerGuard::new(PeriodicTasksTypes::UpgradeLedgerSuite)
+        .map_err(|_| StartUpgradeCampaignError::UpgradeInProgress)?;
+    let version = read_state(|s| s.ledger_suite_version().cloned())
+        .ok_or(StartUpgradeCampaignError::NothingToUpgrade)?
+        .with_wasm_hashes(
+            ledger_compressed_wasm_hash,
+            index_compressed_wasm_hash,
+            archive_compressed_wasm_hash,
+        );
+
+    mutate_state(|s| {
+        s.start_upgrade_campaign(
+            version.clone(),
+            canary_tokens,
+            arg.batch_size as usize,
+            arg.pause_between_batches_secs.saturating_mul(1_000_000_000),
+            ic_cdk::api::time(),
+        )
+    })?;
+    log!(
+        INFO,
+        "[start_upgrade_campaign]: started upgrade campaign to ledger suite version {:?}",
+        version
+    );
+    Ok(())
+}
+
+/// Resumes a campaign stopped by a failed upgrade with its next batch.
+#[update]
+fn resume_upgrade_campaign() -> Result<(), ResumeUpgradeCampaignError> {
+    if !is_authorized_caller(ic_cdk::caller()) {
+        panic!("Only admins can resume an upgrade campaign")
+    }
+
+    if !mutate_state(|s| s.resume_upgrade_campaign()) {
+        return Err(ResumeUpgradeCampaignError::NoStoppedCampaign);
+    }
+    log!(INFO, "[resume_upgrade_campaign]: resumed upgrade campaign");
+    Ok(())
+}
+
+/// Cancels the upgrade campaign.
+/// The upgrades held back by the campaign are then executed without waiting for a batch.
+#[update]
+fn cancel_upgrade_campaign() -> Result<(), CancelUpgradeCampaignError> {
+    if !is_authorized_caller(ic_cdk::caller()) {
+        panic!("Only admins can cancel an upgrade campaign")
+    }
+
+    if !mutate_state(|s| s.cancel_upgrade_campaign()) {
+        return Err(CancelUpgradeCampaignError::NoActiveCampaign);
+    }
+    log!(
+        INFO,
+        "[cancel_upgrade_campaign]: cancelled upgrade campaign"
+    );
+    Ok(())
+}
+
+#[query]
+fn get_upgrade_campaign() -> Option<UpgradeCampaignInfo> {
+    read_state(|s| s.upgrade_campaign().cloned().map(UpgradeCampaignInfo::from))
+}
+
+#[update]
+async fn load_canister_snapshot(
+    arg: LoadCanisterSnapshotArg,
+) -> Result<(), LoadCanisterSnapshotError> {
+    if !is_authorized_caller(ic_cdk::caller()) {
+        panic!("Only admins can load canister snapshots")
+    }
+
+    let token = Erc20Token::try_from(arg.erc20_contract)
+        .map_err(LoadCanisterSnapshotError::InvalidErc20Contract)?;
+    // Prevents the upgrade task from upgrading the canister while its snapshot is loaded.
+    let _guard = TimerGuard::new(PeriodicTasksTypes::UpgradeLedgerSuite)
+        .map_err(|_| LoadCanisterSnapshotError::UpgradeInProgress)?;
+
+    upgrade_ls::load_canister_snapshot(&token, arg.canister_type.into(), &IcCanisterRuntime {})
+        .await
+        .map_err(|e| {
+            log!(
+                INFO,
+                "[load_canister_snapshot]: failed to load snapshot for {:?}: {:?}",
+                token,
+                e
+            );
+            LoadCanisterSnapshotError::from(e)
+        })
+}
+
+#[query]
+fn get_ledger_suite_requests_pending_approval() -> Vec<PendingLedgerSuiteRequest> {
+    if !is_authorized_caller(ic_cdk::caller()) {
+        panic!("Only admins can list ledger suite requests pending approval")
+    }
+
+    read_state(|s| {
+        s.ledger_suite_requests_pending_approval()
+            .iter()
+            .map(|(token, install_args)| PendingLedgerSuiteRequest {
+                erc20_contract: token.clone().into(),
+                creator: install_args.creator,
+                token_name: install_args.ledger_init_arg.token_name.clone(),
+                token_symbol: install_args.ledger_init_arg.token_symbol.clone(),
+                created_at: install_args.created_at,
+                fee_paid: s
+                    .received_deposit(token)
+                    .map(|deposit| Nat::from(deposit.amount)),
+            })
+            .collect()
+    })
+}
+
+#[update]
+fn approve_ls_request(
+    erc20_contract: Erc20Contract,
+) -> Result<(), LedgerSuiteRequestModerationError> {
+    if !is_authorized_caller(ic_cdk::caller()) {
+        panic!("Only admins can approve ledger suite requests")
+    }
+
+    let token = Erc20Token::try_from(erc20_contract)
+        .map_err(LedgerSuiteRequestModerationError::InvalidErc20Contract)?;
+    let approved = mutate_state(|s| s.approve_ledger_suite_request(&token, ic_cdk::api::time()));
+    if !approved {
+        return Err(LedgerSuiteRequestModerationError::RequestNotFound);
+    }
+    log!(
+        INFO,
+        "[approve_ls_request]: approved ledger suite request for {:?}",
+        token
+    );
+    Ok(())
+}
+
+#[update]
+fn reject_ls_request(
+    erc20_contract: Erc20Contract,
+    reason: String,
+) -> Result<(), LedgerSuiteRequestModerationError> {
+    if !is_authorized_caller(ic_cdk::caller()) {
+        panic!("Only admins can reject ledger suite requests")
+    }
+
+    let token = Erc20Token::try_from(erc20_contract)
+        .map_err(LedgerSuiteRequestModerationError::InvalidErc20Contract)?;
+    let now = ic_cdk::api::time();
+    // The deposit is refunded by the refund task and the appic helper notified by the outbox task.
+    mutate_state(|s| {
+        let install_args = s
+            .reject_ledger_suite_request(&token, reason.clone(), now)
+            .ok_or(LedgerSuiteRequestModerationError::RequestNotFound)?;
+        // The fee may have changed since the creator paid it.
+        let fee_charged_icp = s
+            .received_deposit(&token)
+            .map(|deposit| deposit.amount)
+            .unwrap_or_default();
+        s.enqueue_notification(
+            Notification::UpdateLedgerSuiteRequest {
+                token: token.clone(),
+                creator: install_args.creator,
+                ledger_id: None,
+                token_name: install_args.ledger_init_arg.token_name,
+                token_symbol: install_args.ledger_init_arg.token_symbol,
+                created_at: install_args.created_at,
+                fee_charged_icp,
+                status: LedgerSuiteRequestStatus::Rejected,
+            },
+            now,
+        );
+        Ok::<_, LedgerSuiteRequestModerationError>(())
+    })?;
+    log!(
+        INFO,
+        "[reject_ls_request]: rejected ledger suite request for {:?}: {}",
+        token,
+        reason
+    );
+    Ok(())
+}
+
+#[query]
+fn get_failed_ledger_suite_installs() -> Vec<FailedLedgerSuiteInstall> {
+    if !is_authorized_caller(ic_cdk::caller()) {
+        panic!("Only admins can list failed ledger suite installs")
+    }
+
+    read_state(|s| {
+        s.failed_ledger_suite_installs()
+            .iter()
+            .map(|(token, install_args)| FailedLedgerSuiteInstall {
+                erc20_contract: token.clone().into(),
+                creator: install_args.creator,
+                token_name: install_args.ledger_init_arg.token_name.clone(),
+                token_symbol: install_args.ledger_init_arg.token_symbol.clone(),
+                created_at: install_args.created_at,
+                fee_paid: s
+                    .received_deposit(token)
+                    .map(|deposit| Nat::from(deposit.amount)),
+                attempts: install_args.attempts,
+                failure_reason: install_args.last_error.clone(),
+                refund_block_index: s.received_deposit(token).and_then(|deposit| {
+                    match deposit.refund {
+                        Some(IcpRefund::Refunded { block_index, .. }) => Some(block_index),
+                        _ => None,
+                    }
+                }),
+            })
+            .collect()
+    })
+}
+
+#[update]
+fn resume_failed_ledger_suite_install(
+    arg: ResumeFailedInstallArg,
+) -> Result<(), ResumeFailedInstallError> {
+    if !is_authorized_caller(ic_cdk::caller()) {
+        panic!("Only admins can resume failed ledger suite installs")
+    }
+
+    let token = Erc20Token::try_from(arg.erc20_contract)
+        .map_err(ResumeFailedInstallError::InvalidErc20Contract)?;
+    let [ledger_compressed_wasm_hash, index_compressed_wasm_hash, _archive_compressed_wasm_hash] =
+        read_wasm_store(|w| {
+            validate_wasm_hashes(
+                w,
+                arg.ledger_compressed_wasm_hash.as_deref(),
+                arg.index_compressed_wasm_hash.as_deref(),
+                None,
+            )
+        })?;
+
+    mutate_state(|s| {
+        s.resume_failed_ls_install(
+            &token,
+            ledger_compressed_wasm_hash,
+            index_compressed_wasm_hash,
+            ic_cdk::api::time(),
+        )
+    })?;
+    log!(
+        INFO,
+        "[resume_failed_ledger_suite_install]: resumed install of {:?}",
+        token
+    );
+    Ok(())
+}
+
+#[update]
+fn discard_failed_ledger_suite_install(
+    erc20_contract: Erc20Contract,
+) -> Result<(), DiscardFailedInstallError> {
+    if !is_authorized_caller(ic_cdk::caller()) {
+        panic!("Only admins can discard failed ledger suite installs")
+    }
+
+    let token = Erc20Token::try_from(erc20_contract)
+        .map_err(DiscardFailedInstallError::InvalidErc20Contract)?;
+    let discarded = mutate_state(|s| s.discard_failed_ls_install(&token))
+        .ok_or(DiscardFailedInstallError::FailedInstallNotFound)?;
+    log!(
+        INFO,
+        "[discard_failed_ledger_suite_install]: discarded install {:?}",
+        discarded
+    );
+    Ok(())
+}
+
+#[query]
+fn get_icp_refunds_to_reconcile() -> Vec<IcpRefundToReconcile> {
+    if !is_authorized_caller(ic_cdk::caller()) {
+        panic!("Only admins can list icp refunds to reconcile")
+    }
+
+    read_state(|s| {
+        s.icp_refunds_to_reconcile()
+            .into_iter()
+            .filter_map(|deposit| match deposit.refund {
+                Some(IcpRefund::InFlight {
+                    created_at_time, ..
+                }) => Some(IcpRefundToReconcile {
+                    erc20_contract: deposit.erc20_token.into(),
+                    depositor: deposit.from_principal,
+                    amount: Nat::from(deposit.amount),
+                    memo: deposit.transfer_index,
+                    created_at_time,
+                }),
+                _ => None,
+            })
+            .collect()
+    })
+}
+
+#[update]
+fn reconcile_icp_refund(arg: ReconcileIcpRefundArg) -> Result<(), ReconcileIcpRefundError> {
+    if !is_authorized_caller(ic_cdk::caller()) {
+        panic!("Only admins can reconcile icp refunds")
+    }
+
+    let reconciled = mutate_state(|s| {
+        s.reconcile_icp_refund(arg.memo, arg.refund_block_index, ic_cdk::api::time())
+    });
+    if !reconciled {
+        return Err(ReconcileIcpRefundError::RefundNotToReconcile);
+    }
+    log!(
+        INFO,
+        "[reconcile_icp_refund]: reconciled refund of deposit {} with block {:?}",
+        arg.memo,
+        arg.refund_block_index
+    );
+    Ok(())
+}
+
+#[query]
+fn get_token_denylists() -> TokenDenylists {
+    read_state(|s| TokenDenylists::from(s.token_denylists()))
+}
+
+#[update]
+fn update_token_denylists(arg: UpdateTokenDenylistsArg) -> Result<(), UpdateTokenDenylistsError> {
+    if !is_authorized_caller(ic_cdk::caller()) {
+        panic!("Only admins can update the token denylists")
+    }
+
+    let parse_contracts = |contracts: Vec<Erc20Contract>| {
+        contracts
+            .into_iter()
+            .map(Erc20Token::try_from)
+            .collect::<Result<Vec<_>, _>>()
+            .map_err(UpdateTokenDenylistsError::InvalidErc20Contract)
+    };
+    let add_denied_erc20_contracts = parse_contracts(arg.add_denied_erc20_contracts)?;
+    let remove_denied_erc20_contracts = parse_contracts(arg.remove_denied_erc20_contracts)?;
+
+    mutate_state(|s| {
+        let denylists = s.token_denylists_mut();
+        arg.add_denied_token_symbols
+            .iter()
+            .for_each(|symbol| denylists.deny_token_symbol(symbol));
+        arg.remove_denied_token_symbols
+            .iter()
+            .for_each(|symbol| denylists.allow_token_symbol(symbol));
+        add_denied_erc20_contracts
+            .into_iter()
+            .for_each(|token| denylists.deny_erc20_contract(token));
+        remove_denied_erc20_contracts
+            .iter()
+            .for_each(|token| denylists.allow_erc20_contract(token));
+        arg.add_reserved_token_names
+            .iter()
+            .for_each(|name| denylists.reserve_token_name(name));
+        arg.remove_reserved_token_names
+            .iter()
+            .for_each(|name| denylists.release_token_name(name));
+    });
+    log!(
+        INFO,
+        "[update_token_denylists]: token denylists are now {:?}",
+        read_state(|s| s.token_denylists().clone())
+    );
+    Ok(())
+}
+
+#[query]
+fn get_archive_profiles() -> Vec<NamedArchiveProfile> {
+    read_state(|s| {
+        s.archive_profiles()
+            .iter()
+            .map(|(name, profile)| NamedArchiveProfile {
+                name: name.clone(),
+                profile: profile.clone().into(),
+            })
+            .collect()
+    })
+}
+
+#[update]
+fn upsert_archive_profile(arg: NamedArchiveProfile) -> Result<(), ArchiveProfileError> {
+    if !is_authorized_caller(ic_cdk::caller()) {
+        panic!("Only admins can define archive profiles")
+    }
+
+    if arg.name.trim().is_empty() {
+        return Err(ArchiveProfileError::InvalidName);
+    }
+    let profile = ArchiveProfile::from(arg.profile);
+    profile
+        .validate()
+        .map_err(ArchiveProfileError::InvalidArchiveProfile)?;
+    log!(
+        INFO,
+        "[upsert_archive_profile]: archive profile {} is now {:?}",
+        arg.name,
+        profile
+    );
+    mutate_state(|s| s.upsert_archive_profile(arg.name, profile));
+    Ok(())
+}
+
+/// Removes an archive profile.
+/// Ledger suites that chose the profile keep its archive options.
+#[update]
+fn remove_archive_profile(name: String) -> Result<(), ArchiveProfileError> {
+    if !is_authorized_caller(ic_cdk::caller()) {
+        panic!("Only admins can remove archive profiles")
+    }
+
+    let removed = mutate_state(|s| s.remove_archive_profile(&name))
+        .ok_or(ArchiveProfileError::ArchiveProfileNotFound)?;
+    log!(
+        INFO,
+        "[remove_archive_profile]: removed archive profile {}: {:?}",
+        name,
+        removed
+    );
+    Ok(())
+}
+
+/// Overrides the archive profile of a token.
+/// The profile applies to the ledger when it is installed or, if already installed, at its next upgrade.
+#[update]
+fn set_token_archive_profile(
+    erc20_contract: Erc20Contract,
+    profile_name: String,
+) -> Result<(), ArchiveProfileError> {
+    if !is_authorized_caller(ic_cdk::caller()) {
+        panic!("Only admins can set the archive profile of a token")
+    }
+
+    let token =
+        Erc20Token::try_from(erc20_contract).map_err(ArchiveProfileError::InvalidErc20Contract)?;
+    mutate_state(|s| {
+        let profile = s
+            .archive_profile(&profile_name)
+            .cloned()
+            .ok_or(ArchiveProfileError::ArchiveProfileNotFound)?;
+        if !s.set_token_archive_profile(&token, profile) {
+            return Err(ArchiveProfileError::TokenNotFound);
+        }
+        Ok(())
+    })?;
+    log!(
+        INFO,
+        "[set_token_archive_profile]: archive profile of {:?} set to {}",
+        token,
+        profile_name
+    );
+    Ok(())
+}
+
+#[update]
+fn collect_wasm_garbage() -> WasmGarbageCollectionReport {
+    if !is_authorized_caller(ic_cdk::caller()) {
+        panic!("Only admins can remove wasms")
+    }
+
+    WasmGarbageCollectionReport::from(process_wasm_garbage_collection())
+}
+
+fn upload_wasm_chunk<T: StorableWasm>(
+    wasm_hash: WasmHash,
+    arg: UploadWasmArg,
+) -> Result<bool, WasmUploadError> {
+    mutate_wasm_store(|w| {
+        mutate_wasm_uploads(|u| {
+            wasm_store_try_insert_chunk::<T>(
+                w,
+                u,
+                ic_cdk::api::time(),
+                wasm_hash,
+                arg.chunk_index,
+                arg.total_chunks,
+                arg.chunk.into_vec(),
+            )
+        })
+    })
+}
 
 // Enable Candid export
 ic_cdk::export_candid!();
@@ -1,17 +1,34 @@
+use crate::cmc_client::cmc_declarations::{
+    CanisterSettings as CmcCanisterSettings, CreateCanisterArg as CmcCreateCanisterArg,
+    CreateCanisterError as CmcCreateCanisterError, CreateCanisterResult as CmcCreateCanisterResult,
+    LogVisibility as CmcLogVisibility, SubnetFilter, SubnetSelection,
+};
+use crate::cmc_client::MAINNET_CYCLE_MINTER_CANISTER_ID;
+use crate::endpoints::{CanisterPlacement, CanisterSettings, LogVisibility};
 use crate::logs::DEBUG;
 use async_trait::async_trait;
-use candid::{CandidType, Encode, Principal};
+use candid::{CandidType, Deserialize, Encode, Nat, Principal};
 use ic_base_types::PrincipalId;
 use ic_canister_log::log;
 use ic_cdk::api::call::RejectionCode;
 use ic_management_canister_types::{
-    CanisterIdRecord, CanisterInstallMode, CanisterSettingsArgsBuilder, CreateCanisterArgs,
-    InstallCodeArgs,
+    CanisterIdRecord, CanisterInstallMode, CanisterSettingsArgs, CanisterSettingsArgsBuilder,
+    CreateCanisterArgs, InstallCodeArgs, LogVisibilityV2, UpdateSettingsArgs,
 };
 use serde::de::DeserializeOwned;
+use serde_bytes::ByteBuf;
 use std::fmt;
 use std::fmt::Debug;
 
+/// The mode in which a wasm module is installed on a canister.
+#[derive(Clone, Copy, Eq, PartialEq, Debug)]
+pub enum InstallMode {
+    /// Installs the wasm module on an empty canister.
+    Install,
+    /// Upgrades the wasm module of a canister, keeping its stable memory.
+    Upgrade,
+}
+
 /// Represents an error from a management canister call, such as
 /// `sign_with_ecdsa`.
 #[derive(Clone, Eq, PartialEq, Debug)]
@@ -89,6 +106,21 @@ impl Reason {
     }
 }
 
+/// A canister snapshot as returned by the management canister's `take_canister_snapshot`.
+#[derive(Clone, Eq, PartialEq, Debug, CandidType, Deserialize)]
+pub struct CanisterSnapshotResponse {
+    pub id: Vec<u8>,
+    /// The time at which the snapshot was taken
+    /// in nanoseconds since the epoch (1970-01-01).
+    pub taken_at_timestamp: u64,
+    pub total_size: u64,
+}
+
+#[derive(CandidType, Deserialize)]
+struct ChunkHash {
+    hash: ByteBuf,
+}
+
 #[async_trait]
 pub trait CanisterRuntime {
     /// Returns the canister id of the current canister.
@@ -102,13 +134,29 @@ pub trait CanisterRuntime {
     /// See the [IC specification](https://internetcomputer.org/docs/current/references/ic-interface-spec#global-timer-1).
     fn global_timer_set(&self, timestamp: u64);
 
-    /// Creates a new canister with the given cycles.
+    /// Creates a new canister with the given cycles and settings on the given subnet.
     async fn create_canister(
         &self,
         controllers: Vec<Principal>,
         cycles_for_canister_creation: u64,
+        settings: CanisterSettings,
+        placement: CanisterPlacement,
     ) -> Result<Principal, CallError>;
 
+    /// Updates the given settings of the given canister, leaving its controllers unchanged.
+    async fn update_settings(
+        &self,
+        canister_id: Principal,
+        settings: CanisterSettings,
+    ) -> Result<(), CallError>;
+
+    /// Replaces the controllers of the given canister, leaving its other settings unchanged.
+    async fn set_controllers(
+        &self,
+        canister_id: Principal,
+        controllers: Vec<Principal>,
+    ) -> Result<(), CallError>;
+
     /// Stops the given canister.
     async fn stop_canister(&self, canister_id: Principal) -> Result<(), CallError>;
 
@@ -123,6 +171,28 @@ pub trait CanisterRuntime {
         arg: Vec<u8>,
     ) -> Result<(), CallError>;
 
+    /// Uploads a chunk of a wasm module to the chunk store of the given canister.
+    /// Returns the SHA-256 hash of the chunk.
+    async fn upload_chunk(
+        &self,
+        canister_id: Principal,
+        chunk: Vec<u8>,
+    ) -> Result<Vec<u8>, CallError>;
+
+    /// Installs or upgrades the wasm module made of the given chunks,
+    /// taken from the canister's own chunk store, with the given arguments on the given canister.
+    async fn install_chunked_code(
+        &self,
+        canister_id: Principal,
+        mode: InstallMode,
+        chunk_hashes: Vec<Vec<u8>>,
+        wasm_module_hash: Vec<u8>,
+        arg: Vec<u8>,
+    ) -> Result<(), CallError>;
+
+    /// Removes all chunks from the chunk store of the given canister.
+    async fn clear_chunk_store(&self, canister_id: Principal) -> Result<(), CallError>;
+
     /// Upgrade the given canister without any upgrade arguments.
     async fn upgrade_canister(
         &self,
@@ -131,8 +201,30 @@ pub trait CanisterRuntime {
         upgrade_args: Vec<u8>,
     ) -> Result<(), CallError>;
 
+    /// Takes a snapshot of the given canister.
+    /// The snapshot `replace_snapshot`, if any, is replaced by the new snapshot.
+    async fn take_canister_snapshot(
+        &self,
+        canister_id: Principal,
+        replace_snapshot: Option<Vec<u8>>,
+    ) -> Result<CanisterSnapshotResponse, CallError>;
+
+    /// Loads the given snapshot on the given canister, which must be stopped.
+    async fn load_canister_snapshot(
+        &self,
+        canister_id: Principal,
+        snapshot_id: Vec<u8>,
+    ) -> Result<(), CallError>;
+
     async fn canister_cycles(&self, canister_id: Principal) -> Result<u128, CallError>;
 
+    /// Returns the SHA-256 hash of the wasm module installed on the given canister,
+    /// or `None` if the canister is empty.
+    async fn canister_module_hash(
+        &self,
+        canister_id: Principal,
+    ) -> Result<Option<Vec<u8>>, CallError>;
+
     fn send_cycles(&self, canister_id: Principal, cycles: u128) -> Result<(), CallError>;
 
     async fn call_canister<I, O>(
@@ -149,6 +241,32 @@ pub trait CanisterRuntime {
 #[derive(Copy, Clone)]
 pub struct IcCanisterRuntime {}
 
+/// Adds the settings that are given to the settings built so far.
+fn canister_settings_args(
+    mut builder: CanisterSettingsArgsBuilder,
+    settings: CanisterSettings,
+) -> CanisterSettingsArgs {
+    if let Some(freezing_threshold) = settings.freezing_threshold {
+        builder = builder.with_freezing_threshold(freezing_threshold);
+    }
+    if let Some(memory_allocation) = settings.memory_allocation {
+        builder = builder.with_memory_allocation(memory_allocation);
+    }
+    if let Some(wasm_memory_limit) = settings.wasm_memory_limit {
+        builder = builder.with_wasm_memory_limit(wasm_memory_limit);
+    }
+    if let Some(reserved_cycles_limit) = settings.reserved_cycles_limit {
+        builder = builder.with_reserved_cycles_limit(reserved_cycles_limit);
+    }
+    if let Some(log_visibility) = settings.log_visibility {
+        builder = builder.with_log_visibility(match log_visibility {
+            LogVisibility::Controllers => LogVisibilityV2::Controllers,
+            LogVisibility::Public => LogVisibilityV2::Public,
+        });
+    }
+    builder.build()
+}
+
 impl IcCanisterRuntime {
     async fn call<I, O>(&self, method: &str, payment: u64, input: &I) -> Result<O, CallError>
     where
@@ -179,6 +297,69 @@ impl IcCanisterRuntime {
             }),
         }
     }
+
+    /// Creates a canister through the cycles minting canister, which can target any subnet
+    /// the lsm is authorized to create canisters on.
+    async fn create_canister_with_cmc(
+        &self,
+        controllers: Vec<Principal>,
+        cycles_for_canister_creation: u64,
+        settings: CanisterSettings,
+        subnet_selection: SubnetSelection,
+    ) -> Result<Principal, CallError> {
+        const METHOD: &str = "create_canister";
+
+        if ic_cdk::api::canister_balance128() < cycles_for_canister_creation as u128 {
+            return Err(CallError {
+                method: METHOD.to_string(),
+                reason: Reason::OutOfCycles,
+            });
+        }
+        let create_args = CmcCreateCanisterArg {
+            subnet_selection: Some(subnet_selection),
+            settings: Some(CmcCanisterSettings {
+                controllers: Some(controllers),
+                freezing_threshold: settings.freezing_threshold.map(Nat::from),
+                memory_allocation: settings.memory_allocation.map(Nat::from),
+                wasm_memory_limit: settings.wasm_memory_limit.map(Nat::from),
+                reserved_cycles_limit: settings.reserved_cycles_limit.map(Nat::from),
+                log_visibility: settings.log_visibility.map(
+                    |log_visibility| match log_visibility {
+                        LogVisibility::Controllers => CmcLogVisibility::Controllers,
+                        LogVisibility::Public => CmcLogVisibility::Public,
+                    },
+                ),
+                wasm_memory_threshold: None,
+                compute_allocation: None,
+            }),
+            subnet_type: None,
+        };
+        let result: Result<(CmcCreateCanisterResult,), _> = ic_cdk::api::call::call_with_payment(
+            MAINNET_CYCLE_MINTER_CANISTER_ID,
+            METHOD,
+            (create_args,),
+            cycles_for_canister_creation,
+        )
+        .await;
+
+        match result {
+            Ok((CmcCreateCanisterResult::Ok(canister_id),)) => Ok(canister_id),
+            Ok((CmcCreateCanisterResult::Err(CmcCreateCanisterError::Refunded {
+                create_error,
+                refund_amount,
+            }),)) => Err(CallError {
+                method: METHOD.to_string(),
+                reason: Reason::Rejected(format!(
+                    "{} (refunded {} cycles)",
+                    create_error, refund_amount
+                )),
+            }),
+            Err((code, msg)) => Err(CallError {
+                method: METHOD.to_string(),
+                reason: Reason::from_reject(code, msg),
+            }),
+        }
+    }
 }
 
 #[async_trait]
@@ -203,6 +384,8 @@ impl CanisterRuntime for IcCanisterRuntime {
         &self,
         controllers: Vec<Principal>,
         cycles_for_canister_creation: u64,
+        settings: CanisterSettings,
+        placement: CanisterPlacement,
     ) -> Result<Principal, CallError> {
         // See https://internetcomputer.org/docs/current/references/ic-interface-spec#ic-create_canister
         assert!(
@@ -210,12 +393,31 @@ impl CanisterRuntime for IcCanisterRuntime {
             "BUG: too many controllers. Expected at most 10, got {}",
             controllers.len()
         );
+        let subnet_selection = match placement {
+            CanisterPlacement::LsmSubnet => None,
+            CanisterPlacement::Subnet(subnet) => Some(SubnetSelection::Subnet { subnet }),
+            CanisterPlacement::SubnetType(subnet_type) => {
+                Some(SubnetSelection::Filter(SubnetFilter {
+                    subnet_type: Some(subnet_type),
+                }))
+            }
+        };
+        if let Some(subnet_selection) = subnet_selection {
+            return self
+                .create_canister_with_cmc(
+                    controllers,
+                    cycles_for_canister_creation,
+                    settings,
+                    subnet_selection,
+                )
+                .await;
+        }
         let create_args = CreateCanisterArgs {
-            settings: Some(
+            settings: Some(canister_settings_args(
                 CanisterSettingsArgsBuilder::new()
-                    .with_controllers(controllers.into_iter().map(|p| p.into()).collect())
-                    .build(),
-            ),
+                    .with_controllers(controllers.into_iter().map(|p| p.into()).collect()),
+                settings,
+            )),
             ..Default::default()
         };
         let result: CanisterIdRecord = self
@@ -229,6 +431,50 @@ impl CanisterRuntime for IcCanisterRuntime {
         Ok(result.get_canister_id().get().into())
     }
 
+    async fn update_settings(
+        &self,
+        canister_id: Principal,
+        settings: CanisterSettings,
+    ) -> Result<(), CallError> {
+        let update_settings_args = UpdateSettingsArgs {
+            canister_id: PrincipalId::from(canister_id),
+            settings: canister_settings_args(CanisterSettingsArgsBuilder::new(), settings),
+            sender_canister_version: None,
+        };
+
+        let result: () = self
+            .call("update_settings", 0, &update_settings_args)
+            .await?;
+
+        Ok(result)
+    }
+
+    async fn set_controllers(
+        &self,
+        canister_id: Principal,
+        controllers: Vec<Principal>,
+    ) -> Result<(), CallError> {
+        // See https://internetcomputer.org/docs/current/references/ic-interface-spec#ic-update_settings
+        assert!(
+            controllers.len() <= 10,
+            "BUG: too many controllers. Expected at most 10, got {}",
+            controllers.len()
+        );
+        let update_settings_args = UpdateSettingsArgs {
+            canister_id: PrincipalId::from(canister_id),
+            settings: CanisterSettingsArgsBuilder::new()
+                .with_controllers(controllers.into_iter().map(|p| p.into()).collect())
+                .build(),
+            sender_canister_version: None,
+        };
+
+        let result: () = self
+            .call("update_settings", 0, &update_settings_args)
+            .await?;
+
+        Ok(result)
+    }
+
     async fn stop_canister(&self, canister_id: Principal) -> Result<(), CallError> {
         ic_cdk::api::management_canister::main::stop_canister(
             ic_cdk::api::management_canister::main::CanisterIdRecord { canister_id },
@@ -272,6 +518,101 @@ impl CanisterRuntime for IcCanisterRuntime {
         Ok(result)
     }
 
+    async fn upload_chunk(
+        &self,
+        canister_id: Principal,
+        chunk: Vec<u8>,
+    ) -> Result<Vec<u8>, CallError> {
+        #[derive(CandidType)]
+        struct UploadChunkArgs {
+            canister_id: Principal,
+            chunk: ByteBuf,
+        }
+
+        let result: ChunkHash = self
+            .call(
+                "upload_chunk",
+                0,
+                &UploadChunkArgs {
+                    canister_id,
+                    chunk: ByteBuf::from(chunk),
+                },
+            )
+            .await?;
+
+        Ok(result.hash.into_vec())
+    }
+
+    async fn install_chunked_code(
+        &self,
+        canister_id: Principal,
+        mode: InstallMode,
+        chunk_hashes: Vec<Vec<u8>>,
+        wasm_module_hash: Vec<u8>,
+        arg: Vec<u8>,
+    ) -> Result<(), CallError> {
+        #[derive(CandidType)]
+        struct UpgradeFlags {
+            skip_pre_upgrade: Option<bool>,
+        }
+
+        #[derive(CandidType)]
+        enum ChunkedInstallMode {
+            #[serde(rename = "install")]
+            Install,
+            #[serde(rename = "upgrade")]
+            Upgrade(Option<UpgradeFlags>),
+        }
+
+        #[derive(CandidType)]
+        struct InstallChunkedCodeArgs {
+            mode: ChunkedInstallMode,
+            target_canister: Principal,
+            store_canister: Option<Principal>,
+            chunk_hashes_list: Vec<ChunkHash>,
+            wasm_module_hash: ByteBuf,
+            arg: ByteBuf,
+            sender_canister_version: Option<u64>,
+        }
+
+        let install_chunked_code = InstallChunkedCodeArgs {
+            mode: match mode {
+                InstallMode::Install => ChunkedInstallMode::Install,
+                InstallMode::Upgrade => ChunkedInstallMode::Upgrade(None),
+            },
+            target_canister: canister_id,
+            // the target canister is its own chunk store
+            store_canister: None,
+            chunk_hashes_list: chunk_hashes
+                .into_iter()
+                .map(|hash| ChunkHash {
+                    hash: ByteBuf::from(hash),
+                })
+                .collect(),
+            wasm_module_hash: ByteBuf::from(wasm_module_hash),
+            arg: ByteBuf::from(arg),
+            sender_canister_version: None,
+        };
+
+        let result: () = self
+            .call("install_chunked_code", 0, &install_chunked_code)
+            .await?;
+
+        Ok(result)
+    }
+
+    async fn clear_chunk_store(&self, canister_id: Principal) -> Result<(), CallError> {
+        let result: () = self
+            .call(
+                "clear_chunk_store",
+                0,
+                &ic_cdk::api::management_canister::main::CanisterIdRecord { canister_id },
+            )
+            .await?;
+
+        Ok(result)
+    }
+
     async fn upgrade_canister(
         &self,
         canister_id: Principal,
@@ -293,6 +634,52 @@ impl CanisterRuntime for IcCanisterRuntime {
         Ok(result)
     }
 
+    async fn take_canister_snapshot(
+        &self,
+        canister_id: Principal,
+        replace_snapshot: Option<Vec<u8>>,
+    ) -> Result<CanisterSnapshotResponse, CallError> {
+        #[derive(CandidType)]
+        struct TakeCanisterSnapshotArgs {
+            canister_id: Principal,
+            replace_snapshot: Option<Vec<u8>>,
+        }
+
+        self.call(
+            "take_canister_snapshot",
+            0,
+            &TakeCanisterSnapshotArgs {
+                canister_id,
+                replace_snapshot,
+            },
+        )
+        .await
+    }
+
+    async fn load_canister_snapshot(
+        &self,
+        canister_id: Principal,
+        snapshot_id: Vec<u8>,
+    ) -> Result<(), CallError> {
+        #[derive(CandidType)]
+        struct LoadCanisterSnapshotArgs {
+            canister_id: Principal,
+            snapshot_id: Vec<u8>,
+            sender_canister_version: Option<u64>,
+        }
+
+        self.call(
+            "load_canister_snapshot",
+            0,
+            &LoadCanisterSnapshotArgs {
+                canister_id,
+                snapshot_id,
+                sender_canister_version: None,
+            },
+        )
+        .await
+    }
+
     async fn canister_cycles(&self, canister_id: Principal) -> Result<u128, CallError> {
         let result = ic_cdk::api::management_canister::main::canister_status(
             ic_cdk::api::management_canister::main::CanisterIdRecord { canister_id },
@@ -311,6 +698,24 @@ impl CanisterRuntime for IcCanisterRuntime {
         Ok(result)
     }
 
+    async fn canister_module_hash(
+        &self,
+        canister_id: Principal,
+    ) -> Result<Option<Vec<u8>>, CallError> {
+        let result = ic_cdk::api::management_canister::main::canister_status(
+            ic_cdk::api::management_canister::main::CanisterIdRecord { canister_id },
+        )
+        .await
+        .map_err(|(code, msg)| CallError {
+            method: "canister_status".to_string(),
+            reason: Reason::from_reject(code, msg),
+        })?
+        .0
+        .module_hash;
+
+        Ok(result)
+    }
+
     fn send_cycles(&self, canister_id: Principal, cycles: u128) -> Result<(), CallError> {
         #[derive(CandidType)]
         struct DepositCyclesArgs {
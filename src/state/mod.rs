@@ -20,10 +20,21 @@ use std::marker::PhantomData;
 use std::str::FromStr;
 
 use crate::endpoints::{
-    CyclesManagement, Erc20Contract, InitArg, InstalledNativeLedgerSuite,
-    InvalidNativeInstalledCanistersError, UpdateLedgerSuiteCreationFee,
+    CanisterPlacement, CanisterPoolConfig, CanisterSettings, CyclesManagement, Erc20Contract,
+    InitArg, InstallRetryPolicy, InstalledNativeLedgerSuite, InvalidNativeInstalledCanistersError,
+    ManagedCanisterSettings, UpdateLedgerSuiteCreationFee,
 };
-use crate::ledger_suite_manager::install_ls::InstallLedgerSuiteArgs;
+use crate::ledger_suite_manager::canister_pool::PooledCanister;
+use crate::ledger_suite_manager::controllers::ControllersReport;
+use crate::ledger_suite_manager::health_check::{HealthBaseline, UpgradeRollback};
+use crate::ledger_suite_manager::install_ls::{
+    ArchiveProfile, InstallLedgerSuiteArgs, InstallStep, LedgerSuiteInstallProgress,
+    DEFAULT_MAX_CONCURRENT_LS_INSTALLS,
+};
+use crate::ledger_suite_manager::module_hash_drift::ModuleHashDriftReport;
+use crate::ledger_suite_manager::notifications::{Notification, OutboxEntry};
+use crate::ledger_suite_manager::upgrade_campaign::{UpgradeCampaign, UpgradeCampaignError};
+use crate::ledger_suite_manager::upgrade_ls::UpgradeLedgerSuite;
 use crate::storage::memory::{state_memory, StableMemory};
 
 thread_local! {
@@ -294,6 +305,26 @@ pub struct Canisters {
     pub index: Option<IndexCanister>,
     pub archives: Vec<Principal>,
     pub metadata: CanistersMetadata,
+    // Latest snapshot of the ledger and index, taken before upgrading them
+    #[serde(default)]
+    pub snapshots: BTreeMap<CanisterKind, CanisterSnapshot>,
+    // Archiving settings re-applied when upgrading the ledger, if any
+    #[serde(default)]
+    pub archive_profile: Option<ArchiveProfile>,
+    // Subnet the ledger and index were created on, if created by the lsm
+    #[serde(default)]
+    pub placement: Option<CanisterPlacement>,
+}
+
+/// Snapshot of a managed canister taken before upgrading it.
+#[derive(Clone, Eq, PartialEq, Debug, Deserialize, Serialize)]
+pub struct CanisterSnapshot {
+    pub snapshot_id: Vec<u8>,
+    /// The hash of the wasm installed when the snapshot was taken.
+    pub wasm_hash: WasmHash,
+    /// The time at which the snapshot was taken
+    /// in nanoseconds since the epoch (1970-01-01).
+    pub taken_at: u64,
 }
 
 #[derive(Clone, Eq, PartialEq, Ord, PartialOrd, Debug, Deserialize, Serialize)]
@@ -308,6 +339,9 @@ impl Canisters {
             index: None,
             archives: vec![],
             metadata,
+            snapshots: BTreeMap::new(),
+            archive_profile: None,
+            placement: None,
         }
     }
 
@@ -395,6 +429,14 @@ pub type IndexCanister = Canister<Index>;
 #[derive(Debug)]
 pub enum Archive {}
 
+/// The kinds of canisters making up a ledger suite.
+#[derive(Clone, Copy, Eq, PartialEq, Ord, PartialOrd, Debug, Deserialize, Serialize)]
+pub enum CanisterKind {
+    Ledger,
+    Index,
+    Archive,
+}
+
 #[derive(Clone, PartialEq, Debug, Deserialize, Serialize)]
 pub enum ManagedCanisterStatus {
     /// Canister created with the given principal
@@ -436,6 +478,54 @@ pub struct LedgerSuiteVersion {
     pub archive_compressed_wasm_hash: WasmHash,
 }
 
+impl LedgerSuiteVersion {
+    /// Returns this version with the given wasm hashes replaced.
+    pub fn with_wasm_hashes(
+        self,
+        ledger_compressed_wasm_hash: Option<WasmHash>,
+        index_compressed_wasm_hash: Option<WasmHash>,
+        archive_compressed_wasm_hash: Option<WasmHash>,
+    ) -> Self {
+        Self {
+            ledger_compressed_wasm_hash: ledger_compressed_wasm_hash
+                .unwrap_or(self.ledger_compressed_wasm_hash),
+            index_compressed_wasm_hash: index_compressed_wasm_hash
+                .unwrap_or(self.index_compressed_wasm_hash),
+            archive_compressed_wasm_hash: archive_compressed_wasm_hash
+                .unwrap_or(self.archive_compressed_wasm_hash),
+        }
+    }
+
+    pub fn contains(&self, wasm_hash: &WasmHash) -> bool {
+        &self.ledger_compressed_wasm_hash == wasm_hash
+            || &self.index_compressed_wasm_hash == wasm_hash
+            || &self.archive_compressed_wasm_hash == wasm_hash
+    }
+}
+
+/// A ledger suite version together with the time at which it became the current version.
+#[derive(Clone, PartialEq, Debug, Deserialize, Serialize)]
+pub struct ActivatedLedgerSuiteVersion {
+    pub version: LedgerSuiteVersion,
+    /// The canister time at which the version became current
+    /// in nanoseconds since the epoch (1970-01-01).
+    pub activated_at: u64,
+}
+
+#[derive(Clone, Eq, PartialEq, Debug)]
+pub enum ResumeFailedLedgerSuiteInstallError {
+    FailedInstallNotFound,
+    /// The deposit of the creator was already refunded or its refund may have been executed.
+    DepositRefunded,
+}
+
+#[derive(Clone, Eq, PartialEq, Debug)]
+pub enum LedgerSuiteVersionRollbackError {
+    NoPreviousVersion,
+    UnknownHistoryIndex(usize),
+    AlreadyCurrentVersion,
+}
+
 /// Configuration state of the lsm.
 #[derive(Clone, PartialEq, Debug, Default)]
 enum ConfigState {
@@ -513,26 +603,216 @@ pub struct ReceivedDeposit {
     pub from_principal: Principal,
     pub erc20_token: Erc20Token,
     pub transfer_index: u64,
+    #[serde(default)]
+    pub refund: Option<IcpRefund>,
+}
+
+#[derive(Clone, PartialEq, Debug, Deserialize, Serialize)]
+pub struct RejectedLedgerSuiteRequest {
+    pub install_args: InstallLedgerSuiteArgs,
+    pub reason: String,
+    /// The canister time at which the request was rejected
+    /// in nanoseconds since the epoch (1970-01-01).
+    pub rejected_at: u64,
+}
+
+/// Token symbols, contracts and token names that new ledger suite requests may not use,
+/// e.g. to prevent twins impersonating well-known tokens.
+///
+/// Symbols and names are compared case-insensitively.
+#[derive(Clone, Default, Eq, PartialEq, Debug, Deserialize, Serialize)]
+pub struct TokenDenylists {
+    denied_token_symbols: BTreeSet<String>,
+    denied_erc20_contracts: BTreeSet<Erc20Token>,
+    reserved_token_names: BTreeSet<String>,
+}
+
+impl TokenDenylists {
+    pub fn denied_token_symbols(&self) -> &BTreeSet<String> {
+        &self.denied_token_symbols
+    }
+
+    pub fn denied_erc20_contracts(&self) -> &BTreeSet<Erc20Token> {
+        &self.denied_erc20_contracts
+    }
+
+    pub fn reserved_token_names(&self) -> &BTreeSet<String> {
+        &self.reserved_token_names
+    }
+
+    pub fn is_token_symbol_denied(&self, symbol: &str) -> bool {
+        self.denied_token_symbols
+            .contains(&normalize_token_symbol(symbol))
+    }
+
+    pub fn is_erc20_contract_denied(&self, token: &Erc20Token) -> bool {
+        self.denied_erc20_contracts.contains(token)
+    }
+
+    pub fn is_token_name_reserved(&self, name: &str) -> bool {
+        self.reserved_token_names
+            .contains(&normalize_token_name(name))
+    }
+
+    pub fn deny_token_symbol(&mut self, symbol: &str) {
+        self.denied_token_symbols
+            .insert(normalize_token_symbol(symbol));
+    }
+
+    pub fn allow_token_symbol(&mut self, symbol: &str) {
+        self.denied_token_symbols
+            .remove(&normalize_token_symbol(symbol));
+    }
+
+    pub fn deny_erc20_contract(&mut self, token: Erc20Token) {
+        self.denied_erc20_contracts.insert(token);
+    }
+
+    pub fn allow_erc20_contract(&mut self, token: &Erc20Token) {
+        self.denied_erc20_contracts.remove(token);
+    }
+
+    pub fn reserve_token_name(&mut self, name: &str) {
+        self.reserved_token_names.insert(normalize_token_name(name));
+    }
+
+    pub fn release_token_name(&mut self, name: &str) {
+        self.reserved_token_names
+            .remove(&normalize_token_name(name));
+    }
+}
+
+pub fn normalize_token_symbol(symbol: &str) -> String {
+    symbol.trim().to_uppercase()
+}
+
+/// Lowercases the name and collapses its whitespaces,
+/// so that e.g. "USD  Coin" and "usd coin" are the same name.
+pub fn normalize_token_name(name: &str) -> String {
+    name.split_whitespace()
+        .collect::<Vec<_>>()
+        .join(" ")
+        .to_lowercase()
+}
+
+/// Refund of a deposit whose ledger suite request was rejected or whose installation failed.
+#[derive(Clone, PartialEq, Debug, Deserialize, Serialize)]
+pub enum IcpRefund {
+    /// The refund is scheduled and no transfer may have been executed yet.
+    Scheduled,
+    /// A transfer created at `created_at_time` may have been executed by the icp ledger.
+    /// Retries reuse the same `created_at_time`, so that the ledger deduplicates them.
+    InFlight {
+        created_at_time: u64,
+        /// Set once the ledger no longer deduplicates the retries,
+        /// so that the refund is only resolved by a manual reconciliation.
+        #[serde(default)]
+        requires_reconciliation: bool,
+    },
+    Refunded {
+        block_index: u64,
+        refunded_at: u64,
+    },
 }
 
 #[derive(Clone, PartialEq, Debug, Deserialize, Serialize)]
 pub struct State {
     managed_canisters: ManagedCanisters,
     cycles_management: CyclesManagement,
+
+    #[serde(default)]
+    install_retry_policy: InstallRetryPolicy,
+
+    // Maximum number of ledger suites installed concurrently
+    #[serde(default = "default_max_concurrent_ls_installs")]
+    max_concurrent_ls_installs: u32,
     more_controller_ids: Vec<Principal>,
 
     // For every evm chain there is a specific minter canister
     minter_id: BTreeMap<ChainId, Principal>,
 
+    // New LedgerSuite requests waiting to be approved by an admin
+    #[serde(default)]
+    ledger_suite_requests_pending_approval: BTreeMap<Erc20Token, InstallLedgerSuiteArgs>,
+
+    // LedgerSuite requests rejected by an admin
+    #[serde(default)]
+    rejected_ledger_suite_requests: BTreeMap<Erc20Token, RejectedLedgerSuiteRequest>,
+
     // New LedgerSuite requests waiting to be created
     pub twin_ledger_suites_to_be_installed: BTreeMap<Erc20Token, InstallLedgerSuiteArgs>,
 
     // The ledger suites installs that are not recoverable
     failed_ledger_suite_installs: BTreeMap<Erc20Token, InstallLedgerSuiteArgs>,
 
+    // Progress of every step of the pending and failed ledger suite installs
+    #[serde(default)]
+    ledger_suite_install_progress: BTreeMap<Erc20Token, LedgerSuiteInstallProgress>,
+
+    // Ledger suites waiting to be upgraded to the current ledger suite version
+    #[serde(default)]
+    ledger_suites_to_be_upgraded: BTreeMap<Erc20Token, UpgradeLedgerSuite>,
+
     #[serde(default)]
     ledger_suite_version: Option<LedgerSuiteVersion>,
 
+    // Staged rollout of the scheduled ledger suite upgrades
+    #[serde(default)]
+    upgrade_campaign: Option<UpgradeCampaign>,
+
+    // Latest module hash drift check of every managed ledger suite
+    #[serde(default)]
+    module_hash_drift_reports: BTreeMap<Erc20Token, ModuleHashDriftReport>,
+
+    // Wasm installed by the lsm on every archive it upgraded.
+    // Archives spawned by their ledger run the archive wasm embedded in the ledger, which is unknown.
+    #[serde(default)]
+    archive_wasm_hashes: BTreeMap<Principal, WasmHash>,
+
+    // Canisters rolled back to their previous wasm after failing their post-upgrade health checks
+    #[serde(default)]
+    upgrade_rollbacks: BTreeMap<Erc20Token, Vec<UpgradeRollback>>,
+
+    // Every ledger suite version that became current, in activation order.
+    // The last entry is the current ledger suite version.
+    #[serde(default)]
+    ledger_suite_version_history: Vec<ActivatedLedgerSuiteVersion>,
+
+    // Symbols, contracts and names new ledger suite requests may not use
+    #[serde(default)]
+    token_denylists: TokenDenylists,
+
+    // Archiving settings that ledger suite requests can choose by name
+    #[serde(default)]
+    archive_profiles: BTreeMap<String, ArchiveProfile>,
+
+    // Settings of the ledger, index and archive canisters
+    #[serde(default)]
+    canister_settings: ManagedCanisterSettings,
+
+    // Settings last applied to every managed canister
+    #[serde(default)]
+    applied_canister_settings: BTreeMap<Principal, CanisterSettings>,
+
+    // Subnet on which the canisters of new ledger suites are created
+    #[serde(default)]
+    canister_placement: CanisterPlacement,
+
+    #[serde(default)]
+    canister_pool_config: CanisterPoolConfig,
+
+    // Empty canisters created in advance, by creation order
+    #[serde(default)]
+    canister_pool: Vec<PooledCanister>,
+
+    // Controllers last set on every managed canister
+    #[serde(default)]
+    applied_controllers: BTreeMap<Principal, BTreeSet<Principal>>,
+
+    // Latest update of the controllers of the canisters of every ledger suite
+    #[serde(default)]
+    controllers_reports: BTreeMap<Erc20Token, ControllersReport>,
+
     // Collected icp or appic token in the beginning for ledger suite creation
     collected_icp_token: u128,
     collected_appic_token: u128,
@@ -541,8 +821,12 @@ pub struct State {
     // Received deposits for twin ledger suite creation
     received_deposits: Vec<ReceivedDeposit>,
 
-    // Minters to be notified of new erc20
-    pub notify_add_erc20_list: BTreeMap<Erc20Token, Principal>,
+    // Notifications of other canisters waiting to be delivered, by enqueuing order
+    #[serde(default)]
+    notification_outbox: BTreeMap<u64, OutboxEntry>,
+
+    #[serde(default)]
+    next_notification_id: u64,
 }
 
 impl State {
@@ -550,6 +834,81 @@ impl State {
         &self.more_controller_ids
     }
 
+    /// Adds and removes additional controllers.
+    /// The additional controllers are left unchanged if there would be too many of them.
+    pub fn update_more_controller_ids(
+        &mut self,
+        add: Vec<Principal>,
+        remove: &[Principal],
+    ) -> Result<(), InvalidStateError> {
+        let mut more_controller_ids: Vec<Principal> = self
+            .more_controller_ids
+            .iter()
+            .filter(|id| !remove.contains(id))
+            .copied()
+            .collect();
+        for id in add {
+            if !more_controller_ids.contains(&id) && !remove.contains(&id) {
+                more_controller_ids.push(id);
+            }
+        }
+        let previous = std::mem::replace(&mut self.more_controller_ids, more_controller_ids);
+        if let Err(e) = self.validate_config() {
+            self.more_controller_ids = previous;
+            return Err(e);
+        }
+        Ok(())
+    }
+
+    /// The controllers every managed canister should have: the lsm and the additional controllers.
+    pub fn canister_controllers(&self, lsm_id: Principal) -> BTreeSet<Principal> {
+        once(lsm_id)
+            .chain(self.more_controller_ids.iter().copied())
+            .collect()
+    }
+
+    /// Returns the managed canisters whose controllers were not yet set to the given ones.
+    pub fn canisters_with_outdated_controllers(
+        &self,
+        controllers: &BTreeSet<Principal>,
+    ) -> Vec<(Erc20Token, Principal, CanisterKind)> {
+        let mut result = vec![];
+        for (token, canisters) in self.all_managed_canisters_iter() {
+            let ledger = canisters
+                .ledger_canister_id()
+                .map(|id| (*id, CanisterKind::Ledger));
+            let index = canisters
+                .index_canister_id()
+                .map(|id| (*id, CanisterKind::Index));
+            let archives = canisters
+                .archive_canister_ids()
+                .iter()
+                .map(|id| (*id, CanisterKind::Archive));
+            for (canister_id, kind) in ledger.into_iter().chain(index).chain(archives) {
+                if self.applied_controllers.get(&canister_id) != Some(controllers) {
+                    result.push((token.clone(), canister_id, kind));
+                }
+            }
+        }
+        result
+    }
+
+    pub fn record_applied_controllers(
+        &mut self,
+        canister_id: Principal,
+        controllers: BTreeSet<Principal>,
+    ) {
+        self.applied_controllers.insert(canister_id, controllers);
+    }
+
+    pub fn controllers_reports(&self) -> impl Iterator<Item = (&Erc20Token, &ControllersReport)> {
+        self.controllers_reports.iter()
+    }
+
+    pub fn record_controllers_report(&mut self, token: Erc20Token, report: ControllersReport) {
+        self.controllers_reports.insert(token, report);
+    }
+
     pub fn minter_id(&self, chain_id: &ChainId) -> Option<&Principal> {
         self.minter_id.get(chain_id)
     }
@@ -567,17 +926,51 @@ impl State {
             .append(&mut BTreeMap::from_iter(new_minters.into_iter()));
     }
 
-    pub fn record_new_erc20_minter_notification(
-        &mut self,
-        token: &Erc20Token,
-        minter_id: &Principal,
-    ) {
-        self.notify_add_erc20_list
-            .insert(token.clone(), minter_id.clone());
+    pub fn enqueue_notification(&mut self, notification: Notification, now: u64) {
+        let id = self.next_notification_id;
+        self.next_notification_id += 1;
+        self.notification_outbox.insert(
+            id,
+            OutboxEntry {
+                notification,
+                enqueued_at: now,
+                attempts: 0,
+                last_error: None,
+                next_attempt_at: now,
+            },
+        );
     }
 
-    pub fn remove_erc20_from_minter_notification_list(&mut self, token: &Erc20Token) {
-        self.notify_add_erc20_list.remove(token);
+    pub fn notification_outbox(&self) -> impl Iterator<Item = (&u64, &OutboxEntry)> {
+        self.notification_outbox.iter()
+    }
+
+    /// Returns the notifications that are due.
+    /// A notification is held back while an earlier notification about the same token is not delivered.
+    pub fn notifications_to_send(&self, now: u64) -> Vec<(u64, Notification)> {
+        let mut tokens_with_earlier_notification = BTreeSet::new();
+        let mut result = vec![];
+        for (id, entry) in &self.notification_outbox {
+            let token = entry.notification.token();
+            if tokens_with_earlier_notification.insert(token) && entry.next_attempt_at <= now {
+                result.push((*id, entry.notification.clone()));
+            }
+        }
+        result
+    }
+
+    pub fn record_notification_sent(&mut self, id: u64) {
+        self.notification_outbox.remove(&id);
+    }
+
+    /// Records a failed delivery of a notification, which is retried according to the retry policy.
+    pub fn record_notification_failure(&mut self, id: u64, error: String, now: u64) {
+        if let Some(entry) = self.notification_outbox.get_mut(&id) {
+            entry.attempts = entry.attempts.saturating_add(1);
+            entry.last_error = Some(error);
+            entry.next_attempt_at =
+                now.saturating_add(self.install_retry_policy.backoff_nanos(entry.attempts));
+        }
     }
 
     pub fn cycles_management(&self) -> &CyclesManagement {
@@ -588,6 +981,220 @@ impl State {
         &mut self.cycles_management
     }
 
+    pub fn install_retry_policy(&self) -> &InstallRetryPolicy {
+        &self.install_retry_policy
+    }
+
+    pub fn install_retry_policy_mut(&mut self) -> &mut InstallRetryPolicy {
+        &mut self.install_retry_policy
+    }
+
+    pub fn max_concurrent_ls_installs(&self) -> u32 {
+        self.max_concurrent_ls_installs
+    }
+
+    pub fn set_max_concurrent_ls_installs(&mut self, max_concurrent_ls_installs: u32) {
+        assert!(
+            max_concurrent_ls_installs > 0,
+            "BUG: at least one ledger suite must be installed at a time"
+        );
+        self.max_concurrent_ls_installs = max_concurrent_ls_installs;
+    }
+
+    pub fn canister_settings(&self) -> &ManagedCanisterSettings {
+        &self.canister_settings
+    }
+
+    pub fn canister_settings_mut(&mut self) -> &mut ManagedCanisterSettings {
+        &mut self.canister_settings
+    }
+
+    pub fn canister_placement(&self) -> &CanisterPlacement {
+        &self.canister_placement
+    }
+
+    pub fn set_canister_placement(&mut self, placement: CanisterPlacement) {
+        self.canister_placement = placement;
+    }
+
+    /// Returns the subnet on which the canisters of the given token are to be created:
+    /// the placement of its ledger if already created, so that a configuration change
+    /// in the middle of an installation does not move the index away from its ledger.
+    pub fn canister_placement_of(&self, erc20_token: &Erc20Token) -> CanisterPlacement {
+        self.managed_canisters(erc20_token)
+            .and_then(|canisters| canisters.placement.clone())
+            .unwrap_or_else(|| self.canister_placement.clone())
+    }
+
+    pub fn record_canister_placement(
+        &mut self,
+        erc20_token: &Erc20Token,
+        placement: CanisterPlacement,
+    ) {
+        if let Some(canisters) = self.managed_canisters_mut(erc20_token) {
+            canisters.placement = Some(placement);
+        }
+    }
+
+    pub fn canister_pool_config(&self) -> &CanisterPoolConfig {
+        &self.canister_pool_config
+    }
+
+    pub fn canister_pool_config_mut(&mut self) -> &mut CanisterPoolConfig {
+        &mut self.canister_pool_config
+    }
+
+    pub fn pooled_canisters(&self) -> &[PooledCanister] {
+        &self.canister_pool
+    }
+
+    pub fn pooled_canisters_on<'a>(
+        &'a self,
+        placement: &'a CanisterPlacement,
+    ) -> impl Iterator<Item = &'a PooledCanister> {
+        self.canister_pool
+            .iter()
+            .filter(move |canister| &canister.placement == placement)
+    }
+
+    pub fn record_pooled_canister(&mut self, canister: PooledCanister) {
+        self.canister_pool.push(canister);
+    }
+
+    /// Removes from the pool the oldest canister on the given subnet
+    /// that was created with at least the given amount of cycles.
+    pub fn take_pooled_canister(
+        &mut self,
+        placement: &CanisterPlacement,
+        min_cycles: u128,
+    ) -> Option<PooledCanister> {
+        let index = self.canister_pool.iter().position(|canister| {
+            &canister.placement == placement && canister.cycles >= min_cycles
+        })?;
+        Some(self.canister_pool.remove(index))
+    }
+
+    pub fn record_applied_canister_settings(
+        &mut self,
+        canister_id: Principal,
+        settings: CanisterSettings,
+    ) {
+        self.applied_canister_settings.insert(canister_id, settings);
+    }
+
+    /// Returns the managed canisters whose settings differ from the ones last applied to them.
+    /// Canisters whose settings were never applied run with the IC defaults.
+    pub fn canister_settings_to_reconcile(
+        &self,
+    ) -> Vec<(Principal, CanisterKind, CanisterSettings)> {
+        let mut result = vec![];
+        for (_token, canisters) in self.all_managed_canisters_iter() {
+            let ledger = canisters
+                .ledger_canister_id()
+                .map(|id| (*id, CanisterKind::Ledger));
+            let index = canisters
+                .index_canister_id()
+                .map(|id| (*id, CanisterKind::Index));
+            let archives = canisters
+                .archive_canister_ids()
+                .iter()
+                .map(|id| (*id, CanisterKind::Archive));
+            for (canister_id, kind) in ledger.into_iter().chain(index).chain(archives) {
+                let settings = self.canister_settings.for_kind(kind);
+                let applied_settings = self
+                    .applied_canister_settings
+                    .get(&canister_id)
+                    .cloned()
+                    .unwrap_or_default();
+                if settings != &applied_settings {
+                    result.push((canister_id, kind, settings.clone()));
+                }
+            }
+        }
+        result
+    }
+
+    pub fn token_denylists(&self) -> &TokenDenylists {
+        &self.token_denylists
+    }
+
+    pub fn token_denylists_mut(&mut self) -> &mut TokenDenylists {
+        &mut self.token_denylists
+    }
+
+    pub fn archive_profiles(&self) -> &BTreeMap<String, ArchiveProfile> {
+        &self.archive_profiles
+    }
+
+    pub fn archive_profile(&self, name: &str) -> Option<&ArchiveProfile> {
+        self.archive_profiles.get(name)
+    }
+
+    pub fn upsert_archive_profile(&mut self, name: String, profile: ArchiveProfile) {
+        self.archive_profiles.insert(name, profile);
+    }
+
+    pub fn remove_archive_profile(&mut self, name: &str) -> Option<ArchiveProfile> {
+        self.archive_profiles.remove(name)
+    }
+
+    /// Sets the archive profile of a token on its pending, queued or failed installation
+    /// and on its ledger suite, where it is applied at the next upgrade of the ledger.
+    ///
+    /// Returns `false` if the token is unknown.
+    pub fn set_token_archive_profile(
+        &mut self,
+        erc20_token: &Erc20Token,
+        profile: ArchiveProfile,
+    ) -> bool {
+        let mut found = false;
+        for install_args in [
+            self.ledger_suite_requests_pending_approval
+                .get_mut(erc20_token),
+            self.twin_ledger_suites_to_be_installed.get_mut(erc20_token),
+            self.failed_ledger_suite_installs.get_mut(erc20_token),
+        ]
+        .into_iter()
+        .flatten()
+        {
+            install_args.archive_profile = profile.clone();
+            found = true;
+        }
+        if let Some(canisters) = self.managed_canisters_mut(erc20_token) {
+            canisters.archive_profile = Some(profile);
+            found = true;
+        }
+        found
+    }
+
+    pub fn record_archive_profile(&mut self, erc20_token: &Erc20Token, profile: ArchiveProfile) {
+        if let Some(canisters) = self.managed_canisters_mut(erc20_token) {
+            canisters.archive_profile = Some(profile);
+        }
+    }
+
+    /// Returns the token, other than `except`, that already uses the given symbol,
+    /// among the managed tokens and the ledger suite requests pending approval or installation.
+    /// Symbols are compared case-insensitively.
+    pub fn token_with_symbol(&self, symbol: &str, except: &Erc20Token) -> Option<Erc20Token> {
+        let symbol = normalize_token_symbol(symbol);
+        let managed_symbols = self
+            .managed_canisters
+            .all_canisters_iter()
+            .map(|(token, canisters)| (token, canisters.metadata.token_symbol.as_str()));
+        let requested_symbols = self
+            .ledger_suite_requests_pending_approval
+            .iter()
+            .chain(self.twin_ledger_suites_to_be_installed.iter())
+            .map(|(token, args)| (token.clone(), args.ledger_init_arg.token_symbol.as_str()));
+        managed_symbols
+            .chain(requested_symbols)
+            .find(|(token, token_symbol)| {
+                token != except && normalize_token_symbol(token_symbol) == symbol
+            })
+            .map(|(token, _)| token)
+    }
+
     pub fn all_managed_canisters_iter(&self) -> impl Iterator<Item = (Erc20Token, &Canisters)> {
         self.managed_canisters.all_canisters_iter()
     }
@@ -597,6 +1204,71 @@ impl State {
             .flat_map(|(_, canisters)| canisters.principals_iter())
     }
 
+    /// Returns the managed ledger and index canisters grouped by the wasm hash
+    /// recorded when the lsm installed or upgraded them.
+    pub fn managed_canisters_by_installed_wasm_hash(&self) -> BTreeMap<WasmHash, Vec<Principal>> {
+        let mut result: BTreeMap<WasmHash, Vec<Principal>> = BTreeMap::new();
+        for (_token, canisters) in self.all_managed_canisters_iter() {
+            let ledger_status = canisters.ledger.as_ref().map(Canister::status);
+            let index_status = canisters.index.as_ref().map(Canister::status);
+            for status in ledger_status.into_iter().chain(index_status) {
+                if let Some(wasm_hash) = status.installed_wasm_hash() {
+                    result
+                        .entry(wasm_hash.clone())
+                        .or_default()
+                        .push(*status.canister_id());
+                }
+            }
+        }
+        result
+    }
+
+    /// Returns the hashes of all wasms the lsm may still need:
+    /// the current version and every version of the ledger suite version history,
+    /// the wasms installed on managed canisters
+    /// and the wasms referenced by pending or failed installs and by scheduled upgrades.
+    ///
+    /// Keeping the wasms of the whole history ensures that
+    /// the ledger suite version can always be rolled back to any entry of the history.
+    pub fn referenced_wasm_hashes(&self) -> BTreeSet<WasmHash> {
+        let mut result: BTreeSet<WasmHash> = self
+            .managed_canisters_by_installed_wasm_hash()
+            .into_keys()
+            .collect();
+        // snapshots are loaded together with the wasm installed when they were taken
+        for (_token, canisters) in self.all_managed_canisters_iter() {
+            result.extend(
+                canisters
+                    .snapshots
+                    .values()
+                    .map(|snapshot| snapshot.wasm_hash.clone()),
+            );
+        }
+        // every version of the history is kept to be able to roll back to it
+        for version in self.ledger_suite_version.iter().chain(
+            self.ledger_suite_version_history
+                .iter()
+                .map(|activated| &activated.version),
+        ) {
+            result.insert(version.ledger_compressed_wasm_hash.clone());
+            result.insert(version.index_compressed_wasm_hash.clone());
+            result.insert(version.archive_compressed_wasm_hash.clone());
+        }
+        for install_args in self
+            .ledger_suite_requests_pending_approval
+            .values()
+            .chain(self.twin_ledger_suites_to_be_installed.values())
+            .chain(self.failed_ledger_suite_installs.values())
+        {
+            result.insert(install_args.ledger_compressed_wasm_hash.clone());
+            result.insert(install_args.index_compressed_wasm_hash.clone());
+        }
+        for upgrade in self.ledger_suites_to_be_upgraded.values() {
+            result.extend(upgrade.remaining_wasm_hashes().cloned());
+        }
+        result
+    }
+
     pub fn all_managed_tokens_ids_iter(&self) -> impl Iterator<Item = Erc20Token> + '_ {
         self.all_managed_canisters_iter().map(|(id, _)| id)
     }
@@ -620,16 +1292,315 @@ impl State {
         self.minimum_tokens_for_new_ledger_suite = new_ls_fees
     }
 
+    pub fn ledger_suite_version_history(&self) -> &[ActivatedLedgerSuiteVersion] {
+        &self.ledger_suite_version_history
+    }
+
+    /// Returns the most recently activated version that differs from the current one.
+    pub fn previous_ledger_suite_version(&self) -> Option<&LedgerSuiteVersion> {
+        self.ledger_suite_version_history
+            .iter()
+            .rev()
+            .map(|activated| &activated.version)
+            .find(|version| Some(*version) != self.ledger_suite_version.as_ref())
+    }
+
+    /// Returns the version of the history to roll back to:
+    /// the entry at `history_index` if given, or the previous ledger suite version otherwise.
+    pub fn ledger_suite_version_to_roll_back_to(
+        &self,
+        history_index: Option<usize>,
+    ) -> Result<LedgerSuiteVersion, LedgerSuiteVersionRollbackError> {
+        let version = match history_index {
+            Some(index) => self
+                .ledger_suite_version_history
+                .get(index)
+                .map(|activated| &activated.version)
+                .ok_or(LedgerSuiteVersionRollbackError::UnknownHistoryIndex(index))?,
+            None => self
+                .previous_ledger_suite_version()
+                .ok_or(LedgerSuiteVersionRollbackError::NoPreviousVersion)?,
+        };
+        if Some(version) == self.ledger_suite_version.as_ref() {
+            return Err(LedgerSuiteVersionRollbackError::AlreadyCurrentVersion);
+        }
+        Ok(version.clone())
+    }
+
     /// Initializes the ledger suite version if it is not already set.
     /// No-op if the ledger suite version is already set.
-    pub fn init_ledger_suite_version(&mut self, version: LedgerSuiteVersion) {
+    pub fn init_ledger_suite_version(&mut self, version: LedgerSuiteVersion, timestamp: u64) {
         if self.ledger_suite_version.is_none() {
             self.ledger_suite_version = Some(version);
+            self.init_ledger_suite_version_history(timestamp);
+        }
+    }
+
+    /// Starts the history with the current ledger suite version, if the history is empty.
+    /// This is the case for a state written by an lsm that did not keep a history.
+    pub fn init_ledger_suite_version_history(&mut self, timestamp: u64) {
+        if !self.ledger_suite_version_history.is_empty() {
+            return;
+        }
+        if let Some(version) = &self.ledger_suite_version {
+            self.ledger_suite_version_history
+                .push(ActivatedLedgerSuiteVersion {
+                    version: version.clone(),
+                    activated_at: timestamp,
+                });
+        }
+    }
+
+    /// Sets the ledger suite version and schedules an upgrade for every managed token
+    /// whose ledger suite does not run the new version.
+    /// A previously scheduled upgrade of a token is replaced,
+    /// or removed if the ledger suite of that token already runs the new version.
+    /// An unfinished upgrade campaign is cancelled, which releases the upgrades it held back.
+    ///
+    /// Pending installs are moved to the new version as well,
+    /// so that they do not need to be upgraded once installed.
+    pub fn update_ledger_suite_version(&mut self, new_version: LedgerSuiteVersion, timestamp: u64) {
+        let previous_version = self.ledger_suite_version.replace(new_version.clone());
+        if previous_version.as_ref() == Some(&new_version) {
+            return;
+        }
+        self.ledger_suite_version_history
+            .push(ActivatedLedgerSuiteVersion {
+                version: new_version.clone(),
+                activated_at: timestamp,
+            });
+        if let Some(campaign) = self.upgrade_campaign.as_mut() {
+            campaign.cancel();
         }
+
+        for install_args in self
+            .ledger_suite_requests_pending_approval
+            .values_mut()
+            .chain(self.twin_ledger_suites_to_be_installed.values_mut())
+        {
+            install_args.ledger_compressed_wasm_hash =
+                new_version.ledger_compressed_wasm_hash.clone();
+            install_args.index_compressed_wasm_hash =
+                new_version.index_compressed_wasm_hash.clone();
+        }
+
+        let archive_compressed_wasm_hash = match previous_version {
+            Some(previous_version)
+                if previous_version.archive_compressed_wasm_hash
+                    == new_version.archive_compressed_wasm_hash =>
+            {
+                None
+            }
+            _ => Some(new_version.archive_compressed_wasm_hash.clone()),
+        };
+        let upgrades: Vec<_> = self
+            .all_managed_canisters_iter()
+            .map(|(token, canisters)| {
+                let upgrade = UpgradeLedgerSuite::builder(token.clone())
+                    .ledger_wasm_hash(outdated_wasm_hash(
+                        canisters.ledger.as_ref(),
+                        &new_version.ledger_compressed_wasm_hash,
+                    ))
+                    .index_wasm_hash(outdated_wasm_hash(
+                        canisters.index.as_ref(),
+                        &new_version.index_compressed_wasm_hash,
+                    ))
+                    .archive_wasm_hash(
+                        archive_compressed_wasm_hash
+                            .clone()
+                            .or_else(|| {
+                                // archives may not have been upgraded to the previous version yet
+                                self.ledger_suites_to_be_upgraded
+                                    .get(&token)
+                                    .filter(|upgrade| upgrade.has_pending_archives_upgrade())
+                                    .map(|_| new_version.archive_compressed_wasm_hash.clone())
+                            })
+                            .filter(|_| canisters.ledger.is_some()),
+                    )
+                    .build();
+                (token, upgrade)
+            })
+            .collect();
+        for (token, upgrade) in upgrades {
+            if upgrade.is_empty() {
+                self.remove_ledger_suite_upgrade(&token);
+            } else {
+                self.record_ledger_suite_upgrade(token, upgrade);
+            }
+        }
+    }
+
+    /// Returns the upgrade scheduled for the ledger suite of the given token, if any.
+    pub fn ledger_suite_upgrade(&self, token: &Erc20Token) -> Option<&UpgradeLedgerSuite> {
+        self.ledger_suites_to_be_upgraded.get(token)
+    }
+
+    /// Schedules the upgrade of the ledger suite of the given token.
+    /// Replaces any upgrade already scheduled for that token.
+    pub fn record_ledger_suite_upgrade(&mut self, token: Erc20Token, upgrade: UpgradeLedgerSuite) {
+        self.ledger_suites_to_be_upgraded.insert(token, upgrade);
+    }
+
+    /// Records that the next subtask of the upgrade of the given token succeeded.
+    /// The upgrade is removed once all its subtasks are completed.
+    pub fn record_completed_upgrade_subtask(&mut self, token: &Erc20Token) {
+        let upgrade = self
+            .ledger_suites_to_be_upgraded
+            .get_mut(token)
+            .unwrap_or_else(|| panic!("BUG: no upgrade scheduled for token {:?}", token));
+        if upgrade.complete_next_subtask() {
+            self.remove_ledger_suite_upgrade(token);
+        }
+    }
+
+    /// Records that the upgrade of the given token took a snapshot of the canister of its next subtask.
+    pub fn record_upgrade_snapshot_taken(&mut self, token: &Erc20Token) {
+        if let Some(upgrade) = self.ledger_suites_to_be_upgraded.get_mut(token) {
+            upgrade.record_next_subtask_snapshot_taken();
+        }
+    }
+
+    /// Returns the health of the canister of the next subtask of the upgrade of the given token,
+    /// probed before the canister was first stopped.
+    pub fn upgrade_health_baseline(&self, token: &Erc20Token) -> Option<HealthBaseline> {
+        self.ledger_suites_to_be_upgraded
+            .get(token)
+            .and_then(UpgradeLedgerSuite::next_subtask_health_baseline)
+            .cloned()
+    }
+
+    /// Records the health of the canister of the next subtask of the upgrade of the given token,
+    /// probed before the canister is stopped.
+    pub fn record_upgrade_health_baseline(&mut self, token: &Erc20Token, baseline: HealthBaseline) {
+        if let Some(upgrade) = self.ledger_suites_to_be_upgraded.get_mut(token) {
+            upgrade.record_next_subtask_health_baseline(baseline);
+        }
+    }
+
+    pub fn remove_ledger_suite_upgrade(&mut self, token: &Erc20Token) {
+        self.ledger_suites_to_be_upgraded.remove(token);
+    }
+
+    /// Removes the upgrade of the given token after an unrecoverable error,
+    /// which stops the upgrade campaign if the token is part of its current batch.
+    pub fn record_failed_ledger_suite_upgrade(&mut self, token: &Erc20Token, error: String) {
+        self.remove_ledger_suite_upgrade(token);
+        if let Some(campaign) = self.upgrade_campaign.as_mut() {
+            campaign.record_failed_upgrade(token, error);
+        }
+    }
+
+    /// Returns the scheduled upgrades that are not held back by an upgrade campaign.
+    pub fn ledger_suite_upgrades_to_execute(&self) -> BTreeMap<Erc20Token, UpgradeLedgerSuite> {
+        self.ledger_suites_to_be_upgraded
+            .iter()
+            .filter(|(token, _upgrade)| {
+                !self
+                    .upgrade_campaign
+                    .as_ref()
+                    .is_some_and(|campaign| campaign.holds_back(token))
+            })
+            .map(|(token, upgrade)| (token.clone(), upgrade.clone()))
+            .collect()
+    }
+
+    pub fn upgrade_campaign(&self) -> Option<&UpgradeCampaign> {
+        self.upgrade_campaign.as_ref()
+    }
+
+    /// Sets the ledger suite version and starts a campaign rolling out the resulting upgrades,
+    /// together with the upgrades that were already scheduled.
+    /// The state is left unchanged if the campaign cannot be started.
+    pub fn start_upgrade_campaign(
+        &mut self,
+        new_version: LedgerSuiteVersion,
+        canary_tokens: BTreeSet<Erc20Token>,
+        batch_size: usize,
+        pause_between_batches: u64,
+        now: u64,
+    ) -> Result<(), UpgradeCampaignError> {
+        if self
+            .upgrade_campaign
+            .as_ref()
+            .is_some_and(UpgradeCampaign::is_running)
+        {
+            return Err(UpgradeCampaignError::CampaignAlreadyRunning);
+        }
+        let mut state = self.clone();
+        state.update_ledger_suite_version(new_version.clone(), now);
+        let campaign = UpgradeCampaign::new(
+            new_version,
+            state.ledger_suites_to_be_upgraded.keys().cloned().collect(),
+            canary_tokens,
+            batch_size,
+            pause_between_batches,
+            now,
+        )?;
+        state.upgrade_campaign = Some(campaign);
+        *self = state;
+        Ok(())
+    }
+
+    /// Resumes a stopped upgrade campaign, see [`UpgradeCampaign::resume`].
+    /// Returns `false` if there is no stopped campaign.
+    pub fn resume_upgrade_campaign(&mut self) -> bool {
+        self.upgrade_campaign
+            .as_mut()
+            .is_some_and(UpgradeCampaign::resume)
+    }
+
+    /// Cancels the upgrade campaign, which releases the upgrades it held back.
+    /// Returns `false` if there is no running or stopped campaign.
+    pub fn cancel_upgrade_campaign(&mut self) -> bool {
+        match self.upgrade_campaign.as_mut() {
+            Some(campaign) if campaign.is_active() => {
+                campaign.cancel();
+                true
+            }
+            _ => false,
+        }
+    }
+
+    /// Moves the upgrade campaign forward, see [`UpgradeCampaign::advance`].
+    pub fn advance_upgrade_campaign(&mut self, now: u64) {
+        let scheduled_upgrades: BTreeSet<_> =
+            self.ledger_suites_to_be_upgraded.keys().cloned().collect();
+        if let Some(campaign) = self.upgrade_campaign.as_mut() {
+            campaign.advance(&scheduled_upgrades, now);
+        }
+    }
+
+    pub fn module_hash_drift_reports(
+        &self,
+    ) -> impl Iterator<Item = (&Erc20Token, &ModuleHashDriftReport)> {
+        self.module_hash_drift_reports.iter()
     }
 
-    pub fn update_ledger_suite_version(&mut self, new_version: LedgerSuiteVersion) {
-        self.ledger_suite_version = Some(new_version);
+    pub fn record_module_hash_drift_report(
+        &mut self,
+        token: Erc20Token,
+        report: ModuleHashDriftReport,
+    ) {
+        self.module_hash_drift_reports.insert(token, report);
+    }
+
+    pub fn archive_wasm_hash(&self, archive_id: &Principal) -> Option<&WasmHash> {
+        self.archive_wasm_hashes.get(archive_id)
+    }
+
+    pub fn record_upgraded_archive(&mut self, archive_id: Principal, wasm_hash: WasmHash) {
+        self.archive_wasm_hashes.insert(archive_id, wasm_hash);
+    }
+
+    pub fn upgrade_rollbacks(&self) -> impl Iterator<Item = (&Erc20Token, &Vec<UpgradeRollback>)> {
+        self.upgrade_rollbacks.iter()
+    }
+
+    pub fn record_upgrade_rollback(&mut self, token: &Erc20Token, rollback: UpgradeRollback) {
+        self.upgrade_rollbacks
+            .entry(token.clone())
+            .or_default()
+            .push(rollback);
     }
 
     fn managed_canisters_mut(&mut self, token_id: &Erc20Token) -> Option<&mut Canisters> {
@@ -667,28 +1638,213 @@ impl State {
         transfer_index: u64,
         icp_amount: u128,
         from_principal: Principal,
+        timestamp: u64,
     ) {
         let deposit = ReceivedDeposit {
-            timestamp: ic_cdk::api::time(),
+            timestamp,
             amount: icp_amount,
             from_principal,
             erc20_token,
             transfer_index,
+            refund: None,
         };
         // Add to the collected icp
         self.collected_icp_token += icp_amount;
         self.received_deposits.push(deposit);
     }
 
+    /// Returns the amount of icp that was deposited for ledger suites
+    /// that may still have to be refunded.
+    pub fn icp_reserved_for_refunds(&self) -> u128 {
+        self.received_deposits
+            .iter()
+            .filter(|deposit| match deposit.refund {
+                Some(IcpRefund::Scheduled) | Some(IcpRefund::InFlight { .. }) => true,
+                Some(IcpRefund::Refunded { .. }) => false,
+                None => {
+                    self.ledger_suite_requests_pending_approval
+                        .contains_key(&deposit.erc20_token)
+                        || self
+                            .twin_ledger_suites_to_be_installed
+                            .contains_key(&deposit.erc20_token)
+                        || self
+                            .failed_ledger_suite_installs
+                            .contains_key(&deposit.erc20_token)
+                }
+            })
+            .map(|deposit| deposit.amount)
+            .sum()
+    }
+
+    pub fn icp_refunds_to_execute(&self) -> Vec<ReceivedDeposit> {
+        self.received_deposits
+            .iter()
+            .filter(|deposit| {
+                matches!(
+                    deposit.refund,
+                    Some(IcpRefund::Scheduled)
+                        | Some(IcpRefund::InFlight {
+                            requires_reconciliation: false,
+                            ..
+                        })
+                )
+            })
+            .cloned()
+            .collect()
+    }
+
+    /// Returns the deposits whose refund may have been executed
+    /// but can no longer be retried safely.
+    pub fn icp_refunds_to_reconcile(&self) -> Vec<ReceivedDeposit> {
+        self.received_deposits
+            .iter()
+            .filter(|deposit| {
+                matches!(
+                    deposit.refund,
+                    Some(IcpRefund::InFlight {
+                        requires_reconciliation: true,
+                        ..
+                    })
+                )
+            })
+            .cloned()
+            .collect()
+    }
+
+    /// Resolves a refund requiring a manual reconciliation, either as executed in the given
+    /// icp ledger block or as never executed, in which case a new refund is scheduled.
+    ///
+    /// Returns `false` if the refund of the deposit does not require a reconciliation.
+    pub fn reconcile_icp_refund(
+        &mut self,
+        transfer_index: u64,
+        refund_block_index: Option<u64>,
+        now: u64,
+    ) -> bool {
+        if !self
+            .icp_refunds_to_reconcile()
+            .iter()
+            .any(|deposit| deposit.transfer_index == transfer_index)
+        {
+            return false;
+        }
+        let refund = match refund_block_index {
+            Some(block_index) => IcpRefund::Refunded {
+                block_index,
+                refunded_at: now,
+            },
+            None => IcpRefund::Scheduled,
+        };
+        self.record_icp_refund(transfer_index, refund);
+        true
+    }
+
+    pub fn record_icp_refund(&mut self, transfer_index: u64, refund: IcpRefund) {
+        let deposit = self
+            .received_deposits
+            .iter_mut()
+            .find(|deposit| deposit.transfer_index == transfer_index)
+            .unwrap_or_else(|| panic!("BUG: deposit {} not found", transfer_index));
+        let was_refunded = matches!(deposit.refund, Some(IcpRefund::Refunded { .. }));
+        if matches!(refund, IcpRefund::Refunded { .. }) && !was_refunded {
+            self.collected_icp_token = self.collected_icp_token.saturating_sub(deposit.amount);
+        }
+        deposit.refund = Some(refund);
+    }
+
+    /// Records the refund of the deposit as in flight, if it is still scheduled.
+    pub fn start_icp_refund(&mut self, transfer_index: u64, created_at_time: u64) -> bool {
+        let scheduled = self.received_deposits.iter().any(|deposit| {
+            deposit.transfer_index == transfer_index && deposit.refund == Some(IcpRefund::Scheduled)
+        });
+        if scheduled {
+            self.record_icp_refund(
+                transfer_index,
+                IcpRefund::InFlight {
+                    created_at_time,
+                    requires_reconciliation: false,
+                },
+            );
+        }
+        scheduled
+    }
+
+    fn latest_deposit_mut(&mut self, erc20_token: &Erc20Token) -> Option<&mut ReceivedDeposit> {
+        self.received_deposits
+            .iter_mut()
+            .rev()
+            .find(|deposit| &deposit.erc20_token == erc20_token)
+    }
+
+    /// Records a new ledger suite request, which is only installed once approved by an admin.
     pub fn record_new_ledger_suite_request(
         &mut self,
         erc20_token: Erc20Token,
         install_args: InstallLedgerSuiteArgs,
     ) {
-        self.twin_ledger_suites_to_be_installed
+        self.ledger_suite_requests_pending_approval
             .insert(erc20_token, install_args);
     }
 
+    pub fn ledger_suite_requests_pending_approval(
+        &self,
+    ) -> &BTreeMap<Erc20Token, InstallLedgerSuiteArgs> {
+        &self.ledger_suite_requests_pending_approval
+    }
+
+    pub fn rejected_ledger_suite_requests(
+        &self,
+    ) -> &BTreeMap<Erc20Token, RejectedLedgerSuiteRequest> {
+        &self.rejected_ledger_suite_requests
+    }
+
+    /// Moves a request pending approval to the ledger suites to be installed.
+    ///
+    /// Returns `false` if there is no request pending approval for the token.
+    pub fn approve_ledger_suite_request(&mut self, erc20_token: &Erc20Token, now: u64) -> bool {
+        let mut install_args = match self
+            .ledger_suite_requests_pending_approval
+            .remove(erc20_token)
+        {
+            Some(install_args) => install_args,
+            None => return false,
+        };
+        install_args.next_attempt_at = now;
+        self.twin_ledger_suites_to_be_installed
+            .insert(erc20_token.clone(), install_args);
+        true
+    }
+
+    /// Rejects a request pending approval and schedules the refund of its deposit.
+    pub fn reject_ledger_suite_request(
+        &mut self,
+        erc20_token: &Erc20Token,
+        reason: String,
+        now: u64,
+    ) -> Option<InstallLedgerSuiteArgs> {
+        let install_args = self
+            .ledger_suite_requests_pending_approval
+            .remove(erc20_token)?;
+        self.schedule_icp_refund(erc20_token);
+        self.rejected_ledger_suite_requests.insert(
+            erc20_token.clone(),
+            RejectedLedgerSuiteRequest {
+                install_args: install_args.clone(),
+                reason,
+                rejected_at: now,
+            },
+        );
+        Some(install_args)
+    }
+
+    fn schedule_icp_refund(&mut self, erc20_token: &Erc20Token) {
+        if let Some(deposit) = self.latest_deposit_mut(erc20_token) {
+            if deposit.refund.is_none() {
+                deposit.refund = Some(IcpRefund::Scheduled);
+            }
+        }
+    }
+
     pub fn record_archives(&mut self, token: &Erc20Token, archives: Vec<Principal>) {
         let canisters = self
             .managed_canisters_mut(token)
@@ -696,6 +1852,18 @@ impl State {
         canisters.archives = archives;
     }
 
+    pub fn record_canister_snapshot(
+        &mut self,
+        token: &Erc20Token,
+        kind: CanisterKind,
+        snapshot: CanisterSnapshot,
+    ) {
+        let canisters = self
+            .managed_canisters_mut(token)
+            .unwrap_or_else(|| panic!("BUG: token {:?} is not managed", token));
+        canisters.snapshots.insert(kind, snapshot);
+    }
+
     pub fn record_created_canister<T: Debug>(&mut self, token: &Erc20Token, canister_id: Principal)
     where
         Canisters: ManageSingleCanister<T>,
@@ -741,16 +1909,131 @@ impl State {
         self.twin_ledger_suites_to_be_installed.remove(&erc20_token);
     }
 
+    pub fn failed_ledger_suite_installs(&self) -> &BTreeMap<Erc20Token, InstallLedgerSuiteArgs> {
+        &self.failed_ledger_suite_installs
+    }
+
+    /// Moves a failed installation back to the pending installations,
+    /// optionally with other wasms for the canisters that are not yet installed.
+    ///
+    /// An installation whose deposit was refunded, or whose refund may have been executed,
+    /// is not resumed, since the creator would get the ledger suite for free.
+    pub fn resume_failed_ls_install(
+        &mut self,
+        erc20_token: &Erc20Token,
+        ledger_compressed_wasm_hash: Option<WasmHash>,
+        index_compressed_wasm_hash: Option<WasmHash>,
+        now: u64,
+    ) -> Result<(), ResumeFailedLedgerSuiteInstallError> {
+        if !self.failed_ledger_suite_installs.contains_key(erc20_token) {
+            return Err(ResumeFailedLedgerSuiteInstallError::FailedInstallNotFound);
+        }
+        if matches!(
+            self.received_deposit(erc20_token)
+                .and_then(|deposit| deposit.refund.as_ref()),
+            Some(IcpRefund::InFlight { .. }) | Some(IcpRefund::Refunded { .. })
+        ) {
+            return Err(ResumeFailedLedgerSuiteInstallError::DepositRefunded);
+        }
+        let mut install_args = self
+            .failed_ledger_suite_installs
+            .remove(erc20_token)
+            .expect("BUG: failed install not found");
+        if let Some(ledger_compressed_wasm_hash) = ledger_compressed_wasm_hash {
+            install_args.ledger_compressed_wasm_hash = ledger_compressed_wasm_hash;
+        }
+        if let Some(index_compressed_wasm_hash) = index_compressed_wasm_hash {
+            install_args.index_compressed_wasm_hash = index_compressed_wasm_hash;
+        }
+        install_args.attempts = 0;
+        install_args.last_error = None;
+        install_args.next_attempt_at = now;
+        if let Some(deposit) = self.latest_deposit_mut(erc20_token) {
+            deposit.refund = None;
+        }
+        self.twin_ledger_suites_to_be_installed
+            .insert(erc20_token.clone(), install_args);
+        Ok(())
+    }
+
+    /// Permanently removes a failed installation together with its install progress.
+    /// Canisters already created for the token stay managed.
+    pub fn discard_failed_ls_install(
+        &mut self,
+        erc20_token: &Erc20Token,
+    ) -> Option<InstallLedgerSuiteArgs> {
+        let install_args = self.failed_ledger_suite_installs.remove(erc20_token)?;
+        self.remove_ledger_suite_install_progress(erc20_token);
+        Some(install_args)
+    }
+
+    /// Returns the latest deposit paid for the creation of the ledger suite of the given token.
+    pub fn received_deposit(&self, erc20_token: &Erc20Token) -> Option<&ReceivedDeposit> {
+        self.received_deposits
+            .iter()
+            .rev()
+            .find(|deposit| &deposit.erc20_token == erc20_token)
+    }
+
     pub fn record_failed_ls_install(
         &mut self,
         erc20_token: Erc20Token,
         install_args: InstallLedgerSuiteArgs,
     ) {
         self.remove_installed_ls_from_installing_queue(erc20_token.clone());
+        self.schedule_icp_refund(&erc20_token);
         self.failed_ledger_suite_installs
             .insert(erc20_token, install_args);
     }
 
+    /// Records a failed attempt of a pending installation.
+    /// The installation is moved to the failed installs if the error is not recoverable
+    /// or once the maximum number of attempts is reached.
+    ///
+    /// Returns `true` if the installation will be retried.
+    pub fn record_failed_ls_install_attempt(
+        &mut self,
+        erc20_token: &Erc20Token,
+        error: String,
+        is_recoverable: bool,
+        now: u64,
+    ) -> bool {
+        let install_args = match self.twin_ledger_suites_to_be_installed.get_mut(erc20_token) {
+            Some(install_args) => install_args,
+            None => return false,
+        };
+        install_args.record_failed_attempt(error, now, &self.install_retry_policy);
+        if is_recoverable && !install_args.retries_exhausted(&self.install_retry_policy) {
+            return true;
+        }
+        let install_args = install_args.clone();
+        self.record_failed_ls_install(erc20_token.clone(), install_args);
+        false
+    }
+
+    pub fn ledger_suite_install_progress(
+        &self,
+    ) -> impl Iterator<Item = (&Erc20Token, &LedgerSuiteInstallProgress)> {
+        self.ledger_suite_install_progress.iter()
+    }
+
+    pub fn record_ledger_suite_install_step(
+        &mut self,
+        erc20_token: &Erc20Token,
+        step: InstallStep,
+        timestamp: u64,
+        error: Option<String>,
+    ) {
+        self.ledger_suite_install_progress
+            .entry(erc20_token.clone())
+            .or_default()
+            .record_step(step, timestamp, error);
+    }
+
+    pub fn remove_ledger_suite_install_progress(&mut self, erc20_token: &Erc20Token) {
+        self.ledger_suite_install_progress.remove(erc20_token);
+    }
+
     pub fn validate_config(&self) -> Result<(), InvalidStateError> {
         const MAX_ADDITIONAL_CONTROLLERS: usize = 9;
         if self.more_controller_ids.len() > MAX_ADDITIONAL_CONTROLLERS {
@@ -810,11 +2093,31 @@ impl TryFrom<InitArg> for State {
         let state = Self {
             managed_canisters: Default::default(),
             cycles_management: cycles_management.unwrap_or_default(),
+            install_retry_policy: Default::default(),
+            max_concurrent_ls_installs: DEFAULT_MAX_CONCURRENT_LS_INSTALLS,
             more_controller_ids,
             minter_id: minter_ids_map,
             ledger_suite_version: Default::default(),
+            ledger_suite_version_history: Default::default(),
+            module_hash_drift_reports: Default::default(),
+            archive_wasm_hashes: Default::default(),
+            upgrade_rollbacks: Default::default(),
+            upgrade_campaign: Default::default(),
+            ledger_suite_requests_pending_approval: Default::default(),
+            rejected_ledger_suite_requests: Default::default(),
             twin_ledger_suites_to_be_installed: Default::default(),
             failed_ledger_suite_installs: Default::default(),
+            ledger_suite_install_progress: Default::default(),
+            ledger_suites_to_be_upgraded: Default::default(),
+            token_denylists: Default::default(),
+            archive_profiles: Default::default(),
+            canister_settings: Default::default(),
+            applied_canister_settings: Default::default(),
+            canister_placement: Default::default(),
+            canister_pool_config: Default::default(),
+            canister_pool: Default::default(),
+            applied_controllers: Default::default(),
+            controllers_reports: Default::default(),
             collected_icp_token: 0,
             collected_appic_token: 0,
             minimum_tokens_for_new_ledger_suite: LedgerSuiteCreationFee::new(
@@ -822,13 +2125,26 @@ impl TryFrom<InitArg> for State {
                 appic_ls_creation_fee,
             ),
             received_deposits: Default::default(),
-            notify_add_erc20_list: Default::default(),
+            notification_outbox: Default::default(),
+            next_notification_id: 0,
         };
         state.validate_config()?;
         Ok(state)
     }
 }
 
+fn default_max_concurrent_ls_installs() -> u32 {
+    DEFAULT_MAX_CONCURRENT_LS_INSTALLS
+}
+
+/// Returns the given wasm hash if the canister is installed with a different one.
+fn outdated_wasm_hash<T>(canister: Option<&Canister<T>>, wasm_hash: &WasmHash) -> Option<WasmHash> {
+    canister
+        .and_then(Canister::installed_wasm_hash)
+        .filter(|installed_wasm_hash| *installed_wasm_hash != wasm_hash)
+        .map(|_| wasm_hash.clone())
+}
+
 pub fn read_state<R>(f: impl FnOnce(&State) -> R) -> R {
     STATE.with(|cell| f(cell.borrow().get().expect_initialized()))
 }
@@ -151,6 +151,29 @@ mod manage_canister {
         test::<Index>();
         test::<Ledger>();
     }
+
+    #[test]
+    fn should_group_installed_canisters_by_wasm_hash() {
+        let mut state = new_state();
+        let wasm_hash = WasmHash::from([1_u8; 32]);
+        let usdc_ledger_id = Principal::from_slice(&[1_u8; 29]);
+        let usdt_ledger_id = Principal::from_slice(&[2_u8; 29]);
+        let usdt_index_id = Principal::from_slice(&[3_u8; 29]);
+        state.record_new_erc20_token(usdc(), usdc_metadata());
+        state.record_created_canister::<Ledger>(&usdc(), usdc_ledger_id);
+        state.record_installed_canister::<Ledger>(&usdc(), wasm_hash.clone());
+        state.record_new_erc20_token(usdt(), usdt_metadata());
+        state.record_created_canister::<Ledger>(&usdt(), usdt_ledger_id);
+        state.record_installed_canister::<Ledger>(&usdt(), wasm_hash.clone());
+        state.record_created_canister::<Index>(&usdt(), usdt_index_id);
+
+        assert_eq!(
+            state.managed_canisters_by_installed_wasm_hash(),
+            maplit::btreemap! {
+                wasm_hash => vec![usdc_ledger_id, usdt_ledger_id]
+            }
+        );
+    }
 }
 
 mod installed_ledger_suite {
@@ -411,6 +434,546 @@ mod validate_config {
 
            assert_eq!(result, Err(InvalidStateError::TooManyAdditionalControllers{max: 9, actual: additional_controllers.len()}));
         }
+
+        #[test]
+        fn should_keep_additional_controllers_when_update_adds_too_many(
+            init_arg in arb_init_arg(0..=9),
+            added_controllers in vec(arb_principal(), 10..=20)
+        ) {
+            let mut state = State::try_from(init_arg.clone()).expect("valid init arg");
+
+            let result = state.update_more_controller_ids(added_controllers, &[]);
+
+            assert!(matches!(result, Err(InvalidStateError::TooManyAdditionalControllers{max: 9, actual}) if actual > 9));
+            assert_eq!(state.more_controller_ids, init_arg.more_controller_ids);
+        }
+    }
+}
+
+mod upgrade_ledger_suite {
+    use crate::ledger_suite_manager::test_fixtures::{usdc, usdc_metadata, usdt, usdt_metadata};
+    use crate::ledger_suite_manager::upgrade_ls::UpgradeLedgerSuite;
+    use crate::state::test_fixtures::new_state;
+    use crate::state::{
+        CanisterKind, CanisterSnapshot, Index, Ledger, LedgerSuiteVersion, State, WasmHash,
+    };
+    use crate::storage::test_fixtures::embedded_ledger_suite_version;
+    use candid::Principal;
+    use maplit::btreemap;
+    use std::collections::BTreeSet;
+
+    #[test]
+    fn should_not_schedule_upgrade_when_version_unchanged() {
+        let mut state = state_with_installed_usdc_ledger_suite();
+
+        state.update_ledger_suite_version(embedded_ledger_suite_version(), 0);
+
+        assert_eq!(state.ledger_suites_to_be_upgraded, btreemap! {});
+    }
+
+    #[test]
+    fn should_schedule_upgrade_of_changed_wasms_only() {
+        let mut state = state_with_installed_usdc_ledger_suite();
+        let new_ledger_wasm_hash = WasmHash::from([1_u8; 32]);
+
+        state.update_ledger_suite_version(
+            LedgerSuiteVersion {
+                ledger_compressed_wasm_hash: new_ledger_wasm_hash.clone(),
+                ..embedded_ledger_suite_version()
+            },
+            0,
+        );
+
+        assert_eq!(
+            state.ledger_suites_to_be_upgraded,
+            btreemap! {
+                usdc() => UpgradeLedgerSuite::builder(usdc())
+                    .ledger_wasm_hash(new_ledger_wasm_hash)
+                    .build()
+            }
+        );
+    }
+
+    #[test]
+    fn should_schedule_upgrade_of_whole_ledger_suite() {
+        let mut state = state_with_installed_usdc_ledger_suite();
+        // token without any created canister yet
+        state.record_new_erc20_token(usdt(), usdt_metadata());
+        let new_version = LedgerSuiteVersion {
+            ledger_compressed_wasm_hash: WasmHash::from([1_u8; 32]),
+            index_compressed_wasm_hash: WasmHash::from([2_u8; 32]),
+            archive_compressed_wasm_hash: WasmHash::from([3_u8; 32]),
+        };
+
+        state.update_ledger_suite_version(new_version.clone(), 0);
+
+        assert_eq!(
+            state.ledger_suites_to_be_upgraded,
+            btreemap! {
+                usdc() => UpgradeLedgerSuite::builder(usdc())
+                    .ledger_wasm_hash(new_version.ledger_compressed_wasm_hash)
+                    .index_wasm_hash(new_version.index_compressed_wasm_hash)
+                    .archive_wasm_hash(new_version.archive_compressed_wasm_hash)
+                    .build()
+            }
+        );
+    }
+
+    #[test]
+    fn should_remove_upgrade_once_all_subtasks_completed() {
+        let mut state = state_with_installed_usdc_ledger_suite();
+        state.update_ledger_suite_version(
+            LedgerSuiteVersion {
+                ledger_compressed_wasm_hash: WasmHash::from([1_u8; 32]),
+                index_compressed_wasm_hash: WasmHash::from([2_u8; 32]),
+                ..embedded_ledger_suite_version()
+            },
+            0,
+        );
+
+        state.record_completed_upgrade_subtask(&usdc());
+        assert_eq!(
+            state
+                .ledger_suites_to_be_upgraded
+                .get(&usdc())
+                .map(UpgradeLedgerSuite::next_subtask_index),
+            Some(1)
+        );
+
+        state.record_completed_upgrade_subtask(&usdc());
+        assert_eq!(state.ledger_suites_to_be_upgraded.get(&usdc()), None);
+    }
+
+    #[test]
+    fn should_reference_wasms_of_installed_canisters_and_scheduled_upgrades() {
+        let mut state = state_with_installed_usdc_ledger_suite();
+        let previous_version = embedded_ledger_suite_version();
+        let new_version = LedgerSuiteVersion {
+            ledger_compressed_wasm_hash: WasmHash::from([1_u8; 32]),
+            index_compressed_wasm_hash: WasmHash::from([2_u8; 32]),
+            archive_compressed_wasm_hash: WasmHash::from([3_u8; 32]),
+        };
+        state.update_ledger_suite_version(new_version.clone(), 0);
+
+        assert_eq!(
+            state.referenced_wasm_hashes(),
+            BTreeSet::from([
+                previous_version.ledger_compressed_wasm_hash.clone(),
+                previous_version.index_compressed_wasm_hash.clone(),
+                previous_version.archive_compressed_wasm_hash.clone(),
+                new_version.ledger_compressed_wasm_hash.clone(),
+                new_version.index_compressed_wasm_hash.clone(),
+                new_version.archive_compressed_wasm_hash.clone(),
+            ])
+        );
+
+        // index and ledger upgraded
+        state.record_completed_upgrade_subtask(&usdc());
+        state.record_installed_canister::<Index>(
+            &usdc(),
+            new_version.index_compressed_wasm_hash.clone(),
+        );
+        state.record_completed_upgrade_subtask(&usdc());
+        state.record_installed_canister::<Ledger>(
+            &usdc(),
+            new_version.ledger_compressed_wasm_hash.clone(),
+        );
+
+        // the previous version is kept in the history to be able to roll back to it
+        assert_eq!(
+            state.referenced_wasm_hashes(),
+            BTreeSet::from([
+                previous_version.ledger_compressed_wasm_hash,
+                previous_version.index_compressed_wasm_hash,
+                previous_version.archive_compressed_wasm_hash,
+                new_version.ledger_compressed_wasm_hash,
+                new_version.index_compressed_wasm_hash,
+                new_version.archive_compressed_wasm_hash,
+            ])
+        );
+    }
+
+    #[test]
+    fn should_reference_wasms_of_every_version_of_the_history() {
+        let mut state = state_with_installed_usdc_ledger_suite();
+        let first_version = embedded_ledger_suite_version();
+        let second_version = LedgerSuiteVersion {
+            ledger_compressed_wasm_hash: WasmHash::from([1_u8; 32]),
+            index_compressed_wasm_hash: WasmHash::from([2_u8; 32]),
+            archive_compressed_wasm_hash: WasmHash::from([3_u8; 32]),
+        };
+        let third_version = LedgerSuiteVersion {
+            ledger_compressed_wasm_hash: WasmHash::from([4_u8; 32]),
+            index_compressed_wasm_hash: WasmHash::from([5_u8; 32]),
+            archive_compressed_wasm_hash: WasmHash::from([6_u8; 32]),
+        };
+        state.update_ledger_suite_version(second_version.clone(), 1);
+        state.update_ledger_suite_version(third_version.clone(), 2);
+
+        let referenced_wasm_hashes = state.referenced_wasm_hashes();
+        for version in [first_version, second_version, third_version] {
+            assert!(referenced_wasm_hashes.contains(&version.ledger_compressed_wasm_hash));
+            assert!(referenced_wasm_hashes.contains(&version.index_compressed_wasm_hash));
+            assert!(referenced_wasm_hashes.contains(&version.archive_compressed_wasm_hash));
+        }
+    }
+
+    #[test]
+    fn should_reference_wasms_of_snapshots() {
+        let mut state = state_with_installed_usdc_ledger_suite();
+        let snapshot_wasm_hash = WasmHash::from([4_u8; 32]);
+        state.record_canister_snapshot(
+            &usdc(),
+            CanisterKind::Ledger,
+            CanisterSnapshot {
+                snapshot_id: vec![1, 2, 3],
+                wasm_hash: snapshot_wasm_hash.clone(),
+                taken_at: 0,
+            },
+        );
+
+        assert!(state.referenced_wasm_hashes().contains(&snapshot_wasm_hash));
+    }
+
+    fn state_with_installed_usdc_ledger_suite() -> State {
+        let mut state = new_state();
+        let version = embedded_ledger_suite_version();
+        state.init_ledger_suite_version(version.clone(), 0);
+        state.record_new_erc20_token(usdc(), usdc_metadata());
+        state.record_created_canister::<Ledger>(&usdc(), Principal::from_slice(&[1_u8; 29]));
+        state.record_installed_canister::<Ledger>(&usdc(), version.ledger_compressed_wasm_hash);
+        state.record_created_canister::<Index>(&usdc(), Principal::from_slice(&[2_u8; 29]));
+        state.record_installed_canister::<Index>(&usdc(), version.index_compressed_wasm_hash);
+        state
+    }
+}
+
+mod ledger_suite_version_history {
+    use crate::ledger_suite_manager::test_fixtures::{usdc, usdc_metadata, usdt, usdt_metadata};
+    use crate::ledger_suite_manager::upgrade_ls::UpgradeLedgerSuite;
+    use crate::state::test_fixtures::new_state;
+    use crate::state::{
+        ActivatedLedgerSuiteVersion, Index, Ledger, LedgerSuiteVersion,
+        LedgerSuiteVersionRollbackError, WasmHash,
+    };
+    use crate::storage::test_fixtures::embedded_ledger_suite_version;
+    use candid::Principal;
+    use maplit::btreemap;
+
+    #[test]
+    fn should_record_activated_versions_in_order() {
+        let mut state = new_state();
+        let first_version = embedded_ledger_suite_version();
+        let second_version = new_ledger_version();
+
+        state.init_ledger_suite_version(first_version.clone(), 1);
+        state.init_ledger_suite_version(second_version.clone(), 2);
+        state.update_ledger_suite_version(second_version.clone(), 3);
+        state.update_ledger_suite_version(second_version.clone(), 4);
+        state.update_ledger_suite_version(first_version.clone(), 5);
+
+        assert_eq!(
+            state.ledger_suite_version_history(),
+            &[
+                ActivatedLedgerSuiteVersion {
+                    version: first_version.clone(),
+                    activated_at: 1
+                },
+                ActivatedLedgerSuiteVersion {
+                    version: second_version.clone(),
+                    activated_at: 3
+                },
+                ActivatedLedgerSuiteVersion {
+                    version: first_version,
+                    activated_at: 5
+                },
+            ]
+        );
+        assert_eq!(state.previous_ledger_suite_version(), Some(&second_version));
+    }
+
+    #[test]
+    fn should_start_history_with_current_version() {
+        let mut state = new_state();
+        state.ledger_suite_version = Some(embedded_ledger_suite_version());
+
+        state.init_ledger_suite_version_history(1);
+        state.init_ledger_suite_version_history(2);
+
+        assert_eq!(
+            state.ledger_suite_version_history(),
+            &[ActivatedLedgerSuiteVersion {
+                version: embedded_ledger_suite_version(),
+                activated_at: 1
+            }]
+        );
+    }
+
+    #[test]
+    fn should_not_roll_back_without_previous_version() {
+        let mut state = new_state();
+        assert_eq!(
+            state.ledger_suite_version_to_roll_back_to(None),
+            Err(LedgerSuiteVersionRollbackError::NoPreviousVersion)
+        );
+
+        state.init_ledger_suite_version(embedded_ledger_suite_version(), 1);
+
+        assert_eq!(
+            state.ledger_suite_version_to_roll_back_to(None),
+            Err(LedgerSuiteVersionRollbackError::NoPreviousVersion)
+        );
+        assert_eq!(
+            state.ledger_suite_version_to_roll_back_to(Some(0)),
+            Err(LedgerSuiteVersionRollbackError::AlreadyCurrentVersion)
+        );
+        assert_eq!(
+            state.ledger_suite_version_to_roll_back_to(Some(1)),
+            Err(LedgerSuiteVersionRollbackError::UnknownHistoryIndex(1))
+        );
+    }
+
+    #[test]
+    fn should_roll_back_ledger_suites_that_already_moved() {
+        let mut state = new_state();
+        let previous_version = embedded_ledger_suite_version();
+        let new_version = new_ledger_version();
+        state.init_ledger_suite_version(previous_version.clone(), 1);
+        for (token, metadata, ledger_id, index_id) in [
+            (usdc(), usdc_metadata(), [1_u8; 29], [2_u8; 29]),
+            (usdt(), usdt_metadata(), [3_u8; 29], [4_u8; 29]),
+        ] {
+            state.record_new_erc20_token(token.clone(), metadata);
+            state.record_created_canister::<Ledger>(&token, Principal::from_slice(&ledger_id));
+            state.record_installed_canister::<Ledger>(
+                &token,
+                previous_version.ledger_compressed_wasm_hash.clone(),
+            );
+            state.record_created_canister::<Index>(&token, Principal::from_slice(&index_id));
+            state.record_installed_canister::<Index>(
+                &token,
+                previous_version.index_compressed_wasm_hash.clone(),
+            );
+        }
+        state.update_ledger_suite_version(new_version.clone(), 2);
+        // only the usdc ledger was upgraded
+        state.record_completed_upgrade_subtask(&usdc());
+        state.record_installed_canister::<Ledger>(
+            &usdc(),
+            new_version.ledger_compressed_wasm_hash.clone(),
+        );
+
+        let version = state.ledger_suite_version_to_roll_back_to(None).unwrap();
+        assert_eq!(version, previous_version);
+        state.update_ledger_suite_version(version, 3);
+
+        assert_eq!(state.ledger_suite_version(), Some(&previous_version));
+        assert_eq!(
+            state.ledger_suites_to_be_upgraded,
+            btreemap! {
+                usdc() => UpgradeLedgerSuite::builder(usdc())
+                    .ledger_wasm_hash(previous_version.ledger_compressed_wasm_hash)
+                    .build()
+            }
+        );
+    }
+
+    fn new_ledger_version() -> LedgerSuiteVersion {
+        LedgerSuiteVersion {
+            ledger_compressed_wasm_hash: WasmHash::from([1_u8; 32]),
+            ..embedded_ledger_suite_version()
+        }
+    }
+}
+
+mod upgrade_campaign {
+    use crate::ledger_suite_manager::test_fixtures::{
+        usdc, usdc_matic, usdc_metadata, usdt, usdt_metadata,
+    };
+    use crate::ledger_suite_manager::upgrade_campaign::{
+        UpgradeCampaignError, UpgradeCampaignStatus,
+    };
+    use crate::state::test_fixtures::new_state;
+    use crate::state::{Erc20Token, Index, Ledger, LedgerSuiteVersion, State, WasmHash};
+    use crate::storage::test_fixtures::embedded_ledger_suite_version;
+    use candid::Principal;
+    use maplit::btreeset;
+    use std::collections::BTreeSet;
+
+    const PAUSE: u64 = 1_000;
+
+    #[test]
+    fn should_not_start_campaign_when_nothing_to_upgrade() {
+        let mut state = state_with_installed_ledger_suites();
+
+        assert_eq!(
+            state.start_upgrade_campaign(
+                embedded_ledger_suite_version(),
+                BTreeSet::new(),
+                1,
+                PAUSE,
+                10
+            ),
+            Err(UpgradeCampaignError::NothingToUpgrade)
+        );
+        assert_eq!(
+            state.start_upgrade_campaign(new_ledger_version(), BTreeSet::new(), 0, PAUSE, 10),
+            Err(UpgradeCampaignError::InvalidBatchSize)
+        );
+        assert_eq!(
+            state.ledger_suite_version(),
+            Some(&embedded_ledger_suite_version())
+        );
+        assert_eq!(state.upgrade_campaign(), None);
+    }
+
+    #[test]
+    fn should_upgrade_canaries_first_and_pause_between_batches() {
+        let mut state = state_with_installed_ledger_suites();
+        state
+            .start_upgrade_campaign(new_ledger_version(), btreeset! {usdt()}, 1, PAUSE, 10)
+            .unwrap();
+        assert_eq!(state.ledger_suite_upgrades_to_execute().len(), 0);
+
+        state.advance_upgrade_campaign(10);
+        assert_eq!(upgrades_to_execute(&state), btreeset! {usdt()});
+
+        state.record_completed_upgrade_subtask(&usdt());
+        state.advance_upgrade_campaign(20);
+        assert_eq!(upgrades_to_execute(&state), BTreeSet::new());
+        assert_eq!(
+            state.upgrade_campaign().unwrap().next_batch_at(),
+            20 + PAUSE
+        );
+
+        state.advance_upgrade_campaign(20 + PAUSE);
+        assert_eq!(upgrades_to_execute(&state), btreeset! {usdc()});
+
+        state.record_completed_upgrade_subtask(&usdc());
+        state.advance_upgrade_campaign(30 + PAUSE);
+        state.advance_upgrade_campaign(30 + 2 * PAUSE);
+        assert_eq!(upgrades_to_execute(&state), btreeset! {usdc_matic()});
+
+        state.record_completed_upgrade_subtask(&usdc_matic());
+        state.advance_upgrade_campaign(40 + 2 * PAUSE);
+        let campaign = state.upgrade_campaign().unwrap();
+        assert_eq!(campaign.status(), &UpgradeCampaignStatus::Completed);
+        assert_eq!(campaign.done(), &btreeset! {usdc(), usdc_matic(), usdt()});
+    }
+
+    #[test]
+    fn should_stop_campaign_on_failed_upgrade() {
+        let mut state = state_with_installed_ledger_suites();
+        state
+            .start_upgrade_campaign(new_ledger_version(), btreeset! {usdt()}, 2, PAUSE, 10)
+            .unwrap();
+        state.advance_upgrade_campaign(10);
+
+        state.record_failed_ledger_suite_upgrade(&usdt(), "upgrade failed".to_string());
+        state.advance_upgrade_campaign(10 + PAUSE);
+
+        let campaign = state.upgrade_campaign().unwrap();
+        assert_eq!(campaign.status(), &UpgradeCampaignStatus::Stopped);
+        assert_eq!(campaign.failed().get(&usdt()).unwrap(), "upgrade failed");
+        assert_eq!(upgrades_to_execute(&state), BTreeSet::new());
+        assert_eq!(
+            state.start_upgrade_campaign(new_ledger_version(), BTreeSet::new(), 2, PAUSE, 20),
+            Ok(())
+        );
+    }
+
+    #[test]
+    fn should_resume_or_cancel_stopped_campaign() {
+        let mut state = state_with_installed_ledger_suites();
+        state
+            .start_upgrade_campaign(new_ledger_version(), btreeset! {usdt()}, 1, PAUSE, 10)
+            .unwrap();
+        assert!(!state.resume_upgrade_campaign());
+        state.advance_upgrade_campaign(10);
+        state.record_failed_ledger_suite_upgrade(&usdt(), "upgrade failed".to_string());
+        state.advance_upgrade_campaign(10 + PAUSE);
+        assert_eq!(upgrades_to_execute(&state), BTreeSet::new());
+
+        let mut resumed_state = state.clone();
+        assert!(resumed_state.resume_upgrade_campaign());
+        resumed_state.advance_upgrade_campaign(20 + PAUSE);
+        assert_eq!(
+            resumed_state.upgrade_campaign().unwrap().status(),
+            &UpgradeCampaignStatus::Running
+        );
+        assert_eq!(upgrades_to_execute(&resumed_state), btreeset! {usdc()});
+
+        assert!(state.cancel_upgrade_campaign());
+        assert!(!state.cancel_upgrade_campaign());
+        assert_eq!(
+            state.upgrade_campaign().unwrap().status(),
+            &UpgradeCampaignStatus::Cancelled
+        );
+        assert_eq!(
+            upgrades_to_execute(&state),
+            btreeset! {usdc(), usdc_matic()}
+        );
+    }
+
+    #[test]
+    fn should_cancel_campaign_when_ledger_suite_version_changes() {
+        let mut state = state_with_installed_ledger_suites();
+        state
+            .start_upgrade_campaign(new_ledger_version(), BTreeSet::new(), 1, PAUSE, 10)
+            .unwrap();
+        assert_eq!(
+            state.start_upgrade_campaign(new_ledger_version(), BTreeSet::new(), 1, PAUSE, 10),
+            Err(UpgradeCampaignError::CampaignAlreadyRunning)
+        );
+
+        state.update_ledger_suite_version(embedded_ledger_suite_version(), 20);
+
+        assert_eq!(
+            state.upgrade_campaign().unwrap().status(),
+            &UpgradeCampaignStatus::Cancelled
+        );
+        assert_eq!(
+            upgrades_to_execute(&state),
+            state.ledger_suites_to_be_upgraded.keys().cloned().collect()
+        );
+    }
+
+    fn upgrades_to_execute(state: &State) -> BTreeSet<Erc20Token> {
+        state
+            .ledger_suite_upgrades_to_execute()
+            .into_keys()
+            .collect()
+    }
+
+    fn state_with_installed_ledger_suites() -> State {
+        let mut state = new_state();
+        let version = embedded_ledger_suite_version();
+        state.init_ledger_suite_version(version.clone(), 1);
+        for (token, metadata, ledger_id, index_id) in [
+            (usdc(), usdc_metadata(), [1_u8; 29], [2_u8; 29]),
+            (usdc_matic(), usdc_metadata(), [3_u8; 29], [4_u8; 29]),
+            (usdt(), usdt_metadata(), [5_u8; 29], [6_u8; 29]),
+        ] {
+            state.record_new_erc20_token(token.clone(), metadata);
+            state.record_created_canister::<Ledger>(&token, Principal::from_slice(&ledger_id));
+            state.record_installed_canister::<Ledger>(
+                &token,
+                version.ledger_compressed_wasm_hash.clone(),
+            );
+            state.record_created_canister::<Index>(&token, Principal::from_slice(&index_id));
+            state.record_installed_canister::<Index>(
+                &token,
+                version.index_compressed_wasm_hash.clone(),
+            );
+        }
+        state
+    }
+
+    fn new_ledger_version() -> LedgerSuiteVersion {
+        LedgerSuiteVersion {
+            ledger_compressed_wasm_hash: WasmHash::from([1_u8; 32]),
+            ..embedded_ledger_suite_version()
+        }
     }
 }
 
@@ -500,16 +1063,37 @@ mod schema_upgrades {
             State {
                 managed_canisters,
                 cycles_management,
+                install_retry_policy: _,
+                max_concurrent_ls_installs: _,
                 more_controller_ids,
                 minter_id,
                 ledger_suite_version,
+                ledger_suite_requests_pending_approval: _,
+                rejected_ledger_suite_requests: _,
                 twin_ledger_suites_to_be_installed,
                 failed_ledger_suite_installs,
+                ledger_suite_install_progress: _,
+                ledger_suites_to_be_upgraded: _,
+                ledger_suite_version_history: _,
+                module_hash_drift_reports: _,
+                archive_wasm_hashes: _,
+                upgrade_rollbacks: _,
+                upgrade_campaign: _,
                 collected_icp_token,
                 collected_appic_token,
                 minimum_tokens_for_new_ledger_suite,
                 received_deposits,
-                notify_add_erc20_list,
+                notification_outbox: _,
+                next_notification_id: _,
+                token_denylists: _,
+                archive_profiles: _,
+                canister_settings: _,
+                applied_canister_settings: _,
+                canister_placement: _,
+                canister_pool_config: _,
+                canister_pool: _,
+                applied_controllers: _,
+                controllers_reports: _,
             }: State,
         ) -> Self {
             Self {
@@ -524,7 +1108,7 @@ mod schema_upgrades {
                 collected_appic_token,
                 minimum_tokens_for_new_ledger_suite,
                 received_deposits,
-                notify_add_erc20_list,
+                notify_add_erc20_list: Default::default(),
             }
         }
     }
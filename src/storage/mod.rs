@@ -14,6 +14,8 @@ use ic_stable_structures::{BTreeMap, Storable};
 use serde::Serialize;
 use std::borrow::Cow;
 use std::cell::RefCell;
+use std::collections::BTreeSet;
+use std::time::Duration;
 
 // Wasm converted to byte code
 pub(crate) const LEDGER_BYTECODE: &[u8] =
@@ -23,6 +25,15 @@ pub(crate) const INDEX_BYTECODE: &[u8] =
 pub(crate) const ARCHIVE_NODE_BYTECODE: &[u8] =
     include_bytes!("../../icrc_wasm/archive_canister_u256.wasm.gz");
 
+// Upper bound on the size of a decompressed wasm module.
+const MAX_UNCOMPRESSED_WASM_SIZE: u64 = 100 * 1024 * 1024;
+
+// Upper bound on the size of an uploaded wasm, which is the largest wasm module the IC installs.
+pub const MAX_WASM_SIZE: u64 = 100 * 1024 * 1024;
+
+// Uploads that did not receive a chunk for this long are dropped.
+pub const WASM_UPLOAD_TIMEOUT: Duration = Duration::from_secs(60 * 60);
+
 pub(crate) mod memory {
     use ic_stable_structures::memory_manager::{MemoryId, MemoryManager, VirtualMemory};
     use ic_stable_structures::DefaultMemoryImpl;
@@ -50,9 +61,14 @@ pub(crate) mod memory {
 
 pub type WasmStore = BTreeMap<WasmHash, StoredWasm, StableMemory>;
 
+/// Wasm uploads in progress, identified by the hash of the complete wasm.
+pub type WasmUploads = std::collections::BTreeMap<WasmHash, WasmUpload>;
+
 thread_local! {
     static WASM_STORE: RefCell<WasmStore> = RefCell::new(WasmStore::init(wasm_store_memory()));
 
+    // Uploads are only kept on the heap and are therefore lost when the lsm is upgraded.
+    static WASM_UPLOADS: RefCell<WasmUploads> = RefCell::default();
 }
 
 #[derive(Clone, PartialEq, Debug, Deserialize, Serialize)]
@@ -75,6 +91,11 @@ impl StoredWasm {
     pub fn marker(&self) -> u8 {
         self.marker
     }
+
+    /// Size of the wasm binary in bytes.
+    pub fn size(&self) -> usize {
+        self.binary.len()
+    }
 }
 
 pub trait StorableWasm {
@@ -159,6 +180,125 @@ pub fn wasm_store_try_insert<T: StorableWasm>(
     }
 }
 
+/// A wasm being uploaded in chunks.
+#[derive(Clone, PartialEq, Debug)]
+pub struct WasmUpload {
+    /// Encodes which type of wasm is uploaded.
+    marker: u8,
+    total_chunks: u32,
+    next_chunk_index: u32,
+    binary: Vec<u8>,
+    /// The canister time at which the last chunk was received
+    /// in nanoseconds since the epoch (1970-01-01).
+    last_chunk_at: u64,
+}
+
+#[derive(Clone, PartialEq, Debug)]
+pub enum WasmUploadError {
+    InvalidChunkIndex {
+        chunk_index: u32,
+        total_chunks: u32,
+    },
+    UnexpectedChunk {
+        expected_chunk_index: u32,
+        actual_chunk_index: u32,
+    },
+    HashMismatch {
+        expected: WasmHash,
+        actual: WasmHash,
+    },
+    WasmTooLarge {
+        max_size: u64,
+    },
+    WasmStoreError(WasmStoreError),
+}
+
+/// Adds a chunk to the upload of the wasm identified by `wasm_hash`.
+///
+/// Chunks must be sent in order and sending the first chunk (re)starts the upload.
+/// Once the last chunk is received, the hash of the complete wasm is checked
+/// and the wasm is inserted into the store.
+/// Uploads that did not receive a chunk for [`WASM_UPLOAD_TIMEOUT`] are dropped.
+///
+/// Returns `true` if the wasm was complete and is now in the store.
+///
+/// # Errors
+/// * [`WasmUploadError::InvalidChunkIndex`] if `chunk_index` is not smaller than `total_chunks`
+/// * [`WasmUploadError::UnexpectedChunk`] if the chunk is not the next one of an upload of the same type
+/// * [`WasmUploadError::HashMismatch`] if the complete wasm does not have the expected hash
+/// * [`WasmUploadError::WasmTooLarge`] if the wasm exceeds [`MAX_WASM_SIZE`], in which case the upload is dropped
+/// * [`WasmUploadError::WasmStoreError`] if the wasm could not be inserted into the store
+pub fn wasm_store_try_insert_chunk<T: StorableWasm>(
+    wasm_store: &mut WasmStore,
+    uploads: &mut WasmUploads,
+    timestamp: u64,
+    wasm_hash: WasmHash,
+    chunk_index: u32,
+    total_chunks: u32,
+    chunk: Vec<u8>,
+) -> Result<bool, WasmUploadError> {
+    if chunk_index >= total_chunks {
+        return Err(WasmUploadError::InvalidChunkIndex {
+            chunk_index,
+            total_chunks,
+        });
+    }
+    let timeout = WASM_UPLOAD_TIMEOUT.as_nanos() as u64;
+    uploads.retain(|_, upload| upload.last_chunk_at.saturating_add(timeout) > timestamp);
+    if chunk_index == 0 {
+        uploads.insert(
+            wasm_hash.clone(),
+            WasmUpload {
+                marker: T::MARKER,
+                total_chunks,
+                next_chunk_index: 0,
+                binary: vec![],
+                last_chunk_at: timestamp,
+            },
+        );
+    }
+    let upload = match uploads.get_mut(&wasm_hash) {
+        Some(upload) if upload.marker == T::MARKER && upload.total_chunks == total_chunks => upload,
+        _ => {
+            return Err(WasmUploadError::UnexpectedChunk {
+                expected_chunk_index: 0,
+                actual_chunk_index: chunk_index,
+            })
+        }
+    };
+    if upload.next_chunk_index != chunk_index {
+        return Err(WasmUploadError::UnexpectedChunk {
+            expected_chunk_index: upload.next_chunk_index,
+            actual_chunk_index: chunk_index,
+        });
+    }
+    if (upload.binary.len() + chunk.len()) as u64 > MAX_WASM_SIZE {
+        uploads.remove(&wasm_hash);
+        return Err(WasmUploadError::WasmTooLarge {
+            max_size: MAX_WASM_SIZE,
+        });
+    }
+    upload.binary.extend(chunk);
+    upload.next_chunk_index += 1;
+    upload.last_chunk_at = timestamp;
+    if upload.next_chunk_index < total_chunks {
+        return Ok(false);
+    }
+
+    let upload = uploads
+        .remove(&wasm_hash)
+        .expect("BUG: missing wasm upload");
+    let wasm = Wasm::<T>::new(upload.binary);
+    if wasm.hash() != &wasm_hash {
+        return Err(WasmUploadError::HashMismatch {
+            expected: wasm_hash,
+            actual: wasm.hash().clone(),
+        });
+    }
+    wasm_store_try_insert(wasm_store, timestamp, wasm).map_err(WasmUploadError::WasmStoreError)?;
+    Ok(true)
+}
+
 /// Retrieves a wasm identified by its hash from the store, or `Ok(None)` if no such wasm is present.
 ///
 /// # Errors
@@ -202,6 +342,16 @@ pub fn record_icrc1_ledger_suite_wasms(
     })
 }
 
+/// Returns the version of the ledger suite wasms embedded in the lsm,
+/// without recording them in the store.
+pub fn embedded_ledger_suite_version() -> LedgerSuiteVersion {
+    LedgerSuiteVersion {
+        ledger_compressed_wasm_hash: LedgerWasm::from(LEDGER_BYTECODE).hash().clone(),
+        index_compressed_wasm_hash: IndexWasm::from(INDEX_BYTECODE).hash().clone(),
+        archive_compressed_wasm_hash: ArchiveWasm::from(ARCHIVE_NODE_BYTECODE).hash().clone(),
+    }
+}
+
 fn record_wasm<T: StorableWasm>(
     wasm_store: &mut WasmStore,
     timestamp: u64,
@@ -248,6 +398,23 @@ pub fn validate_wasm_hashes(
     ])
 }
 
+/// Returns the hash of a wasm of the given ledger suite version that is missing from the store, if any.
+pub fn find_missing_ledger_suite_wasm(
+    wasm_store: &WasmStore,
+    version: &LedgerSuiteVersion,
+) -> Option<WasmHash> {
+    if !wasm_store_contain::<Ledger>(wasm_store, &version.ledger_compressed_wasm_hash) {
+        return Some(version.ledger_compressed_wasm_hash.clone());
+    }
+    if !wasm_store_contain::<Index>(wasm_store, &version.index_compressed_wasm_hash) {
+        return Some(version.index_compressed_wasm_hash.clone());
+    }
+    if !wasm_store_contain::<Archive>(wasm_store, &version.archive_compressed_wasm_hash) {
+        return Some(version.archive_compressed_wasm_hash.clone());
+    }
+    None
+}
+
 pub fn wasm_store_contain<T: StorableWasm>(wasm_store: &WasmStore, wasm_hash: &WasmHash) -> bool {
     match wasm_store_try_get::<T>(wasm_store, wasm_hash) {
         Ok(Some(_)) => true,
@@ -255,6 +422,88 @@ pub fn wasm_store_contain<T: StorableWasm>(wasm_store: &WasmStore, wasm_hash: &W
     }
 }
 
+/// Returns the hash of the uncompressed module of the wasm with the given hash,
+/// or `None` if no such wasm is present or it cannot be decompressed.
+///
+/// This is the module hash reported by the IC for a canister on which this wasm is installed,
+/// while the wasm hash recorded by the lsm is the one of the possibly gzip-compressed wasm.
+pub fn wasm_store_try_get_uncompressed_hash(
+    wasm_store: &WasmStore,
+    wasm_hash: &WasmHash,
+) -> Option<WasmHash> {
+    let stored_wasm = wasm_store.get(wasm_hash)?;
+    let uncompressed_binary = if stored_wasm.binary.starts_with(&GZIP_MAGIC_BYTES) {
+        gunzip(&stored_wasm.binary).ok()?
+    } else {
+        stored_wasm.binary
+    };
+    Some(WasmHash::from(ic_crypto_sha2::Sha256::hash(
+        uncompressed_binary.as_slice(),
+    )))
+}
+
+const GZIP_MAGIC_BYTES: [u8; 2] = [0x1f, 0x8b];
+
+fn gunzip(binary: &[u8]) -> std::io::Result<Vec<u8>> {
+    use std::io::Read;
+
+    let mut uncompressed = Vec::new();
+    flate2::read::GzDecoder::new(binary)
+        .take(MAX_UNCOMPRESSED_WASM_SIZE + 1)
+        .read_to_end(&mut uncompressed)?;
+    if uncompressed.len() as u64 > MAX_UNCOMPRESSED_WASM_SIZE {
+        return Err(std::io::Error::new(
+            std::io::ErrorKind::InvalidData,
+            "uncompressed wasm is too large",
+        ));
+    }
+    Ok(uncompressed)
+}
+
+/// A wasm removed from the store by [`wasm_store_remove_unreferenced`].
+#[derive(Clone, PartialEq, Debug)]
+pub struct RemovedWasm {
+    pub wasm_hash: WasmHash,
+    pub marker: u8,
+    pub timestamp: u64,
+    pub size: usize,
+}
+
+/// Removes every wasm from the store that is not in `referenced_wasm_hashes`
+/// and that was stored more than `retention_period` before `now` (in nanoseconds since the epoch).
+///
+/// Returns the removed wasms.
+pub fn wasm_store_remove_unreferenced(
+    wasm_store: &mut WasmStore,
+    referenced_wasm_hashes: &BTreeSet<WasmHash>,
+    now: u64,
+    retention_period: Duration,
+) -> Vec<RemovedWasm> {
+    let retention_period_nanos = u64::try_from(retention_period.as_nanos()).unwrap_or(u64::MAX);
+    let unreferenced_wasm_hashes: Vec<WasmHash> = wasm_store
+        .iter()
+        .filter(|(wasm_hash, stored_wasm)| {
+            !referenced_wasm_hashes.contains(wasm_hash)
+                && now.saturating_sub(stored_wasm.timestamp) >= retention_period_nanos
+        })
+        .map(|(wasm_hash, _stored_wasm)| wasm_hash)
+        .collect();
+    unreferenced_wasm_hashes
+        .into_iter()
+        .map(|wasm_hash| {
+            let stored_wasm = wasm_store
+                .remove(&wasm_hash)
+                .expect("BUG: missing stored wasm");
+            RemovedWasm {
+                wasm_hash,
+                marker: stored_wasm.marker,
+                timestamp: stored_wasm.timestamp,
+                size: stored_wasm.size(),
+            }
+        })
+        .collect()
+}
+
 pub fn read_wasm_store<R>(f: impl FnOnce(&WasmStore) -> R) -> R {
     WASM_STORE.with(|w| f(&w.borrow()))
 }
@@ -262,3 +511,7 @@ pub fn read_wasm_store<R>(f: impl FnOnce(&WasmStore) -> R) -> R {
 pub fn mutate_wasm_store<R>(f: impl FnOnce(&mut WasmStore) -> R) -> R {
     WASM_STORE.with(|w| f(&mut w.borrow_mut()))
 }
+
+pub fn mutate_wasm_uploads<R>(f: impl FnOnce(&mut WasmUploads) -> R) -> R {
+    WASM_UPLOADS.with(|u| f(&mut u.borrow_mut()))
+}
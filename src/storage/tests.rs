@@ -230,6 +230,385 @@ mod validate_wasm_hashes {
     }
 }
 
+mod wasm_store_try_insert_chunk {
+    use crate::state::{Index, IndexWasm, Ledger, LedgerWasm, WasmHash};
+    use crate::storage::test_fixtures::empty_wasm_store;
+    use crate::storage::tests::arb_binary;
+    use crate::storage::{
+        wasm_store_try_get, wasm_store_try_insert, wasm_store_try_insert_chunk, StorableWasm,
+        WasmStoreError, WasmUploadError, WasmUploads, MAX_WASM_SIZE, WASM_UPLOAD_TIMEOUT,
+    };
+    use proptest::{prop_assert_eq, proptest};
+
+    const TIMESTAMP: u64 = 1_620_328_630_000_000_000;
+
+    proptest! {
+        #[test]
+        fn should_record_wasm_uploaded_in_chunks(binary in arb_binary(), chunk_size in 1_usize..100) {
+            let mut wasm_store = empty_wasm_store();
+            let mut uploads = WasmUploads::default();
+            let wasm = LedgerWasm::from(binary.clone());
+            let chunks: Vec<_> = if binary.is_empty() {
+                vec![vec![]]
+            } else {
+                binary.chunks(chunk_size).map(|c| c.to_vec()).collect()
+            };
+            let total_chunks = chunks.len() as u32;
+
+            for (chunk_index, chunk) in chunks.into_iter().enumerate() {
+                let chunk_index = chunk_index as u32;
+                prop_assert_eq!(
+                    wasm_store_try_insert_chunk::<Ledger>(&mut wasm_store, &mut uploads, TIMESTAMP, wasm.hash().clone(), chunk_index, total_chunks, chunk),
+                    Ok(chunk_index + 1 == total_chunks)
+                );
+            }
+
+            prop_assert_eq!(wasm_store_try_get::<Ledger>(&wasm_store, wasm.hash()), Ok(Some(wasm)));
+            prop_assert_eq!(uploads, WasmUploads::default());
+        }
+    }
+
+    #[test]
+    fn should_error_on_hash_mismatch() {
+        let mut wasm_store = empty_wasm_store();
+        let mut uploads = WasmUploads::default();
+        let wasm = LedgerWasm::from(vec![1_u8, 2, 3]);
+        let wrong_hash = WasmHash::from([0_u8; 32]);
+
+        assert_eq!(
+            wasm_store_try_insert_chunk::<Ledger>(
+                &mut wasm_store,
+                &mut uploads,
+                TIMESTAMP,
+                wrong_hash.clone(),
+                0,
+                1,
+                vec![1_u8, 2, 3]
+            ),
+            Err(WasmUploadError::HashMismatch {
+                expected: wrong_hash.clone(),
+                actual: wasm.hash().clone(),
+            })
+        );
+        assert_eq!(
+            wasm_store_try_get::<Ledger>(&wasm_store, &wrong_hash),
+            Ok(None)
+        );
+        assert_eq!(
+            wasm_store_try_get::<Ledger>(&wasm_store, wasm.hash()),
+            Ok(None)
+        );
+    }
+
+    #[test]
+    fn should_error_on_unexpected_chunk() {
+        let mut wasm_store = empty_wasm_store();
+        let mut uploads = WasmUploads::default();
+        let wasm = LedgerWasm::from(vec![1_u8, 2, 3]);
+
+        assert_eq!(
+            wasm_store_try_insert_chunk::<Ledger>(
+                &mut wasm_store,
+                &mut uploads,
+                TIMESTAMP,
+                wasm.hash().clone(),
+                1,
+                3,
+                vec![2_u8]
+            ),
+            Err(WasmUploadError::UnexpectedChunk {
+                expected_chunk_index: 0,
+                actual_chunk_index: 1,
+            })
+        );
+        assert_eq!(
+            wasm_store_try_insert_chunk::<Ledger>(
+                &mut wasm_store,
+                &mut uploads,
+                TIMESTAMP,
+                wasm.hash().clone(),
+                0,
+                3,
+                vec![1_u8]
+            ),
+            Ok(false)
+        );
+        assert_eq!(
+            wasm_store_try_insert_chunk::<Ledger>(
+                &mut wasm_store,
+                &mut uploads,
+                TIMESTAMP,
+                wasm.hash().clone(),
+                2,
+                3,
+                vec![3_u8]
+            ),
+            Err(WasmUploadError::UnexpectedChunk {
+                expected_chunk_index: 1,
+                actual_chunk_index: 2,
+            })
+        );
+        assert_eq!(
+            wasm_store_try_insert_chunk::<Index>(
+                &mut wasm_store,
+                &mut uploads,
+                TIMESTAMP,
+                wasm.hash().clone(),
+                1,
+                3,
+                vec![2_u8]
+            ),
+            Err(WasmUploadError::UnexpectedChunk {
+                expected_chunk_index: 0,
+                actual_chunk_index: 1,
+            })
+        );
+        assert_eq!(
+            wasm_store_try_insert_chunk::<Ledger>(
+                &mut wasm_store,
+                &mut uploads,
+                TIMESTAMP,
+                wasm.hash().clone(),
+                3,
+                3,
+                vec![]
+            ),
+            Err(WasmUploadError::InvalidChunkIndex {
+                chunk_index: 3,
+                total_chunks: 3,
+            })
+        );
+    }
+
+    #[test]
+    fn should_drop_upload_of_too_large_wasm() {
+        let mut wasm_store = empty_wasm_store();
+        let mut uploads = WasmUploads::default();
+        let wasm_hash = WasmHash::from([1_u8; 32]);
+        let chunk_size = 2 * 1024 * 1024;
+        let total_chunks = (MAX_WASM_SIZE as usize / chunk_size + 1) as u32;
+
+        for chunk_index in 0..total_chunks - 1 {
+            assert_eq!(
+                wasm_store_try_insert_chunk::<Ledger>(
+                    &mut wasm_store,
+                    &mut uploads,
+                    TIMESTAMP,
+                    wasm_hash.clone(),
+                    chunk_index,
+                    total_chunks,
+                    vec![0_u8; chunk_size]
+                ),
+                Ok(false)
+            );
+        }
+        assert_eq!(
+            wasm_store_try_insert_chunk::<Ledger>(
+                &mut wasm_store,
+                &mut uploads,
+                TIMESTAMP,
+                wasm_hash.clone(),
+                total_chunks - 1,
+                total_chunks,
+                vec![0_u8; chunk_size]
+            ),
+            Err(WasmUploadError::WasmTooLarge {
+                max_size: MAX_WASM_SIZE
+            })
+        );
+        assert_eq!(uploads, WasmUploads::default());
+    }
+
+    #[test]
+    fn should_drop_stale_upload() {
+        let mut wasm_store = empty_wasm_store();
+        let mut uploads = WasmUploads::default();
+        let wasm = LedgerWasm::from(vec![1_u8, 2]);
+        let timeout = WASM_UPLOAD_TIMEOUT.as_nanos() as u64;
+
+        assert_eq!(
+            wasm_store_try_insert_chunk::<Ledger>(
+                &mut wasm_store,
+                &mut uploads,
+                TIMESTAMP,
+                wasm.hash().clone(),
+                0,
+                2,
+                vec![1_u8]
+            ),
+            Ok(false)
+        );
+        assert_eq!(
+            wasm_store_try_insert_chunk::<Index>(
+                &mut wasm_store,
+                &mut uploads,
+                TIMESTAMP + timeout,
+                WasmHash::from([1_u8; 32]),
+                0,
+                2,
+                vec![1_u8]
+            ),
+            Ok(false)
+        );
+        assert_eq!(uploads.len(), 1);
+        assert_eq!(
+            wasm_store_try_insert_chunk::<Ledger>(
+                &mut wasm_store,
+                &mut uploads,
+                TIMESTAMP + timeout,
+                wasm.hash().clone(),
+                1,
+                2,
+                vec![2_u8]
+            ),
+            Err(WasmUploadError::UnexpectedChunk {
+                expected_chunk_index: 0,
+                actual_chunk_index: 1,
+            })
+        );
+    }
+
+    #[test]
+    fn should_error_when_uploading_wasm_with_other_type() {
+        let mut wasm_store = empty_wasm_store();
+        let mut uploads = WasmUploads::default();
+        let binary = vec![1_u8, 2, 3];
+        let ledger_wasm = LedgerWasm::from(binary.clone());
+        let index_wasm = IndexWasm::from(binary.clone());
+        assert_eq!(
+            wasm_store_try_insert(&mut wasm_store, TIMESTAMP, ledger_wasm),
+            Ok(())
+        );
+
+        assert_eq!(
+            wasm_store_try_insert_chunk::<Index>(
+                &mut wasm_store,
+                &mut uploads,
+                TIMESTAMP,
+                index_wasm.hash().clone(),
+                0,
+                1,
+                binary
+            ),
+            Err(WasmUploadError::WasmStoreError(
+                WasmStoreError::WasmMismatch {
+                    wasm_hash: index_wasm.hash().clone(),
+                    expected_marker: Index::MARKER,
+                    actual_marker: Ledger::MARKER,
+                }
+            ))
+        );
+    }
+}
+
+mod wasm_store_remove_unreferenced {
+    use crate::state::{ArchiveWasm, IndexWasm, Ledger, LedgerWasm};
+    use crate::storage::test_fixtures::empty_wasm_store;
+    use crate::storage::{
+        wasm_store_remove_unreferenced, wasm_store_try_insert, RemovedWasm, StorableWasm,
+    };
+    use std::collections::BTreeSet;
+    use std::time::Duration;
+
+    const RETENTION_PERIOD: Duration = Duration::from_secs(60);
+    const STORED_AT: u64 = 1_000_000_000;
+
+    #[test]
+    fn should_remove_unreferenced_wasms_outside_retention_period() {
+        let mut wasm_store = empty_wasm_store();
+        let referenced_wasm = LedgerWasm::from(vec![1_u8; 10]);
+        let unreferenced_wasm = LedgerWasm::from(vec![2_u8; 20]);
+        let recent_wasm = IndexWasm::from(vec![3_u8; 30]);
+        wasm_store_try_insert(&mut wasm_store, STORED_AT, referenced_wasm.clone()).unwrap();
+        wasm_store_try_insert(&mut wasm_store, STORED_AT, unreferenced_wasm.clone()).unwrap();
+        let now = STORED_AT + RETENTION_PERIOD.as_nanos() as u64;
+        wasm_store_try_insert(&mut wasm_store, now - 1, recent_wasm.clone()).unwrap();
+
+        let removed_wasms = wasm_store_remove_unreferenced(
+            &mut wasm_store,
+            &BTreeSet::from([referenced_wasm.hash().clone()]),
+            now,
+            RETENTION_PERIOD,
+        );
+
+        assert_eq!(
+            removed_wasms,
+            vec![RemovedWasm {
+                wasm_hash: unreferenced_wasm.hash().clone(),
+                marker: Ledger::MARKER,
+                timestamp: STORED_AT,
+                size: 20,
+            }]
+        );
+        assert!(wasm_store.contains_key(referenced_wasm.hash()));
+        assert!(!wasm_store.contains_key(unreferenced_wasm.hash()));
+        assert!(wasm_store.contains_key(recent_wasm.hash()));
+    }
+
+    #[test]
+    fn should_not_remove_anything_when_all_wasms_referenced() {
+        let mut wasm_store = empty_wasm_store();
+        let archive_wasm = ArchiveWasm::from(vec![1_u8; 10]);
+        wasm_store_try_insert(&mut wasm_store, STORED_AT, archive_wasm.clone()).unwrap();
+
+        let removed_wasms = wasm_store_remove_unreferenced(
+            &mut wasm_store,
+            &BTreeSet::from([archive_wasm.hash().clone()]),
+            u64::MAX,
+            RETENTION_PERIOD,
+        );
+
+        assert_eq!(removed_wasms, vec![]);
+        assert_eq!(wasm_store.len(), 1);
+    }
+}
+
+mod wasm_store_try_get_uncompressed_hash {
+    use crate::state::{LedgerWasm, WasmHash};
+    use crate::storage::test_fixtures::empty_wasm_store;
+    use crate::storage::{
+        gunzip, wasm_store_try_get_uncompressed_hash, wasm_store_try_insert, LEDGER_BYTECODE,
+    };
+
+    #[test]
+    fn should_hash_decompressed_wasm() {
+        let mut wasm_store = empty_wasm_store();
+        let ledger_wasm = LedgerWasm::from(LEDGER_BYTECODE);
+        wasm_store_try_insert(&mut wasm_store, 0, ledger_wasm.clone()).unwrap();
+        let uncompressed_binary = gunzip(LEDGER_BYTECODE).unwrap();
+        assert!(uncompressed_binary.starts_with(b"\0asm"));
+
+        assert_eq!(
+            wasm_store_try_get_uncompressed_hash(&wasm_store, ledger_wasm.hash()),
+            Some(WasmHash::from(ic_crypto_sha2::Sha256::hash(
+                uncompressed_binary.as_slice()
+            )))
+        );
+    }
+
+    #[test]
+    fn should_hash_uncompressed_wasm_as_is() {
+        let mut wasm_store = empty_wasm_store();
+        let ledger_wasm = LedgerWasm::from(b"\0asm\x01\0\0\0".to_vec());
+        wasm_store_try_insert(&mut wasm_store, 0, ledger_wasm.clone()).unwrap();
+
+        assert_eq!(
+            wasm_store_try_get_uncompressed_hash(&wasm_store, ledger_wasm.hash()),
+            Some(ledger_wasm.hash().clone())
+        );
+        assert_eq!(
+            wasm_store_try_get_uncompressed_hash(&wasm_store, &WasmHash::default()),
+            None
+        );
+    }
+
+    #[test]
+    fn should_error_on_truncated_gzip_data() {
+        assert!(gunzip(&LEDGER_BYTECODE[..5]).is_err());
+        assert!(gunzip(&LEDGER_BYTECODE[..LEDGER_BYTECODE.len() / 2]).is_err());
+    }
+}
+
 fn arb_binary() -> impl Strategy<Value = Vec<u8>> {
     vec(any::<u8>(), 0..1000)
 }